@@ -1,6 +1,7 @@
 use super::Locked;
 use alloc::alloc::Layout;
 use core::{alloc::GlobalAlloc, mem, ptr, ptr::NonNull};
+use x86_64::instructions::interrupts::without_interrupts;
 
 /// Размеры блоков для использования.
 ///
@@ -53,8 +54,21 @@ impl FixedSizeBlockAllocator {
     }
 }
 
+// Выделение памяти выполняется с выключенными прерываниями: иначе поток,
+// вытесненный с захваченной блокировкой, или обработчик прерывания могли бы
+// навсегда зависнуть на ней.
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| unsafe { self.alloc_inner(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| unsafe { self.dealloc_inner(ptr, layout) })
+    }
+}
+
+impl Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc_inner(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
 
         match list_index(&layout) {
@@ -80,7 +94,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe fn dealloc_inner(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
 
         match list_index(&layout) {
@@ -11,7 +11,7 @@ use x86_64::{
 pub mod fixed_size_block;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 1024 * 100; // 100 Kib
+pub const HEAP_SIZE: usize = 1024 * 1024 * 4; // 4 MiB
 
 use fixed_size_block::FixedSizeBlockAllocator as Allocator;
 
@@ -17,6 +17,9 @@ entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
     config.mappings.physical_memory = Some(Mapping::Dynamic);
+    // Всё, что размещает загрузчик, попадает в верхнюю половину адресов,
+    // а нижняя остается процессам (см. `memory::KERNEL_SPACE_START`).
+    config.mappings.dynamic_range_start = Some(0xFFFF_8000_0000_0000);
 
     config
 };
@@ -24,8 +27,9 @@ static BOOTLOADER_CONFIG: BootloaderConfig = {
 /// Точка входа в ядро.
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     use bootloader_api::info::Optional;
-    use enigma_kernel::task::{Task, executor::Executor, keyboard};
-    use enigma_kernel::{drivers::apic, memory};
+    use enigma_kernel::drivers::{ahci, apic, pci};
+    use enigma_kernel::task::{Priority, Task, executor::Executor, keyboard};
+    use enigma_kernel::{fs, memory};
     use x86_64::VirtAddr;
 
     // Проверка на наличие фреймбуфера.
@@ -58,9 +62,14 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     // Инициализация кучи ядра.
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Head initialization failed");
 
+    // Инициализация планировщика потоков (текущий поток становится `kernel-main`)
+    // и потока `kworker` для отложенной обработки прерываний.
+    enigma_kernel::thread::init();
+    enigma_kernel::deferred::init();
+
     // Инициализация APIC контроллера.
+    let rsdp: Option<u64> = boot_info.rsdp_addr.take();
     unsafe {
-        let rsdp: Option<u64> = boot_info.rsdp_addr.take();
         apic::init(
             rsdp.expect("") as usize,
             phys_mem_offset,
@@ -69,13 +78,34 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         );
     }
 
+    // Дальше кадры выделяются через общий распределитель (процессы, драйверы).
+    memory::init_frame_allocator(frame_allocator);
+
+    // Перечисление устройств PCI (окно MMIO для ECAM уже доступно) и
+    // подключение дисков SATA.
+    unsafe { pci::init(rsdp.map(|rsdp| rsdp as usize)) };
+    ahci::init();
+
+    // Файловая система загрузки (ramdisk), если загрузчик её передал,
+    // становится корнем дерева файлов, а `/tmp` - tmpfs.
+    let ramdisk = boot_info.ramdisk_addr.into_option().and_then(|addr| {
+        unsafe { fs::ramdisk::init(VirtAddr::new(addr), boot_info.ramdisk_len) }
+            .inspect_err(|error| serial_println!("ramdisk is corrupted: {}", error))
+            .ok()
+    });
+    fs::init(ramdisk).expect("failed to mount the root file system");
+
     // Запуск тестов (если требуется).
     #[cfg(test)]
     test_main();
 
-    // Запуск ассинхронных служб ядра.
+    // Запуск ассинхронных служб ядра (исполнитель работает в потоке `kernel-main`).
     let mut executor = Executor::new();
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(
+        Task::new(keyboard::print_keypresses())
+            .with_name("keyboard")
+            .with_priority(Priority::High),
+    );
 
     executor.run();
 }
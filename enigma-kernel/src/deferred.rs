@@ -0,0 +1,243 @@
+//! Данный модуль содержит отложенную обработку прерываний (нижние половины).
+//!
+//! Обработчик прерывания делает только необходимый минимум (например,
+//! читает порт устройства) и планирует статический [`Work`]. Сама работа
+//! выполняется позже потоком ядра `kworker` с включенными прерываниями,
+//! поэтому в ней можно брать блокировки, печатать и будить задачи.
+//!
+//! Повторное планирование ещё не выполненной работы объединяется с уже
+//! запланированным (coalescing): функция выполнится один раз. Для каждой
+//! работы ведется статистика задержки от планирования до запуска.
+
+use crate::serial_println;
+use crate::thread::{self, ThreadId};
+use crate::time;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use core::time::Duration;
+
+/// Значение `KWORKER`, пока поток `kworker` не запущен.
+const NO_THREAD: u64 = u64::MAX;
+
+/// Запланированные работы (стек Трайбера через `Work::next`).
+static PENDING: AtomicPtr<Work> = AtomicPtr::new(ptr::null_mut());
+/// Все работы, которые хотя бы раз планировались (через `Work::next_registered`).
+static REGISTERED: AtomicPtr<Work> = AtomicPtr::new(ptr::null_mut());
+static KWORKER: AtomicU64 = AtomicU64::new(NO_THREAD);
+
+/// Отложенная работа. Объявляется как `static` и планируется из прерываний
+/// через [`Work::schedule`].
+pub struct Work {
+    name: &'static str,
+    func: fn(),
+    /// Стоит ли работа в очереди.
+    pending: AtomicBool,
+    next: AtomicPtr<Work>,
+    registered: AtomicBool,
+    next_registered: AtomicPtr<Work>,
+    /// Время (в тактах TSC) первого планирования после последнего запуска.
+    queued_at: AtomicU64,
+    runs: AtomicU64,
+    coalesced: AtomicU64,
+    total_latency: AtomicU64,
+    max_latency: AtomicU64,
+}
+
+impl Work {
+    pub const fn new(name: &'static str, func: fn()) -> Self {
+        Self {
+            name,
+            func,
+            pending: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+            registered: AtomicBool::new(false),
+            next_registered: AtomicPtr::new(ptr::null_mut()),
+            queued_at: AtomicU64::new(0),
+            runs: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            total_latency: AtomicU64::new(0),
+            max_latency: AtomicU64::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Планирует выполнение работы. Не блокирует и не выделяет память,
+    /// поэтому может вызываться из прерываний.
+    ///
+    /// Возвращает `false`, если работа уже была запланирована и вызов
+    /// объединен с предыдущим.
+    pub fn schedule(&'static self) -> bool {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        if !self.registered.swap(true, Ordering::AcqRel) {
+            push(&REGISTERED, self, &self.next_registered);
+        }
+
+        self.queued_at.store(time::tsc(), Ordering::Relaxed);
+        push(&PENDING, self, &self.next);
+
+        let kworker = KWORKER.load(Ordering::Acquire);
+        if kworker != NO_THREAD {
+            thread::unpark(ThreadId::from_u64(kworker));
+        }
+        true
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    pub fn stats(&self) -> WorkStats {
+        WorkStats {
+            runs: self.runs.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            total_latency: time::tsc_to_duration(self.total_latency.load(Ordering::Relaxed)),
+            max_latency: time::tsc_to_duration(self.max_latency.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn run(&self) {
+        let latency = time::tsc().wrapping_sub(self.queued_at.load(Ordering::Relaxed));
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.total_latency.fetch_add(latency, Ordering::Relaxed);
+        self.max_latency.fetch_max(latency, Ordering::Relaxed);
+
+        (self.func)();
+    }
+}
+
+/// Статистика одной работы.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkStats {
+    /// Сколько раз работа была выполнена.
+    pub runs: u64,
+    /// Сколько планирований было объединено с уже запланированным.
+    pub coalesced: u64,
+    /// Суммарная задержка от планирования до запуска.
+    pub total_latency: Duration,
+    /// Самая большая задержка от планирования до запуска.
+    pub max_latency: Duration,
+}
+
+impl WorkStats {
+    pub fn average_latency(&self) -> Duration {
+        match self.runs {
+            0 => Duration::ZERO,
+            runs => self.total_latency / runs as u32,
+        }
+    }
+}
+
+fn push(list: &AtomicPtr<Work>, work: &'static Work, next: &AtomicPtr<Work>) {
+    let work = work as *const Work as *mut Work;
+    let mut head = list.load(Ordering::Acquire);
+
+    loop {
+        next.store(head, Ordering::Relaxed);
+        match list.compare_exchange_weak(head, work, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return,
+            Err(current) => head = current,
+        }
+    }
+}
+
+/// Выполняет все запланированные работы в порядке планирования.
+/// Возвращает количество выполненных работ.
+///
+/// Обычно вызывается потоком `kworker`, но может вызываться и напрямую,
+/// если потоки ещё не запущены.
+pub fn run_pending() -> usize {
+    // Работа стоит в стеке не более одного раза, и стек забирается целиком,
+    // поэтому проблемы ABA нет.
+    let mut head = PENDING.swap(ptr::null_mut(), Ordering::AcqRel);
+
+    // Разворачиваем стек, чтобы работы выполнялись в порядке FIFO.
+    let mut reversed: *mut Work = ptr::null_mut();
+    while let Some(work) = unsafe { head.as_ref() } {
+        head = work.next.swap(reversed, Ordering::Relaxed);
+        reversed = work as *const Work as *mut Work;
+    }
+
+    let mut count = 0;
+    while let Some(work) = unsafe { reversed.as_ref() } {
+        reversed = work.next.load(Ordering::Relaxed);
+
+        // Флаг снимается до запуска (и после чтения `next`), чтобы
+        // планирование во время работы снова поставило её в очередь.
+        work.pending.store(false, Ordering::Release);
+        work.run();
+        count += 1;
+    }
+    count
+}
+
+/// Запускает поток `kworker`, выполняющий отложенные работы.
+/// Требует инициализированного планировщика потоков.
+pub fn init() {
+    let thread = thread::spawn("kworker", || {
+        loop {
+            run_pending();
+            if PENDING.load(Ordering::Acquire).is_null() {
+                thread::park();
+            }
+        }
+    });
+
+    KWORKER.store(thread.as_u64(), Ordering::Release);
+    // Работы, запланированные до запуска потока.
+    thread::unpark(thread);
+}
+
+/// Выводит статистику всех работ в последовательный порт.
+pub fn dump_stats() {
+    serial_println!(
+        "{:<20} {:>9} {:>10} {:>10} {:>10}",
+        "WORK",
+        "RUNS",
+        "COALESCED",
+        "AVG(us)",
+        "MAX(us)"
+    );
+
+    let mut work = REGISTERED.load(Ordering::Acquire);
+    while let Some(current) = unsafe { work.as_ref() } {
+        let stats = current.stats();
+        serial_println!(
+            "{:<20} {:>9} {:>10} {:>10} {:>10}",
+            current.name,
+            stats.runs,
+            stats.coalesced,
+            stats.average_latency().as_micros(),
+            stats.max_latency.as_micros()
+        );
+        work = current.next_registered.load(Ordering::Acquire);
+    }
+}
+
+// --- TEST ZONE --- //
+
+#[test_case]
+fn test_schedule_coalesces() {
+    static RAN: AtomicU64 = AtomicU64::new(0);
+    static WORK: Work = Work::new("test", || {
+        RAN.fetch_add(1, Ordering::Relaxed);
+    });
+
+    assert!(WORK.schedule());
+    assert!(!WORK.schedule());
+    assert!(WORK.is_pending());
+
+    run_pending();
+    assert_eq!(RAN.load(Ordering::Relaxed), 1);
+    assert!(!WORK.is_pending());
+
+    let stats = WORK.stats();
+    assert_eq!(stats.runs, 1);
+    assert_eq!(stats.coalesced, 1);
+}
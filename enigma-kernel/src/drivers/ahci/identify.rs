@@ -0,0 +1,92 @@
+//! Ответ на команду ATA IDENTIFY DEVICE.
+//!
+//! Ответ - 256 слов по 16 бит. Строки хранятся парами символов с
+//! переставленными байтами и дополняются пробелами.
+
+use alloc::string::String;
+
+/// Размер ответа IDENTIFY в байтах.
+pub const IDENTIFY_SIZE: usize = 512;
+/// Адрес LBA48 занимает слова 100-103, но старшее слово 103 зарезервировано.
+const LBA48_MASK: u64 = (1 << 48) - 1;
+
+/// Сведения о диске из IDENTIFY DEVICE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identify {
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+    /// Количество логических секторов.
+    pub sectors: u64,
+    /// Размер логического сектора в байтах.
+    pub sector_size: usize,
+    /// Поддерживаются 48-битные адреса (команды `EXT`).
+    pub lba48: bool,
+    /// Поддерживается NCQ (команды `FPDMA QUEUED`).
+    pub ncq: bool,
+    /// Глубина очереди NCQ (1, если NCQ нет).
+    pub queue_depth: u8,
+}
+
+impl Identify {
+    /// Разбирает ответ. `None`, если диск не поддерживает адресацию LBA.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        assert_eq!(data.len(), IDENTIFY_SIZE, "IDENTIFY data is 512 bytes");
+        let word = |index: usize| u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]);
+        let dword = |index: usize| word(index) as u32 | (word(index + 1) as u32) << 16;
+
+        // Слово 49, бит 9: LBA.
+        if word(49) & 1 << 9 == 0 {
+            return None;
+        }
+        let lba48 = word(83) & 1 << 10 != 0;
+        let sectors = match lba48 {
+            true => (dword(100) as u64 | (dword(102) as u64) << 32) & LBA48_MASK,
+            false => dword(60) as u64,
+        };
+
+        // Слово 106 действительно, если биты 15:14 равны 01; бит 12 -
+        // сектор длиннее 256 слов, его длина в словах 117-118.
+        let layout = word(106);
+        let sector_size = match layout & 0xC000 == 0x4000 && layout & 1 << 12 != 0 {
+            true => dword(117) as usize * 2,
+            false => 512,
+        };
+
+        let ncq = word(76) & 1 << 8 != 0;
+        let queue_depth = match ncq {
+            true => (word(75) & 0x1F) as u8 + 1,
+            false => 1,
+        };
+
+        Some(Self {
+            model: string(data, 27, 47),
+            serial: string(data, 10, 20),
+            firmware: string(data, 23, 27),
+            sectors,
+            sector_size,
+            lba48,
+            ncq,
+            queue_depth,
+        })
+    }
+
+    /// Емкость в байтах. `None`, если она не помещается в `u64`.
+    pub fn capacity(&self) -> Option<u64> {
+        self.sectors.checked_mul(self.sector_size as u64)
+    }
+}
+
+/// Строка из слов `start..end`.
+fn string(data: &[u8], start: usize, end: usize) -> String {
+    data[start * 2..end * 2]
+        .chunks_exact(2)
+        .flat_map(|pair| [pair[1], pair[0]])
+        .map(|byte| match byte {
+            0x20..=0x7E => byte as char,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .trim()
+        .into()
+}
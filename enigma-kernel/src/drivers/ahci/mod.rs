@@ -0,0 +1,265 @@
+//! Контроллер SATA AHCI.
+//!
+//! Драйвер берет функции PCI класса 01:06:01. Регистры контроллера (ABAR)
+//! лежат в BAR5: сначала контроллер забирается у прошивки (BIOS/OS
+//! handoff), сбрасывается и переводится в режим AHCI, затем на каждом
+//! реализованном порту с диском SATA выделяется память команд и выполняется
+//! IDENTIFY ([`Disk`]). Найденные диски регистрируются как блочные
+//! устройства `sda`, `sdb`, ... вместе со своими разделами.
+//!
+//! Прерывание у контроллера одно на все порты и приходит через MSI или
+//! MSI-X. Обработчик сбрасывает состояние портов и планирует отложенную
+//! работу, которая завершает выполненные запросы.
+
+pub mod identify;
+pub mod port;
+
+pub use identify::Identify;
+pub use port::Disk;
+
+use crate::deferred::Work;
+use crate::drivers::apic;
+use crate::drivers::block::{self, BlockDevice, partition};
+use crate::drivers::pci::{self, Device, DeviceId, Driver, MsiVectors, msi};
+use crate::errno::Errno;
+use crate::interrupts::VectorHandler;
+use crate::{serial_println, time};
+use alloc::{format, sync::Arc, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Регистры контроллера.
+const CAP: u64 = 0x00;
+const GHC: u64 = 0x04;
+const IS: u64 = 0x08;
+const PI: u64 = 0x0C;
+const VS: u64 = 0x10;
+const CAP2: u64 = 0x24;
+const BOHC: u64 = 0x28;
+/// Блоки регистров портов.
+const PORTS: u64 = 0x100;
+const PORT_SIZE: u64 = 0x80;
+/// Регистр PxCMD порта.
+const PORT_CMD: u64 = 0x18;
+
+/// Биты CAP.
+const CAP_SSS: u32 = 1 << 27;
+const CAP_SNCQ: u32 = 1 << 30;
+const CAP_S64A: u32 = 1 << 31;
+/// Бит CAP2: контроллер поддерживает передачу от прошивки.
+const CAP2_BOH: u32 = 1 << 0;
+/// Биты BOHC: владение прошивки и запрос владения системой.
+const BOHC_BOS: u32 = 1 << 0;
+const BOHC_OOS: u32 = 1 << 1;
+/// Биты GHC.
+const GHC_HR: u32 = 1 << 0;
+const GHC_IE: u32 = 1 << 1;
+const GHC_AE: u32 = 1 << 31;
+/// Бит PxCMD: раскрутка диска при поэтапной раскрутке (CAP.SSS).
+const CMD_SUD: u32 = 1 << 1;
+
+/// Ожидание, пока прошивка отдаст контроллер (с учетом ее завершения
+/// текущих команд).
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(2);
+/// Ожидание сброса контроллера.
+const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+/// Ограничение числа опросов, пока TSC не откалиброван и время не
+/// измеряется.
+const MAX_POLLS: u64 = 10_000_000;
+
+static AHCI_DRIVER: Driver = Driver {
+    name: "ahci",
+    ids: &[DeviceId::class(0x01, 0x06, Some(0x01))],
+    probe,
+};
+
+/// Завершение запросов после прерываний.
+static COMPLETION: Work = Work::new("ahci", complete);
+
+/// Подключенные контроллеры.
+static CONTROLLERS: Mutex<Vec<Controller>> = Mutex::new(Vec::new());
+/// Диски всех контроллеров.
+static DISKS: Mutex<Vec<Arc<Disk>>> = Mutex::new(Vec::new());
+/// Следующая буква имени диска.
+static NEXT_DISK: AtomicU8 = AtomicU8::new(0);
+
+/// Контроллер: его функция PCI и векторы прерываний. Пока контроллер
+/// подключен, векторы заняты.
+struct Controller {
+    _device: Arc<Device>,
+    _vectors: MsiVectors,
+}
+
+/// Блок 32-битных регистров в памяти.
+#[derive(Clone, Copy)]
+struct Registers(VirtAddr);
+
+impl Registers {
+    fn read(&self, offset: u64) -> u32 {
+        unsafe { (self.0 + offset).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        unsafe { (self.0 + offset).as_mut_ptr::<u32>().write_volatile(value) };
+    }
+}
+
+/// Регистрирует драйвер AHCI. Возвращает количество подключенных
+/// контроллеров.
+pub fn init() -> usize {
+    pci::register_driver(&AHCI_DRIVER)
+}
+
+/// Найденные диски.
+pub fn disks() -> Vec<Arc<Disk>> {
+    without_interrupts(|| DISKS.lock().clone())
+}
+
+fn probe(device: &Arc<Device>) -> Result<(), Errno> {
+    device.enable();
+    let hba = Registers(device.map_bar(5)?);
+    take_ownership(hba);
+    reset(hba)?;
+
+    let capabilities = hba.read(CAP);
+    let slots = ((capabilities >> 8) & 0x1F) as usize + 1;
+    let ncq = capabilities & CAP_SNCQ != 0;
+    let wide = capabilities & CAP_S64A != 0;
+    let version = hba.read(VS);
+    serial_println!(
+        "ahci {}: version {:x}.{:x}, {} slots{}",
+        device.address(),
+        version >> 16,
+        version & 0xFFFF,
+        slots,
+        if ncq { ", NCQ" } else { "" }
+    );
+
+    // Векторы выделяются до опроса портов: без них контроллер не
+    // подключается, и буквы дисков не расходуются зря. Порты появляются в
+    // обработчике позже, но до этого прерывания контроллера выключены.
+    let ports = Arc::new(OnceCell::<Vec<Arc<Disk>>>::uninit());
+    let handled = ports.clone();
+    let handler: VectorHandler = Arc::new(move |_vector| {
+        let pending = hba.read(IS);
+        if let Ok(ports) = handled.try_get() {
+            for disk in ports {
+                if pending & 1 << disk.number() != 0 {
+                    disk.interrupt();
+                }
+            }
+        }
+        hba.write(IS, pending);
+        COMPLETION.schedule();
+    });
+    let vectors = msi::enable(device, 1, apic::current_apic_id(), handler)?;
+
+    let mut disks = Vec::new();
+    let implemented = hba.read(PI);
+    for number in (0..32).filter(|number| implemented & 1 << number != 0) {
+        let registers = Registers(hba.0 + PORTS + number as u64 * PORT_SIZE);
+        if capabilities & CAP_SSS != 0 {
+            registers.write(PORT_CMD, registers.read(PORT_CMD) | CMD_SUD);
+        }
+        let letter = NEXT_DISK.load(Ordering::Relaxed);
+        if letter >= 26 {
+            break;
+        }
+        let name = format!("sd{}", (b'a' + letter) as char);
+        match Disk::probe(name, number, registers, slots, ncq, wide) {
+            Ok(Some(disk)) => {
+                NEXT_DISK.fetch_add(1, Ordering::Relaxed);
+                let identify = disk.identify();
+                serial_println!(
+                    "ahci {}: port {}: {} \"{}\", {} MiB, {} B sectors, depth {}",
+                    device.address(),
+                    number,
+                    disk.name(),
+                    identify.model,
+                    identify.capacity().map_or(0, |bytes| bytes >> 20),
+                    identify.sector_size,
+                    disk.depth()
+                );
+                disks.push(Arc::new(disk));
+            }
+            Ok(None) => {}
+            Err(error) => {
+                serial_println!("ahci {}: port {}: {}", device.address(), number, error);
+            }
+        }
+    }
+
+    ports.init_once(|| disks.clone());
+    for disk in &disks {
+        disk.enable_interrupts();
+    }
+    hba.write(GHC, hba.read(GHC) | GHC_IE);
+
+    without_interrupts(|| {
+        CONTROLLERS.lock().push(Controller {
+            _device: device.clone(),
+            _vectors: vectors,
+        });
+        DISKS.lock().extend(disks.iter().cloned());
+    });
+
+    for disk in disks {
+        let name = disk.name();
+        let disk: Arc<dyn BlockDevice> = disk.clone();
+        let registered = block::register(name, disk.clone())
+            .and_then(|()| partition::register_partitions(name, &disk).map(drop));
+        if let Err(error) = registered {
+            serial_println!("ahci: {}: {}", name, error);
+        }
+    }
+    Ok(())
+}
+
+/// Забирает контроллер у прошивки, если она им владеет.
+fn take_ownership(hba: Registers) {
+    if hba.read(CAP2) & CAP2_BOH == 0 {
+        return;
+    }
+    hba.write(BOHC, hba.read(BOHC) | BOHC_OOS);
+    if !wait(HANDOFF_TIMEOUT, || hba.read(BOHC) & BOHC_BOS == 0) {
+        serial_println!("ahci: firmware did not release the controller");
+    }
+}
+
+/// Сбрасывает контроллер и включает режим AHCI.
+fn reset(hba: Registers) -> Result<(), Errno> {
+    hba.write(GHC, hba.read(GHC) | GHC_AE);
+    hba.write(GHC, GHC_AE | GHC_HR);
+    if !wait(RESET_TIMEOUT, || hba.read(GHC) & GHC_HR == 0) {
+        return Err(Errno::EIO);
+    }
+    // Сброс выключает режим AHCI.
+    hba.write(GHC, GHC_AE);
+    Ok(())
+}
+
+fn complete() {
+    for disk in disks() {
+        disk.complete();
+    }
+}
+
+/// Ждет выполнения `condition` не дольше `timeout`. Возвращает `false`
+/// по истечении времени.
+fn wait(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let start = time::tsc();
+    for _ in 0..MAX_POLLS {
+        if condition() {
+            return true;
+        }
+        if time::tsc_to_duration(time::tsc() - start) >= timeout {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    condition()
+}
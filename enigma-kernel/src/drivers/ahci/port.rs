@@ -0,0 +1,718 @@
+//! Порт AHCI с подключенным диском SATA.
+//!
+//! У порта в памяти DMA лежат список команд (32 заголовка по 32 байта),
+//! область для принятых от диска FIS и таблицы команд: FIS команды и
+//! список фрагментов буфера (PRDT). Данные передаются прямо в буфер
+//! запроса: PRDT составляется из физических адресов его страниц.
+//!
+//! Запросы берутся из [`RequestQueue`] и занимают свободные слоты. С NCQ на
+//! диске одновременно до 32 команд `READ/WRITE FPDMA QUEUED`, и диск сам
+//! выбирает порядок их выполнения; без NCQ команды `READ/WRITE DMA EXT`
+//! идут по одной. Сброс кэша (`FLUSH CACHE EXT`) в очередь NCQ не входит,
+//! поэтому выполняется на пустом порту. Прерывание порта только запоминает
+//! события, а завершает запросы и выдает следующие отложенная работа.
+
+use super::identify::{IDENTIFY_SIZE, Identify};
+use super::{Registers, wait};
+use crate::drivers::block::queue::{Operation, QueueStats, Request};
+use crate::drivers::block::{BlockDevice, BlockFuture, RequestQueue, check_request};
+use crate::errno::Errno;
+use crate::memory::{self, GlobalFrameAllocator};
+use crate::{serial_println, thread};
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering, fence};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::{FrameDeallocator, PhysFrame, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Регистры порта (смещения от начала его блока).
+const CLB: u64 = 0x00;
+const CLBU: u64 = 0x04;
+const FB: u64 = 0x08;
+const FBU: u64 = 0x0C;
+const IS: u64 = 0x10;
+const IE: u64 = 0x14;
+const CMD: u64 = 0x18;
+const TFD: u64 = 0x20;
+const SIG: u64 = 0x24;
+const SSTS: u64 = 0x28;
+const SCTL: u64 = 0x2C;
+const SERR: u64 = 0x30;
+const SACT: u64 = 0x34;
+const CI: u64 = 0x38;
+
+/// Биты PxCMD.
+const CMD_ST: u32 = 1 << 0;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+
+/// Биты PxTFD (регистр состояния ATA).
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BSY: u32 = 1 << 7;
+
+/// Прерывания порта: D2H Register FIS (завершение команды без NCQ), Set
+/// Device Bits FIS (завершение команд NCQ) и ошибки.
+const INT_DHRS: u32 = 1 << 0;
+const INT_PSS: u32 = 1 << 1;
+const INT_SDBS: u32 = 1 << 3;
+const INT_DPS: u32 = 1 << 5;
+const INT_OFS: u32 = 1 << 24;
+const INT_IFS: u32 = 1 << 27;
+const INT_HBDS: u32 = 1 << 28;
+const INT_HBFS: u32 = 1 << 29;
+const INT_TFES: u32 = 1 << 30;
+const INT_ERRORS: u32 = INT_TFES | INT_HBFS | INT_HBDS | INT_IFS | INT_OFS;
+const INT_ENABLED: u32 = INT_DHRS | INT_PSS | INT_SDBS | INT_DPS | INT_ERRORS;
+
+/// Состояние связи в PxSSTS: устройство есть, связь установлена.
+const DET_PRESENT: u32 = 3;
+const IPM_ACTIVE: u32 = 1;
+/// Подпись диска SATA (у ATAPI и множителей портов другие).
+const SIG_ATA: u32 = 0x0000_0101;
+
+/// Команды ATA.
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_READ_FPDMA_QUEUED: u8 = 0x60;
+const ATA_WRITE_FPDMA_QUEUED: u8 = 0x61;
+const ATA_IDENTIFY: u8 = 0xEC;
+const ATA_FLUSH_CACHE_EXT: u8 = 0xEA;
+/// Регистр устройства: адрес LBA.
+const DEVICE_LBA: u8 = 1 << 6;
+
+/// Слотов команд у порта.
+const SLOTS: usize = 32;
+/// Размер заголовка команды и смещение принятых FIS в кадре списка.
+const HEADER_SIZE: u64 = 32;
+const RECEIVED_FIS: u64 = 0x400;
+/// Наибольший запрос; столько же байт покрывает PRDT таблицы команды.
+const MAX_TRANSFER: usize = 64 * 1024;
+/// Фрагментов в PRDT: по странице на каждые 4 КиБ и одна неполная.
+const PRDT_ENTRIES: usize = MAX_TRANSFER / 4096 + 1;
+/// Таблица команды: FIS, ATAPI, резерв и PRDT (выравнивание 128 байт).
+const TABLE_SIZE: u64 = 512;
+const PRDT_OFFSET: u64 = 0x80;
+const TABLES_PER_FRAME: usize = 4096 / TABLE_SIZE as usize;
+const TABLE_FRAMES: usize = SLOTS / TABLES_PER_FRAME;
+/// Наибольший фрагмент PRDT.
+const MAX_FRAGMENT: u64 = 4 << 20;
+
+/// Ожидание остановки движка порта.
+const STOP_TIMEOUT: Duration = Duration::from_millis(500);
+/// Ожидание готовности диска и выполнения команд при опросе.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// Ожидание связи после сброса.
+const LINK_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Команда ATA для FIS Register H2D.
+struct Ata {
+    command: u8,
+    features: u16,
+    lba: u64,
+    count: u16,
+    device: u8,
+}
+
+impl Ata {
+    fn fis(&self) -> [u8; 20] {
+        let lba = self.lba.to_le_bytes();
+        let [features_low, features_high] = self.features.to_le_bytes();
+        let [count_low, count_high] = self.count.to_le_bytes();
+        [
+            0x27, // Register H2D.
+            0x80, // Это команда, а не управление.
+            self.command,
+            features_low,
+            lba[0],
+            lba[1],
+            lba[2],
+            self.device,
+            lba[3],
+            lba[4],
+            lba[5],
+            features_high,
+            count_low,
+            count_high,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]
+    }
+}
+
+/// Регистры порта и память его команд: кадр со списком команд и
+/// принятыми FIS, за ним кадры таблиц команд.
+struct Port {
+    registers: Registers,
+    /// Контроллер умеет 64-битные адреса DMA.
+    wide: bool,
+    frames: Vec<PhysFrame>,
+}
+
+impl Port {
+    /// Выделяет память команд и запускает порт.
+    fn new(registers: Registers, wide: bool) -> Result<Self, Errno> {
+        let mut port = Self {
+            registers,
+            wide,
+            frames: Vec::with_capacity(TABLE_FRAMES + 1),
+        };
+        for _ in 0..=TABLE_FRAMES {
+            let frame = memory::allocate_zeroed_frame().ok_or(Errno::ENOMEM)?;
+            port.frames.push(frame);
+            if !wide && frame.start_address().as_u64() >> 32 != 0 {
+                return Err(Errno::ENOMEM);
+            }
+        }
+
+        if !port.stop() {
+            return Err(Errno::EBUSY);
+        }
+        let list = port.list().as_u64();
+        let received = list + RECEIVED_FIS;
+        registers.write(CLB, list as u32);
+        registers.write(CLBU, (list >> 32) as u32);
+        registers.write(FB, received as u32);
+        registers.write(FBU, (received >> 32) as u32);
+        registers.write(SERR, u32::MAX);
+        registers.write(IS, u32::MAX);
+        match port.start() {
+            true => Ok(port),
+            false => Err(Errno::EIO),
+        }
+    }
+
+    fn list(&self) -> PhysAddr {
+        self.frames[0].start_address()
+    }
+
+    fn table(&self, slot: usize) -> PhysAddr {
+        self.frames[1 + slot / TABLES_PER_FRAME].start_address()
+            + (slot % TABLES_PER_FRAME) as u64 * TABLE_SIZE
+    }
+
+    /// Заполняет заголовок и таблицу команды в слоте `slot`. `buffer` -
+    /// данные команды в памяти ядра.
+    fn prepare(
+        &self,
+        slot: usize,
+        ata: &Ata,
+        buffer: VirtAddr,
+        len: usize,
+        write: bool,
+    ) -> Result<(), Errno> {
+        let table_address = self.table(slot);
+        let table = memory::phys_to_virt(table_address).as_mut_ptr::<u8>();
+        let fis = ata.fis();
+        unsafe {
+            table.write_bytes(0, PRDT_OFFSET as usize);
+            table.copy_from_nonoverlapping(fis.as_ptr(), fis.len());
+        }
+
+        let fragments = fragments(buffer, len)?;
+        if fragments.len() > PRDT_ENTRIES {
+            return Err(Errno::EINVAL);
+        }
+        for (index, &(address, size)) in fragments.iter().enumerate() {
+            if !self.wide && (address.as_u64() + size) >> 32 != 0 {
+                return Err(Errno::EIO);
+            }
+            unsafe {
+                let entry = table.add(PRDT_OFFSET as usize + index * 16).cast::<u32>();
+                entry.write_volatile(address.as_u64() as u32);
+                entry.add(1).write_volatile((address.as_u64() >> 32) as u32);
+                entry.add(2).write_volatile(0);
+                entry.add(3).write_volatile(size as u32 - 1);
+            }
+        }
+
+        // FIS из 5 двойных слов, бит 6 - запись, в старшей половине длина
+        // PRDT.
+        let flags = 5 | (write as u32) << 6 | (fragments.len() as u32) << 16;
+        let table_address = table_address.as_u64();
+        let header =
+            memory::phys_to_virt(self.list() + slot as u64 * HEADER_SIZE).as_mut_ptr::<u32>();
+        unsafe {
+            header.write_volatile(flags);
+            header.add(1).write_volatile(0);
+            header.add(2).write_volatile(table_address as u32);
+            header.add(3).write_volatile((table_address >> 32) as u32);
+        }
+        // Контроллер должен увидеть таблицу до записи в PxCI.
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Выполняет команду в слоте 0 с опросом, пока прерывания порта
+    /// выключены (при подготовке диска).
+    fn execute(&self, ata: &Ata, data: &mut [u8]) -> Result<(), Errno> {
+        let buffer = VirtAddr::from_ptr(data.as_mut_ptr());
+        self.prepare(0, ata, buffer, data.len(), false)?;
+        let registers = &self.registers;
+        registers.write(CI, 1);
+
+        let finished = wait(COMMAND_TIMEOUT, || {
+            registers.read(CI) & 1 == 0 || registers.read(IS) & INT_TFES != 0
+        });
+        let failed = registers.read(IS) & INT_TFES != 0 || registers.read(TFD) & TFD_ERR != 0;
+        registers.write(IS, u32::MAX);
+        match finished && !failed {
+            true => Ok(()),
+            false => {
+                self.recover();
+                Err(Errno::EIO)
+            }
+        }
+    }
+
+    /// Останавливает движок порта. `false`, если порт не остановился.
+    fn stop(&self) -> bool {
+        let registers = &self.registers;
+        registers.write(CMD, registers.read(CMD) & !CMD_ST);
+        if !wait(STOP_TIMEOUT, || registers.read(CMD) & CMD_CR == 0) {
+            return false;
+        }
+        registers.write(CMD, registers.read(CMD) & !CMD_FRE);
+        wait(STOP_TIMEOUT, || registers.read(CMD) & CMD_FR == 0)
+    }
+
+    /// Запускает движок порта, когда диск готов.
+    fn start(&self) -> bool {
+        let registers = &self.registers;
+        registers.write(CMD, registers.read(CMD) | CMD_FRE);
+        if !wait(COMMAND_TIMEOUT, || {
+            registers.read(TFD) & (TFD_BSY | TFD_DRQ) == 0
+        }) {
+            return false;
+        }
+        registers.write(CMD, registers.read(CMD) | CMD_ST);
+        true
+    }
+
+    /// Перезапускает порт после ошибки. Если диск так и остался занят,
+    /// связь сбрасывается (COMRESET). `false`, если порт не запустился.
+    fn recover(&self) -> bool {
+        let registers = &self.registers;
+        self.stop();
+        registers.write(SERR, u32::MAX);
+        registers.write(IS, u32::MAX);
+        if registers.read(TFD) & (TFD_BSY | TFD_DRQ) != 0 {
+            registers.write(SCTL, (registers.read(SCTL) & !0xF) | 1);
+            // COMRESET держится не меньше 1 мс.
+            wait(Duration::from_millis(1), || false);
+            registers.write(SCTL, registers.read(SCTL) & !0xF);
+            wait(LINK_TIMEOUT, || registers.read(SSTS) & 0xF == DET_PRESENT);
+            registers.write(SERR, u32::MAX);
+        }
+        self.start()
+    }
+}
+
+impl Drop for Port {
+    fn drop(&mut self) {
+        self.registers.write(IE, 0);
+        // Пока движок не остановлен, память команд отдавать нельзя.
+        if !self.stop() {
+            return;
+        }
+        for &frame in &self.frames {
+            unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Диск на порту AHCI.
+pub struct Disk {
+    name: String,
+    number: u8,
+    port: Port,
+    identify: Identify,
+    /// Команды идут через NCQ.
+    ncq: bool,
+    /// Сколько команд может быть на диске одновременно.
+    depth: usize,
+    queue: RequestQueue,
+    /// Биты PxIS, накопленные обработчиком прерывания.
+    events: AtomicU32,
+    state: Mutex<State>,
+}
+
+/// Команды на диске.
+struct State {
+    /// Запросы по слотам.
+    slots: [Option<Request>; SLOTS],
+    /// Занятые слоты.
+    issued: u32,
+    /// Запрос из очереди, который ждет, пока освободится порт.
+    held: Option<Request>,
+    /// Порт перезапускается после ошибки: новые команды не выдаются.
+    recovering: bool,
+}
+
+impl Disk {
+    /// Запускает порт `number` и опрашивает диск. `Ok(None)`, если на
+    /// порту нет диска SATA. `slots`, `ncq` и `wide` - возможности
+    /// контроллера.
+    pub(super) fn probe(
+        name: String,
+        number: u8,
+        registers: Registers,
+        slots: usize,
+        ncq: bool,
+        wide: bool,
+    ) -> Result<Option<Self>, Errno> {
+        // Связь после сброса контроллера устанавливается заново.
+        wait(LINK_TIMEOUT, || registers.read(SSTS) & 0xF == DET_PRESENT);
+        let status = registers.read(SSTS);
+        if status & 0xF != DET_PRESENT
+            || (status >> 8) & 0xF != IPM_ACTIVE
+            || registers.read(SIG) != SIG_ATA
+        {
+            return Ok(None);
+        }
+
+        let port = Port::new(registers, wide)?;
+        let mut data = vec![0; IDENTIFY_SIZE];
+        let identify = Ata {
+            command: ATA_IDENTIFY,
+            features: 0,
+            lba: 0,
+            count: 0,
+            device: 0,
+        };
+        port.execute(&identify, &mut data)?;
+        let identify = Identify::parse(&data).ok_or(Errno::ENODEV)?;
+        if !identify.lba48
+            || !identify.sector_size.is_power_of_two()
+            || !(512..=4096).contains(&identify.sector_size)
+        {
+            return Err(Errno::ENODEV);
+        }
+
+        let ncq = ncq && identify.ncq;
+        let depth = match ncq {
+            true => slots.min(identify.queue_depth as usize),
+            false => 1,
+        };
+        let max_blocks = (MAX_TRANSFER / identify.sector_size) as u64;
+        Ok(Some(Self {
+            name,
+            number,
+            port,
+            ncq,
+            depth,
+            queue: RequestQueue::new(identify.sector_size, max_blocks),
+            identify,
+            events: AtomicU32::new(0),
+            state: Mutex::new(State {
+                slots: [const { None }; SLOTS],
+                issued: 0,
+                held: None,
+                recovering: false,
+            }),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Номер порта на контроллере.
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    pub fn identify(&self) -> &Identify {
+        &self.identify
+    }
+
+    /// Команды идут через NCQ.
+    pub fn uses_ncq(&self) -> bool {
+        self.ncq
+    }
+
+    /// Сколько команд может быть на диске одновременно.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn queue_stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    /// Включает прерывания порта. Завершенные команды ищет [`Disk::complete`].
+    pub(super) fn enable_interrupts(&self) {
+        self.port.registers.write(IS, u32::MAX);
+        self.port.registers.write(IE, INT_ENABLED);
+    }
+
+    /// Обработчик прерывания порта: сбрасывает и запоминает события.
+    pub(super) fn interrupt(&self) {
+        let status = self.port.registers.read(IS);
+        self.port.registers.write(IS, status);
+        self.events.fetch_or(status, Ordering::AcqRel);
+    }
+
+    /// Завершает выполненные команды и выдает следующие. Вызывается из
+    /// отложенной работы после прерывания.
+    pub(super) fn complete(&self) {
+        let events = self.events.swap(0, Ordering::AcqRel);
+        let failed = events & INT_ERRORS != 0;
+        let mut finished = Vec::new();
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            let active = self.port.registers.read(CI) | self.port.registers.read(SACT);
+            let done = state.issued & !active;
+            for slot in slots(done) {
+                finished.extend(state.slots[slot].take().map(|request| (request, Ok(()))));
+            }
+            state.issued &= !done;
+
+            if failed {
+                // Команды, оставшиеся на диске после ошибки, считаются
+                // неудавшимися: порт перезапускается и забывает их.
+                for slot in slots(state.issued) {
+                    finished.extend(
+                        state.slots[slot]
+                            .take()
+                            .map(|request| (request, Err(Errno::EIO))),
+                    );
+                }
+                state.issued = 0;
+                state.recovering = true;
+            }
+        });
+
+        if failed {
+            // Перезапуск ждет порт несколько секунд, поэтому идет с включенными
+            // прерываниями; `dispatch` пока не выдает команды.
+            serial_println!(
+                "ahci {}: error {:#x}, task file {:#x}, SError {:#x}",
+                self.name,
+                events & INT_ERRORS,
+                self.port.registers.read(TFD),
+                self.port.registers.read(SERR)
+            );
+            if !self.port.recover() {
+                serial_println!("ahci {}: port did not restart", self.name);
+            }
+            without_interrupts(|| self.state.lock().recovering = false);
+        }
+
+        for (request, result) in finished {
+            request.complete(result);
+        }
+        self.dispatch();
+    }
+
+    /// Выдает диску запросы из очереди, пока есть свободные слоты.
+    fn dispatch(&self) {
+        let mut failed = Vec::new();
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.recovering {
+                return;
+            }
+            while let Some(request) = state.held.take().or_else(|| self.queue.pop()) {
+                let Some(slot) = self.free_slot(&state, &request) else {
+                    state.held = Some(request);
+                    break;
+                };
+                if let Err(error) = self.issue(&mut state, slot, request) {
+                    failed.push(error);
+                }
+            }
+        });
+
+        for (request, error) in failed {
+            request.complete(Err(error));
+        }
+    }
+
+    /// Запрос идет через очередь NCQ.
+    fn is_queued(&self, request: &Request) -> bool {
+        self.ncq && request.operation() != Operation::Flush
+    }
+
+    /// Слот, в котором запрос можно выдать сейчас. Команда без NCQ ждет
+    /// пустого порта, а команды NCQ - завершения команды без NCQ и
+    /// пересекающихся с ними записей: диск может выполнить их в любом
+    /// порядке.
+    fn free_slot(&self, state: &State, request: &Request) -> Option<usize> {
+        if !self.is_queued(request) {
+            return (state.issued == 0).then_some(0);
+        }
+        let mut in_flight = state.slots.iter().flatten();
+        if in_flight.any(|other| !self.is_queued(other) || overlaps(other, request)) {
+            return None;
+        }
+        (0..self.depth).find(|slot| state.issued & 1 << slot == 0)
+    }
+
+    fn issue(
+        &self,
+        state: &mut State,
+        slot: usize,
+        mut request: Request,
+    ) -> Result<(), (Request, Errno)> {
+        let queued = self.is_queued(&request);
+        let sectors = request.count() as u16;
+        let (command, write) = match (request.operation(), queued) {
+            (Operation::Read, true) => (ATA_READ_FPDMA_QUEUED, false),
+            (Operation::Write, true) => (ATA_WRITE_FPDMA_QUEUED, true),
+            (Operation::Read, false) => (ATA_READ_DMA_EXT, false),
+            (Operation::Write, false) => (ATA_WRITE_DMA_EXT, true),
+            (Operation::Flush, _) => (ATA_FLUSH_CACHE_EXT, false),
+        };
+        // У команд NCQ длина передается в features, а в count - номер
+        // слота (тег).
+        let (features, count) = match queued {
+            true => (sectors, (slot as u16) << 3),
+            false => (0, sectors),
+        };
+        let ata = Ata {
+            command,
+            features,
+            lba: request.lba(),
+            count,
+            device: DEVICE_LBA,
+        };
+
+        let data = request.data_mut();
+        let buffer = VirtAddr::from_ptr(data.as_mut_ptr());
+        if let Err(error) = self.port.prepare(slot, &ata, buffer, data.len(), write) {
+            return Err((request, error));
+        }
+        if queued {
+            self.port.registers.write(SACT, 1 << slot);
+        }
+        self.port.registers.write(CI, 1 << slot);
+        state.slots[slot] = Some(request);
+        state.issued |= 1 << slot;
+        Ok(())
+    }
+
+    /// Читает частями не длиннее одной команды. Все части ставятся в
+    /// очередь сразу и с NCQ выполняются одновременно.
+    async fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+        check_request(self, lba, buffer.len())?;
+        let completions: Vec<_> = self
+            .parts(lba, buffer.len())
+            .map(|(lba, count)| self.queue.read(lba, count))
+            .collect();
+        self.dispatch();
+        for (part, completion) in buffer.chunks_mut(MAX_TRANSFER).zip(completions) {
+            part.copy_from_slice(&completion.await?);
+        }
+        Ok(())
+    }
+
+    async fn write(&self, lba: u64, buffer: &[u8]) -> Result<(), Errno> {
+        check_request(self, lba, buffer.len())?;
+        let completions: Vec<_> = buffer
+            .chunks(MAX_TRANSFER)
+            .zip(self.parts(lba, buffer.len()))
+            .map(|(part, (lba, _))| self.queue.write(lba, part.to_vec()))
+            .collect();
+        self.dispatch();
+        for completion in completions {
+            completion.await?;
+        }
+        Ok(())
+    }
+
+    /// Начала и длины частей запроса из `len` байт с `lba`.
+    fn parts(&self, lba: u64, len: usize) -> impl Iterator<Item = (u64, u64)> {
+        let block_size = self.identify.sector_size;
+        (0..len).step_by(MAX_TRANSFER).map(move |offset| {
+            let size = (len - offset).min(MAX_TRANSFER);
+            (
+                lba + (offset / block_size) as u64,
+                (size / block_size) as u64,
+            )
+        })
+    }
+
+    async fn flush_cache(&self) -> Result<(), Errno> {
+        let completion = self.queue.flush();
+        self.dispatch();
+        completion.await.map(drop)
+    }
+}
+
+impl BlockDevice for Disk {
+    fn block_size(&self) -> usize {
+        self.identify.sector_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.identify.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+        thread::block_on(self.read(lba, buffer))
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), Errno> {
+        thread::block_on(self.write(lba, buffer))
+    }
+
+    fn flush(&self) -> Result<(), Errno> {
+        thread::block_on(self.flush_cache())
+    }
+
+    fn read_async<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read(lba, buffer))
+    }
+
+    fn write_async<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write(lba, buffer))
+    }
+
+    fn flush_async(&self) -> BlockFuture<'_, ()> {
+        Box::pin(self.flush_cache())
+    }
+}
+
+/// Номера слотов, отмеченных в `mask`.
+fn slots(mask: u32) -> impl Iterator<Item = usize> {
+    (0..SLOTS).filter(move |slot| mask & 1 << slot != 0)
+}
+
+/// Запросы не могут выполняться в любом порядке: пересекаются, и хотя бы
+/// один из них - запись.
+fn overlaps(first: &Request, second: &Request) -> bool {
+    let writes = first.operation() == Operation::Write || second.operation() == Operation::Write;
+    writes
+        && first.lba() < second.lba() + second.count()
+        && second.lba() < first.lba() + first.count()
+}
+
+/// Физически непрерывные фрагменты буфера ядра.
+fn fragments(buffer: VirtAddr, len: usize) -> Result<Vec<(PhysAddr, u64)>, Errno> {
+    let mapper = unsafe { memory::kernel_mapper() };
+    let mut fragments: Vec<(PhysAddr, u64)> = Vec::new();
+    let mut offset = 0;
+    while offset < len as u64 {
+        let address = buffer + offset;
+        let size = (4096 - address.as_u64() % 4096).min(len as u64 - offset);
+        let physical = mapper.translate_addr(address).ok_or(Errno::EFAULT)?;
+        match fragments.last_mut() {
+            Some((start, length))
+                if *start + *length == physical && *length + size <= MAX_FRAGMENT =>
+            {
+                *length += size;
+            }
+            _ => fragments.push((physical, size)),
+        }
+        offset += size;
+    }
+    Ok(fragments)
+}
@@ -1,11 +1,12 @@
 // Данный модуль содержит код для работы с APIC.
 
 use crate::interrupts::{IDT, InterruptIndex};
+use crate::{memory, time};
 use acpi::{AcpiHandler, AcpiTables, PhysicalMapping};
 use core::ptr::NonNull;
 use lazy_static::lazy_static;
 use spin::Mutex;
-use x86_64::structures::paging::{FrameAllocator, Mapper, PhysFrame, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
 
 lazy_static! {
@@ -219,14 +220,65 @@ unsafe fn init_timer(lapic_pointer: *mut u32) {
         let svr = lapic_pointer.offset(APICOffset::Svr as isize / 4);
         svr.write_volatile(svr.read_volatile() | 0x100); // Установить бит 8.
 
-        let lvt_lint1 = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
-        lvt_lint1.write_volatile(0x20 | (1 << 17)); // Вектор 0x20, периодический режим.
-
         let tdcr = lapic_pointer.offset(APICOffset::Tdcr as isize / 4);
         tdcr.write_volatile(0x3); // Режим деления на 16.
 
+        let count_per_tick = calibrate_timer(lapic_pointer) / (time::TICK_HZ / 100) as u32;
+
+        let lvt_timer = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
+        lvt_timer.write_volatile(InterruptIndex::Timer as u32 | (1 << 17)); // Периодический режим.
+
         let ticr = lapic_pointer.offset(APICOffset::Ticr as isize / 4);
-        ticr.write_volatile(0x100000); // Произвольное значение для начального значения таймера.
+        ticr.write_volatile(count_per_tick.max(1));
+    }
+}
+
+/// Частота PIT в герцах.
+const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// Измеряет, на сколько уменьшается счетчик таймера LAPIC за 10 мс,
+/// используя канал 2 PIT в качестве эталона. Заодно измеряется частота TSC.
+unsafe fn calibrate_timer(lapic_pointer: *mut u32) -> u32 {
+    use x86_64::instructions::port::Port;
+
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+    let pit_count = (PIT_FREQUENCY / 100) as u16; // 10 мс.
+
+    unsafe {
+        // Включить gate канала 2 и выключить динамик.
+        let value = gate.read();
+        gate.write((value & !0x02) | 0x01);
+
+        // Канал 2, lobyte/hibyte, режим 0 (прерывание по окончанию счета).
+        command.write(0b1011_0000);
+        channel2.write(pit_count as u8);
+        channel2.write((pit_count >> 8) as u8);
+
+        // Перезапуск счета сбросом и установкой gate.
+        let value = gate.read() & !0x01;
+        gate.write(value);
+        gate.write(value | 0x01);
+
+        let lvt_timer = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
+        lvt_timer.write_volatile(1 << 16); // Таймер замаскирован на время калибровки.
+
+        let ticr = lapic_pointer.offset(APICOffset::Ticr as isize / 4);
+        ticr.write_volatile(u32::MAX);
+        let tsc_start = time::tsc();
+
+        while gate.read() & 0x20 == 0 {
+            core::hint::spin_loop();
+        }
+
+        let tccr = lapic_pointer.offset(APICOffset::Tccr as isize / 4);
+        let elapsed = u32::MAX - tccr.read_volatile();
+        ticr.write_volatile(0);
+
+        time::set_tsc_hz((time::tsc() - tsc_start) * 100);
+
+        elapsed
     }
 }
 
@@ -259,23 +311,13 @@ fn map_apic(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> VirtAddr {
-    use x86_64::structures::paging::Page;
-    use x86_64::structures::paging::PageTableFlags as Flags;
-
-    let physical_address = PhysAddr::new(physical_address);
-    let page = Page::containing_address(VirtAddr::new(physical_address.as_u64()));
-    let frame = PhysFrame::containing_address(physical_address);
-
-    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE;
-
-    unsafe {
-        mapper
-            .map_to(page, frame, flags, frame_allocator)
-            .expect("APIC mapping failed")
-            .flush();
-    }
-
-    page.start_address()
+    memory::map_mmio(
+        PhysAddr::new(physical_address),
+        4096,
+        mapper,
+        frame_allocator,
+    )
+    .expect("APIC mapping failed")
 }
 
 fn disable_pic() {
@@ -296,3 +338,9 @@ pub fn end_interrupt() {
             .write_volatile(0);
     }
 }
+
+/// Возвращает APIC ID текущего процессора (из CPUID, не требует LAPIC).
+pub fn current_apic_id() -> u32 {
+    let leaf = unsafe { core::arch::x86_64::__cpuid(1) };
+    leaf.ebx >> 24
+}
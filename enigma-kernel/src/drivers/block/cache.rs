@@ -0,0 +1,353 @@
+//! Кэш блоков с отложенной записью.
+//!
+//! [`BufferCache`] сам является блочным устройством и ставится поверх
+//! другого. Прочитанные блоки остаются в памяти, а записанные только
+//! помечаются грязными: на устройство они уходят при сбросе, при вытеснении
+//! или фоновой задачей [`BufferCache::writeback_task`]. Когда кэш полон,
+//! вытесняется блок, который дольше всех не использовался (LRU); грязные
+//! блоки перед этим записываются. Соседние блоки читаются и пишутся одним
+//! запросом.
+
+use super::{BlockDevice, BlockFuture, check_request};
+use crate::errno::Errno;
+use crate::serial_println;
+use crate::sync::Mutex as AsyncMutex;
+use crate::task::timer;
+use crate::thread;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::future::Future;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+/// Снимок статистики кэша.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Блоков прочитано из кэша.
+    pub hits: u64,
+    /// Блоков прочитано с устройства.
+    pub misses: u64,
+    /// Блоков вытеснено.
+    pub evictions: u64,
+    /// Блоков записано на устройство.
+    pub written: u64,
+    /// Блоков в кэше сейчас.
+    pub cached: usize,
+    /// Из них грязных.
+    pub dirty: usize,
+}
+
+pub struct BufferCache {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    /// Сколько блоков держать в памяти.
+    capacity: usize,
+    state: Mutex<State>,
+    /// Запись на устройство идет по одной: иначе старая версия блока могла
+    /// бы лечь на диск поверх новой.
+    writeback: AsyncMutex<()>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    written: AtomicU64,
+}
+
+struct Buffer {
+    data: Box<[u8]>,
+    dirty: bool,
+    /// Меняется при каждой записи: по ней видно, что блок изменили, пока
+    /// его старое содержимое писалось на устройство.
+    version: u64,
+    /// Момент последнего использования (ключ в `State::lru`).
+    used: u64,
+}
+
+#[derive(Default)]
+struct State {
+    buffers: BTreeMap<u64, Buffer>,
+    /// Блоки в порядке использования: момент -> LBA.
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    dirty: usize,
+    /// Сколько раз блоки записывались на устройство.
+    writebacks: u64,
+}
+
+impl State {
+    /// Содержимое блока `lba` (блок становится последним использованным).
+    fn get(&mut self, lba: u64) -> Option<&[u8]> {
+        self.touch(lba);
+        self.buffers.get(&lba).map(|buffer| &*buffer.data)
+    }
+
+    /// Кладет в кэш блок, прочитанный с устройства.
+    fn insert_clean(&mut self, lba: u64, data: &[u8]) {
+        self.clock += 1;
+        let buffer = Buffer {
+            data: data.into(),
+            dirty: false,
+            version: self.clock,
+            used: self.clock,
+        };
+        self.buffers.insert(lba, buffer);
+        self.lru.insert(self.clock, lba);
+    }
+
+    /// Записывает блок в кэш и помечает его грязным.
+    fn store(&mut self, lba: u64, data: &[u8]) {
+        self.clock += 1;
+        match self.buffers.get_mut(&lba) {
+            Some(buffer) => {
+                buffer.data.copy_from_slice(data);
+                buffer.version = self.clock;
+                if !buffer.dirty {
+                    buffer.dirty = true;
+                    self.dirty += 1;
+                }
+                self.touch(lba);
+            }
+            None => {
+                self.insert_clean(lba, data);
+                let buffer = self.buffers.get_mut(&lba).unwrap();
+                buffer.dirty = true;
+                self.dirty += 1;
+            }
+        }
+    }
+
+    fn touch(&mut self, lba: u64) {
+        if let Some(buffer) = self.buffers.get_mut(&lba) {
+            self.lru.remove(&buffer.used);
+            self.clock += 1;
+            buffer.used = self.clock;
+            self.lru.insert(self.clock, lba);
+        }
+    }
+
+    /// Вытесняет давно не использованные чистые блоки, пока их больше
+    /// `capacity`. Возвращает количество вытесненных.
+    fn evict(&mut self, capacity: usize) -> usize {
+        let excess = self.buffers.len().saturating_sub(capacity);
+        let victims: Vec<_> = self
+            .lru
+            .iter()
+            .filter(|(_, lba)| !self.buffers[lba].dirty)
+            .map(|(&used, &lba)| (used, lba))
+            .take(excess)
+            .collect();
+
+        for &(used, lba) in &victims {
+            self.lru.remove(&used);
+            self.buffers.remove(&lba);
+        }
+        victims.len()
+    }
+}
+
+impl BufferCache {
+    /// Создает кэш на `capacity` блоков поверх `device`.
+    pub fn new(device: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        assert!(capacity > 0, "empty buffer cache");
+        Self {
+            block_size: device.block_size(),
+            device,
+            capacity,
+            state: Mutex::new(State::default()),
+            writeback: AsyncMutex::new(()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+            cached: state.buffers.len(),
+            dirty: state.dirty,
+        }
+    }
+
+    /// Записывает все грязные блоки на устройство (без сброса его кэша).
+    pub async fn write_back(&self) -> Result<(), Errno> {
+        self.write_back_oldest(usize::MAX).await
+    }
+
+    /// Фоновая задача для исполнителя: раз в `period` записывает грязные
+    /// блоки на устройство. Завершается, когда кэш удален.
+    pub fn writeback_task(
+        self: &Arc<Self>,
+        period: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let cache = Arc::downgrade(self);
+        async move {
+            let mut interval = timer::interval(period);
+            loop {
+                interval.tick().await;
+                let Some(cache) = cache.upgrade() else {
+                    return;
+                };
+                if let Err(error) = cache.write_back().await {
+                    serial_println!("buffer cache: write-back failed: {}", error);
+                }
+            }
+        }
+    }
+
+    async fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+        let count = check_request(self, lba, buffer.len())?;
+        let block_size = self.block_size;
+
+        let mut missing = Vec::new();
+        let writebacks = {
+            let mut state = self.state.lock();
+            for (index, block) in buffer.chunks_exact_mut(block_size).enumerate() {
+                match state.get(lba + index as u64) {
+                    Some(data) => block.copy_from_slice(data),
+                    None => missing.push(index),
+                }
+            }
+            state.writebacks
+        };
+        self.hits
+            .fetch_add(count - missing.len() as u64, Ordering::Relaxed);
+        self.misses
+            .fetch_add(missing.len() as u64, Ordering::Relaxed);
+
+        for run in missing.chunk_by(|a, b| a + 1 == *b) {
+            let first = run[0];
+            let target = &mut buffer[first * block_size..(first + run.len()) * block_size];
+            self.device.read_async(lba + first as u64, target).await?;
+
+            let mut state = self.state.lock();
+            // Если за время чтения блоки записывались на устройство и
+            // вытеснялись, прочитанное могло устареть: в кэш его не кладем.
+            let fresh = state.writebacks == writebacks;
+            for (index, block) in target.chunks_exact_mut(block_size).enumerate() {
+                let lba = lba + (first + index) as u64;
+                // Пока шло чтение, блок могли записать: его версия новее.
+                match state.get(lba) {
+                    Some(data) => block.copy_from_slice(data),
+                    None if fresh => state.insert_clean(lba, block),
+                    None => {}
+                }
+            }
+            let evicted = state.evict(self.capacity);
+            self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    async fn write(&self, lba: u64, buffer: &[u8]) -> Result<(), Errno> {
+        check_request(self, lba, buffer.len())?;
+        if self.is_read_only() {
+            return Err(Errno::EROFS);
+        }
+
+        let excess = {
+            let mut state = self.state.lock();
+            for (index, block) in buffer.chunks_exact(self.block_size).enumerate() {
+                state.store(lba + index as u64, block);
+            }
+            let evicted = state.evict(self.capacity);
+            self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+            state.buffers.len().saturating_sub(self.capacity)
+        };
+
+        // Кэш забит грязными блоками: самые старые уходят на устройство.
+        if excess > 0 {
+            self.write_back_oldest(excess).await?;
+            let evicted = self.state.lock().evict(self.capacity);
+            self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Записывает на устройство до `limit` грязных блоков, начиная с давно
+    /// не использованных.
+    async fn write_back_oldest(&self, limit: usize) -> Result<(), Errno> {
+        let _writeback = self.writeback.lock().await;
+        let mut blocks: Vec<_> = {
+            let state = self.state.lock();
+            state
+                .lru
+                .values()
+                .map(|lba| (*lba, &state.buffers[lba]))
+                .filter(|(_, buffer)| buffer.dirty)
+                .take(limit)
+                .map(|(lba, buffer)| (lba, buffer.version, buffer.data.clone()))
+                .collect()
+        };
+        blocks.sort_unstable_by_key(|&(lba, ..)| lba);
+
+        for run in blocks.chunk_by(|a, b| a.0 + 1 == b.0) {
+            let data: Vec<u8> = run
+                .iter()
+                .flat_map(|(_, _, data)| data.iter().copied())
+                .collect();
+            self.device.write_async(run[0].0, &data).await?;
+
+            let mut state = self.state.lock();
+            state.writebacks += 1;
+            for &(lba, version, _) in run {
+                let Some(buffer) = state.buffers.get_mut(&lba) else {
+                    continue;
+                };
+                if buffer.dirty && buffer.version == version {
+                    buffer.dirty = false;
+                    state.dirty -= 1;
+                }
+            }
+            self.written.fetch_add(run.len() as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Errno> {
+        self.write_back().await?;
+        self.device.flush_async().await
+    }
+}
+
+impl BlockDevice for BufferCache {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+        thread::block_on(self.read(lba, buffer))
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), Errno> {
+        thread::block_on(self.write(lba, buffer))
+    }
+
+    fn flush(&self) -> Result<(), Errno> {
+        thread::block_on(BufferCache::flush(self))
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+
+    fn read_async<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read(lba, buffer))
+    }
+
+    fn write_async<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write(lba, buffer))
+    }
+
+    fn flush_async(&self) -> BlockFuture<'_, ()> {
+        Box::pin(BufferCache::flush(self))
+    }
+}
@@ -0,0 +1,119 @@
+//! Блочные устройства: диски и их разделы, образы в памяти.
+//!
+//! Устройство читается и пишется целыми блоками, номер первого блока -
+//! LBA. Файловые системы работают с устройством через [`BlockDevice`] и
+//! не знают, что за ним стоит.
+//!
+//! У устройства два интерфейса: синхронный для кода, который может
+//! заблокировать поток (файловые системы, системные вызовы), и асинхронный
+//! для задач исполнителя. Драйвер реализует тот, что ему естественнее, а
+//! второй получается из него: по умолчанию асинхронные методы просто
+//! вызывают синхронные. Драйверы, которые ждут прерываний, ставят запросы в
+//! [`RequestQueue`], а поверх любого устройства можно поставить
+//! [`BufferCache`].
+//!
+//! Драйверы регистрируют найденные диски под именами (`sda`, `ram0`); их
+//! разделы регистрируются рядом как отдельные устройства (`sda1`), см.
+//! [`partition`].
+
+pub mod cache;
+pub mod partition;
+pub mod queue;
+pub mod ram;
+
+pub use cache::BufferCache;
+pub use queue::RequestQueue;
+pub use ram::RamBlockDevice;
+
+use crate::errno::Errno;
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{future::Future, pin::Pin};
+use spin::Mutex;
+
+/// Зарегистрированные устройства по имени.
+static DEVICES: Mutex<BTreeMap<String, Arc<dyn BlockDevice>>> = Mutex::new(BTreeMap::new());
+
+/// Future асинхронной операции с устройством.
+pub type BlockFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Errno>> + Send + 'a>>;
+
+/// Блочное устройство.
+pub trait BlockDevice: Send + Sync {
+    /// Размер блока в байтах (степень двойки, не меньше 512).
+    fn block_size(&self) -> usize;
+
+    /// Количество блоков.
+    fn block_count(&self) -> u64;
+
+    /// Читает `buffer.len() / block_size()` блоков начиная с `lba`. Длина
+    /// буфера должна быть кратна размеру блока.
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), Errno>;
+
+    /// Пишет блоки начиная с `lba` (как [`BlockDevice::read_blocks`]).
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), Errno>;
+
+    /// Дожидается, пока записанные данные окажутся на носителе.
+    fn flush(&self) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    /// Устройство не поддерживает запись.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Асинхронный вариант [`BlockDevice::read_blocks`].
+    fn read_async<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move { self.read_blocks(lba, buffer) })
+    }
+
+    /// Асинхронный вариант [`BlockDevice::write_blocks`].
+    fn write_async<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move { self.write_blocks(lba, buffer) })
+    }
+
+    /// Асинхронный вариант [`BlockDevice::flush`].
+    fn flush_async(&self) -> BlockFuture<'_, ()> {
+        Box::pin(async move { self.flush() })
+    }
+}
+
+/// Проверяет, что запрос из `len` байт начиная с `lba` укладывается в
+/// устройство и состоит из целых блоков. Возвращает количество блоков.
+pub fn check_request(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<u64, Errno> {
+    let block_size = device.block_size();
+    if len % block_size != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let count = (len / block_size) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= device.block_count() => Ok(count),
+        _ => Err(Errno::EIO),
+    }
+}
+
+/// Регистрирует устройство под именем `name`. Возвращает `EEXIST`, если имя
+/// занято.
+pub fn register(name: &str, device: Arc<dyn BlockDevice>) -> Result<(), Errno> {
+    let mut devices = DEVICES.lock();
+    if devices.contains_key(name) {
+        return Err(Errno::EEXIST);
+    }
+    devices.insert(name.into(), device);
+    Ok(())
+}
+
+/// Убирает устройство из реестра (разделы устройства остаются).
+pub fn unregister(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().remove(name)
+}
+
+/// Устройство с именем `name`.
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().get(name).cloned()
+}
+
+/// Имена зарегистрированных устройств по порядку.
+pub fn devices() -> Vec<String> {
+    DEVICES.lock().keys().cloned().collect()
+}
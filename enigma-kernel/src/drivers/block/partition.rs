@@ -0,0 +1,536 @@
+//! Таблицы разделов: MBR (с расширенными разделами) и GPT.
+//!
+//! Диск с защитной записью `0xEE` в MBR считается диском GPT. Заголовок GPT
+//! хранится дважды: в начале диска и в последнем блоке; у каждой копии
+//! проверяются CRC32 заголовка и массива записей. Если основная копия
+//! испорчена, разделы читаются из резервной.
+//!
+//! Каждый раздел становится отдельным блочным устройством ([`Partition`])
+//! с именем диска и номером раздела. Номера как в Linux: в MBR основные
+//! разделы - 1-4, логические - с 5; в GPT - порядковый номер записи.
+//! Первый найденный системный раздел EFI запоминается ([`esp`]).
+
+use super::{BlockDevice, BlockFuture, check_request};
+use crate::errno::Errno;
+use crate::serial_println;
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt;
+use spin::Mutex;
+
+/// Подпись загрузочного сектора (MBR и EBR).
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// Смещение таблицы разделов в MBR.
+const TABLE_OFFSET: usize = 446;
+const MBR_PROTECTIVE: u8 = 0xEE;
+const MBR_ESP: u8 = 0xEF;
+/// Сколько логических разделов читать из цепочки EBR.
+const MAX_LOGICAL: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_REVISION: u32 = 0x0001_0000;
+const GPT_HEADER_SIZE: usize = 92;
+/// Ограничение на размер массива записей GPT.
+const GPT_MAX_ENTRIES_SIZE: usize = 1 << 20;
+
+/// Системный раздел EFI, найденный первым.
+static ESP: Mutex<Option<Arc<Partition>>> = Mutex::new(None);
+
+/// GUID в порядке байтов GPT (первые три поля - little-endian).
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Тип системного раздела EFI.
+    pub const ESP: Self = Self::new(
+        0xC12A_7328,
+        0xF81F,
+        0x11D2,
+        [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B],
+    );
+
+    /// GUID из полей канонической записи
+    /// `aaaaaaaa-bbbb-cccc-dddd-dddddddddddd`.
+    pub const fn new(a: u32, b: u16, c: u16, d: [u8; 8]) -> Self {
+        let a = a.to_le_bytes();
+        let b = b.to_le_bytes();
+        let c = c.to_le_bytes();
+        Self([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5],
+            d[6], d[7],
+        ])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..].iter().try_for_each(|byte| write!(f, "{byte:02X}"))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Тип раздела.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// Код типа из MBR.
+    Mbr(u8),
+    /// GUID типа из GPT.
+    Gpt(Guid),
+}
+
+/// Раздел из таблицы.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub number: u32,
+    /// Первый блок раздела на диске.
+    pub start: u64,
+    /// Длина в блоках.
+    pub count: u64,
+    pub kind: PartitionKind,
+    /// Уникальный GUID раздела (только GPT).
+    pub guid: Option<Guid>,
+    /// Имя раздела (только GPT).
+    pub name: String,
+    /// Флаг активного раздела (только MBR).
+    pub bootable: bool,
+}
+
+impl PartitionInfo {
+    /// Системный раздел EFI.
+    pub fn is_esp(&self) -> bool {
+        match self.kind {
+            PartitionKind::Mbr(kind) => kind == MBR_ESP,
+            PartitionKind::Gpt(kind) => kind == Guid::ESP,
+        }
+    }
+}
+
+/// Вид таблицы разделов.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Mbr,
+    Gpt {
+        disk_guid: Guid,
+        /// Основной заголовок и его записи целы.
+        primary_valid: bool,
+        /// Резервный заголовок цел и совпадает с основным.
+        backup_valid: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub scheme: Scheme,
+    pub partitions: Vec<PartitionInfo>,
+}
+
+/// Читает таблицу разделов `device`. Возвращает `None`, если таблицы нет,
+/// и `EINVAL`, если диск помечен как GPT, но обе копии GPT испорчены.
+pub fn scan(device: &dyn BlockDevice) -> Result<Option<PartitionTable>, Errno> {
+    let block_size = device.block_size();
+    let mut mbr = vec![0; block_size];
+    device.read_blocks(0, &mut mbr)?;
+
+    let Some(entries) = mbr_entries(&mbr, device.block_count()) else {
+        return Ok(None);
+    };
+    if entries.iter().any(|entry| entry.kind == MBR_PROTECTIVE) {
+        return scan_gpt(device).map(Some);
+    }
+
+    let mut partitions = Vec::new();
+    for (slot, entry) in entries.iter().enumerate() {
+        match entry.kind {
+            0 => {}
+            0x05 | 0x0F | 0x85 => scan_extended(device, entry, &mut partitions)?,
+            kind => partitions.push(PartitionInfo {
+                number: slot as u32 + 1,
+                start: entry.start,
+                count: entry.count,
+                kind: PartitionKind::Mbr(kind),
+                guid: None,
+                name: String::new(),
+                bootable: entry.bootable,
+            }),
+        }
+    }
+    Ok(Some(PartitionTable {
+        scheme: Scheme::Mbr,
+        partitions,
+    }))
+}
+
+/// Запись таблицы MBR или EBR.
+#[derive(Debug, Clone, Copy)]
+struct MbrEntry {
+    bootable: bool,
+    kind: u8,
+    start: u64,
+    count: u64,
+}
+
+/// Записи таблицы из сектора MBR или EBR; `None`, если это не таблица
+/// разделов (нет подписи, неверный флаг активности или раздел за концом
+/// диска). Пустые записи имеют тип 0. Начало записи не пересчитывается: в
+/// EBR оно задано относительно.
+fn mbr_entries(sector: &[u8], block_count: u64) -> Option<[MbrEntry; 4]> {
+    if sector[510..512] != BOOT_SIGNATURE {
+        return None;
+    }
+
+    let mut entries = [MbrEntry {
+        bootable: false,
+        kind: 0,
+        start: 0,
+        count: 0,
+    }; 4];
+    for (slot, raw) in sector[TABLE_OFFSET..TABLE_OFFSET + 64]
+        .chunks_exact(16)
+        .enumerate()
+    {
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        let entry = MbrEntry {
+            bootable: match raw[0] {
+                0x00 => false,
+                0x80 => true,
+                _ => return None,
+            },
+            kind: raw[4],
+            start: u32_at(8) as u64,
+            count: u32_at(12) as u64,
+        };
+        if entry.kind == 0 || entry.count == 0 {
+            continue;
+        }
+        // Защитная запись GPT может быть длиннее диска (0xFFFFFFFF).
+        if entry.kind != MBR_PROTECTIVE && entry.start + entry.count > block_count {
+            return None;
+        }
+        entries[slot] = entry;
+    }
+    Some(entries)
+}
+
+/// Читает логические разделы из цепочки EBR расширенного раздела.
+fn scan_extended(
+    device: &dyn BlockDevice,
+    extended: &MbrEntry,
+    partitions: &mut Vec<PartitionInfo>,
+) -> Result<(), Errno> {
+    let mut sector = vec![0; device.block_size()];
+    let end = extended.start + extended.count;
+    let mut ebr = extended.start;
+
+    for number in 5..5 + MAX_LOGICAL as u32 {
+        device.read_blocks(ebr, &mut sector)?;
+        let Some([logical, next, ..]) = mbr_entries(&sector, u64::MAX) else {
+            break;
+        };
+
+        let start = ebr + logical.start;
+        if logical.kind != 0 && start > ebr && start + logical.count <= end {
+            partitions.push(PartitionInfo {
+                number,
+                start,
+                count: logical.count,
+                kind: PartitionKind::Mbr(logical.kind),
+                guid: None,
+                name: String::new(),
+                bootable: logical.bootable,
+            });
+        }
+
+        // Ссылка на следующий EBR отсчитывается от начала расширенного
+        // раздела; цепочка должна идти вперед, иначе она зациклена.
+        let following = extended.start + next.start;
+        if !matches!(next.kind, 0x05 | 0x0F | 0x85) || following <= ebr || following >= end {
+            break;
+        }
+        ebr = following;
+    }
+    Ok(())
+}
+
+/// Заголовок GPT.
+struct GptHeader {
+    alternate: u64,
+    first_usable: u64,
+    last_usable: u64,
+    disk_guid: Guid,
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+fn scan_gpt(device: &dyn BlockDevice) -> Result<PartitionTable, Errno> {
+    let last = device.block_count() - 1;
+    let primary = read_gpt(device, 1);
+    let backup_lba = match &primary {
+        Some((header, _)) => header.alternate,
+        None => last,
+    };
+    let backup = read_gpt(device, backup_lba).filter(|(header, _)| header.alternate == 1);
+
+    let (header, entries, primary_valid, backup_valid) = match (primary, backup) {
+        (Some((header, entries)), Some((copy, _))) => {
+            let same = copy.disk_guid == header.disk_guid
+                && copy.entries_crc == header.entries_crc
+                && copy.first_usable == header.first_usable
+                && copy.last_usable == header.last_usable;
+            (header, entries, true, same)
+        }
+        (Some((header, entries)), None) => (header, entries, true, false),
+        (None, Some((header, entries))) => (header, entries, false, true),
+        (None, None) => return Err(Errno::EINVAL),
+    };
+
+    let mut partitions = Vec::new();
+    for (index, raw) in entries.chunks_exact(header.entry_size).enumerate() {
+        let kind = Guid(raw[0..16].try_into().unwrap());
+        if kind.is_zero() {
+            continue;
+        }
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(raw[offset..offset + 8].try_into().unwrap());
+        let (first, last) = (u64_at(32), u64_at(40));
+        if first < header.first_usable || last > header.last_usable || first > last {
+            serial_println!("gpt: partition {} is out of bounds", index + 1);
+            continue;
+        }
+
+        let units: Vec<u16> = raw[56..128]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        partitions.push(PartitionInfo {
+            number: index as u32 + 1,
+            start: first,
+            count: last - first + 1,
+            kind: PartitionKind::Gpt(kind),
+            guid: Some(Guid(raw[16..32].try_into().unwrap())),
+            name: String::from_utf16_lossy(&units),
+            bootable: false,
+        });
+    }
+
+    Ok(PartitionTable {
+        scheme: Scheme::Gpt {
+            disk_guid: header.disk_guid,
+            primary_valid,
+            backup_valid,
+        },
+        partitions,
+    })
+}
+
+/// Читает и проверяет заголовок GPT в блоке `lba` и его массив записей.
+fn read_gpt(device: &dyn BlockDevice, lba: u64) -> Option<(GptHeader, Vec<u8>)> {
+    let block_size = device.block_size();
+    let block_count = device.block_count();
+    let mut raw = vec![0; block_size];
+    device.read_blocks(lba, &mut raw).ok()?;
+
+    let u32_at = |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(raw[offset..offset + 8].try_into().unwrap());
+    let size = u32_at(12) as usize;
+    if &raw[0..8] != GPT_SIGNATURE
+        || u32_at(8) != GPT_REVISION
+        || !(GPT_HEADER_SIZE..=block_size).contains(&size)
+    {
+        return None;
+    }
+    let mut copy = raw[..size].to_vec();
+    copy[16..20].fill(0);
+    if crc32(&copy) != u32_at(16) || u64_at(24) != lba {
+        return None;
+    }
+
+    let header = GptHeader {
+        alternate: u64_at(32),
+        first_usable: u64_at(40),
+        last_usable: u64_at(48),
+        disk_guid: Guid(raw[56..72].try_into().unwrap()),
+        entries_lba: u64_at(72),
+        entry_count: u32_at(80) as usize,
+        entry_size: u32_at(84) as usize,
+        entries_crc: u32_at(88),
+    };
+    let entries_size = header.entry_count.checked_mul(header.entry_size)?;
+    if header.entry_size < 128
+        || !header.entry_size.is_power_of_two()
+        || entries_size > GPT_MAX_ENTRIES_SIZE
+        || header.first_usable > header.last_usable
+        || header.last_usable >= block_count
+        || header.alternate >= block_count
+    {
+        return None;
+    }
+
+    let mut entries = vec![0; entries_size.div_ceil(block_size) * block_size];
+    device.read_blocks(header.entries_lba, &mut entries).ok()?;
+    entries.truncate(entries_size);
+    if crc32(&entries) != header.entries_crc {
+        return None;
+    }
+    Some((header, entries))
+}
+
+/// CRC-32 (IEEE 802.3), которой GPT защищает заголовки и записи.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Раздел диска как блочное устройство.
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    info: PartitionInfo,
+}
+
+impl Partition {
+    pub fn new(name: String, disk: Arc<dyn BlockDevice>, info: PartitionInfo) -> Self {
+        Self { name, disk, info }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn info(&self) -> &PartitionInfo {
+        &self.info
+    }
+}
+
+impl BlockDevice for Partition {
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.info.count
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+        check_request(self, lba, buffer.len())?;
+        self.disk.read_blocks(self.info.start + lba, buffer)
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), Errno> {
+        check_request(self, lba, buffer.len())?;
+        self.disk.write_blocks(self.info.start + lba, buffer)
+    }
+
+    fn flush(&self) -> Result<(), Errno> {
+        self.disk.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+
+    fn read_async<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        match check_request(self, lba, buffer.len()) {
+            Ok(_) => self.disk.read_async(self.info.start + lba, buffer),
+            Err(error) => alloc::boxed::Box::pin(async move { Err(error) }),
+        }
+    }
+
+    fn write_async<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> BlockFuture<'a, ()> {
+        match check_request(self, lba, buffer.len()) {
+            Ok(_) => self.disk.write_async(self.info.start + lba, buffer),
+            Err(error) => alloc::boxed::Box::pin(async move { Err(error) }),
+        }
+    }
+
+    fn flush_async(&self) -> BlockFuture<'_, ()> {
+        self.disk.flush_async()
+    }
+}
+
+/// Имя раздела `number` диска `disk`: `sda1`, но `nvme0n1p1`.
+pub fn partition_name(disk: &str, number: u32) -> String {
+    match disk.ends_with(|c: char| c.is_ascii_digit()) {
+        true => format!("{disk}p{number}"),
+        false => format!("{disk}{number}"),
+    }
+}
+
+/// Читает таблицу разделов диска, зарегистрированного как `name`, и
+/// регистрирует его разделы. Возвращает их (пустой список, если таблицы
+/// нет).
+pub fn register_partitions(
+    name: &str,
+    disk: &Arc<dyn BlockDevice>,
+) -> Result<Vec<Arc<Partition>>, Errno> {
+    let Some(table) = scan(&**disk)? else {
+        return Ok(Vec::new());
+    };
+    if let Scheme::Gpt {
+        primary_valid,
+        backup_valid,
+        ..
+    } = table.scheme
+    {
+        match (primary_valid, backup_valid) {
+            (true, true) => {}
+            (true, false) => {
+                serial_println!("{}: backup GPT header is damaged", name);
+            }
+            _ => {
+                serial_println!("{}: primary GPT header is damaged, using the backup", name);
+            }
+        }
+    }
+
+    let partitions: Vec<_> = table
+        .partitions
+        .into_iter()
+        .map(|info| {
+            let name = partition_name(name, info.number);
+            Arc::new(Partition::new(name, disk.clone(), info))
+        })
+        .collect();
+    for (index, partition) in partitions.iter().enumerate() {
+        if let Err(error) = super::register(&partition.name, partition.clone()) {
+            for registered in &partitions[..index] {
+                super::unregister(&registered.name);
+            }
+            return Err(error);
+        }
+    }
+
+    if let Some(esp) = partitions.iter().find(|partition| partition.info.is_esp()) {
+        ESP.lock().get_or_insert_with(|| esp.clone());
+    }
+    Ok(partitions)
+}
+
+/// Системный раздел EFI, найденный первым.
+pub fn esp() -> Option<Arc<Partition>> {
+    ESP.lock().clone()
+}
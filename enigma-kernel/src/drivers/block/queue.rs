@@ -0,0 +1,331 @@
+//! Очередь запросов к устройству.
+//!
+//! Запрос встает в очередь сразу при вызове [`RequestQueue::read`],
+//! [`RequestQueue::write`] или [`RequestQueue::flush`], а вызывающий ждет
+//! возвращенный [`Completion`]. Драйвер забирает запросы по одному
+//! ([`RequestQueue::next`]) и завершает их ([`Request::complete`]), обычно
+//! из отложенной обработки прерывания.
+//!
+//! Пока драйвер не забрал запрос, к нему присоединяются запросы того же вида
+//! к соседним блокам: устройство получает одну большую команду вместо
+//! нескольких маленьких, а каждый вызывающий - свою часть результата.
+//! Запрос не сливается с более ранним, если между ними есть сброс или
+//! запрос к тем же блокам, поэтому порядок операций над каждым блоком
+//! сохраняется.
+
+use crate::errno::Errno;
+use crate::sync::{Notify, oneshot};
+use alloc::{collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+    /// Сброс кэша записи устройства.
+    Flush,
+}
+
+/// Снимок статистики очереди.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Запросов поставлено в очередь.
+    pub submitted: u64,
+    /// Из них присоединено к уже стоящим.
+    pub merged: u64,
+    /// Запросов отдано драйверу (после слияния).
+    pub dispatched: u64,
+    pub completed: u64,
+    /// Из них завершено с ошибкой.
+    pub errors: u64,
+    /// Запросов в очереди и у драйвера сейчас.
+    pub depth: usize,
+    /// Наибольшая глубина очереди.
+    pub max_depth: usize,
+}
+
+/// Счетчики очереди. Запрос держит их, чтобы отметить свое завершение.
+#[derive(Default)]
+struct Counters {
+    submitted: AtomicU64,
+    merged: AtomicU64,
+    dispatched: AtomicU64,
+    completed: AtomicU64,
+    errors: AtomicU64,
+    in_flight: AtomicUsize,
+    max_depth: AtomicUsize,
+}
+
+pub struct RequestQueue {
+    block_size: usize,
+    /// Наибольший размер запроса после слияния, в блоках.
+    max_blocks: u64,
+    pending: Mutex<VecDeque<Request>>,
+    notify: Notify,
+    counters: Arc<Counters>,
+}
+
+impl RequestQueue {
+    pub fn new(block_size: usize, max_blocks: u64) -> Self {
+        assert!(max_blocks > 0, "empty request limit");
+        Self {
+            block_size,
+            max_blocks,
+            pending: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Ставит в очередь чтение `count` блоков с `lba`. Future возвращает
+    /// прочитанные данные.
+    pub fn read(&self, lba: u64, count: u64) -> Completion {
+        let data = vec![0; count as usize * self.block_size];
+        self.submit(Operation::Read, lba, count, data)
+    }
+
+    /// Ставит в очередь запись `data` (целых блоков) с `lba`.
+    pub fn write(&self, lba: u64, data: Vec<u8>) -> Completion {
+        assert_eq!(data.len() % self.block_size, 0, "partial block write");
+        let count = (data.len() / self.block_size) as u64;
+        self.submit(Operation::Write, lba, count, data)
+    }
+
+    /// Ставит в очередь сброс кэша записи. Сброс выполняется после всех
+    /// запросов, поставленных до него.
+    pub fn flush(&self) -> Completion {
+        self.submit(Operation::Flush, 0, 0, Vec::new())
+    }
+
+    fn submit(&self, operation: Operation, lba: u64, count: u64, data: Vec<u8>) -> Completion {
+        let (sender, receiver) = oneshot::channel();
+        let part = Part {
+            offset: 0,
+            count,
+            sender,
+        };
+        self.counters.submitted.fetch_add(1, Ordering::Relaxed);
+
+        let merged = without_interrupts(|| {
+            let mut pending = self.pending.lock();
+            let merged = self.merge(&mut pending, operation, lba, count, data, part);
+            let depth = pending.len() + self.counters.in_flight.load(Ordering::Relaxed);
+            self.counters.max_depth.fetch_max(depth, Ordering::Relaxed);
+            merged
+        });
+        if merged {
+            self.counters.merged.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.notify.notify_one();
+        }
+        Completion(receiver)
+    }
+
+    /// Присоединяет запрос к стоящему в очереди или ставит его в конец.
+    /// Возвращает `true`, если запрос присоединен.
+    fn merge(
+        &self,
+        pending: &mut VecDeque<Request>,
+        operation: Operation,
+        lba: u64,
+        count: u64,
+        mut data: Vec<u8>,
+        mut part: Part,
+    ) -> bool {
+        let end = lba + count;
+        // Ищем с конца: запрос можно перенести вперед только мимо запросов
+        // к другим блокам.
+        for request in pending.iter_mut().rev() {
+            if request.operation == Operation::Flush {
+                if operation == Operation::Flush {
+                    request.parts.push(part);
+                    return true;
+                }
+                break;
+            }
+            if operation == Operation::Flush {
+                break;
+            }
+
+            let fits = request.count + count <= self.max_blocks;
+            let same = request.operation == operation;
+            if same && fits && request.lba + request.count == lba {
+                part.offset = request.count;
+                request.data.append(&mut data);
+                request.count += count;
+                request.parts.push(part);
+                return true;
+            }
+            if same && fits && end == request.lba {
+                for existing in &mut request.parts {
+                    existing.offset += count;
+                }
+                data.append(&mut request.data);
+                request.data = data;
+                request.lba = lba;
+                request.count += count;
+                request.parts.push(part);
+                return true;
+            }
+            if lba < request.lba + request.count && request.lba < end {
+                break;
+            }
+        }
+
+        pending.push_back(Request {
+            operation,
+            lba,
+            count,
+            block_size: self.block_size,
+            data,
+            parts: vec![part],
+            counters: None,
+        });
+        false
+    }
+
+    /// Забирает следующий запрос, если он есть. Может вызываться из
+    /// прерываний.
+    pub fn pop(&self) -> Option<Request> {
+        let mut request = without_interrupts(|| self.pending.lock().pop_front())?;
+        self.counters.dispatched.fetch_add(1, Ordering::Relaxed);
+        self.counters.in_flight.fetch_add(1, Ordering::Relaxed);
+        request.counters = Some(self.counters.clone());
+        Some(request)
+    }
+
+    /// Ждет следующий запрос.
+    pub async fn next(&self) -> Request {
+        loop {
+            if let Some(request) = self.pop() {
+                return request;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let counters = &self.counters;
+        let pending = without_interrupts(|| self.pending.lock().len());
+        QueueStats {
+            submitted: counters.submitted.load(Ordering::Relaxed),
+            merged: counters.merged.load(Ordering::Relaxed),
+            dispatched: counters.dispatched.load(Ordering::Relaxed),
+            completed: counters.completed.load(Ordering::Relaxed),
+            errors: counters.errors.load(Ordering::Relaxed),
+            depth: pending + counters.in_flight.load(Ordering::Relaxed),
+            max_depth: counters.max_depth.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Часть запроса, принадлежащая одному вызывающему.
+struct Part {
+    /// Смещение части от начала запроса в блоках.
+    offset: u64,
+    count: u64,
+    sender: oneshot::Sender<Result<Vec<u8>, Errno>>,
+}
+
+/// Запрос, отданный драйверу. Если драйвер удалит запрос, не завершив его,
+/// все ждущие получат `EIO`.
+pub struct Request {
+    operation: Operation,
+    lba: u64,
+    count: u64,
+    block_size: usize,
+    data: Vec<u8>,
+    parts: Vec<Part>,
+    /// Счетчики очереди; `None`, пока запрос стоит в очереди.
+    counters: Option<Arc<Counters>>,
+}
+
+impl Request {
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn lba(&self) -> u64 {
+        self.lba
+    }
+
+    /// Длина запроса в блоках.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Данные для записи или буфер для чтения.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Сколько вызывающих ждут запрос.
+    pub fn waiters(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Завершает запрос и будит всех ждущих; при чтении каждый получает
+    /// свою часть данных. Выделяет память, поэтому вызывается не из
+    /// обработчика прерывания, а из задачи или отложенной работы.
+    pub fn complete(mut self, result: Result<(), Errno>) {
+        if let Some(counters) = &self.counters {
+            let counter = match result {
+                Ok(()) => &counters.completed,
+                Err(_) => &counters.errors,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let parts = core::mem::take(&mut self.parts);
+        let single = parts.len() == 1;
+        for part in parts {
+            let value = result.map(|()| match self.operation {
+                Operation::Read if single => core::mem::take(&mut self.data),
+                Operation::Read => {
+                    let start = part.offset as usize * self.block_size;
+                    let end = start + part.count as usize * self.block_size;
+                    self.data[start..end].to_vec()
+                }
+                Operation::Write | Operation::Flush => Vec::new(),
+            });
+            // Вызывающий мог перестать ждать.
+            let _ = part.sender.send(value);
+        }
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        if let Some(counters) = &self.counters {
+            if !self.parts.is_empty() {
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Ожидание завершения запроса. Возвращает прочитанные данные (для записи
+/// и сброса - пустой вектор).
+pub struct Completion(oneshot::Receiver<Result<Vec<u8>, Errno>>);
+
+impl Future for Completion {
+    type Output = Result<Vec<u8>, Errno>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(Errno::EIO)))
+    }
+}
@@ -0,0 +1,131 @@
+//! Блочное устройство в оперативной памяти.
+
+use super::{BlockDevice, check_request};
+use crate::errno::Errno;
+use crate::memory::{self, GlobalFrameAllocator};
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameDeallocator, PhysFrame};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Диск в памяти. Данные хранятся в физических кадрах, которые выделяются
+/// при первой записи ненулевых данных, поэтому большой пустой диск почти не
+/// занимает памяти, а неписаные блоки читаются как нули.
+pub struct RamBlockDevice {
+    block_size: usize,
+    block_count: u64,
+    pages: Mutex<BTreeMap<u64, PhysFrame>>,
+}
+
+impl RamBlockDevice {
+    pub fn new(block_size: usize, block_count: u64) -> Self {
+        assert!(
+            block_size.is_power_of_two() && (512..=PAGE_SIZE).contains(&block_size),
+            "unsupported block size"
+        );
+
+        Self {
+            block_size,
+            block_count,
+            pages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Занятая данными память в байтах.
+    pub fn used_bytes(&self) -> usize {
+        self.pages.lock().len() * PAGE_SIZE
+    }
+}
+
+impl BlockDevice for RamBlockDevice {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+        check_request(self, lba, buffer.len())?;
+        let pages = self.pages.lock();
+        let start = lba as usize * self.block_size;
+
+        for (index, chunk) in chunks(start, buffer.len()) {
+            let target = &mut buffer[index..index + chunk.len];
+            match pages.get(&chunk.page) {
+                Some(frame) => unsafe {
+                    let page = memory::phys_to_virt(frame.start_address()).as_ptr::<u8>();
+                    let source = page.add(chunk.offset);
+                    core::ptr::copy_nonoverlapping(source, target.as_mut_ptr(), chunk.len);
+                },
+                None => target.fill(0),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), Errno> {
+        check_request(self, lba, buffer.len())?;
+        let mut pages = self.pages.lock();
+        let start = lba as usize * self.block_size;
+
+        for (index, chunk) in chunks(start, buffer.len()) {
+            let source = &buffer[index..index + chunk.len];
+            let frame = match pages.get(&chunk.page) {
+                Some(&frame) => frame,
+                None if source.iter().all(|&byte| byte == 0) => continue,
+                None => {
+                    let frame = memory::allocate_zeroed_frame().ok_or(Errno::ENOMEM)?;
+                    pages.insert(chunk.page, frame);
+                    frame
+                }
+            };
+
+            unsafe {
+                let page = memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+                let target = page.add(chunk.offset);
+                core::ptr::copy_nonoverlapping(source.as_ptr(), target, chunk.len);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RamBlockDevice {
+    fn drop(&mut self) {
+        for (_, frame) in core::mem::take(&mut *self.pages.lock()) {
+            unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Часть запроса, попадающая в одну страницу.
+struct Chunk {
+    page: u64,
+    offset: usize,
+    len: usize,
+}
+
+/// Делит `len` байт с позиции `start` на части по страницам. Возвращает
+/// смещение каждой части в буфере запроса.
+fn chunks(start: usize, len: usize) -> impl Iterator<Item = (usize, Chunk)> {
+    let mut done = 0;
+    core::iter::from_fn(move || {
+        if done == len {
+            return None;
+        }
+
+        let position = start + done;
+        let offset = position % PAGE_SIZE;
+        let chunk = Chunk {
+            page: (position / PAGE_SIZE) as u64,
+            offset,
+            len: (PAGE_SIZE - offset).min(len - done),
+        };
+        let index = done;
+        done += chunk.len;
+        Some((index, chunk))
+    })
+}
@@ -0,0 +1,27 @@
+//! Консоль процессов: стандартные дескрипторы 0, 1 и 2.
+
+use crate::errno::Errno;
+use crate::process::fd::File;
+use crate::{print, serial_print};
+
+/// Вывод идет на экран и в последовательный порт. Ввода с клавиатуры в
+/// процессы пока нет, поэтому чтение сразу возвращает конец файла.
+pub struct Console;
+
+impl File for Console {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, Errno> {
+        Ok(0)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, Errno> {
+        for chunk in buffer.utf8_chunks() {
+            print!("{}", chunk.valid());
+            serial_print!("{}", chunk.valid());
+            if !chunk.invalid().is_empty() {
+                print!("\u{FFFD}");
+                serial_print!("\u{FFFD}");
+            }
+        }
+        Ok(buffer.len())
+    }
+}
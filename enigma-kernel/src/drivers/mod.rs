@@ -4,5 +4,9 @@
 //! дополнением (например работа с принтером, видеокартой и т.д) должны
 //! поставлятся отдельно.
 
+pub mod ahci;
 pub mod apic;
+pub mod block;
+pub mod console;
+pub mod pci;
 pub mod serial;
@@ -0,0 +1,139 @@
+//! Доступ к конфигурационному пространству PCI.
+//!
+//! Основной способ - ECAM (PCIe): у каждой функции 4 КиБ регистров в памяти,
+//! адреса областей берутся из таблицы ACPI MCFG. Область шины отображается
+//! при первом обращении к ней. Без MCFG используется старый механизм через
+//! порты `0xCF8`/`0xCFC`: он видит только сегмент 0 и первые 256 байт
+//! каждой функции.
+
+use super::Address;
+use crate::memory::{self, GlobalFrameAllocator};
+use acpi::mcfg::PciConfigRegions;
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+/// Размер области ECAM одной шины: 32 устройства по 8 функций по 4 КиБ.
+const BUS_SIZE: u64 = 1 << 20;
+
+/// Способ доступа к конфигурационному пространству.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// Отображение в память (PCIe ECAM).
+    Ecam,
+    /// Порты `0xCF8`/`0xCFC`.
+    Legacy,
+}
+
+struct Ecam {
+    regions: PciConfigRegions,
+    /// Отображенные области шин: (сегмент, шина) -> адрес функции 0:0.
+    /// `None` - шина не описана в MCFG или не отобразилась.
+    buses: BTreeMap<(u16, u8), Option<VirtAddr>>,
+}
+
+static ECAM: Mutex<Option<Ecam>> = Mutex::new(None);
+/// Порты механизма 1 используются парой, поэтому под одной блокировкой.
+static LEGACY: Mutex<()> = Mutex::new(());
+
+/// Включает доступ через ECAM по областям из MCFG.
+pub(super) fn init_ecam(regions: PciConfigRegions) {
+    *ECAM.lock() = Some(Ecam {
+        regions,
+        buses: BTreeMap::new(),
+    });
+}
+
+pub fn mechanism() -> Mechanism {
+    match without_interrupts(|| ECAM.lock().is_some()) {
+        true => Mechanism::Ecam,
+        false => Mechanism::Legacy,
+    }
+}
+
+/// Адрес регистров функции в ECAM, если шина доступна через ECAM.
+fn ecam_address(address: Address) -> Option<VirtAddr> {
+    without_interrupts(|| {
+        let mut ecam = ECAM.lock();
+        let ecam = ecam.as_mut()?;
+        let key = (address.segment, address.bus);
+        let base = *ecam.buses.entry(key).or_insert_with(|| {
+            let physical = ecam
+                .regions
+                .physical_address(address.segment, address.bus, 0, 0)?;
+            let mut mapper = unsafe { memory::kernel_mapper() };
+            memory::map_mmio(
+                PhysAddr::new(physical),
+                BUS_SIZE,
+                &mut mapper,
+                &mut GlobalFrameAllocator,
+            )
+            .ok()
+        });
+        Some(base? + ((address.device as u64) << 15 | (address.function as u64) << 12))
+    })
+}
+
+/// Размер конфигурационного пространства функции, доступный для чтения.
+pub fn space_size(address: Address) -> u16 {
+    match ecam_address(address) {
+        Some(_) => 4096,
+        None => 256,
+    }
+}
+
+/// Читает 32-битный регистр по смещению `offset` (кратному 4). Вне
+/// доступного пространства возвращает единицы, как отсутствующее устройство.
+pub fn read(address: Address, offset: u16) -> u32 {
+    debug_assert_eq!(offset % 4, 0, "unaligned PCI config access");
+    if let Some(base) = ecam_address(address) {
+        return unsafe { (base + offset as u64).as_ptr::<u32>().read_volatile() };
+    }
+    if address.segment != 0 || offset >= 256 {
+        return u32::MAX;
+    }
+
+    without_interrupts(|| {
+        let _guard = LEGACY.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(legacy_address(address, offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    })
+}
+
+/// Записывает 32-битный регистр по смещению `offset` (кратному 4).
+pub fn write(address: Address, offset: u16, value: u32) {
+    debug_assert_eq!(offset % 4, 0, "unaligned PCI config access");
+    if let Some(base) = ecam_address(address) {
+        unsafe {
+            (base + offset as u64)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value)
+        };
+        return;
+    }
+    if address.segment != 0 || offset >= 256 {
+        return;
+    }
+
+    without_interrupts(|| {
+        let _guard = LEGACY.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(legacy_address(address, offset));
+            Port::new(CONFIG_DATA).write(value);
+        }
+    });
+}
+
+fn legacy_address(address: Address, offset: u16) -> u32 {
+    0x8000_0000
+        | (address.bus as u32) << 16
+        | (address.device as u32) << 11
+        | (address.function as u32) << 8
+        | (offset as u32 & 0xFC)
+}
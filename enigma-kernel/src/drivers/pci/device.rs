@@ -0,0 +1,458 @@
+//! Функция PCI: заголовок, BAR и список возможностей.
+
+use super::{Address, config};
+use crate::errno::Errno;
+use crate::memory::{self, GlobalFrameAllocator};
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Смещения регистров общего заголовка.
+pub const VENDOR_ID: u16 = 0x00;
+pub const COMMAND: u16 = 0x04;
+pub const STATUS: u16 = 0x06;
+pub const CLASS: u16 = 0x08;
+pub const HEADER_TYPE: u16 = 0x0E;
+pub const BAR0: u16 = 0x10;
+/// Шины за мостом (заголовок типа 1).
+pub const PRIMARY_BUS: u16 = 0x18;
+pub const SECONDARY_BUS: u16 = 0x19;
+pub const SUBORDINATE_BUS: u16 = 0x1A;
+pub const CAPABILITIES: u16 = 0x34;
+pub const INTERRUPT_LINE: u16 = 0x3C;
+pub const INTERRUPT_PIN: u16 = 0x3D;
+
+/// Биты регистра команд.
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+/// Типы заголовка.
+pub const HEADER_GENERAL: u8 = 0x00;
+pub const HEADER_BRIDGE: u8 = 0x01;
+pub const HEADER_CARDBUS: u8 = 0x02;
+pub(super) const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+/// Возможности (capability ID).
+pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR: u8 = 0x09;
+pub const CAP_PCI_EXPRESS: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// Начало расширенных возможностей PCIe (только через ECAM).
+const EXTENDED_CAPABILITIES: u16 = 0x100;
+/// Защита от зацикленного списка возможностей.
+const MAX_CAPABILITIES: usize = 64;
+
+/// Область, которую функция декодирует по BAR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// BAR занимает два регистра.
+        wide: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+impl Bar {
+    pub fn size(&self) -> u64 {
+        match *self {
+            Self::Memory { size, .. } => size,
+            Self::Io { size, .. } => size as u64,
+        }
+    }
+}
+
+/// Запись списка возможностей.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u16,
+    /// Смещение структуры в конфигурационном пространстве.
+    pub offset: u16,
+    /// Расширенная возможность PCIe (ID из другого пространства номеров).
+    pub extended: bool,
+}
+
+/// Функция устройства PCI, найденная при перечислении.
+pub struct Device {
+    address: Address,
+    vendor_id: u16,
+    device_id: u16,
+    class: u8,
+    subclass: u8,
+    prog_if: u8,
+    revision: u8,
+    header_type: u8,
+    bars: [Option<Bar>; 6],
+    capabilities: Vec<Capability>,
+    interrupt_pin: u8,
+    interrupt_line: u8,
+    /// Имя драйвера, который взял устройство.
+    driver: Mutex<Option<&'static str>>,
+    /// Уже отображенные BAR памяти.
+    mapped: Mutex<[Option<VirtAddr>; 6]>,
+}
+
+impl Device {
+    /// Читает функцию `address`; `None`, если ее нет.
+    pub(super) fn probe(address: Address) -> Option<Self> {
+        if !super::present(address) {
+            return None;
+        }
+        let id = config::read(address, VENDOR_ID);
+        let class = config::read(address, CLASS);
+        let header = (config::read(address, HEADER_TYPE & !3) >> 16) as u8;
+        let interrupt = config::read(address, INTERRUPT_LINE);
+
+        let mut device = Self {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: header,
+            bars: [None; 6],
+            capabilities: Vec::new(),
+            interrupt_pin: (interrupt >> 8) as u8,
+            interrupt_line: interrupt as u8,
+            driver: Mutex::new(None),
+            mapped: Mutex::new([None; 6]),
+        };
+        device.read_bars();
+        device.read_capabilities();
+        Some(device)
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.device_id
+    }
+
+    pub fn class(&self) -> u8 {
+        self.class
+    }
+
+    pub fn subclass(&self) -> u8 {
+        self.subclass
+    }
+
+    /// Программный интерфейс (например, AHCI у контроллера SATA).
+    pub fn prog_if(&self) -> u8 {
+        self.prog_if
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    /// Тип заголовка без бита многофункциональности.
+    pub fn header_type(&self) -> u8 {
+        self.header_type & !HEADER_MULTIFUNCTION
+    }
+
+    pub fn is_multifunction(&self) -> bool {
+        self.header_type & HEADER_MULTIFUNCTION != 0
+    }
+
+    /// Мост PCI-PCI.
+    pub fn is_bridge(&self) -> bool {
+        self.header_type() == HEADER_BRIDGE
+    }
+
+    /// Вторичная и последняя подчиненная шины моста.
+    pub fn bridge_buses(&self) -> Option<(u8, u8)> {
+        self.is_bridge().then(|| {
+            let buses = self.read_u32(PRIMARY_BUS);
+            ((buses >> 8) as u8, (buses >> 16) as u8)
+        })
+    }
+
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        self.bars.get(index).copied().flatten()
+    }
+
+    pub fn bars(&self) -> impl Iterator<Item = (usize, Bar)> + '_ {
+        self.bars
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bar)| Some((index, (*bar)?)))
+    }
+
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// Смещение обычной (не расширенной) возможности `id`.
+    pub fn capability(&self, id: u8) -> Option<u16> {
+        self.capabilities
+            .iter()
+            .find(|cap| !cap.extended && cap.id == id as u16)
+            .map(|cap| cap.offset)
+    }
+
+    /// Смещение расширенной возможности PCIe `id`.
+    pub fn extended_capability(&self, id: u16) -> Option<u16> {
+        self.capabilities
+            .iter()
+            .find(|cap| cap.extended && cap.id == id)
+            .map(|cap| cap.offset)
+    }
+
+    /// Вывод INTx (1 = INTA#), 0 - не использует INTx.
+    pub fn interrupt_pin(&self) -> u8 {
+        self.interrupt_pin
+    }
+
+    /// Линия IRQ, назначенная прошивкой.
+    pub fn interrupt_line(&self) -> u8 {
+        self.interrupt_line
+    }
+
+    /// Имя драйвера, который взял устройство.
+    pub fn driver(&self) -> Option<&'static str> {
+        *self.driver.lock()
+    }
+
+    pub(super) fn bind(&self, driver: &'static str) -> bool {
+        let mut bound = self.driver.lock();
+        match *bound {
+            Some(_) => false,
+            None => {
+                *bound = Some(driver);
+                true
+            }
+        }
+    }
+
+    pub(super) fn unbind(&self) {
+        *self.driver.lock() = None;
+    }
+
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        config::read(self.address, offset)
+    }
+
+    pub fn read_u16(&self, offset: u16) -> u16 {
+        (self.read_u32(offset & !3) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        (self.read_u32(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    pub fn write_u32(&self, offset: u16, value: u32) {
+        config::write(self.address, offset, value);
+    }
+
+    /// Пишет 16 бит через чтение-изменение-запись слова. Соседнее поле
+    /// `STATUS` сбрасывается записью единиц, поэтому при записи `COMMAND`
+    /// вместо него пишутся нули.
+    pub fn write_u16(&self, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let mut word = self.read_u32(offset & !3);
+        if offset & !3 == COMMAND {
+            word &= 0xFFFF;
+        }
+        word = (word & !(0xFFFF << shift)) | (value as u32) << shift;
+        self.write_u32(offset & !3, word);
+    }
+
+    pub fn write_u8(&self, offset: u16, value: u8) {
+        let shift = (offset & 3) * 8;
+        let mut word = self.read_u32(offset & !3);
+        if offset & !3 == COMMAND {
+            word &= 0xFFFF;
+        }
+        word = (word & !(0xFF << shift)) | (value as u32) << shift;
+        self.write_u32(offset & !3, word);
+    }
+
+    pub fn command(&self) -> u16 {
+        self.read_u16(COMMAND)
+    }
+
+    pub fn set_command(&self, command: u16) {
+        self.write_u16(COMMAND, command);
+    }
+
+    /// Разрешает декодирование памяти и портов по BAR и прямой доступ
+    /// устройства к памяти (DMA).
+    pub fn enable(&self) {
+        let mut command = self.command() | COMMAND_MEMORY | COMMAND_BUS_MASTER;
+        if self.bars().any(|(_, bar)| matches!(bar, Bar::Io { .. })) {
+            command |= COMMAND_IO;
+        }
+        self.set_command(command);
+    }
+
+    /// Отображает BAR памяти `index` в окно MMIO ядра. BAR отображается
+    /// один раз, повторные вызовы возвращают тот же адрес.
+    pub fn map_bar(&self, index: usize) -> Result<VirtAddr, Errno> {
+        let Some(Bar::Memory { address, size, .. }) = self.bar(index) else {
+            return Err(Errno::ENODEV);
+        };
+        let mut mapped = self.mapped.lock();
+        if let Some(virt) = mapped[index] {
+            return Ok(virt);
+        }
+        let mut mapper = unsafe { memory::kernel_mapper() };
+        let virt = memory::map_mmio(
+            PhysAddr::new(address),
+            size,
+            &mut mapper,
+            &mut GlobalFrameAllocator,
+        )
+        .map_err(|_| Errno::ENOMEM)?;
+        mapped[index] = Some(virt);
+        Ok(virt)
+    }
+
+    /// Читает BAR и определяет их размеры: в регистр пишутся единицы, и
+    /// устройство оставляет единицами только биты адреса. На это время
+    /// декодирование выключается, чтобы устройство не отвечало по
+    /// временному адресу.
+    fn read_bars(&mut self) {
+        let count = match self.header_type() {
+            HEADER_GENERAL => 6,
+            HEADER_BRIDGE => 2,
+            _ => return,
+        };
+        let command = self.command();
+        self.set_command(command & !(COMMAND_IO | COMMAND_MEMORY));
+
+        let mut index = 0;
+        while index < count {
+            let offset = BAR0 + index as u16 * 4;
+            let low = self.read_u32(offset);
+            let low_mask = self.size_register(offset, low);
+
+            if low & 1 == 1 {
+                // Старшие 16 бит порта устройство может не декодировать.
+                let mask = match low_mask >> 16 {
+                    0 => low_mask | 0xFFFF_0000,
+                    _ => low_mask,
+                } & !0x3;
+                if low_mask & !0x3 != 0 {
+                    self.bars[index] = Some(Bar::Io {
+                        port: low & !0x3,
+                        size: (!mask).wrapping_add(1),
+                    });
+                }
+                index += 1;
+                continue;
+            }
+
+            let wide = (low >> 1) & 0x3 == 0x2 && index + 1 < count;
+            let mut address = (low & !0xF) as u64;
+            let mut mask = (low_mask & !0xF) as u64 | 0xFFFF_FFFF_0000_0000;
+            let mut implemented = low_mask & !0xF != 0;
+            if wide {
+                let high = self.read_u32(offset + 4);
+                let high_mask = self.size_register(offset + 4, high);
+                address |= (high as u64) << 32;
+                mask = (mask & 0xFFFF_FFFF) | (high_mask as u64) << 32;
+                implemented |= high_mask != 0;
+            }
+            if implemented {
+                self.bars[index] = Some(Bar::Memory {
+                    address,
+                    size: (!mask).wrapping_add(1),
+                    prefetchable: low & 0x8 != 0,
+                    wide,
+                });
+            }
+            index += if wide { 2 } else { 1 };
+        }
+        self.set_command(command);
+    }
+
+    /// Пишет единицы в регистр BAR и возвращает прочитанную маску, после
+    /// чего восстанавливает значение `value`.
+    fn size_register(&self, offset: u16, value: u32) -> u32 {
+        self.write_u32(offset, u32::MAX);
+        let mask = self.read_u32(offset);
+        self.write_u32(offset, value);
+        mask
+    }
+
+    fn read_capabilities(&mut self) {
+        if self.read_u16(STATUS) & STATUS_CAPABILITIES != 0 {
+            let pointer = match self.header_type() {
+                HEADER_CARDBUS => 0x14,
+                _ => CAPABILITIES,
+            };
+            let mut offset = (self.read_u8(pointer) & !3) as u16;
+            while offset >= 0x40 && self.capabilities.len() < MAX_CAPABILITIES {
+                let header = self.read_u16(offset);
+                self.capabilities.push(Capability {
+                    id: header & 0xFF,
+                    offset,
+                    extended: false,
+                });
+                offset = (header >> 8) & 0xFC;
+            }
+        }
+
+        // Расширенные возможности есть только у функций PCIe и видны только
+        // через ECAM.
+        if self.capability(CAP_PCI_EXPRESS).is_none()
+            || config::space_size(self.address) <= EXTENDED_CAPABILITIES
+        {
+            return;
+        }
+        let mut offset = EXTENDED_CAPABILITIES;
+        let limit = self.capabilities.len() + MAX_CAPABILITIES;
+        while offset >= EXTENDED_CAPABILITIES && self.capabilities.len() < limit {
+            let header = self.read_u32(offset);
+            if header == 0 || header == u32::MAX {
+                break;
+            }
+            self.capabilities.push(Capability {
+                id: header as u16,
+                offset,
+                extended: true,
+            });
+            offset = (header >> 20) as u16 & !3;
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    /// Строка в духе `lspci -nn`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{:02x}{:02x}]: [{:04x}:{:04x}] (rev {:02x})",
+            self.address, self.class, self.subclass, self.vendor_id, self.device_id, self.revision
+        )?;
+        if self.prog_if != 0 {
+            write!(f, " (prog-if {:02x})", self.prog_if)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,114 @@
+//! Реестр драйверов PCI.
+//!
+//! Драйвер описывает, какие устройства он поддерживает ([`DeviceId`]), и
+//! получает каждое подходящее свободное устройство в `probe`: и уже
+//! найденные при регистрации драйвера, и найденные позже.
+
+use super::Device;
+use crate::errno::Errno;
+use crate::serial_println;
+use alloc::{sync::Arc, vec::Vec};
+use spin::Mutex;
+
+/// Шаблон устройства. Поле `None` подходит к любому значению.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId {
+    pub vendor: Option<u16>,
+    pub device: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub prog_if: Option<u8>,
+}
+
+impl DeviceId {
+    /// Конкретная модель устройства.
+    pub const fn device(vendor: u16, device: u16) -> Self {
+        Self {
+            vendor: Some(vendor),
+            device: Some(device),
+            class: None,
+            subclass: None,
+            prog_if: None,
+        }
+    }
+
+    /// Любое устройство класса; `prog_if` уточняет интерфейс.
+    pub const fn class(class: u8, subclass: u8, prog_if: Option<u8>) -> Self {
+        Self {
+            vendor: None,
+            device: None,
+            class: Some(class),
+            subclass: Some(subclass),
+            prog_if,
+        }
+    }
+
+    pub fn matches(&self, device: &Device) -> bool {
+        self.vendor.is_none_or(|id| id == device.vendor_id())
+            && self.device.is_none_or(|id| id == device.device_id())
+            && self.class.is_none_or(|class| class == device.class())
+            && self.subclass.is_none_or(|class| class == device.subclass())
+            && self
+                .prog_if
+                .is_none_or(|prog_if| prog_if == device.prog_if())
+    }
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub ids: &'static [DeviceId],
+    /// Подключает драйвер к устройству. При ошибке устройство остается
+    /// свободным для других драйверов.
+    pub probe: fn(&Arc<Device>) -> Result<(), Errno>,
+}
+
+impl Driver {
+    pub fn matches(&self, device: &Device) -> bool {
+        self.ids.iter().any(|id| id.matches(device))
+    }
+}
+
+static DRIVERS: Mutex<Vec<&'static Driver>> = Mutex::new(Vec::new());
+
+/// Регистрирует драйвер и отдает ему подходящие свободные устройства.
+/// Возвращает количество подключенных устройств.
+pub fn register_driver(driver: &'static Driver) -> usize {
+    DRIVERS.lock().push(driver);
+    super::devices()
+        .iter()
+        .filter(|device| attach(driver, device))
+        .count()
+}
+
+/// Ищет драйвер для нового устройства.
+pub(super) fn probe(device: &Arc<Device>) {
+    // Список копируется: `probe` драйвера может регистрировать другие.
+    let drivers = DRIVERS.lock().clone();
+    for driver in drivers {
+        if attach(driver, device) {
+            return;
+        }
+    }
+}
+
+fn attach(driver: &'static Driver, device: &Arc<Device>) -> bool {
+    if !driver.matches(device) || !device.bind(driver.name) {
+        return false;
+    }
+    match (driver.probe)(device) {
+        Ok(()) => {
+            serial_println!("pci {}: bound to {}", device.address(), driver.name);
+            true
+        }
+        Err(error) => {
+            serial_println!(
+                "pci {}: {} failed to probe: {}",
+                device.address(),
+                driver.name,
+                error
+            );
+            device.unbind();
+            false
+        }
+    }
+}
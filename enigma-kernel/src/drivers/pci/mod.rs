@@ -0,0 +1,192 @@
+//! Шина PCI/PCIe.
+//!
+//! [`init`] выбирает способ доступа к конфигурационному пространству
+//! ([`config`]) и перечисляет устройства: с шины 0 (или с каждой шины
+//! хост-мостов, если хост-мост многофункциональный) и рекурсивно за каждым
+//! мостом PCI-PCI. Для каждой функции читаются BAR с размерами и список
+//! возможностей ([`Device`]), после чего ей ищется драйвер из реестра
+//! ([`driver`]). Найденные устройства печатаются в последовательный порт в
+//! духе `lspci -nn`. Драйверы получают прерывания через MSI или MSI-X
+//! ([`msi`]).
+//!
+//! Перечисляется только сегмент 0: в обычных машинах (QEMU `pc` и `q35`)
+//! других нет.
+
+pub mod config;
+pub mod device;
+pub mod driver;
+pub mod msi;
+
+pub use config::Mechanism;
+pub use device::{Bar, Capability, Device};
+pub use driver::{DeviceId, Driver, register_driver};
+pub use msi::MsiVectors;
+
+use crate::drivers::apic::AcpiHandlerImpl;
+use crate::{memory, serial_println};
+use acpi::AcpiTables;
+use acpi::mcfg::PciConfigRegions;
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt;
+use spin::Mutex;
+
+/// Функций в одном устройстве.
+const FUNCTIONS: u8 = 8;
+/// Устройств на одной шине.
+const DEVICES_PER_BUS: u8 = 32;
+
+/// Найденные функции по порядку адресов.
+static DEVICES: Mutex<Vec<Arc<Device>>> = Mutex::new(Vec::new());
+
+/// Адрес функции: сегмент, шина, устройство, функция.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Address {
+    pub const fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+/// Перечисляет устройства PCI. Если передан `rsdp` и в ACPI есть таблица
+/// MCFG, доступ идет через ECAM, иначе через порты. Вызывается один раз при
+/// загрузке. Возвращает количество найденных функций.
+///
+/// ## Safety
+///
+/// `rsdp` должен быть адресом RSDP, переданным загрузчиком.
+pub unsafe fn init(rsdp: Option<usize>) -> usize {
+    let regions = rsdp.and_then(|rsdp| {
+        let handler = AcpiHandlerImpl::new(memory::physical_memory_offset());
+        let tables = unsafe { AcpiTables::from_rsdp(handler, rsdp) }.ok()?;
+        PciConfigRegions::new(&tables).ok()
+    });
+    if let Some(regions) = regions {
+        config::init_ecam(regions);
+    }
+
+    let found = scan();
+    serial_println!("pci: {} functions ({:?})", found.len(), config::mechanism());
+    for device in &found {
+        serial_println!("pci {}", device);
+    }
+    found.len()
+}
+
+/// Все найденные функции.
+pub fn devices() -> Vec<Arc<Device>> {
+    DEVICES.lock().clone()
+}
+
+/// Функция по адресу.
+pub fn get(address: Address) -> Option<Arc<Device>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.address() == address)
+        .cloned()
+}
+
+/// Функции, подходящие под шаблон.
+pub fn find(id: DeviceId) -> Vec<Arc<Device>> {
+    DEVICES
+        .lock()
+        .iter()
+        .filter(|device| id.matches(device))
+        .cloned()
+        .collect()
+}
+
+/// Обходит шины и заполняет реестр.
+fn scan() -> Vec<Arc<Device>> {
+    let mut scanner = Scanner {
+        visited: [false; 256],
+        found: Vec::new(),
+    };
+    let host = Address::new(0, 0, 0, 0);
+    if !present(host) {
+        return Vec::new();
+    }
+
+    let header = (config::read(host, device::HEADER_TYPE & !3) >> 16) as u8;
+    if header & device::HEADER_MULTIFUNCTION != 0 {
+        // Несколько хост-мостов: функция N отвечает за шину N.
+        for function in 0..FUNCTIONS {
+            if present(Address::new(0, 0, 0, function)) {
+                scanner.scan_bus(function);
+            }
+        }
+    } else {
+        scanner.scan_bus(0);
+    }
+
+    let mut found: Vec<_> = scanner.found.into_iter().map(Arc::new).collect();
+    found.sort_by_key(|device| device.address());
+    DEVICES.lock().clone_from(&found);
+    for device in &found {
+        driver::probe(device);
+    }
+    found
+}
+
+fn present(address: Address) -> bool {
+    config::read(address, device::VENDOR_ID) as u16 != 0xFFFF
+}
+
+struct Scanner {
+    /// Пройденные шины (защита от неверно настроенных мостов).
+    visited: [bool; 256],
+    found: Vec<Device>,
+}
+
+impl Scanner {
+    fn scan_bus(&mut self, bus: u8) {
+        if core::mem::replace(&mut self.visited[bus as usize], true) {
+            return;
+        }
+        for device in 0..DEVICES_PER_BUS {
+            let Some(first) = Device::probe(Address::new(0, bus, device, 0)) else {
+                continue;
+            };
+            let functions = match first.is_multifunction() {
+                true => FUNCTIONS,
+                false => 1,
+            };
+            self.add(first);
+            for function in 1..functions {
+                if let Some(device) = Device::probe(Address::new(0, bus, device, function)) {
+                    self.add(device);
+                }
+            }
+        }
+    }
+
+    fn add(&mut self, device: Device) {
+        let secondary = device.bridge_buses().map(|(secondary, _)| secondary);
+        self.found.push(device);
+        // Мост, которому прошивка не назначила шину, пропускается.
+        if let Some(secondary) = secondary.filter(|&bus| bus != 0) {
+            self.scan_bus(secondary);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! Прерывания по сообщениям: MSI и MSI-X.
+//!
+//! Устройство сообщает о прерывании записью `data` по адресу `address` в
+//! окне LAPIC (`0xFEE0_0000`), поэтому прерывание не зависит от
+//! маршрутизации INTx через IO APIC и не делится с другими устройствами.
+//! Вектор выделяется драйверу динамически ([`interrupts::allocate_vectors`]),
+//! адрес задает процессор по APIC ID.
+//!
+//! У MSI все векторы выделяются одним выровненным блоком, одним адресом
+//! (все идут на один процессор), а маскирование по вектору есть не у всех
+//! устройств. У MSI-X у каждого вектора своя запись в таблице (в памяти по
+//! одному из BAR) со своим адресом и битом маски.
+
+use super::Device;
+use super::device::{CAP_MSI, CAP_MSIX, COMMAND_INTX_DISABLE};
+use crate::errno::Errno;
+use crate::interrupts::{self, VectorHandler};
+use alloc::{sync::Arc, vec::Vec};
+use x86_64::VirtAddr;
+
+/// Окно LAPIC для сообщений.
+const MESSAGE_ADDRESS: u32 = 0xFEE0_0000;
+
+/// Биты регистра управления MSI.
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_64BIT: u16 = 1 << 7;
+const MSI_MASKABLE: u16 = 1 << 8;
+
+/// Биты регистра управления MSI-X.
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+/// Размер записи таблицы MSI-X.
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_VECTOR_MASKED: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Msi,
+    MsiX,
+}
+
+enum Mode {
+    Msi {
+        /// Адрес 64-битный: регистры данных и маски сдвинуты на 4.
+        wide: bool,
+        maskable: bool,
+    },
+    MsiX {
+        table: VirtAddr,
+    },
+}
+
+/// Включенные на устройстве MSI или MSI-X и выделенные под них векторы.
+/// При удалении прерывания выключаются, а векторы освобождаются.
+pub struct MsiVectors {
+    device: Arc<Device>,
+    /// Смещение возможности MSI или MSI-X.
+    capability: u16,
+    mode: Mode,
+    vectors: Vec<u8>,
+}
+
+/// Включает на устройстве MSI-X, а если его нет - MSI. `count` - нужное
+/// число векторов; для MSI оно уменьшается до того, что поддерживает
+/// устройство (не меньше одного). Прерывания приходят процессору
+/// `apic_id`.
+pub fn enable(
+    device: &Arc<Device>,
+    count: u16,
+    apic_id: u32,
+    handler: VectorHandler,
+) -> Result<MsiVectors, Errno> {
+    if device.capability(CAP_MSIX).is_some() {
+        return enable_msix(device, count, apic_id, handler);
+    }
+    let offset = device.capability(CAP_MSI).ok_or(Errno::ENODEV)?;
+    let capable = 1 << ((device.read_u16(offset + 2) >> 1) & 0x7).min(5);
+    let count = count.clamp(1, capable);
+    // Наибольшая степень двойки, не превосходящая `count`.
+    enable_msi(device, 1 << count.ilog2(), apic_id, handler)
+}
+
+/// Включает MSI с `count` векторами (степень двойки не больше, чем
+/// поддерживает устройство). `EBUSY`, если MSI уже включен.
+pub fn enable_msi(
+    device: &Arc<Device>,
+    count: u16,
+    apic_id: u32,
+    handler: VectorHandler,
+) -> Result<MsiVectors, Errno> {
+    let capability = device.capability(CAP_MSI).ok_or(Errno::ENODEV)?;
+    let control = device.read_u16(capability + 2);
+    let capable = 1 << ((control >> 1) & 0x7).min(5);
+    if !count.is_power_of_two() || count > capable {
+        return Err(Errno::EINVAL);
+    }
+    if control & MSI_ENABLE != 0 {
+        return Err(Errno::EBUSY);
+    }
+    let address = message_address(apic_id)?;
+
+    let first = interrupts::allocate_vectors(count as u8, handler)?;
+    let wide = control & MSI_64BIT != 0;
+    let msi = MsiVectors {
+        device: device.clone(),
+        capability,
+        mode: Mode::Msi {
+            wide,
+            maskable: control & MSI_MASKABLE != 0,
+        },
+        vectors: (first..first + count as u8).collect(),
+    };
+
+    device.write_u16(capability + 2, control & !MSI_ENABLE);
+    device.write_u32(capability + 4, address);
+    let data = match wide {
+        true => {
+            device.write_u32(capability + 8, 0);
+            capability + 0xC
+        }
+        false => capability + 8,
+    };
+    // Устройство само подставляет номер сообщения в младшие биты.
+    device.write_u16(data, first as u16);
+
+    let enabled = (control & !(0x7 << 4)) | (count.ilog2() as u16) << 4 | MSI_ENABLE;
+    device.write_u16(capability + 2, enabled);
+    device.set_command(device.command() | COMMAND_INTX_DISABLE);
+    Ok(msi)
+}
+
+/// Включает MSI-X с `count` векторами (не больше размера таблицы).
+/// `EBUSY`, если MSI-X уже включен.
+pub fn enable_msix(
+    device: &Arc<Device>,
+    count: u16,
+    apic_id: u32,
+    handler: VectorHandler,
+) -> Result<MsiVectors, Errno> {
+    let capability = device.capability(CAP_MSIX).ok_or(Errno::ENODEV)?;
+    let control = device.read_u16(capability + 2);
+    if count == 0 || count > (control & 0x7FF) + 1 {
+        return Err(Errno::EINVAL);
+    }
+    if control & MSIX_ENABLE != 0 {
+        return Err(Errno::EBUSY);
+    }
+    let address = message_address(apic_id)?;
+
+    // Таблица лежит в BAR с номером BIR по смещению из регистра. BAR
+    // отображается один раз на устройство (его же может использовать
+    // драйвер).
+    let location = device.read_u32(capability + 4);
+    let table = device.map_bar((location & 0x7) as usize)? + (location & !0x7) as u64;
+
+    let mut msix = MsiVectors {
+        device: device.clone(),
+        capability,
+        mode: Mode::MsiX { table },
+        vectors: Vec::new(),
+    };
+    for _ in 0..count {
+        // При ошибке `Drop` освобождает уже выделенные.
+        msix.vectors
+            .push(interrupts::allocate_vector(handler.clone())?);
+    }
+
+    // Пока таблица заполняется, функция замаскирована целиком.
+    device.write_u16(capability + 2, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+    device.set_command(device.command() | COMMAND_INTX_DISABLE);
+    for (index, &vector) in msix.vectors.iter().enumerate() {
+        msix.write_entry(index, 8, vector as u32);
+        msix.write_entry(index, 0, address);
+        msix.write_entry(index, 4, 0);
+        msix.write_entry(index, 12, 0);
+    }
+    device.write_u16(
+        capability + 2,
+        (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK,
+    );
+    Ok(msix)
+}
+
+/// Адрес сообщения для процессора `apic_id` (физическая адресация xAPIC).
+fn message_address(apic_id: u32) -> Result<u32, Errno> {
+    match apic_id {
+        0..=0xFF => Ok(MESSAGE_ADDRESS | apic_id << 12),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+impl MsiVectors {
+    pub fn kind(&self) -> Kind {
+        match self.mode {
+            Mode::Msi { .. } => Kind::Msi,
+            Mode::MsiX { .. } => Kind::MsiX,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.vectors.len()
+    }
+
+    /// Вектор сообщения `index`.
+    pub fn vector(&self, index: usize) -> Option<u8> {
+        self.vectors.get(index).copied()
+    }
+
+    pub fn vectors(&self) -> &[u8] {
+        &self.vectors
+    }
+
+    /// Запрещает сообщение `index`; пришедшее за это время прерывание
+    /// устройство запоминает и отправит после снятия маски. `ENOSYS`, если
+    /// устройство MSI не умеет маскировать векторы.
+    pub fn mask(&self, index: usize) -> Result<(), Errno> {
+        self.set_masked(index, true)
+    }
+
+    pub fn unmask(&self, index: usize) -> Result<(), Errno> {
+        self.set_masked(index, false)
+    }
+
+    pub fn is_masked(&self, index: usize) -> Result<bool, Errno> {
+        self.check(index)?;
+        match self.mode {
+            Mode::Msi {
+                maskable: false, ..
+            } => Err(Errno::ENOSYS),
+            Mode::Msi { wide, .. } => {
+                Ok(self.device.read_u32(self.msi_mask(wide)) & 1 << index != 0)
+            }
+            Mode::MsiX { .. } => Ok(self.read_entry(index, 12) & MSIX_VECTOR_MASKED != 0),
+        }
+    }
+
+    /// Направляет сообщение `index` процессору `apic_id`. У MSI адрес
+    /// общий, поэтому переезжают все векторы.
+    pub fn set_target(&self, index: usize, apic_id: u32) -> Result<(), Errno> {
+        self.check(index)?;
+        let address = message_address(apic_id)?;
+        match self.mode {
+            Mode::Msi { .. } => self.device.write_u32(self.capability + 4, address),
+            Mode::MsiX { .. } => {
+                // Запись меняется только под маской, чтобы устройство не
+                // отправило сообщение по половине адреса.
+                let control = self.read_entry(index, 12);
+                self.write_entry(index, 12, control | MSIX_VECTOR_MASKED);
+                self.write_entry(index, 0, address);
+                self.write_entry(index, 12, control);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_masked(&self, index: usize, masked: bool) -> Result<(), Errno> {
+        self.check(index)?;
+        let (offset, bit, value) = match self.mode {
+            Mode::Msi {
+                maskable: false, ..
+            } => return Err(Errno::ENOSYS),
+            Mode::Msi { wide, .. } => {
+                let offset = self.msi_mask(wide);
+                (offset, 1 << index, self.device.read_u32(offset))
+            }
+            Mode::MsiX { .. } => (12, MSIX_VECTOR_MASKED, self.read_entry(index, 12)),
+        };
+        let value = match masked {
+            true => value | bit,
+            false => value & !bit,
+        };
+        match self.mode {
+            Mode::Msi { .. } => self.device.write_u32(offset, value),
+            Mode::MsiX { .. } => self.write_entry(index, offset, value),
+        }
+        Ok(())
+    }
+
+    fn check(&self, index: usize) -> Result<(), Errno> {
+        match index < self.vectors.len() {
+            true => Ok(()),
+            false => Err(Errno::EINVAL),
+        }
+    }
+
+    /// Смещение регистра маски MSI.
+    fn msi_mask(&self, wide: bool) -> u16 {
+        self.capability + if wide { 0x10 } else { 0xC }
+    }
+
+    fn entry(&self, index: usize, offset: u16) -> *mut u32 {
+        let Mode::MsiX { table } = self.mode else {
+            unreachable!("MSI has no vector table");
+        };
+        (table + index as u64 * MSIX_ENTRY_SIZE + offset as u64).as_mut_ptr()
+    }
+
+    fn read_entry(&self, index: usize, offset: u16) -> u32 {
+        unsafe { self.entry(index, offset).read_volatile() }
+    }
+
+    fn write_entry(&self, index: usize, offset: u16, value: u32) {
+        unsafe { self.entry(index, offset).write_volatile(value) };
+    }
+}
+
+impl Drop for MsiVectors {
+    fn drop(&mut self) {
+        let control = self.device.read_u16(self.capability + 2);
+        let enable = match self.mode {
+            Mode::Msi { .. } => MSI_ENABLE,
+            Mode::MsiX { .. } => MSIX_ENABLE,
+        };
+        self.device
+            .write_u16(self.capability + 2, control & !enable);
+        self.device
+            .set_command(self.device.command() & !COMMAND_INTX_DISABLE);
+
+        match self.mode {
+            Mode::Msi { .. } => {
+                if let Some(&first) = self.vectors.first() {
+                    interrupts::free_vectors(first, self.vectors.len() as u8);
+                }
+            }
+            Mode::MsiX { .. } => {
+                for &vector in &self.vectors {
+                    interrupts::free_vectors(vector, 1);
+                }
+            }
+        }
+    }
+}
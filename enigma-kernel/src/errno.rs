@@ -0,0 +1,86 @@
+//! Данный модуль содержит коды ошибок ядра. Значения совпадают с Linux,
+//! чтобы системные вызовы могли возвращать их без преобразования.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(i32)]
+pub enum Errno {
+    /// Операция не разрешена.
+    EPERM = 1,
+    /// Нет такого файла или каталога.
+    ENOENT = 2,
+    /// Нет такого процесса.
+    ESRCH = 3,
+    /// Вызов прерван.
+    EINTR = 4,
+    /// Ошибка ввода-вывода.
+    EIO = 5,
+    /// Слишком длинный список аргументов.
+    E2BIG = 7,
+    /// Неверный формат исполняемого файла.
+    ENOEXEC = 8,
+    /// Неверный файловый дескриптор.
+    EBADF = 9,
+    /// Нет дочерних процессов.
+    ECHILD = 10,
+    /// Ресурс временно недоступен.
+    EAGAIN = 11,
+    /// Недостаточно памяти.
+    ENOMEM = 12,
+    /// Доступ запрещен.
+    EACCES = 13,
+    /// Неверный адрес.
+    EFAULT = 14,
+    /// Устройство или ресурс заняты.
+    EBUSY = 16,
+    /// Файл уже существует.
+    EEXIST = 17,
+    /// Ссылка между разными файловыми системами.
+    EXDEV = 18,
+    /// Нет такого устройства.
+    ENODEV = 19,
+    /// Не является каталогом.
+    ENOTDIR = 20,
+    /// Является каталогом.
+    EISDIR = 21,
+    /// Неверный аргумент.
+    EINVAL = 22,
+    /// Слишком много открытых файлов в системе.
+    ENFILE = 23,
+    /// Слишком много открытых файлов в процессе.
+    EMFILE = 24,
+    /// Файл слишком большой.
+    EFBIG = 27,
+    /// Нет места на устройстве.
+    ENOSPC = 28,
+    /// Недопустимое позиционирование.
+    ESPIPE = 29,
+    /// Файловая система только для чтения.
+    EROFS = 30,
+    /// Слишком много ссылок.
+    EMLINK = 31,
+    /// Результат вне допустимого диапазона.
+    ERANGE = 34,
+    /// Слишком длинное имя файла.
+    ENAMETOOLONG = 36,
+    /// Системный вызов не реализован.
+    ENOSYS = 38,
+    /// Каталог не пуст.
+    ENOTEMPTY = 39,
+    /// Слишком много уровней символических ссылок.
+    ELOOP = 40,
+}
+
+impl Errno {
+    /// Возвращает значение, которое системный вызов возвращает в `rax`.
+    pub const fn as_syscall_return(self) -> i64 {
+        -(self as i64)
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self, *self as i32)
+    }
+}
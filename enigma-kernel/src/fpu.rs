@@ -0,0 +1,287 @@
+//! Данный модуль содержит логику для работы с FPU/SSE/AVX, а именно их
+//! включение и сохранение/восстановление состояния при переключении контекста.
+//!
+//! Само ядро собирается под `x86_64-unknown-none` (soft-float) и не трогает
+//! векторные регистры, поэтому состояние принадлежит только потокам и процессам.
+//! Поддерживается два режима:
+//! + [`FpuMode::Eager`] - состояние сохраняется и восстанавливается при каждом переключении;
+//! + [`FpuMode::Lazy`] - при переключении выставляется CR0.TS, а сохранение происходит
+//!   только при первом обращении нового контекста к FPU (исключение #NM).
+
+use alloc::alloc::{Layout, alloc_zeroed, dealloc};
+use core::arch::{asm, x86_64::__cpuid_count};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+/// Размер области FXSAVE.
+const FXSAVE_AREA_SIZE: usize = 512;
+/// Выравнивание области XSAVE (для FXSAVE достаточно 16).
+const SAVE_AREA_ALIGN: usize = 64;
+/// Смещение регистра MXCSR в legacy области.
+const MXCSR_OFFSET: usize = 24;
+/// Значение MXCSR после сброса (все исключения замаскированы).
+const MXCSR_DEFAULT: u32 = 0x1F80;
+/// Значение FCW после сброса.
+const FCW_DEFAULT: u16 = 0x037F;
+
+static XSAVE_ENABLED: AtomicBool = AtomicBool::new(false);
+static SAVE_AREA_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_AREA_SIZE);
+static MODE: AtomicU8 = AtomicU8::new(FpuMode::Eager as u8);
+
+/// Состояние текущего (выполняющегося) контекста.
+static CURRENT: AtomicPtr<FpuState> = AtomicPtr::new(ptr::null_mut());
+/// Состояние, которое сейчас находится в регистрах процессора.
+static OWNER: AtomicPtr<FpuState> = AtomicPtr::new(ptr::null_mut());
+
+/// Режим переключения состояния FPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FpuMode {
+    Eager = 0,
+    Lazy = 1,
+}
+
+/// Включает FPU, SSE и (если поддерживается) XSAVE/AVX.
+pub fn init() {
+    let features = unsafe { __cpuid_count(1, 0) };
+    let has_fxsr = features.edx & (1 << 24) != 0;
+    let has_sse = features.edx & (1 << 25) != 0;
+    let has_xsave = features.ecx & (1 << 26) != 0;
+    let has_avx = features.ecx & (1 << 28) != 0;
+
+    assert!(has_fxsr && has_sse, "CPU without FXSR/SSE is not supported");
+
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+
+        Cr4::update(|flags| {
+            flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+            if has_xsave {
+                flags.insert(Cr4Flags::OSXSAVE);
+            }
+        });
+
+        if has_xsave {
+            let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+            if has_avx {
+                xcr0 |= XCr0Flags::AVX;
+            }
+            XCr0::write(xcr0);
+
+            // EBX содержит размер области для компонентов, включенных в XCR0.
+            let size = __cpuid_count(0xD, 0).ebx as usize;
+            SAVE_AREA_SIZE.store(size.max(FXSAVE_AREA_SIZE), Ordering::Relaxed);
+        }
+
+        asm!("fninit", options(nomem, nostack));
+    }
+
+    XSAVE_ENABLED.store(has_xsave, Ordering::Relaxed);
+}
+
+/// Возвращает `true`, если для сохранения состояния используется XSAVE.
+pub fn xsave_enabled() -> bool {
+    XSAVE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Возвращает размер области сохранения состояния в байтах.
+pub fn save_area_size() -> usize {
+    SAVE_AREA_SIZE.load(Ordering::Relaxed)
+}
+
+/// Устанавливает режим переключения состояния.
+///
+/// При переходе в [`FpuMode::Eager`] регистры сразу загружаются состоянием
+/// текущего контекста, так как энергичный режим считает его их владельцем.
+pub fn set_mode(mode: FpuMode) {
+    without_interrupts(|| {
+        MODE.store(mode as u8, Ordering::Relaxed);
+        if mode == FpuMode::Eager {
+            handle_device_not_available();
+        }
+    });
+}
+
+/// Возвращает текущий режим переключения состояния.
+pub fn mode() -> FpuMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => FpuMode::Lazy,
+        _ => FpuMode::Eager,
+    }
+}
+
+/// Область сохранения состояния FPU/SSE/AVX одного контекста.
+pub struct FpuState {
+    area: NonNull<u8>,
+    size: usize,
+}
+
+unsafe impl Send for FpuState {}
+unsafe impl Sync for FpuState {}
+
+impl FpuState {
+    /// Создает состояние, соответствующее только что сброшенному FPU.
+    pub fn new() -> Self {
+        let size = save_area_size();
+        let layout = Self::layout(size);
+        let area = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout));
+
+        unsafe {
+            area.as_ptr().cast::<u16>().write(FCW_DEFAULT);
+            area.as_ptr()
+                .add(MXCSR_OFFSET)
+                .cast::<u32>()
+                .write(MXCSR_DEFAULT);
+        }
+
+        Self { area, size }
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, SAVE_AREA_ALIGN).unwrap()
+    }
+
+    /// Сохраняет регистры процессора в данную область.
+    ///
+    /// ## Safety
+    ///
+    /// FPU должен быть инициализирован функцией [`init`], а CR0.TS сброшен.
+    pub unsafe fn save(&mut self) {
+        let area = self.area.as_ptr();
+
+        unsafe {
+            if xsave_enabled() {
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) area,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags),
+                );
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags));
+            }
+        }
+    }
+
+    /// Загружает регистры процессора из данной области.
+    ///
+    /// ## Safety
+    ///
+    /// FPU должен быть инициализирован функцией [`init`], а CR0.TS сброшен.
+    pub unsafe fn restore(&self) {
+        let area = self.area.as_ptr();
+
+        unsafe {
+            if xsave_enabled() {
+                asm!(
+                    "xrstor64 [{}]",
+                    in(reg) area,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags),
+                );
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags));
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        let this = self as *mut FpuState;
+        let _ = OWNER.compare_exchange(this, ptr::null_mut(), Ordering::AcqRel, Ordering::Relaxed);
+        let _ =
+            CURRENT.compare_exchange(this, ptr::null_mut(), Ordering::AcqRel, Ordering::Relaxed);
+
+        unsafe { dealloc(self.area.as_ptr(), Self::layout(self.size)) };
+    }
+}
+
+/// Переключает состояние FPU на состояние `next` (может быть null для
+/// контекстов без собственного состояния).
+///
+/// ## Safety
+///
+/// Вызывается планировщиком с выключенными прерываниями. `next` должен
+/// оставаться валидным, пока он является текущим состоянием.
+pub unsafe fn switch_to(next: *mut FpuState) {
+    let prev = CURRENT.swap(next, Ordering::AcqRel);
+    if prev == next {
+        return;
+    }
+
+    match mode() {
+        FpuMode::Eager => unsafe {
+            clear_task_switched();
+            if !prev.is_null() {
+                (*prev).save();
+            }
+            if !next.is_null() {
+                (*next).restore();
+            }
+            OWNER.store(next, Ordering::Release);
+        },
+        FpuMode::Lazy => {
+            if OWNER.load(Ordering::Acquire) == next {
+                clear_task_switched();
+            } else {
+                set_task_switched();
+            }
+        }
+    }
+}
+
+/// Обработчик исключения #NM (Device Not Available) для ленивого режима.
+pub(crate) fn handle_device_not_available() {
+    clear_task_switched();
+
+    let current = CURRENT.load(Ordering::Acquire);
+    let owner = OWNER.swap(current, Ordering::AcqRel);
+    if owner == current {
+        return;
+    }
+
+    unsafe {
+        if !owner.is_null() {
+            (*owner).save();
+        }
+        if current.is_null() {
+            asm!("fninit", options(nomem, nostack));
+        } else {
+            (*current).restore();
+        }
+    }
+}
+
+#[inline]
+fn clear_task_switched() {
+    unsafe { asm!("clts", options(nomem, nostack, preserves_flags)) };
+}
+
+#[inline]
+fn set_task_switched() {
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+}
+
+// --- TEST ZONE --- //
+
+#[test_case]
+fn test_sse_enabled() {
+    assert!(Cr4::read().contains(Cr4Flags::OSFXSR));
+    assert!(!Cr0::read().contains(Cr0Flags::EMULATE_COPROCESSOR));
+}
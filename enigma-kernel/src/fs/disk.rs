@@ -0,0 +1,66 @@
+//! Побайтовый доступ к блочному устройству для драйверов файловых систем.
+
+use crate::drivers::block::BlockDevice;
+use crate::errno::Errno;
+use alloc::{sync::Arc, vec};
+
+/// Побайтовый доступ к блочному устройству.
+pub struct Disk {
+    pub device: Arc<dyn BlockDevice>,
+    pub block_size: usize,
+}
+
+impl Disk {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        let block_size = device.block_size();
+        Self { device, block_size }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.device.block_count() * self.block_size as u64
+    }
+
+    pub fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+        let block_size = self.block_size as u64;
+        if offset % block_size == 0 && buffer.len() % self.block_size == 0 {
+            return self.device.read_blocks(offset / block_size, buffer);
+        }
+
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let in_block = (position % block_size) as usize;
+            let len = (self.block_size - in_block).min(buffer.len() - done);
+
+            self.device.read_blocks(position / block_size, &mut block)?;
+            buffer[done..done + len].copy_from_slice(&block[in_block..in_block + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, offset: u64, data: &[u8]) -> Result<(), Errno> {
+        let block_size = self.block_size as u64;
+        if offset % block_size == 0 && data.len() % self.block_size == 0 {
+            return self.device.write_blocks(offset / block_size, data);
+        }
+
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let lba = position / block_size;
+            let in_block = (position % block_size) as usize;
+            let len = (self.block_size - in_block).min(data.len() - done);
+
+            if len < self.block_size {
+                self.device.read_blocks(lba, &mut block)?;
+            }
+            block[in_block..in_block + len].copy_from_slice(&data[done..done + len]);
+            self.device.write_blocks(lba, &block)?;
+            done += len;
+        }
+        Ok(())
+    }
+}
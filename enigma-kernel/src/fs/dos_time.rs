@@ -0,0 +1,67 @@
+//! Дата и время в формате DOS (FAT и exFAT).
+
+use super::vfs::Timestamp;
+
+/// Секунды от эпохи Unix до 1980-01-01, начала времени DOS.
+const DOS_EPOCH: i64 = 315_532_800;
+
+/// Дата, время (с точностью до 2 секунд) и десятки миллисекунд в формате
+/// DOS. Время вне 1980..2107 годов приводится к ближайшей границе.
+pub(super) fn to_dos(timestamp: Timestamp) -> (u16, u16, u8) {
+    let last = days_from_civil(2107, 12, 31) * 86400 + 86399;
+    let secs = timestamp.secs.clamp(DOS_EPOCH, last);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let seconds = secs.rem_euclid(86400);
+
+    let date = ((year - 1980) << 9 | month << 5 | day) as u16;
+    let time = ((seconds / 3600) << 11 | (seconds / 60 % 60) << 5 | ((seconds % 60) / 2)) as u16;
+    let hundredths = (seconds % 2) * 100 + (timestamp.nanos / 10_000_000) as i64;
+    (date, time, hundredths as u8)
+}
+
+pub(super) fn from_dos(date: u16, time: u16, hundredths: u8) -> Timestamp {
+    if date == 0 {
+        return Timestamp::new(DOS_EPOCH, 0);
+    }
+
+    let year = (date >> 9) as i64 + 1980;
+    let month = ((date >> 5) & 0xF).clamp(1, 12) as i64;
+    let day = (date & 0x1F).max(1) as i64;
+    let seconds =
+        (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3F) as i64 * 60 + (time & 0x1F) as i64 * 2;
+    let hundredths = hundredths.min(199) as i64;
+
+    Timestamp::new(
+        days_from_civil(year, month, day) * 86400 + seconds + hundredths / 100,
+        (hundredths % 100) as u32 * 10_000_000,
+    )
+}
+
+/// Номер дня от 1970-01-01 для даты григорианского календаря.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Дата по номеру дня от 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,132 @@
+//! Загрузочная область и геометрия тома exFAT.
+
+use crate::errno::Errno;
+
+/// Секторов в загрузочной области: загрузочный сектор, 8 расширенных,
+/// параметры OEM, резерв и сектор контрольной суммы.
+pub(super) const BOOT_REGION_SECTORS: usize = 12;
+
+/// Смещения полей, которые меняются при работе и поэтому не входят в
+/// контрольную сумму загрузочной области.
+pub(super) const VOLUME_FLAGS: usize = 106;
+pub(super) const PERCENT_IN_USE: usize = 112;
+
+/// Том изменяется: флаг в `VolumeFlags`.
+pub(super) const VOLUME_DIRTY: u16 = 0x0002;
+
+/// Разобранный загрузочный сектор. Все смещения - в байтах от начала тома.
+#[derive(Debug, Clone)]
+pub(super) struct Geometry {
+    pub cluster_size: u32,
+    pub fat_offset: u64,
+    pub heap_offset: u64,
+    /// Количество кластеров данных (номера `2..cluster_count + 2`).
+    pub cluster_count: u32,
+    pub root_cluster: u32,
+    /// `VolumeFlags` на момент монтирования.
+    pub flags: u16,
+}
+
+impl Geometry {
+    /// Проверяет основную загрузочную область (`region` - ее секторы
+    /// подряд) тома размером `volume_size`.
+    pub fn parse(region: &[u8], volume_size: u64) -> Result<Self, Errno> {
+        let u16_at = |offset: usize| u16::from_le_bytes([region[offset], region[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(region[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(region[offset..offset + 8].try_into().unwrap());
+
+        if region.len() < 512
+            || region[..3] != [0xEB, 0x76, 0x90]
+            || &region[3..11] != b"EXFAT   "
+            || region[11..64].iter().any(|&byte| byte != 0)
+            || region[510..512] != [0x55, 0xAA]
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let sector_shift = region[108] as u32;
+        let cluster_shift = region[109] as u32;
+        // Поддерживается только ревизия 1.x и одна FAT: второй FAT и
+        // второй битовой картой пользуется лишь TexFAT.
+        if !(9..=12).contains(&sector_shift)
+            || sector_shift + cluster_shift > 25
+            || region[110] != 1
+            || region[105] != 1
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let sector_size = 1 << sector_shift;
+        let cluster_size = sector_size << cluster_shift;
+        let region_size = BOOT_REGION_SECTORS * sector_size as usize;
+        if region.len() < region_size || !boot_checksum(&region[..region_size]) {
+            return Err(Errno::EINVAL);
+        }
+
+        let volume_length = u64_at(72);
+        let fat_offset = u32_at(80) as u64;
+        let fat_length = u32_at(84) as u64;
+        let heap_offset = u32_at(88) as u64;
+        let cluster_count = u32_at(92);
+        let root_cluster = u32_at(96);
+        let sectors_per_cluster = 1u64 << cluster_shift;
+
+        if fat_offset < 2 * BOOT_REGION_SECTORS as u64
+            || fat_length * sector_size as u64 / 4 < cluster_count as u64 + 2
+            || heap_offset < fat_offset + fat_length
+            || cluster_count > 0xFFFF_FFF5
+            || heap_offset + cluster_count as u64 * sectors_per_cluster > volume_length
+            || volume_length
+                .checked_mul(sector_size as u64)
+                .is_none_or(|length| length > volume_size)
+            || !(2..cluster_count + 2).contains(&root_cluster)
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let sector_offset = |sector: u64| sector * sector_size as u64;
+        Ok(Self {
+            cluster_size,
+            fat_offset: sector_offset(fat_offset),
+            heap_offset: sector_offset(heap_offset),
+            cluster_count,
+            root_cluster,
+            flags: u16_at(VOLUME_FLAGS),
+        })
+    }
+
+    /// Смещение начала кластера данных.
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.heap_offset + (cluster - 2) as u64 * self.cluster_size as u64
+    }
+
+    /// Номер последнего кластера данных.
+    pub fn last_cluster(&self) -> u32 {
+        self.cluster_count + 1
+    }
+
+    /// Лежит ли `cluster` в куче кластеров.
+    pub fn contains(&self, cluster: u32) -> bool {
+        (2..=self.last_cluster()).contains(&cluster)
+    }
+}
+
+/// Сверяет контрольную сумму первых 11 секторов области с последним,
+/// который заполнен ее копиями.
+fn boot_checksum(region: &[u8]) -> bool {
+    let sector_size = region.len() / BOOT_REGION_SECTORS;
+    let (sectors, checksums) = region.split_at(sector_size * (BOOT_REGION_SECTORS - 1));
+
+    let checksum = sectors
+        .iter()
+        .enumerate()
+        .filter(|&(offset, _)| !matches!(offset, 106 | 107 | PERCENT_IN_USE))
+        .fold(0u32, |sum, (_, &byte)| {
+            sum.rotate_right(1).wrapping_add(byte as u32)
+        });
+    checksums
+        .chunks_exact(4)
+        .all(|chunk| chunk == checksum.to_le_bytes())
+}
@@ -0,0 +1,377 @@
+//! Наборы записей каталога: запись файла, запись потока и записи имени.
+
+use super::Volume;
+use super::table::{Clusters, Stream};
+use super::upcase::UpcaseTable;
+use crate::errno::Errno;
+use crate::fs::dos_time::{from_dos, to_dos};
+use crate::fs::vfs::Timestamp;
+use alloc::{string::String, vec, vec::Vec};
+
+pub(super) const ENTRY_SIZE: usize = 32;
+
+/// Бит `InUse` в типе записи: удаленная запись отличается только им.
+const IN_USE: u8 = 0x80;
+/// Конец каталога: дальше записей нет.
+const END: u8 = 0x00;
+
+pub(super) const TYPE_BITMAP: u8 = 0x81;
+pub(super) const TYPE_UPCASE: u8 = 0x82;
+pub(super) const TYPE_LABEL: u8 = 0x83;
+const TYPE_FILE: u8 = 0x85;
+const TYPE_STREAM: u8 = 0xC0;
+const TYPE_NAME: u8 = 0xC1;
+
+pub(super) const ATTR_READ_ONLY: u16 = 0x01;
+pub(super) const ATTR_DIRECTORY: u16 = 0x10;
+pub(super) const ATTR_ARCHIVE: u16 = 0x20;
+
+/// Флаги записи потока (`GeneralSecondaryFlags`).
+const ALLOCATION_POSSIBLE: u8 = 0x01;
+const NO_FAT_CHAIN: u8 = 0x02;
+
+/// Символов UTF-16 в одной записи имени.
+const NAME_CHARS: usize = 15;
+const NAME_MAX: usize = 255;
+/// Каталог не может быть больше 256 МиБ.
+pub(super) const DIR_MAX_SIZE: u64 = 256 << 20;
+
+/// Файл или каталог: содержимое набора записей.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct FileSet {
+    /// Имя в UTF-16, как оно записано на диске.
+    pub name: Vec<u16>,
+    pub attributes: u16,
+    pub created: Timestamp,
+    pub modified: Timestamp,
+    pub accessed: Timestamp,
+    pub stream: Stream,
+    /// Необязательные вторичные записи (расширения производителей). Их
+    /// смысл драйвер не знает, но сохраняет их при перезаписи набора.
+    pub extra: Vec<[u8; ENTRY_SIZE]>,
+}
+
+impl FileSet {
+    pub fn new(name: Vec<u16>, attributes: u16) -> Self {
+        let now = Timestamp::now();
+        Self {
+            name,
+            attributes,
+            created: now,
+            modified: now,
+            accessed: now,
+            stream: Stream::default(),
+            extra: Vec::new(),
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn name(&self) -> String {
+        decode(&self.name)
+    }
+
+    /// Количество записей набора.
+    pub fn entry_count(&self) -> usize {
+        2 + self.name.len().div_ceil(NAME_CHARS) + self.extra.len()
+    }
+
+    /// Записи набора с контрольной суммой.
+    pub fn encode(&self, upcase: &UpcaseTable) -> Vec<[u8; ENTRY_SIZE]> {
+        let mut file = [0; ENTRY_SIZE];
+        file[0] = TYPE_FILE;
+        file[1] = (self.entry_count() - 1) as u8;
+        file[4..6].copy_from_slice(&self.attributes.to_le_bytes());
+        let (created, created_10ms) = encode_time(self.created);
+        let (modified, modified_10ms) = encode_time(self.modified);
+        let (accessed, _) = encode_time(self.accessed);
+        file[8..12].copy_from_slice(&created.to_le_bytes());
+        file[12..16].copy_from_slice(&modified.to_le_bytes());
+        file[16..20].copy_from_slice(&accessed.to_le_bytes());
+        file[20] = created_10ms;
+        file[21] = modified_10ms;
+        // Время хранится в UTC: смещение 0 с битом "смещение задано".
+        file[22..25].fill(0x80);
+
+        let stream = &self.stream;
+        let mut entry = [0; ENTRY_SIZE];
+        entry[0] = TYPE_STREAM;
+        entry[1] = ALLOCATION_POSSIBLE | if stream.contiguous { NO_FAT_CHAIN } else { 0 };
+        entry[3] = self.name.len() as u8;
+        entry[4..6].copy_from_slice(&upcase.name_hash(&self.name).to_le_bytes());
+        entry[8..16].copy_from_slice(&stream.valid.to_le_bytes());
+        entry[20..24].copy_from_slice(&stream.first.to_le_bytes());
+        entry[24..32].copy_from_slice(&stream.length.to_le_bytes());
+
+        let mut entries = vec![file, entry];
+        for chunk in self.name.chunks(NAME_CHARS) {
+            let mut entry = [0; ENTRY_SIZE];
+            entry[0] = TYPE_NAME;
+            for (index, unit) in chunk.iter().enumerate() {
+                entry[2 + index * 2..4 + index * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entries.push(entry);
+        }
+        entries.extend_from_slice(&self.extra);
+
+        let checksum = set_checksum(&entries);
+        entries[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+        entries
+    }
+
+    /// Разбирает набор записей. `None`, если набор поврежден: его записи
+    /// не трогаются, но и файлом не считаются.
+    fn parse(entries: &[&[u8]]) -> Option<Self> {
+        let file = entries[0];
+        let stream = entries.get(1)?;
+        let u32_at = |bytes: &[u8], offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+        let u64_at = |bytes: &[u8], offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+        };
+
+        let checksum = u16::from_le_bytes([file[2], file[3]]);
+        if stream[0] != TYPE_STREAM || set_checksum(entries) != checksum {
+            return None;
+        }
+
+        let name_len = stream[3] as usize;
+        let name_entries = name_len.div_ceil(NAME_CHARS);
+        if name_len == 0 || entries.len() < 2 + name_entries {
+            return None;
+        }
+        let mut name = Vec::with_capacity(name_len);
+        for entry in &entries[2..2 + name_entries] {
+            if entry[0] != TYPE_NAME {
+                return None;
+            }
+            name.extend(
+                entry[2..]
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]])),
+            );
+        }
+        name.truncate(name_len);
+
+        let mut extra = Vec::new();
+        for entry in &entries[2 + name_entries..] {
+            // Неизвестная критическая вторичная запись: набор непонятен.
+            if entry[0] & 0x20 == 0 {
+                return None;
+            }
+            extra.push(entry[..ENTRY_SIZE].try_into().unwrap());
+        }
+
+        let stream = Stream {
+            first: u32_at(stream, 20),
+            contiguous: stream[1] & NO_FAT_CHAIN != 0,
+            valid: u64_at(stream, 8),
+            length: u64_at(stream, 24),
+        };
+        if stream.valid > stream.length || (stream.first == 0) != (stream.length == 0) {
+            return None;
+        }
+
+        Some(Self {
+            name,
+            attributes: u16::from_le_bytes([file[4], file[5]]),
+            created: decode_time(u32_at(file, 8), file[20], file[22]),
+            modified: decode_time(u32_at(file, 12), file[21], file[23]),
+            accessed: decode_time(u32_at(file, 16), 0, file[24]),
+            stream,
+            extra,
+        })
+    }
+}
+
+/// Набор записей каталога.
+#[derive(Debug, Clone)]
+pub(super) struct Found {
+    pub set: FileSet,
+    /// Смещение записи файла (первой в наборе).
+    pub offset: u64,
+    /// Смещения всех записей набора.
+    pub slots: Vec<u64>,
+}
+
+/// Содержимое каталога.
+pub(super) struct Listing {
+    /// Смещения всех записей каталога по порядку.
+    pub slots: Vec<u64>,
+    /// Для каждой записи - свободна ли она.
+    pub free: Vec<bool>,
+    pub entries: Vec<Found>,
+    /// Служебные записи корня (битовая карта, таблица регистра, метка).
+    pub special: Vec<[u8; ENTRY_SIZE]>,
+}
+
+impl Listing {
+    pub fn find(&self, upcase: &UpcaseTable, name: &[u16]) -> Option<&Found> {
+        self.entries
+            .iter()
+            .find(|found| upcase.equal(&found.set.name, name))
+    }
+
+    /// Индекс первой из `count` подряд идущих свободных записей.
+    pub fn free_run(&self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        for (index, &free) in self.free.iter().enumerate() {
+            run = if free { run + 1 } else { 0 };
+            if run == count {
+                return Some(index + 1 - count);
+            }
+        }
+        None
+    }
+}
+
+impl Volume {
+    /// Читает каталог из кластеров `clusters` и разбирает его записи.
+    pub(super) fn list(&self, clusters: &Clusters) -> Result<Listing, Errno> {
+        let cluster_size = self.geometry.cluster_size as usize;
+        let mut slots = Vec::new();
+        let mut data = vec![0; clusters.len() * cluster_size];
+        for (index, cluster) in clusters.iter().enumerate() {
+            let offset = self.geometry.cluster_offset(cluster);
+            self.disk
+                .read(offset, &mut data[index * cluster_size..][..cluster_size])?;
+            slots.extend(
+                (0..(cluster_size / ENTRY_SIZE) as u64)
+                    .map(|index| offset + index * ENTRY_SIZE as u64),
+            );
+        }
+
+        let entries: Vec<&[u8]> = data.chunks_exact(ENTRY_SIZE).collect();
+        let mut listing = Listing {
+            free: vec![false; slots.len()],
+            slots,
+            entries: Vec::new(),
+            special: Vec::new(),
+        };
+
+        let mut index = 0;
+        let mut ended = false;
+        while index < entries.len() {
+            let kind = entries[index][0];
+            // После записи конца каталога все записи считаются свободными.
+            ended |= kind == END;
+            if ended || kind & IN_USE == 0 {
+                listing.free[index] = true;
+                index += 1;
+                continue;
+            }
+
+            if kind != TYPE_FILE {
+                listing.special.push(entries[index].try_into().unwrap());
+                index += 1;
+                continue;
+            }
+
+            let count = 1 + entries[index][1] as usize;
+            let end = (index + count).min(entries.len());
+            let set = &entries[index..end];
+            // Набор должен быть целым и состоять из вторичных записей.
+            let whole = set.len() == count && set[1..].iter().all(|entry| entry[0] >= TYPE_STREAM);
+            if let Some(set) = whole.then(|| FileSet::parse(set)).flatten() {
+                listing.entries.push(Found {
+                    set,
+                    offset: listing.slots[index],
+                    slots: listing.slots[index..end].to_vec(),
+                });
+            }
+            index = end;
+        }
+        Ok(listing)
+    }
+
+    /// Записывает набор `set` в записи `slots`.
+    pub(super) fn write_set(&self, slots: &[u64], set: &FileSet) -> Result<(), Errno> {
+        let entries = set.encode(&self.upcase);
+        debug_assert_eq!(entries.len(), slots.len());
+        // Соседние записи одного кластера пишутся за раз.
+        let mut index = 0;
+        while index < entries.len() {
+            let mut end = index + 1;
+            while end < entries.len() && slots[end] == slots[end - 1] + ENTRY_SIZE as u64 {
+                end += 1;
+            }
+            self.disk
+                .write(slots[index], entries[index..end].as_flattened())?;
+            index = end;
+        }
+        Ok(())
+    }
+
+    /// Помечает записи `slots` удаленными.
+    pub(super) fn remove_set(&self, slots: &[u64]) -> Result<(), Errno> {
+        for &slot in slots {
+            let mut kind = [0];
+            self.disk.read(slot, &mut kind)?;
+            self.disk.write(slot, &[kind[0] & !IN_USE])?;
+        }
+        Ok(())
+    }
+}
+
+/// Проверяет имя и возвращает его в UTF-16.
+pub(super) fn check_name(name: &str) -> Result<Vec<u16>, Errno> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name.chars().any(invalid) {
+        return Err(Errno::EINVAL);
+    }
+
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.len() > NAME_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    Ok(units)
+}
+
+pub(super) fn decode(units: &[u16]) -> String {
+    char::decode_utf16(units.iter().copied())
+        .map(|unit| unit.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Метка тома из записи метки.
+pub(super) fn label(entry: &[u8; ENTRY_SIZE]) -> String {
+    let len = (entry[1] as usize).min(11);
+    let units: Vec<u16> = entry[2..2 + len * 2]
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    decode(&units)
+}
+
+/// Контрольная сумма набора (`SetChecksum`): по всем байтам, кроме
+/// самого поля суммы.
+fn set_checksum<T: AsRef<[u8]>>(entries: &[T]) -> u16 {
+    entries
+        .iter()
+        .flat_map(|entry| entry.as_ref()[..ENTRY_SIZE].iter())
+        .enumerate()
+        .filter(|&(offset, _)| !matches!(offset, 2 | 3))
+        .fold(0u16, |sum, (_, &byte)| {
+            sum.rotate_right(1).wrapping_add(byte as u16)
+        })
+}
+
+/// Метка времени exFAT (дата и время DOS) и десятки миллисекунд.
+fn encode_time(timestamp: Timestamp) -> (u32, u8) {
+    let (date, time, hundredths) = to_dos(timestamp);
+    ((date as u32) << 16 | time as u32, hundredths)
+}
+
+/// Время из метки, десятков миллисекунд и смещения от UTC (в 15-минутных
+/// интервалах, если задан бит 7).
+fn decode_time(raw: u32, hundredths: u8, utc_offset: u8) -> Timestamp {
+    let mut timestamp = from_dos((raw >> 16) as u16, raw as u16, hundredths);
+    if utc_offset & 0x80 != 0 {
+        let quarters = ((utc_offset << 1) as i8 >> 1) as i64;
+        timestamp.secs -= quarters * 15 * 60;
+    }
+    timestamp
+}
@@ -0,0 +1,679 @@
+//! Файлы и каталоги тома exFAT.
+
+use super::dir::{
+    ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, DIR_MAX_SIZE, ENTRY_SIZE, FileSet, Found,
+    Listing, check_name,
+};
+use super::table::{Clusters, Stream};
+use super::{State, Volume};
+use crate::errno::Errno;
+use crate::fs::vfs::{DirEntry, FileType, Inode, Metadata, Timestamp};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::Ordering;
+use spin::{Mutex, MutexGuard};
+
+/// Номера удаленных, но открытых файлов (не пересекаются с номерами по
+/// смещению записи).
+pub(super) const FIRST_ORPHAN_INO: u64 = 1 << 63;
+const ROOT_INO: u64 = 1;
+/// После скольких загруженных inode кэш чистится от освобожденных.
+const CACHE_PRUNE: usize = 256;
+
+pub(super) struct ExFatInode {
+    volume: Arc<Volume>,
+    this: Weak<ExFatInode>,
+    kind: FileType,
+    is_root: bool,
+    node: Mutex<Node>,
+}
+
+struct Node {
+    ino: u64,
+    /// Смещения записей набора (пусто у корня и удаленных файлов).
+    slots: Vec<u64>,
+    parent: Option<Arc<ExFatInode>>,
+    /// Копия набора записей. Изменения сразу пишутся на диск.
+    set: FileSet,
+}
+
+/// Номер inode файла, чья запись файла лежит по смещению `offset`.
+fn ino_of(offset: u64) -> u64 {
+    offset / ENTRY_SIZE as u64 + 2
+}
+
+impl ExFatInode {
+    /// Корневой каталог: набора записей у него нет, а кластеры описаны
+    /// цепочкой `stream`.
+    pub(super) fn root(volume: &Arc<Volume>, stream: Stream) -> Arc<Self> {
+        let mut set = FileSet::new(Vec::new(), ATTR_DIRECTORY);
+        set.created = Timestamp::default();
+        set.modified = Timestamp::default();
+        set.accessed = Timestamp::default();
+        set.stream = stream;
+
+        Self::new(volume, true, ROOT_INO, Vec::new(), None, set)
+    }
+
+    fn new(
+        volume: &Arc<Volume>,
+        is_root: bool,
+        ino: u64,
+        slots: Vec<u64>,
+        parent: Option<Arc<ExFatInode>>,
+        set: FileSet,
+    ) -> Arc<Self> {
+        let kind = match set.is_directory() {
+            true => FileType::Directory,
+            false => FileType::Regular,
+        };
+
+        Arc::new_cyclic(|this| Self {
+            volume: volume.clone(),
+            this: this.clone(),
+            kind,
+            is_root,
+            node: Mutex::new(Node {
+                ino,
+                slots,
+                parent,
+                set,
+            }),
+        })
+    }
+
+    fn this(&self) -> Arc<ExFatInode> {
+        self.this.upgrade().unwrap()
+    }
+
+    /// Inode набора `found` этого каталога (из кэша или новый).
+    fn load(&self, found: &Found) -> Arc<ExFatInode> {
+        let volume = &self.volume;
+        if let Some(inode) = volume
+            .inodes
+            .lock()
+            .get(&found.offset)
+            .and_then(Weak::upgrade)
+        {
+            return inode;
+        }
+
+        let inode = Self::new(
+            volume,
+            false,
+            ino_of(found.offset),
+            found.slots.clone(),
+            Some(self.this()),
+            found.set.clone(),
+        );
+        let mut inodes = volume.inodes.lock();
+        if inodes.len() >= CACHE_PRUNE {
+            inodes.retain(|_, inode| inode.strong_count() > 0);
+        }
+        inodes.insert(found.offset, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Блокирует том и освобождает кластеры закрытых удаленных файлов.
+    fn lock_volume(&self) -> Result<MutexGuard<'_, State>, Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        Ok(state)
+    }
+
+    /// Кластеры каталога.
+    fn dir_clusters(&self, node: &Node) -> Result<Clusters, Errno> {
+        if self.kind != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        self.volume.clusters(&node.set.stream)
+    }
+
+    /// Каталог для добавления или удаления записей.
+    fn live_dir(&self) -> Result<Listing, Errno> {
+        let node = self.node.lock();
+        if node.slots.is_empty() && !self.is_root {
+            return Err(Errno::ENOENT);
+        }
+        self.volume.list(&self.dir_clusters(&node)?)
+    }
+
+    /// Переписывает набор записей на диске.
+    fn store(&self, node: &Node) -> Result<(), Errno> {
+        match node.slots.is_empty() {
+            true => Ok(()),
+            false => self.volume.write_set(&node.slots, &node.set),
+        }
+    }
+
+    /// Отмечает изменение содержимого каталога.
+    fn touch(&self) -> Result<(), Errno> {
+        let mut node = self.node.lock();
+        node.set.modified = Timestamp::now();
+        self.store(&node)
+    }
+
+    /// Добавляет в каталог кластер и его записи в `listing`.
+    fn grow_dir(&self, state: &mut State, listing: &mut Listing) -> Result<(), Errno> {
+        let volume = &self.volume;
+        let cluster_size = volume.geometry.cluster_size as u64;
+        let mut node = self.node.lock();
+        let stream = &mut node.set.stream;
+        if stream.length + cluster_size > DIR_MAX_SIZE {
+            return Err(Errno::ENOSPC);
+        }
+
+        let have = volume.cluster_count(stream);
+        if volume.resize_stream(state, stream, have + 1)? == have {
+            return Err(Errno::ENOSPC);
+        }
+        stream.length += cluster_size;
+        stream.valid = stream.length;
+        let cluster = volume.clusters(stream)?.last().ok_or(Errno::EIO)?;
+        volume.zero_cluster(cluster)?;
+        self.store(&node)?;
+
+        let offset = volume.geometry.cluster_offset(cluster);
+        let count = cluster_size as usize / ENTRY_SIZE;
+        listing
+            .slots
+            .extend((0..count as u64).map(|index| offset + index * ENTRY_SIZE as u64));
+        listing.free.extend(core::iter::repeat_n(true, count));
+        Ok(())
+    }
+
+    /// Записывает набор `set` в свободные записи каталога, расширяя его при
+    /// необходимости. Возвращает смещения записей набора.
+    fn add_set(
+        &self,
+        state: &mut State,
+        mut listing: Listing,
+        set: &FileSet,
+    ) -> Result<Vec<u64>, Errno> {
+        let count = set.entry_count();
+        let start = loop {
+            if let Some(start) = listing.free_run(count) {
+                break start;
+            }
+            self.grow_dir(state, &mut listing)?;
+        };
+
+        let slots = listing.slots[start..start + count].to_vec();
+        self.volume.write_set(&slots, set)?;
+        Ok(slots)
+    }
+
+    /// Освобождает данные удаленного набора. Если файл открыт, он становится
+    /// сиротой, и кластеры освободятся после его закрытия.
+    fn release(&self, state: &mut State, found: &Found) -> Result<(), Errno> {
+        let volume = &self.volume;
+        let inode = volume
+            .inodes
+            .lock()
+            .remove(&found.offset)
+            .and_then(|inode| inode.upgrade());
+
+        match inode {
+            Some(inode) => {
+                let mut node = inode.node.lock();
+                node.slots.clear();
+                node.ino = volume.next_orphan.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            None => volume.free_stream(state, &found.set.stream),
+        }
+    }
+
+    /// Пишет `data` в кластеры `clusters` начиная с позиции `offset`.
+    fn write_span(&self, clusters: &Clusters, offset: u64, data: &[u8]) -> Result<(), Errno> {
+        let cluster_size = self.volume.geometry.cluster_size as u64;
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let in_cluster = position % cluster_size;
+            let len = ((cluster_size - in_cluster) as usize).min(data.len() - done);
+            let cluster = clusters
+                .get((position / cluster_size) as usize)
+                .ok_or(Errno::EIO)?;
+
+            let target = self.volume.geometry.cluster_offset(cluster) + in_cluster;
+            self.volume.disk.write(target, &data[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Пишет данные файла, выделяя кластеры. Если место кончилось, пишет
+    /// сколько поместилось; ошибка - только если не поместилось ничего.
+    fn write_data(
+        &self,
+        state: &mut State,
+        node: &mut Node,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, Errno> {
+        let volume = &self.volume;
+        let cluster_size = volume.geometry.cluster_size as u64;
+        let stream = &mut node.set.stream;
+        let end = offset + data.len() as u64;
+
+        let have = volume.cluster_count(stream);
+        let mut len = data.len();
+        if end > have as u64 * cluster_size {
+            let want = u32::try_from(end.div_ceil(cluster_size)).map_err(|_| Errno::EFBIG)?;
+            let got = volume.resize_stream(state, stream, want)?;
+            len = (got as u64 * cluster_size)
+                .saturating_sub(offset)
+                .min(len as u64) as usize;
+            if len == 0 && !data.is_empty() {
+                volume.resize_stream(state, stream, have)?;
+                return Err(Errno::ENOSPC);
+            }
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let end = offset + len as u64;
+        stream.length = stream.length.max(end);
+        let clusters = volume.clusters(stream)?;
+        // После записанной части файла на диске может лежать что угодно.
+        let zeroes = [0; 4096];
+        let mut position = stream.valid;
+        while position < offset {
+            let chunk = (offset - position).min(zeroes.len() as u64) as usize;
+            self.write_span(&clusters, position, &zeroes[..chunk])?;
+            position += chunk as u64;
+        }
+        self.write_span(&clusters, offset, &data[..len])?;
+        stream.valid = stream.valid.max(end);
+
+        node.set.modified = Timestamp::now();
+        node.set.attributes |= ATTR_ARCHIVE;
+        self.store(node)?;
+        Ok(len)
+    }
+
+    /// Проверяет, что каталог `found` пуст.
+    fn check_empty(&self, found: &Found) -> Result<(), Errno> {
+        let clusters = self.volume.clusters(&found.set.stream)?;
+        match self.volume.list(&clusters)?.entries.is_empty() {
+            true => Ok(()),
+            false => Err(Errno::ENOTEMPTY),
+        }
+    }
+}
+
+impl Drop for ExFatInode {
+    fn drop(&mut self) {
+        let node = self.node.lock();
+        if node.slots.is_empty() && !self.is_root && node.set.stream.first != 0 {
+            self.volume.orphans.lock().push(node.set.stream);
+        }
+    }
+}
+
+impl Inode for ExFatInode {
+    fn ino(&self) -> u64 {
+        self.node.lock().ino
+    }
+
+    fn metadata(&self) -> Result<Metadata, Errno> {
+        let node = self.node.lock();
+        let set = &node.set;
+        let mut mode = match self.kind {
+            FileType::Directory => 0o755,
+            _ => 0o644,
+        };
+        if set.attributes & ATTR_READ_ONLY != 0 {
+            mode &= !0o222;
+        }
+
+        Ok(Metadata {
+            ino: node.ino,
+            kind: self.kind,
+            mode,
+            // Подкаталоги не считаются: 1 у каталога означает "неизвестно".
+            nlink: match !node.slots.is_empty() || self.is_root {
+                true => 1,
+                false => 0,
+            },
+            uid: 0,
+            gid: 0,
+            size: set.stream.length,
+            atime: set.accessed,
+            mtime: set.modified,
+            ctime: set.created,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+        if self.kind == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+
+        // Время доступа не обновляется: иначе каждое чтение было бы записью.
+        let _state = self.volume.state.lock();
+        let node = self.node.lock();
+        let stream = node.set.stream;
+        let len = stream
+            .length
+            .saturating_sub(offset)
+            .min(buffer.len() as u64) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        // За `ValidDataLength` данных на диске нет: там читаются нули.
+        let on_disk = stream.valid.saturating_sub(offset).min(len as u64) as usize;
+        buffer[on_disk..len].fill(0);
+
+        let geometry = &self.volume.geometry;
+        let cluster_size = geometry.cluster_size as u64;
+        let clusters = self.volume.clusters(&stream)?;
+        let mut done = 0;
+        while done < on_disk {
+            let position = offset + done as u64;
+            let in_cluster = position % cluster_size;
+            let chunk = ((cluster_size - in_cluster) as usize).min(on_disk - done);
+            let cluster = clusters
+                .get((position / cluster_size) as usize)
+                .ok_or(Errno::EIO)?;
+
+            let source = geometry.cluster_offset(cluster) + in_cluster;
+            self.volume
+                .disk
+                .read(source, &mut buffer[done..done + chunk])?;
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, Errno> {
+        if self.kind == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+        if offset.checked_add(buffer.len() as u64).is_none() {
+            return Err(Errno::EFBIG);
+        }
+
+        let mut state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        let written = self.write_data(&mut state, &mut node, offset, buffer);
+        drop(node);
+        self.volume.commit(&mut state)?;
+        written
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), Errno> {
+        if self.kind == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+        let cluster_size = self.volume.geometry.cluster_size as u64;
+        let want = u32::try_from(size.div_ceil(cluster_size)).map_err(|_| Errno::EFBIG)?;
+
+        let mut state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        let stream = &mut node.set.stream;
+        let have = self.volume.cluster_count(stream);
+
+        // Новые кластеры не обнуляются: `ValidDataLength` остается прежней,
+        // и за ней читаются нули.
+        if self.volume.resize_stream(&mut state, stream, want)? < want {
+            self.volume.resize_stream(&mut state, stream, have)?;
+            drop(node);
+            self.volume.commit(&mut state)?;
+            return Err(Errno::ENOSPC);
+        }
+        stream.length = size;
+        stream.valid = stream.valid.min(size);
+
+        node.set.modified = Timestamp::now();
+        node.set.attributes |= ATTR_ARCHIVE;
+        self.store(&node)?;
+        drop(node);
+        self.volume.commit(&mut state)
+    }
+
+    fn set_times(&self, atime: Option<Timestamp>, mtime: Option<Timestamp>) -> Result<(), Errno> {
+        let _state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        if let Some(atime) = atime {
+            node.set.accessed = atime;
+        }
+        if let Some(mtime) = mtime {
+            node.set.modified = mtime;
+        }
+        self.store(&node)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match name {
+            "." => return Ok(self.this()),
+            ".." => {
+                let parent = self.node.lock().parent.clone();
+                return Ok(parent.unwrap_or_else(|| self.this()));
+            }
+            _ => {}
+        }
+
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let _state = self.volume.state.lock();
+        let clusters = self.dir_clusters(&self.node.lock())?;
+        let listing = self.volume.list(&clusters)?;
+        let found = listing
+            .find(&self.volume.upcase, &name)
+            .ok_or(Errno::ENOENT)?;
+        Ok(self.load(found))
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> Result<Arc<dyn Inode>, Errno> {
+        // exFAT хранит только файлы и каталоги.
+        if !matches!(kind, FileType::Regular | FileType::Directory) {
+            return Err(Errno::EPERM);
+        }
+        let name = check_name(name)?;
+
+        let mut state = self.lock_volume()?;
+        let listing = self.live_dir()?;
+        if listing.find(&self.volume.upcase, &name).is_some() {
+            return Err(Errno::EEXIST);
+        }
+
+        let mut attributes = match kind {
+            FileType::Directory => ATTR_DIRECTORY,
+            _ => ATTR_ARCHIVE,
+        };
+        if mode & 0o222 == 0 {
+            attributes |= ATTR_READ_ONLY;
+        }
+        let mut set = FileSet::new(name, attributes);
+
+        // У каталога сразу есть кластер; записей `.` и `..` в exFAT нет.
+        if kind == FileType::Directory {
+            if self.volume.resize_stream(&mut state, &mut set.stream, 1)? == 0 {
+                return Err(Errno::ENOSPC);
+            }
+            self.volume.zero_cluster(set.stream.first)?;
+            set.stream.length = self.volume.geometry.cluster_size as u64;
+            set.stream.valid = set.stream.length;
+        }
+
+        let slots = match self.add_set(&mut state, listing, &set) {
+            Ok(slots) => slots,
+            Err(error) => {
+                self.volume.free_stream(&mut state, &set.stream)?;
+                self.volume.commit(&mut state)?;
+                return Err(error);
+            }
+        };
+        self.touch()?;
+        self.volume.commit(&mut state)?;
+
+        Ok(self.load(&Found {
+            set,
+            offset: slots[0],
+            slots,
+        }))
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let mut state = self.lock_volume()?;
+        let listing = self.live_dir()?;
+        let found = listing
+            .find(&self.volume.upcase, &name)
+            .ok_or(Errno::ENOENT)?;
+        if found.set.is_directory() {
+            return Err(Errno::EISDIR);
+        }
+
+        self.volume.remove_set(&found.slots)?;
+        self.release(&mut state, found)?;
+        self.touch()?;
+        self.volume.commit(&mut state)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let mut state = self.lock_volume()?;
+        let listing = self.live_dir()?;
+        let found = listing
+            .find(&self.volume.upcase, &name)
+            .ok_or(Errno::ENOENT)?;
+        if !found.set.is_directory() {
+            return Err(Errno::ENOTDIR);
+        }
+        self.check_empty(found)?;
+
+        self.volume.remove_set(&found.slots)?;
+        self.release(&mut state, found)?;
+        self.touch()?;
+        self.volume.commit(&mut state)
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        new_parent: &Arc<dyn Inode>,
+        new_name: &str,
+    ) -> Result<(), Errno> {
+        let new_parent = new_parent
+            .as_any()
+            .downcast_ref::<ExFatInode>()
+            .filter(|parent| Arc::ptr_eq(&parent.volume, &self.volume))
+            .ok_or(Errno::EXDEV)?
+            .this();
+        let old_name: Vec<u16> = old_name.encode_utf16().collect();
+        let new_name = check_name(new_name)?;
+        let upcase = &self.volume.upcase;
+
+        let mut state = self.lock_volume()?;
+        let same_dir = core::ptr::eq(self, &*new_parent);
+        let old_listing = self.live_dir()?;
+        let source = old_listing
+            .find(upcase, &old_name)
+            .ok_or(Errno::ENOENT)?
+            .clone();
+        let is_directory = source.set.is_directory();
+
+        // Каталог нельзя перенести в самого себя или своего потомка.
+        if is_directory {
+            let mut current = Some(new_parent.clone());
+            while let Some(dir) = current {
+                let node = dir.node.lock();
+                if node.slots.first() == Some(&source.offset) {
+                    return Err(Errno::EINVAL);
+                }
+                current = node.parent.clone();
+            }
+        }
+
+        let new_listing = match same_dir {
+            true => old_listing,
+            false => new_parent.live_dir()?,
+        };
+        let replaced = match new_listing.find(upcase, &new_name).cloned() {
+            // Та же запись: смена регистра имени или ничего.
+            Some(found) if found.offset == source.offset => {
+                if found.set.name == new_name {
+                    return Ok(());
+                }
+                None
+            }
+            found => found,
+        };
+
+        if let Some(replaced) = &replaced {
+            match (is_directory, replaced.set.is_directory()) {
+                (true, false) => return Err(Errno::ENOTDIR),
+                (false, true) => return Err(Errno::EISDIR),
+                (true, true) => self.check_empty(replaced)?,
+                (false, false) => {}
+            }
+        }
+
+        // Сначала новый набор: если места нет, ничего не меняется.
+        let mut set = source.set.clone();
+        set.name = new_name;
+        let new_slots = new_parent.add_set(&mut state, new_listing, &set)?;
+        if let Some(replaced) = &replaced {
+            self.volume.remove_set(&replaced.slots)?;
+            new_parent.release(&mut state, replaced)?;
+        }
+        self.volume.remove_set(&source.slots)?;
+
+        let new_offset = new_slots[0];
+        let moved = self.volume.inodes.lock().remove(&source.offset);
+        if let Some(inode) = moved.as_ref().and_then(Weak::upgrade) {
+            let mut node = inode.node.lock();
+            node.ino = ino_of(new_offset);
+            node.slots = new_slots;
+            node.set.name = set.name;
+            node.parent = Some(new_parent.clone());
+        }
+        if let Some(moved) = moved {
+            self.volume.inodes.lock().insert(new_offset, moved);
+        }
+
+        self.touch()?;
+        if !same_dir {
+            new_parent.touch()?;
+        }
+        self.volume.commit(&mut state)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        let _state = self.volume.state.lock();
+        let clusters = self.dir_clusters(&self.node.lock())?;
+        let listing = self.volume.list(&clusters)?;
+
+        Ok(listing
+            .entries
+            .into_iter()
+            .map(|found| DirEntry {
+                kind: match found.set.is_directory() {
+                    true => FileType::Directory,
+                    false => FileType::Regular,
+                },
+                ino: ino_of(found.offset),
+                name: found.set.name(),
+            })
+            .collect())
+    }
+}
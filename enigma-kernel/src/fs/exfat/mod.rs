@@ -0,0 +1,212 @@
+//! Драйвер exFAT.
+//!
+//! Занятость кластеров хранит битовая карта размещения (ее копия держится в
+//! памяти), а FAT описывает только цепочки фрагментированных файлов. Новые
+//! файлы растут непрерывно и помечаются `NoFatChain`; цепочка в FAT
+//! выписывается, только когда продолжить файл подряд не удалось.
+//!
+//! Как и у FAT, номером файла служит положение его набора записей в
+//! каталоге. Имена сравниваются без учета регистра по таблице регистра
+//! самого тома. Поддерживается один FAT (TexFAT с двумя копиями - нет).
+
+mod boot;
+mod dir;
+mod inode;
+mod table;
+mod upcase;
+
+use super::disk::Disk;
+use super::vfs::{FileSystem, Inode};
+use crate::drivers::block::BlockDevice;
+use crate::errno::Errno;
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use boot::{BOOT_REGION_SECTORS, Geometry};
+use core::sync::atomic::AtomicU64;
+use dir::{TYPE_BITMAP, TYPE_LABEL, TYPE_UPCASE};
+use inode::ExFatInode;
+use spin::Mutex;
+use table::Stream;
+use upcase::UpcaseTable;
+
+pub struct ExFatFs {
+    volume: Arc<Volume>,
+    root: Arc<ExFatInode>,
+}
+
+impl ExFatFs {
+    /// Открывает том на `device`. Возвращает `EINVAL`, если на устройстве
+    /// нет правильной загрузочной области exFAT или у тома нет битовой
+    /// карты и таблицы регистра.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, Errno> {
+        let disk = Disk::new(device);
+        let mut sector = vec![0; 512];
+        disk.read(0, &mut sector)?;
+        // Размер сектора известен только из самого загрузочного сектора.
+        let sector_size = match sector[108] {
+            shift @ 9..=12 => 1usize << shift,
+            _ => return Err(Errno::EINVAL),
+        };
+        let mut region = vec![0; BOOT_REGION_SECTORS * sector_size];
+        disk.read(0, &mut region)?;
+        let geometry = Geometry::parse(&region, disk.size())?;
+
+        // Корень читается до таблицы регистра: для разбора записей она не
+        // нужна, только для сравнения имен.
+        let mut volume = Volume {
+            disk,
+            geometry,
+            upcase: UpcaseTable::default(),
+            bitmap_clusters: Vec::new(),
+            label: String::new(),
+            state: Mutex::new(State::default()),
+            inodes: Mutex::new(BTreeMap::new()),
+            orphans: Mutex::new(Vec::new()),
+            next_orphan: AtomicU64::new(inode::FIRST_ORPHAN_INO),
+        };
+        let root_chain = volume.chain(volume.geometry.root_cluster)?;
+        let root_size = root_chain.len() as u64 * volume.geometry.cluster_size as u64;
+        let root_stream = Stream {
+            first: volume.geometry.root_cluster,
+            contiguous: false,
+            valid: root_size,
+            length: root_size,
+        };
+        let listing = volume.list(&volume.clusters(&root_stream)?)?;
+
+        let mut bitmap = None;
+        let mut upcase = None;
+        for entry in &listing.special {
+            let first = u32::from_le_bytes(entry[20..24].try_into().unwrap());
+            let length = u64::from_le_bytes(entry[24..32].try_into().unwrap());
+            match entry[0] {
+                TYPE_BITMAP if bitmap.is_none() => bitmap = Some((first, length)),
+                TYPE_UPCASE if upcase.is_none() => {
+                    let checksum = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+                    upcase = Some((first, length, checksum));
+                }
+                TYPE_LABEL => volume.label = dir::label(entry),
+                _ => {}
+            }
+        }
+
+        let (first, length, checksum) = upcase.ok_or(Errno::EINVAL)?;
+        let table = volume.read_system_file(first, length)?;
+        volume.upcase = UpcaseTable::parse(&table, checksum)?;
+
+        let (first, length) = bitmap.ok_or(Errno::EINVAL)?;
+        let cluster_count = volume.geometry.cluster_count;
+        if length < cluster_count.div_ceil(8) as u64 {
+            return Err(Errno::EINVAL);
+        }
+        let bitmap = volume.read_system_file(first, length)?;
+        volume.bitmap_clusters = volume.chain(first)?;
+        *volume.state.lock() = State {
+            free_clusters: table::count_free(&bitmap, cluster_count),
+            bitmap,
+            next_free: 2,
+            dirty: false,
+        };
+
+        let volume = Arc::new(volume);
+        let root = ExFatInode::root(&volume, root_stream);
+        Ok(Arc::new(Self { volume, root }))
+    }
+
+    /// Метка тома (пустая, если ее нет).
+    pub fn label(&self) -> String {
+        self.volume.label.clone()
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        self.volume.geometry.cluster_size
+    }
+
+    pub fn free_clusters(&self) -> Result<u32, Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        self.volume.commit(&mut state)?;
+        Ok(state.free_clusters)
+    }
+}
+
+impl FileSystem for ExFatFs {
+    fn name(&self) -> &'static str {
+        "exfat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.volume.disk.device.is_read_only()
+    }
+
+    fn sync(&self) -> Result<(), Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        self.volume.commit(&mut state)?;
+        self.volume.disk.device.flush()
+    }
+}
+
+/// Общие данные тома.
+struct Volume {
+    disk: Disk,
+    geometry: Geometry,
+    upcase: UpcaseTable,
+    /// Кластеры битовой карты размещения.
+    bitmap_clusters: Vec<u32>,
+    label: String,
+    /// Блокировка метаданных. Порядок блокировок: `state`, затем узлы
+    /// inode; `inodes` и `orphans` берутся последними и ненадолго.
+    state: Mutex<State>,
+    /// Загруженные inode по смещению записи файла.
+    inodes: Mutex<BTreeMap<u64, Weak<ExFatInode>>>,
+    /// Данные удаленных файлов, которые закрылись. Освобождаются при
+    /// следующей операции с томом: `Drop` не может ждать `state`.
+    orphans: Mutex<Vec<Stream>>,
+    next_orphan: AtomicU64,
+}
+
+/// Состояние распределителя кластеров.
+#[derive(Debug, Default)]
+struct State {
+    /// Копия битовой карты размещения: бит на кластер, начиная со второго.
+    bitmap: Vec<u8>,
+    free_clusters: u32,
+    /// С какого кластера искать свободный.
+    next_free: u32,
+    /// На диске выставлен `VolumeDirty`.
+    dirty: bool,
+}
+
+impl Volume {
+    /// Читает служебный файл корня (битовую карту или таблицу регистра),
+    /// описанный цепочкой FAT.
+    fn read_system_file(&self, first: u32, length: u64) -> Result<Vec<u8>, Errno> {
+        let cluster_size = self.geometry.cluster_size as u64;
+        let count = length.div_ceil(cluster_size);
+        if length == 0 || count > self.geometry.cluster_count as u64 {
+            return Err(Errno::EINVAL);
+        }
+
+        let chain = self.chain(first)?;
+        if (chain.len() as u64) < count {
+            return Err(Errno::EIO);
+        }
+        let mut data = vec![0; (count * cluster_size) as usize];
+        for (chunk, &cluster) in data.chunks_exact_mut(cluster_size as usize).zip(&chain) {
+            self.disk
+                .read(self.geometry.cluster_offset(cluster), chunk)?;
+        }
+        data.truncate(length as usize);
+        Ok(data)
+    }
+}
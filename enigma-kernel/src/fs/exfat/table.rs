@@ -0,0 +1,354 @@
+//! Таблица FAT, битовая карта размещения и потоки кластеров.
+//!
+//! В exFAT занятость кластеров определяет только битовая карта, а FAT
+//! нужна лишь для цепочек. Файл, чьи кластеры идут подряд, помечается
+//! `NoFatChain` и в FAT не записывается вовсе.
+
+use super::boot::{PERCENT_IN_USE, VOLUME_DIRTY, VOLUME_FLAGS};
+use super::{State, Volume};
+use crate::errno::Errno;
+use alloc::{vec, vec::Vec};
+
+/// Значение ячейки FAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Link {
+    Free,
+    Next(u32),
+    End,
+    Bad,
+}
+
+const FAT_BAD: u32 = 0xFFFF_FFF7;
+const FAT_END: u32 = 0xFFFF_FFFF;
+
+/// Данные файла или каталога (поля записи потока).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct Stream {
+    /// Первый кластер (0 - кластеров нет).
+    pub first: u32,
+    /// Кластеры идут подряд и в FAT не описаны (`NoFatChain`).
+    pub contiguous: bool,
+    /// Длина записанной части (`ValidDataLength`): дальше читаются нули.
+    pub valid: u64,
+    /// Длина данных (`DataLength`).
+    pub length: u64,
+}
+
+/// Кластеры потока по порядку.
+pub(super) enum Clusters {
+    Contiguous { first: u32, count: u32 },
+    Chain(Vec<u32>),
+}
+
+impl Clusters {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Contiguous { count, .. } => *count as usize,
+            Self::Chain(chain) => chain.len(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<u32> {
+        match self {
+            Self::Contiguous { first, count } => {
+                (index < *count as usize).then(|| first + index as u32)
+            }
+            Self::Chain(chain) => chain.get(index).copied(),
+        }
+    }
+
+    pub fn last(&self) -> Option<u32> {
+        self.len().checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+}
+
+impl Volume {
+    /// Читает ячейку FAT кластера `cluster`.
+    pub(super) fn link(&self, cluster: u32) -> Result<Link, Errno> {
+        let mut bytes = [0; 4];
+        self.disk
+            .read(self.geometry.fat_offset + cluster as u64 * 4, &mut bytes)?;
+
+        match u32::from_le_bytes(bytes) {
+            0 => Ok(Link::Free),
+            FAT_END => Ok(Link::End),
+            FAT_BAD => Ok(Link::Bad),
+            next if self.geometry.contains(next) => Ok(Link::Next(next)),
+            // Ссылка за пределы тома: таблица повреждена.
+            _ => Err(Errno::EIO),
+        }
+    }
+
+    pub(super) fn set_link(&self, cluster: u32, link: Link) -> Result<(), Errno> {
+        let value = match link {
+            Link::Free => 0,
+            Link::Next(next) => next,
+            Link::End => FAT_END,
+            Link::Bad => FAT_BAD,
+        };
+        let offset = self.geometry.fat_offset + cluster as u64 * 4;
+        self.disk.write(offset, &value.to_le_bytes())
+    }
+
+    /// Вся цепочка FAT, начинающаяся с `first`.
+    pub(super) fn chain(&self, first: u32) -> Result<Vec<u32>, Errno> {
+        self.chain_of(first, None)
+    }
+
+    /// Цепочка из `limit` кластеров (или вся, если `limit` не задан).
+    fn chain_of(&self, first: u32, limit: Option<u32>) -> Result<Vec<u32>, Errno> {
+        let mut clusters = Vec::new();
+        let mut current = first;
+        while current != 0 && limit.is_none_or(|limit| clusters.len() < limit as usize) {
+            if !self.geometry.contains(current)
+                || clusters.len() > self.geometry.cluster_count as usize
+            {
+                // Цепочка ведет за пределы тома или зациклена.
+                return Err(Errno::EIO);
+            }
+            clusters.push(current);
+            current = match self.link(current)? {
+                Link::Next(next) => next,
+                Link::End => 0,
+                Link::Free | Link::Bad => return Err(Errno::EIO),
+            };
+        }
+        match limit {
+            Some(limit) if clusters.len() != limit as usize => Err(Errno::EIO),
+            _ => Ok(clusters),
+        }
+    }
+
+    /// Количество кластеров под поток.
+    pub(super) fn cluster_count(&self, stream: &Stream) -> u32 {
+        stream.length.div_ceil(self.geometry.cluster_size as u64) as u32
+    }
+
+    /// Кластеры потока.
+    pub(super) fn clusters(&self, stream: &Stream) -> Result<Clusters, Errno> {
+        let count = self.cluster_count(stream);
+        if count == 0 {
+            return Ok(Clusters::Chain(Vec::new()));
+        }
+        if !stream.contiguous {
+            return self
+                .chain_of(stream.first, Some(count))
+                .map(Clusters::Chain);
+        }
+
+        let last = stream.first as u64 + count as u64 - 1;
+        if !self.geometry.contains(stream.first) || last > self.geometry.last_cluster() as u64 {
+            return Err(Errno::EIO);
+        }
+        Ok(Clusters::Contiguous {
+            first: stream.first,
+            count,
+        })
+    }
+
+    /// Меняет бит кластера в битовой карте (и ее копии в памяти).
+    fn set_allocated(&self, state: &mut State, cluster: u32, allocated: bool) -> Result<(), Errno> {
+        let bit = (cluster - 2) as usize;
+        let byte = &mut state.bitmap[bit / 8];
+        let mask = 1 << (bit % 8);
+        if (*byte & mask != 0) == allocated {
+            // Кластер уже в этом состоянии: на него ссылаются дважды.
+            return Err(Errno::EIO);
+        }
+        match allocated {
+            true => *byte |= mask,
+            false => *byte &= !mask,
+        }
+        let value = *byte;
+
+        self.begin_change(state)?;
+        let cluster_size = self.geometry.cluster_size as usize;
+        let host = self.bitmap_clusters[bit / 8 / cluster_size];
+        let offset = self.geometry.cluster_offset(host) + (bit / 8 % cluster_size) as u64;
+        self.disk.write(offset, &[value])?;
+
+        match allocated {
+            true => state.free_clusters -= 1,
+            false => state.free_clusters += 1,
+        }
+        Ok(())
+    }
+
+    fn is_allocated(state: &State, cluster: u32) -> bool {
+        let bit = (cluster - 2) as usize;
+        state.bitmap[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    /// Занимает свободный кластер, по возможности `goal` или следующий за
+    /// ним. Содержимое кластера не обнуляется.
+    fn allocate_cluster(&self, state: &mut State, goal: u32) -> Result<u32, Errno> {
+        if state.free_clusters == 0 {
+            return Err(Errno::ENOSPC);
+        }
+
+        let count = self.geometry.cluster_count;
+        let start = match goal {
+            goal if self.geometry.contains(goal) => goal,
+            _ => 2,
+        };
+        let cluster = (0..count)
+            .map(|step| 2 + (start - 2 + step) % count)
+            .find(|&cluster| !Self::is_allocated(state, cluster))
+            .ok_or(Errno::EIO)?;
+
+        self.set_allocated(state, cluster, true)?;
+        state.next_free = cluster + 1;
+        Ok(cluster)
+    }
+
+    /// Выписывает в FAT цепочку для `count` кластеров, идущих подряд с
+    /// `first`.
+    fn write_chain(&self, first: u32, count: u32) -> Result<(), Errno> {
+        for cluster in first..first + count - 1 {
+            self.set_link(cluster, Link::Next(cluster + 1))?;
+        }
+        self.set_link(first + count - 1, Link::End)
+    }
+
+    /// Доводит поток до `want` кластеров. Новые кластеры берутся сразу за
+    /// последним, пока это возможно: тогда поток остается непрерывным.
+    /// Если место кончилось, поток растет сколько получилось; возвращается
+    /// итоговое количество кластеров. Длину потока задает вызывающий.
+    pub(super) fn resize_stream(
+        &self,
+        state: &mut State,
+        stream: &mut Stream,
+        want: u32,
+    ) -> Result<u32, Errno> {
+        let clusters = self.clusters(stream)?;
+        let have = clusters.len() as u32;
+        if want < have {
+            self.release_clusters(state, stream, &clusters, want)?;
+            return Ok(want);
+        }
+
+        let mut last = clusters.last();
+        let mut count = have;
+        while count < want {
+            let goal = last.map_or(state.next_free, |last| last + 1);
+            let cluster = match self.allocate_cluster(state, goal) {
+                Ok(cluster) => cluster,
+                Err(Errno::ENOSPC) => break,
+                Err(error) => return Err(error),
+            };
+
+            match last {
+                None => {
+                    stream.first = cluster;
+                    stream.contiguous = true;
+                }
+                Some(last) if stream.contiguous && cluster == last + 1 => {}
+                Some(last) => {
+                    if stream.contiguous {
+                        self.write_chain(stream.first, count)?;
+                        stream.contiguous = false;
+                    }
+                    self.set_link(last, Link::Next(cluster))?;
+                }
+            }
+            if !stream.contiguous {
+                self.set_link(cluster, Link::End)?;
+            }
+            last = Some(cluster);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Освобождает кластеры потока после первых `keep`.
+    fn release_clusters(
+        &self,
+        state: &mut State,
+        stream: &mut Stream,
+        clusters: &Clusters,
+        keep: u32,
+    ) -> Result<(), Errno> {
+        if keep > 0 && !stream.contiguous {
+            self.set_link(clusters.get(keep as usize - 1).unwrap(), Link::End)?;
+        }
+        for cluster in clusters.iter().skip(keep as usize) {
+            if !stream.contiguous {
+                self.set_link(cluster, Link::Free)?;
+            }
+            self.set_allocated(state, cluster, false)?;
+        }
+        if keep == 0 {
+            stream.first = 0;
+            stream.contiguous = false;
+        }
+        Ok(())
+    }
+
+    /// Освобождает все кластеры потока.
+    pub(super) fn free_stream(&self, state: &mut State, stream: &Stream) -> Result<(), Errno> {
+        let mut stream = *stream;
+        self.resize_stream(state, &mut stream, 0).map(drop)
+    }
+
+    pub(super) fn zero_cluster(&self, cluster: u32) -> Result<(), Errno> {
+        let zeroes = vec![0; self.geometry.cluster_size as usize];
+        self.disk
+            .write(self.geometry.cluster_offset(cluster), &zeroes)
+    }
+
+    /// Освобождает кластеры закрытых удаленных файлов.
+    pub(super) fn free_orphans(&self, state: &mut State) -> Result<(), Errno> {
+        let orphans = core::mem::take(&mut *self.orphans.lock());
+        for stream in orphans {
+            self.free_stream(state, &stream)?;
+        }
+        Ok(())
+    }
+
+    /// Отмечает в загрузочном секторе, что том меняется: если изменение не
+    /// завершится, проверка тома будет знать, что его нужно исправлять.
+    fn begin_change(&self, state: &mut State) -> Result<(), Errno> {
+        if !state.dirty {
+            state.dirty = true;
+            self.store_flags(state)?;
+        }
+        Ok(())
+    }
+
+    /// Завершает изменение: снимает `VolumeDirty` (если он не стоял при
+    /// монтировании) и обновляет процент занятого места.
+    pub(super) fn commit(&self, state: &mut State) -> Result<(), Errno> {
+        if state.dirty {
+            state.dirty = false;
+            self.store_flags(state)?;
+        }
+        Ok(())
+    }
+
+    fn store_flags(&self, state: &State) -> Result<(), Errno> {
+        let mut flags = self.geometry.flags;
+        if state.dirty {
+            flags |= VOLUME_DIRTY;
+        }
+        self.disk.write(VOLUME_FLAGS as u64, &flags.to_le_bytes())?;
+
+        let total = self.geometry.cluster_count as u64;
+        let used = total - state.free_clusters as u64;
+        let percent = match total {
+            0 => 0,
+            _ => used * 100 / total,
+        };
+        self.disk.write(PERCENT_IN_USE as u64, &[percent as u8])
+    }
+}
+
+/// Количество свободных кластеров по битовой карте.
+pub(super) fn count_free(bitmap: &[u8], cluster_count: u32) -> u32 {
+    (0..cluster_count as usize)
+        .filter(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0)
+        .count() as u32
+}
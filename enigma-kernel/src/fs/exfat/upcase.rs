@@ -0,0 +1,73 @@
+//! Таблица перевода в верхний регистр (up-case table). По ней имена
+//! сравниваются без учета регистра и считается их хеш.
+
+use crate::errno::Errno;
+use alloc::vec::Vec;
+
+/// В сжатой таблице за этим значением следует длина участка, где символы
+/// переходят сами в себя.
+const IDENTITY_RUN: u16 = 0xFFFF;
+
+#[derive(Default)]
+pub(super) struct UpcaseTable {
+    /// Образы первых кодов UTF-16; коды за концом таблицы переходят сами
+    /// в себя.
+    map: Vec<u16>,
+}
+
+impl UpcaseTable {
+    /// Разбирает таблицу из `raw` (сжатую или нет), проверив ее
+    /// контрольную сумму.
+    pub fn parse(raw: &[u8], expected_checksum: u32) -> Result<Self, Errno> {
+        let checksum = raw.iter().fold(0u32, |sum, &byte| {
+            sum.rotate_right(1).wrapping_add(byte as u32)
+        });
+        if checksum != expected_checksum || raw.len() % 2 != 0 {
+            return Err(Errno::EINVAL);
+        }
+
+        let mut units = raw
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+        let mut map = Vec::with_capacity(raw.len() / 2);
+        while let Some(unit) = units.next() {
+            let count = match unit {
+                IDENTITY_RUN => units.next().ok_or(Errno::EINVAL)? as usize,
+                _ => {
+                    map.push(unit);
+                    continue;
+                }
+            };
+            let start = map.len();
+            if start + count > 0x10000 {
+                return Err(Errno::EINVAL);
+            }
+            map.extend((start..start + count).map(|code| code as u16));
+        }
+        if map.len() > 0x10000 {
+            return Err(Errno::EINVAL);
+        }
+        Ok(Self { map })
+    }
+
+    pub fn upcase(&self, unit: u16) -> u16 {
+        self.map.get(unit as usize).copied().unwrap_or(unit)
+    }
+
+    /// Равны ли имена без учета регистра.
+    pub fn equal(&self, a: &[u16], b: &[u16]) -> bool {
+        a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .all(|(&a, &b)| self.upcase(a) == self.upcase(b))
+    }
+
+    /// Хеш имени (`NameHash` записи потока).
+    pub fn name_hash(&self, name: &[u16]) -> u16 {
+        name.iter()
+            .flat_map(|&unit| self.upcase(unit).to_le_bytes())
+            .fold(0u16, |hash, byte| {
+                hash.rotate_right(1).wrapping_add(byte as u16)
+            })
+    }
+}
@@ -0,0 +1,202 @@
+//! Битовые карты блоков и inode.
+
+use super::superblock::DESCRIPTOR_SIZE;
+use super::{State, Volume};
+use crate::errno::Errno;
+use alloc::{vec, vec::Vec};
+
+impl Volume {
+    /// Переписывает дескриптор группы `group`.
+    fn store_group(&self, state: &mut State, group: u32) -> Result<(), Errno> {
+        let table = self.block_offset(self.superblock.descriptors_block());
+        let offset = table + group as u64 * DESCRIPTOR_SIZE as u64;
+        state.dirty = true;
+        self.disk
+            .write(offset, &state.groups[group as usize].encode())
+    }
+
+    fn read_bitmap(&self, block: u32) -> Result<Vec<u8>, Errno> {
+        let mut bitmap = vec![0; self.block_size()];
+        self.disk.read(self.block_offset(block), &mut bitmap)?;
+        Ok(bitmap)
+    }
+
+    /// Выделяет обнуленный блок, по возможности не раньше `goal` и в той
+    /// же группе.
+    pub(super) fn allocate_block(&self, state: &mut State, goal: u32) -> Result<u32, Errno> {
+        let superblock = &self.superblock;
+        let goal = goal.clamp(superblock.first_data_block, superblock.blocks_count - 1);
+        let goal_group = (goal - superblock.first_data_block) / superblock.blocks_per_group;
+
+        for step in 0..superblock.group_count {
+            let group = (goal_group + step) % superblock.group_count;
+            if state.groups[group as usize].free_blocks == 0 {
+                continue;
+            }
+
+            let first = superblock.group_first_block(group);
+            let limit = superblock.blocks_in_group(group);
+            let start = match step {
+                0 => goal - first,
+                _ => 0,
+            };
+
+            let bitmap_block = state.groups[group as usize].block_bitmap;
+            let mut bitmap = self.read_bitmap(bitmap_block)?;
+            // Счетчик группы говорит, что место есть: карта ему противоречит.
+            let bit = find_zero(&bitmap, start, limit)
+                .or_else(|| find_zero(&bitmap, 0, start))
+                .ok_or(Errno::EIO)?;
+
+            set_bit(&mut bitmap, bit, true);
+            self.disk.write(self.block_offset(bitmap_block), &bitmap)?;
+            state.groups[group as usize].free_blocks -= 1;
+            state.free_blocks -= 1;
+            self.store_group(state, group)?;
+
+            let block = first + bit;
+            self.zero_block(block)?;
+            return Ok(block);
+        }
+        Err(Errno::ENOSPC)
+    }
+
+    /// Освобождает блок `block`.
+    pub(super) fn free_block(&self, state: &mut State, block: u32) -> Result<(), Errno> {
+        let superblock = &self.superblock;
+        self.check_block(block)?;
+        let group = (block - superblock.first_data_block) / superblock.blocks_per_group;
+        let bit = block - superblock.group_first_block(group);
+
+        let bitmap_block = state.groups[group as usize].block_bitmap;
+        let mut bitmap = self.read_bitmap(bitmap_block)?;
+        if !get_bit(&bitmap, bit) {
+            // Блок уже свободен: на него ссылаются дважды.
+            return Err(Errno::EIO);
+        }
+        set_bit(&mut bitmap, bit, false);
+        self.disk.write(self.block_offset(bitmap_block), &bitmap)?;
+
+        state.groups[group as usize].free_blocks += 1;
+        state.free_blocks += 1;
+        self.store_group(state, group)
+    }
+
+    /// Проверяет, что `block` - номер блока данных тома.
+    pub(super) fn check_block(&self, block: u32) -> Result<(), Errno> {
+        let superblock = &self.superblock;
+        match (superblock.first_data_block..superblock.blocks_count).contains(&block) {
+            true => Ok(()),
+            false => Err(Errno::EIO),
+        }
+    }
+
+    pub(super) fn zero_block(&self, block: u32) -> Result<(), Errno> {
+        let zeroes = vec![0; self.block_size()];
+        self.disk.write(self.block_offset(block), &zeroes)
+    }
+
+    /// Выделяет inode. Файлы размещаются в группе родительского каталога,
+    /// а каталоги - в группе с наибольшим числом свободных inode, чтобы
+    /// поддеревья расходились по тому.
+    pub(super) fn allocate_inode(
+        &self,
+        state: &mut State,
+        parent: u32,
+        directory: bool,
+    ) -> Result<u32, Errno> {
+        let superblock = &self.superblock;
+        let start = match directory {
+            true => (0..superblock.group_count)
+                .max_by_key(|&group| (state.groups[group as usize].free_inodes, u32::MAX - group))
+                .unwrap_or(0),
+            false => (parent - 1) / superblock.inodes_per_group,
+        };
+
+        for step in 0..superblock.group_count {
+            let group = (start + step) % superblock.group_count;
+            if state.groups[group as usize].free_inodes == 0 {
+                continue;
+            }
+
+            let bitmap_block = state.groups[group as usize].inode_bitmap;
+            let mut bitmap = self.read_bitmap(bitmap_block)?;
+            // Служебные inode до `first_ino` не выдаются, даже если их
+            // биты почему-то сброшены.
+            let first = match group {
+                0 => superblock.first_ino - 1,
+                _ => 0,
+            };
+            let bit = find_zero(&bitmap, first, superblock.inodes_per_group).ok_or(Errno::EIO)?;
+
+            set_bit(&mut bitmap, bit, true);
+            self.disk.write(self.block_offset(bitmap_block), &bitmap)?;
+            let descriptor = &mut state.groups[group as usize];
+            descriptor.free_inodes -= 1;
+            if directory {
+                descriptor.used_dirs += 1;
+            }
+            state.free_inodes -= 1;
+            self.store_group(state, group)?;
+
+            return Ok(group * superblock.inodes_per_group + bit + 1);
+        }
+        Err(Errno::ENOSPC)
+    }
+
+    /// Освобождает inode `ino` в битовой карте.
+    pub(super) fn free_inode(
+        &self,
+        state: &mut State,
+        ino: u32,
+        directory: bool,
+    ) -> Result<(), Errno> {
+        let group = (ino - 1) / self.superblock.inodes_per_group;
+        let bit = (ino - 1) % self.superblock.inodes_per_group;
+
+        let bitmap_block = state.groups[group as usize].inode_bitmap;
+        let mut bitmap = self.read_bitmap(bitmap_block)?;
+        if !get_bit(&bitmap, bit) {
+            return Err(Errno::EIO);
+        }
+        set_bit(&mut bitmap, bit, false);
+        self.disk.write(self.block_offset(bitmap_block), &bitmap)?;
+
+        let descriptor = &mut state.groups[group as usize];
+        descriptor.free_inodes += 1;
+        if directory {
+            descriptor.used_dirs = descriptor.used_dirs.saturating_sub(1);
+        }
+        state.free_inodes += 1;
+        self.store_group(state, group)
+    }
+}
+
+fn get_bit(bitmap: &[u8], bit: u32) -> bool {
+    bitmap[bit as usize / 8] & (1 << (bit % 8)) != 0
+}
+
+fn set_bit(bitmap: &mut [u8], bit: u32, value: bool) {
+    let mask = 1 << (bit % 8);
+    match value {
+        true => bitmap[bit as usize / 8] |= mask,
+        false => bitmap[bit as usize / 8] &= !mask,
+    }
+}
+
+/// Первый сброшенный бит в диапазоне `from..to`.
+fn find_zero(bitmap: &[u8], from: u32, to: u32) -> Option<u32> {
+    let mut bit = from;
+    while bit < to {
+        let byte = bitmap[bit as usize / 8];
+        if byte == 0xFF && bit % 8 == 0 {
+            bit += 8;
+            continue;
+        }
+        if byte & (1 << (bit % 8)) == 0 {
+            return Some(bit);
+        }
+        bit += 1;
+    }
+    None
+}
@@ -0,0 +1,393 @@
+//! Отображение блоков файла: 12 прямых указателей и деревья косвенных
+//! блоков глубиной 1, 2 и 3.
+
+use super::raw::{RawInode, unix_time};
+use super::superblock::RO_COMPAT_LARGE_FILE;
+use super::{State, Volume};
+use crate::errno::Errno;
+use crate::fs::vfs::{FileType, Timestamp};
+use alloc::{vec, vec::Vec};
+
+const DIRECT_BLOCKS: u64 = 12;
+/// Сигнатура заголовка блока расширенных атрибутов.
+const XATTR_MAGIC: u32 = 0xEA02_0000;
+
+/// Положение блока файла в дереве: корневой указатель в inode, глубина
+/// косвенности и номера указателей на каждом уровне.
+struct BlockPath {
+    root: usize,
+    depth: usize,
+    offsets: [u32; 3],
+}
+
+impl Volume {
+    fn pointers_per_block(&self) -> u64 {
+        self.block_size() as u64 / 4
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        self.block_size() as u32 / 512
+    }
+
+    fn block_path(&self, index: u64) -> Result<BlockPath, Errno> {
+        if index < DIRECT_BLOCKS {
+            return Ok(BlockPath {
+                root: index as usize,
+                depth: 0,
+                offsets: [0; 3],
+            });
+        }
+
+        let pointers = self.pointers_per_block();
+        let mut index = index - DIRECT_BLOCKS;
+        let mut span = 1;
+        for depth in 1..=3 {
+            span *= pointers;
+            if index < span {
+                let mut offsets = [0; 3];
+                for level in (0..depth).rev() {
+                    offsets[level] = (index % pointers) as u32;
+                    index /= pointers;
+                }
+                return Ok(BlockPath {
+                    root: DIRECT_BLOCKS as usize + depth - 1,
+                    depth,
+                    offsets,
+                });
+            }
+            index -= span;
+        }
+        Err(Errno::EFBIG)
+    }
+
+    /// Наибольший размер файла: предел дерева блоков, 32-битного счетчика
+    /// секторов и (без `large_file`) 2 ГиБ.
+    pub(super) fn max_file_size(&self, state: &State) -> u64 {
+        let pointers = self.pointers_per_block();
+        let blocks = DIRECT_BLOCKS + pointers + pointers * pointers + pointers.pow(3);
+        let limit = (blocks * self.block_size() as u64).min(u32::MAX as u64 * 512);
+        match state.ro_compat & RO_COMPAT_LARGE_FILE != 0 || self.superblock.revision() > 0 {
+            true => limit,
+            false => i32::MAX as u64,
+        }
+    }
+
+    fn read_pointer(&self, block: u32, offset: u32) -> Result<u32, Errno> {
+        let mut bytes = [0; 4];
+        self.disk
+            .read(self.block_offset(block) + offset as u64 * 4, &mut bytes)?;
+        let pointer = u32::from_le_bytes(bytes);
+        if pointer != 0 {
+            self.check_block(pointer)?;
+        }
+        Ok(pointer)
+    }
+
+    fn write_pointer(&self, block: u32, offset: u32, pointer: u32) -> Result<(), Errno> {
+        self.disk.write(
+            self.block_offset(block) + offset as u64 * 4,
+            &pointer.to_le_bytes(),
+        )
+    }
+
+    /// Номер блока данных `index` файла (0 - дыра).
+    pub(super) fn map(&self, inode: &RawInode, index: u64) -> Result<u32, Errno> {
+        let path = self.block_path(index)?;
+        let mut block = inode.block[path.root];
+        for &offset in &path.offsets[..path.depth] {
+            if block == 0 {
+                return Ok(0);
+            }
+            self.check_block(block)?;
+            block = self.read_pointer(block, offset)?;
+        }
+        if block != 0 {
+            self.check_block(block)?;
+        }
+        Ok(block)
+    }
+
+    /// Как [`Volume::map`], но выделяет недостающие блоки данных и
+    /// косвенные блоки. `goal` - подсказка для размещения.
+    fn map_or_allocate(
+        &self,
+        state: &mut State,
+        inode: &mut RawInode,
+        index: u64,
+        goal: u32,
+    ) -> Result<u32, Errno> {
+        let path = self.block_path(index)?;
+        if inode.block[path.root] == 0 {
+            inode.block[path.root] = self.allocate_block(state, goal)?;
+            inode.sectors += self.sectors_per_block();
+        }
+
+        let mut block = inode.block[path.root];
+        for &offset in &path.offsets[..path.depth] {
+            let mut next = self.read_pointer(block, offset)?;
+            if next == 0 {
+                next = self.allocate_block(state, block)?;
+                self.write_pointer(block, offset, next)?;
+                inode.sectors += self.sectors_per_block();
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Читает данные файла с позиции `offset` (не дальше его конца).
+    pub(super) fn read_data(
+        &self,
+        inode: &RawInode,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, Errno> {
+        let len = inode.size.saturating_sub(offset).min(buffer.len() as u64) as usize;
+        let block_size = self.block_size() as u64;
+
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let in_block = position % block_size;
+            let chunk = ((block_size - in_block) as usize).min(len - done);
+            let target = &mut buffer[done..done + chunk];
+
+            match self.map(inode, position / block_size)? {
+                0 => target.fill(0),
+                block => self
+                    .disk
+                    .read(self.block_offset(block) + in_block, target)?,
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// Пишет данные файла `ino`, выделяя блоки. Если место кончилось, пишет
+    /// сколько поместилось; ошибка - только если не поместилось ничего.
+    pub(super) fn write_data(
+        &self,
+        state: &mut State,
+        ino: u32,
+        inode: &mut RawInode,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, Errno> {
+        let max_size = self.max_file_size(state);
+        if offset >= max_size && !data.is_empty() {
+            return Err(Errno::EFBIG);
+        }
+        let data = &data[..data.len().min((max_size - offset) as usize)];
+
+        let block_size = self.block_size() as u64;
+        let group = (ino - 1) / self.superblock.inodes_per_group;
+        let mut goal = self.superblock.group_first_block(group);
+
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let in_block = position % block_size;
+            let chunk = ((block_size - in_block) as usize).min(data.len() - done);
+
+            let block = match self.map_or_allocate(state, inode, position / block_size, goal) {
+                Ok(block) => block,
+                Err(Errno::ENOSPC) if done > 0 => break,
+                Err(error) => {
+                    // Косвенные блоки, выделенные для ненаписанных данных.
+                    self.free_blocks_from(state, inode, inode.size.div_ceil(block_size))?;
+                    return Err(error);
+                }
+            };
+            self.disk.write(
+                self.block_offset(block) + in_block,
+                &data[done..done + chunk],
+            )?;
+            goal = block + 1;
+            done += chunk;
+        }
+
+        let end = offset + done as u64;
+        if end > inode.size {
+            inode.size = end;
+            if end > i32::MAX as u64 && state.ro_compat & RO_COMPAT_LARGE_FILE == 0 {
+                state.ro_compat |= RO_COMPAT_LARGE_FILE;
+                state.dirty = true;
+            }
+        }
+        inode.touch_mtime();
+        Ok(done)
+    }
+
+    /// Меняет размер файла. Новое место - дыры, освобожденные блоки за
+    /// новым концом возвращаются в битовую карту.
+    pub(super) fn resize(
+        &self,
+        state: &mut State,
+        inode: &mut RawInode,
+        size: u64,
+    ) -> Result<(), Errno> {
+        if size > self.max_file_size(state) {
+            return Err(Errno::EFBIG);
+        }
+
+        let block_size = self.block_size() as u64;
+        if size < inode.size {
+            self.free_blocks_from(state, inode, size.div_ceil(block_size))?;
+
+            // Хвост последнего блока должен читаться нулями при следующем
+            // расширении файла.
+            let in_block = size % block_size;
+            if in_block != 0 {
+                let block = self.map(inode, size / block_size)?;
+                if block != 0 {
+                    let zeroes = vec![0; (block_size - in_block) as usize];
+                    self.disk
+                        .write(self.block_offset(block) + in_block, &zeroes)?;
+                }
+            }
+        }
+
+        if size > i32::MAX as u64 && state.ro_compat & RO_COMPAT_LARGE_FILE == 0 {
+            state.ro_compat |= RO_COMPAT_LARGE_FILE;
+            state.dirty = true;
+        }
+        inode.size = size;
+        inode.touch_mtime();
+        Ok(())
+    }
+
+    /// Освобождает блоки файла с номерами от `keep` и косвенные блоки,
+    /// которые после этого опустели.
+    pub(super) fn free_blocks_from(
+        &self,
+        state: &mut State,
+        inode: &mut RawInode,
+        keep: u64,
+    ) -> Result<(), Errno> {
+        for index in keep.min(DIRECT_BLOCKS)..DIRECT_BLOCKS {
+            let block = inode.block[index as usize];
+            if block != 0 {
+                self.free_block(state, block)?;
+                inode.block[index as usize] = 0;
+                inode.sectors -= self.sectors_per_block();
+            }
+        }
+
+        let pointers = self.pointers_per_block();
+        let mut base = DIRECT_BLOCKS;
+        let mut span = 1;
+        for depth in 1..=3 {
+            span *= pointers;
+            let root = DIRECT_BLOCKS as usize + depth - 1;
+            let block = inode.block[root];
+            if block != 0
+                && keep < base + span
+                && self.free_tree(state, inode, block, depth, base, keep)?
+            {
+                inode.block[root] = 0;
+            }
+            base += span;
+        }
+        Ok(())
+    }
+
+    /// Освобождает в косвенном блоке `block` глубины `depth`, описывающем
+    /// блоки файла начиная с `base`, все блоки с номерами от `keep`.
+    /// Возвращает `true`, если сам косвенный блок опустел и освобожден.
+    fn free_tree(
+        &self,
+        state: &mut State,
+        inode: &mut RawInode,
+        block: u32,
+        depth: usize,
+        base: u64,
+        keep: u64,
+    ) -> Result<bool, Errno> {
+        self.check_block(block)?;
+        let mut raw = vec![0; self.block_size()];
+        self.disk.read(self.block_offset(block), &mut raw)?;
+        let mut pointers = raw
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+
+        let span = self.pointers_per_block().pow(depth as u32 - 1);
+        let first = (keep.saturating_sub(base) / span) as usize;
+        let mut changed = false;
+        for (slot, pointer) in pointers.iter_mut().enumerate().skip(first) {
+            let child = *pointer;
+            if child == 0 {
+                continue;
+            }
+
+            let child_base = base + slot as u64 * span;
+            let freed = match depth {
+                1 => {
+                    self.free_block(state, child)?;
+                    inode.sectors -= self.sectors_per_block();
+                    true
+                }
+                _ => self.free_tree(state, inode, child, depth - 1, child_base, keep)?,
+            };
+            if freed {
+                *pointer = 0;
+                changed = true;
+            }
+        }
+
+        if pointers.iter().all(|&pointer| pointer == 0) {
+            self.free_block(state, block)?;
+            inode.sectors -= self.sectors_per_block();
+            return Ok(true);
+        }
+        if changed {
+            for (bytes, pointer) in raw.chunks_exact_mut(4).zip(&pointers) {
+                bytes.copy_from_slice(&pointer.to_le_bytes());
+            }
+            self.disk.write(self.block_offset(block), &raw)?;
+        }
+        Ok(false)
+    }
+
+    /// Удаляет inode без ссылок: освобождает его блоки, блок атрибутов и
+    /// сам inode.
+    pub(super) fn destroy_inode(
+        &self,
+        state: &mut State,
+        ino: u32,
+        inode: &mut RawInode,
+    ) -> Result<(), Errno> {
+        if inode.has_blocks(self.block_size()) {
+            self.free_blocks_from(state, inode, 0)?;
+        }
+        if inode.file_acl != 0 {
+            self.release_xattrs(state, inode.file_acl)?;
+            inode.sectors = inode.sectors.saturating_sub(self.sectors_per_block());
+            inode.file_acl = 0;
+        }
+
+        inode.links = 0;
+        inode.dtime = unix_time(Timestamp::now()).max(1);
+        self.write_inode(ino, inode)?;
+        self.free_inode(state, ino, inode.kind() == FileType::Directory)
+    }
+
+    /// Уменьшает счетчик ссылок общего блока атрибутов и освобождает его,
+    /// когда ссылок не остается.
+    fn release_xattrs(&self, state: &mut State, block: u32) -> Result<(), Errno> {
+        self.check_block(block)?;
+        let offset = self.block_offset(block);
+        let mut header = [0; 8];
+        self.disk.read(offset, &mut header)?;
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let references = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if magic != XATTR_MAGIC {
+            return Err(Errno::EIO);
+        }
+
+        match references {
+            0 | 1 => self.free_block(state, block),
+            _ => self.disk.write(offset + 4, &(references - 1).to_le_bytes()),
+        }
+    }
+}
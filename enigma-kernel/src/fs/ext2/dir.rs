@@ -0,0 +1,265 @@
+//! Записи каталогов.
+//!
+//! Каталог - файл из блоков, разбитых на записи переменной длины: номер
+//! inode, длина записи, длина имени, тип файла и само имя. Записи не
+//! пересекают границу блока; свободное место - хвост предыдущей записи
+//! или запись с нулевым номером inode.
+
+use super::raw::{INDEX_FLAG, RawInode};
+use super::{State, Volume};
+use crate::errno::Errno;
+use alloc::{string::String, vec, vec::Vec};
+
+/// Размер заголовка записи.
+const HEADER_SIZE: usize = 8;
+
+/// Запись каталога.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Entry {
+    pub ino: u32,
+    pub name: String,
+    /// Код типа файла (0 - неизвестен или том без `filetype`).
+    pub file_type: u8,
+    /// Смещение записи от начала каталога.
+    pub offset: u64,
+    /// Смещение предыдущей записи того же блока.
+    pub previous: Option<u64>,
+    pub len: u16,
+}
+
+/// Место, которое занимает запись с именем длины `name_len`.
+fn entry_size(name_len: usize) -> usize {
+    (HEADER_SIZE + name_len).next_multiple_of(4)
+}
+
+fn encode_header(bytes: &mut [u8], ino: u32, len: u16, name_len: usize, file_type: u8) {
+    bytes[0..4].copy_from_slice(&ino.to_le_bytes());
+    bytes[4..6].copy_from_slice(&len.to_le_bytes());
+    bytes[6] = name_len as u8;
+    bytes[7] = file_type;
+}
+
+impl Volume {
+    /// Блок каталога `index` (в каталогах дыр не бывает).
+    fn dir_block(&self, dir: &RawInode, index: u64) -> Result<(u32, Vec<u8>), Errno> {
+        let block = match self.map(dir, index)? {
+            0 => return Err(Errno::EIO),
+            block => block,
+        };
+        let mut data = vec![0; self.block_size()];
+        self.disk.read(self.block_offset(block), &mut data)?;
+        Ok((block, data))
+    }
+
+    /// Все занятые записи каталога, включая `.` и `..`.
+    pub(super) fn entries(&self, dir: &RawInode) -> Result<Vec<Entry>, Errno> {
+        let block_size = self.block_size();
+        if dir.size % block_size as u64 != 0 {
+            return Err(Errno::EIO);
+        }
+
+        let mut entries = Vec::new();
+        for index in 0..dir.size / block_size as u64 {
+            let (_, data) = self.dir_block(dir, index)?;
+            let mut position = 0;
+            let mut previous = None;
+
+            while position < block_size {
+                let header = &data[position..position + HEADER_SIZE];
+                let ino = u32::from_le_bytes(header[0..4].try_into().unwrap());
+                let len = u16::from_le_bytes([header[4], header[5]]);
+                let name_len = header[6] as usize;
+
+                if len as usize % 4 != 0
+                    || (len as usize) < entry_size(name_len)
+                    || position + len as usize > block_size
+                {
+                    return Err(Errno::EIO);
+                }
+
+                let offset = index * block_size as u64 + position as u64;
+                if ino != 0 {
+                    let name = &data[position + HEADER_SIZE..position + HEADER_SIZE + name_len];
+                    entries.push(Entry {
+                        ino,
+                        name: String::from_utf8_lossy(name).into(),
+                        file_type: match self.superblock.file_types {
+                            true => header[7],
+                            false => 0,
+                        },
+                        offset,
+                        previous,
+                        len,
+                    });
+                }
+                previous = Some(offset);
+                position += len as usize;
+            }
+        }
+        Ok(entries)
+    }
+
+    pub(super) fn find(&self, dir: &RawInode, name: &str) -> Result<Option<Entry>, Errno> {
+        Ok(self
+            .entries(dir)?
+            .into_iter()
+            .find(|entry| entry.name == name))
+    }
+
+    /// Каталог пуст (кроме `.` и `..`).
+    pub(super) fn is_empty_dir(&self, dir: &RawInode) -> Result<bool, Errno> {
+        Ok(self
+            .entries(dir)?
+            .iter()
+            .all(|entry| entry.name == "." || entry.name == ".."))
+    }
+
+    /// Смещение на устройстве байта `offset` каталога.
+    fn dir_offset(&self, dir: &RawInode, offset: u64) -> Result<u64, Errno> {
+        let block_size = self.block_size() as u64;
+        match self.map(dir, offset / block_size)? {
+            0 => Err(Errno::EIO),
+            block => Ok(self.block_offset(block) + offset % block_size),
+        }
+    }
+
+    /// Добавляет запись `name` -> `ino` в каталог `dir_ino`, при
+    /// необходимости добавляя в каталог блок.
+    pub(super) fn add_entry(
+        &self,
+        state: &mut State,
+        dir_ino: u32,
+        dir: &mut RawInode,
+        name: &str,
+        ino: u32,
+        file_type: u8,
+    ) -> Result<(), Errno> {
+        let block_size = self.block_size();
+        let needed = entry_size(name.len());
+        let file_type = match self.superblock.file_types {
+            true => file_type,
+            false => 0,
+        };
+
+        let write = |data: &mut [u8], position: usize, len: usize| {
+            encode_header(
+                &mut data[position..],
+                ino,
+                len as u16,
+                name.len(),
+                file_type,
+            );
+            let name_start = position + HEADER_SIZE;
+            data[name_start..name_start + name.len()].copy_from_slice(name.as_bytes());
+        };
+
+        for index in 0..dir.size / block_size as u64 {
+            let (block, mut data) = self.dir_block(dir, index)?;
+            let mut position = 0;
+            while position < block_size {
+                let entry_ino =
+                    u32::from_le_bytes(data[position..position + 4].try_into().unwrap());
+                let len = u16::from_le_bytes([data[position + 4], data[position + 5]]) as usize;
+                let name_len = data[position + 6] as usize;
+                if len < HEADER_SIZE || position + len > block_size {
+                    return Err(Errno::EIO);
+                }
+
+                let used = match entry_ino {
+                    0 => 0,
+                    _ => entry_size(name_len),
+                };
+                if len - used >= needed {
+                    if used == 0 {
+                        write(&mut data, position, len);
+                    } else {
+                        data[position + 4..position + 6]
+                            .copy_from_slice(&(used as u16).to_le_bytes());
+                        write(&mut data, position + used, len - used);
+                    }
+                    self.disk.write(self.block_offset(block), &data)?;
+                    return self.dir_changed(dir_ino, dir);
+                }
+                position += len;
+            }
+        }
+
+        // Свободного места нет: новый блок целиком под одну запись.
+        let mut data = vec![0; block_size];
+        write(&mut data, 0, block_size);
+        self.write_data(state, dir_ino, dir, dir.size, &data)?;
+        self.dir_changed(dir_ino, dir)
+    }
+
+    /// Удаляет запись `entry`: присоединяет ее место к предыдущей записи
+    /// блока или, если она первая, обнуляет номер inode.
+    pub(super) fn remove_entry(
+        &self,
+        dir_ino: u32,
+        dir: &mut RawInode,
+        entry: &Entry,
+    ) -> Result<(), Errno> {
+        match entry.previous {
+            Some(previous) => {
+                let offset = self.dir_offset(dir, previous)?;
+                let mut header = [0; 6];
+                self.disk.read(offset, &mut header)?;
+                let len = u16::from_le_bytes([header[4], header[5]]) + entry.len;
+                self.disk.write(offset + 4, &len.to_le_bytes())?;
+            }
+            None => {
+                let offset = self.dir_offset(dir, entry.offset)?;
+                self.disk.write(offset, &0u32.to_le_bytes())?;
+            }
+        }
+        self.dir_changed(dir_ino, dir)
+    }
+
+    /// Перенаправляет запись `entry` на inode `ino`.
+    pub(super) fn retarget_entry(
+        &self,
+        dir_ino: u32,
+        dir: &mut RawInode,
+        entry: &Entry,
+        ino: u32,
+        file_type: u8,
+    ) -> Result<(), Errno> {
+        let offset = self.dir_offset(dir, entry.offset)?;
+        self.disk.write(offset, &ino.to_le_bytes())?;
+        if self.superblock.file_types {
+            self.disk.write(offset + 7, &[file_type])?;
+        }
+        self.dir_changed(dir_ino, dir)
+    }
+
+    /// Первый блок нового каталога с записями `.` и `..`.
+    pub(super) fn init_dir(&self, block: u32, ino: u32, parent: u32) -> Result<(), Errno> {
+        let block_size = self.block_size();
+        let file_type = match self.superblock.file_types {
+            true => super::raw::type_code(crate::fs::vfs::FileType::Directory),
+            false => 0,
+        };
+
+        let mut data = vec![0; block_size];
+        let dot = entry_size(1);
+        encode_header(&mut data, ino, dot as u16, 1, file_type);
+        data[HEADER_SIZE] = b'.';
+        encode_header(
+            &mut data[dot..],
+            parent,
+            (block_size - dot) as u16,
+            2,
+            file_type,
+        );
+        data[dot + HEADER_SIZE..dot + HEADER_SIZE + 2].copy_from_slice(b"..");
+        self.disk.write(self.block_offset(block), &data)
+    }
+
+    /// Записывает измененный каталог. Индекс хеш-дерева драйвер не
+    /// поддерживает, поэтому после изменения каталог считается линейным.
+    fn dir_changed(&self, dir_ino: u32, dir: &mut RawInode) -> Result<(), Errno> {
+        dir.flags &= !INDEX_FLAG;
+        dir.touch_mtime();
+        self.write_inode(dir_ino, dir)
+    }
+}
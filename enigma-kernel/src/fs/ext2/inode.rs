@@ -0,0 +1,590 @@
+//! Файлы и каталоги тома ext2.
+
+use super::dir::Entry;
+use super::raw::{RawInode, timestamp, type_code, unix_time};
+use super::superblock::ROOT_INO;
+use super::{State, Volume};
+use crate::errno::Errno;
+use crate::fs::vfs::{DirEntry, FileType, Inode, Metadata, Timestamp};
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::any::Any;
+use spin::{Mutex, MutexGuard};
+
+/// После скольких загруженных inode кэш чистится от освобожденных.
+const CACHE_PRUNE: usize = 256;
+/// Предел жестких ссылок (как в Linux).
+const LINK_MAX: u16 = 32000;
+
+pub(super) struct Ext2Inode {
+    volume: Arc<Volume>,
+    this: Weak<Ext2Inode>,
+    ino: u32,
+    kind: FileType,
+    /// Копия inode. Изменения сразу пишутся на диск.
+    node: Mutex<RawInode>,
+}
+
+impl Volume {
+    /// Inode `ino` (из кэша или с диска).
+    pub(super) fn load(self: &Arc<Self>, ino: u32) -> Result<Arc<Ext2Inode>, Errno> {
+        if let Some(inode) = self.inodes.lock().get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+
+        let raw = self.read_inode(ino)?;
+        // Запись каталога ссылается на удаленный inode.
+        if raw.links == 0 || raw.mode == 0 {
+            return Err(Errno::EIO);
+        }
+
+        let inode = Arc::new_cyclic(|this| Ext2Inode {
+            volume: self.clone(),
+            this: this.clone(),
+            ino,
+            kind: raw.kind(),
+            node: Mutex::new(raw),
+        });
+        let mut inodes = self.inodes.lock();
+        if inodes.len() >= CACHE_PRUNE {
+            inodes.retain(|_, inode| inode.strong_count() > 0);
+        }
+        inodes.insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    /// Меняет inode `ino` функцией `change` и записывает его. Если inode
+    /// загружен, меняется его копия в памяти.
+    fn update_inode<T>(
+        &self,
+        ino: u32,
+        change: impl FnOnce(&mut RawInode) -> Result<T, Errno>,
+    ) -> Result<T, Errno> {
+        let cached = self.inodes.lock().get(&ino).and_then(Weak::upgrade);
+        let (mut node, mut raw);
+        let inode = match &cached {
+            Some(inode) => {
+                node = inode.node.lock();
+                &mut *node
+            }
+            None => {
+                raw = self.read_inode(ino)?;
+                &mut raw
+            }
+        };
+
+        let result = change(inode);
+        self.write_inode(ino, inode)?;
+        result
+    }
+
+    /// Убирает ссылку на inode `ino` (у каталога - все ссылки). Inode без
+    /// ссылок удаляется сразу, а открытый - после закрытия.
+    fn drop_link(&self, state: &mut State, ino: u32) -> Result<(), Errno> {
+        let mut raw = self.update_inode(ino, |raw| {
+            raw.links = match raw.kind() {
+                FileType::Directory => 0,
+                _ => raw.links.saturating_sub(1),
+            };
+            raw.touch_ctime();
+            Ok(raw.clone())
+        })?;
+
+        let open = self
+            .inodes
+            .lock()
+            .get(&ino)
+            .is_some_and(|inode| inode.strong_count() > 0);
+        match raw.links == 0 && !open {
+            true => self.destroy_inode(state, ino, &mut raw),
+            false => Ok(()),
+        }
+    }
+
+    /// Удаляет закрытые inode без ссылок. Суперблок переписывается сразу:
+    /// операция, которая разблокирует том, может завершиться ошибкой.
+    pub(super) fn free_orphans(&self, state: &mut State) -> Result<(), Errno> {
+        let orphans = core::mem::take(&mut *self.orphans.lock());
+        for ino in orphans {
+            let mut raw = self.read_inode(ino)?;
+            if raw.links == 0 && raw.dtime == 0 {
+                self.destroy_inode(state, ino, &mut raw)?;
+            }
+        }
+        self.commit(state)
+    }
+
+    /// Номер родителя каталога `ino` (из записи `..`).
+    fn parent_of(&self, ino: u32) -> Result<u32, Errno> {
+        let dir = self.read_inode(ino)?;
+        let entry = self.find(&dir, "..")?.ok_or(Errno::EIO)?;
+        Ok(entry.ino)
+    }
+}
+
+impl Ext2Inode {
+    fn this(&self) -> Arc<Ext2Inode> {
+        self.this.upgrade().unwrap()
+    }
+
+    pub(super) fn is_directory(&self) -> bool {
+        self.kind == FileType::Directory
+    }
+
+    /// Блокирует том и удаляет закрытые inode без ссылок.
+    fn lock_volume(&self) -> Result<MutexGuard<'_, State>, Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        Ok(state)
+    }
+
+    /// Копия каталога, в котором можно создавать и удалять записи.
+    fn live_dir(&self) -> Result<MutexGuard<'_, RawInode>, Errno> {
+        if !self.is_directory() {
+            return Err(Errno::ENOTDIR);
+        }
+        let node = self.node.lock();
+        match node.links {
+            0 => Err(Errno::ENOENT),
+            _ => Ok(node),
+        }
+    }
+
+    fn find(&self, name: &str) -> Result<Entry, Errno> {
+        let dir = self.live_dir()?;
+        self.volume.find(&dir, name)?.ok_or(Errno::ENOENT)
+    }
+
+    /// Тип файла записи каталога.
+    fn entry_kind(&self, entry: &Entry) -> Result<FileType, Errno> {
+        let kind = match entry.file_type {
+            1 => FileType::Regular,
+            2 => FileType::Directory,
+            3 => FileType::CharDevice,
+            4 => FileType::BlockDevice,
+            5 => FileType::Fifo,
+            6 => FileType::Socket,
+            7 => FileType::Symlink,
+            _ => self.volume.read_inode(entry.ino)?.kind(),
+        };
+        Ok(kind)
+    }
+
+    /// Создает inode `raw` и запись `name` на него. `target` - цель
+    /// символической ссылки.
+    fn create_node(
+        &self,
+        name: &str,
+        mut raw: RawInode,
+        target: Option<&str>,
+    ) -> Result<Arc<dyn Inode>, Errno> {
+        let volume = &self.volume;
+        let kind = raw.kind();
+        let mut state = self.lock_volume()?;
+        if volume.find(&*self.live_dir()?, name)?.is_some() {
+            return Err(Errno::EEXIST);
+        }
+
+        let directory = kind == FileType::Directory;
+        let ino = volume.allocate_inode(&mut state, self.ino, directory)?;
+        let result = (|| {
+            volume.init_inode(ino, &raw)?;
+            match (kind, target) {
+                (FileType::Directory, _) => {
+                    let block = vec![0; volume.block_size()];
+                    volume.write_data(&mut state, ino, &mut raw, 0, &block)?;
+                    volume.init_dir(volume.map(&raw, 0)?, ino, self.ino)?;
+                    raw.links = 2;
+                }
+                (FileType::Symlink, Some(target)) if target.len() < raw.inline_data().len() => {
+                    raw.set_inline_data(target.as_bytes());
+                    raw.size = target.len() as u64;
+                }
+                (FileType::Symlink, Some(target)) => {
+                    let written =
+                        volume.write_data(&mut state, ino, &mut raw, 0, target.as_bytes())?;
+                    if written < target.len() {
+                        return Err(Errno::ENOSPC);
+                    }
+                }
+                _ => {}
+            }
+            volume.write_inode(ino, &raw)?;
+
+            let mut dir = self.live_dir()?;
+            volume.add_entry(&mut state, self.ino, &mut dir, name, ino, type_code(kind))?;
+            if directory {
+                dir.links += 1;
+                volume.write_inode(self.ino, &dir)?;
+            }
+            Ok(())
+        })();
+
+        if let Err(error) = result {
+            raw.links = 0;
+            volume.destroy_inode(&mut state, ino, &mut raw)?;
+            volume.commit(&mut state)?;
+            return Err(error);
+        }
+        volume.commit(&mut state)?;
+        drop(state);
+        Ok(volume.load(ino)?)
+    }
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        if self.node.lock().links == 0 {
+            self.volume.orphans.lock().push(self.ino);
+        }
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn ino(&self) -> u64 {
+        self.ino as u64
+    }
+
+    fn metadata(&self) -> Result<Metadata, Errno> {
+        let node = self.node.lock();
+        Ok(Metadata {
+            ino: self.ino as u64,
+            kind: self.kind,
+            mode: node.mode & 0o7777,
+            nlink: node.links as u32,
+            uid: node.uid,
+            gid: node.gid,
+            size: node.size,
+            atime: timestamp(node.atime),
+            mtime: timestamp(node.mtime),
+            ctime: timestamp(node.ctime),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+        match self.kind {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Errno::EISDIR),
+            _ => return Err(Errno::EINVAL),
+        }
+
+        // Время доступа не обновляется: иначе каждое чтение было бы записью.
+        let _state = self.volume.state.lock();
+        let node = self.node.lock();
+        self.volume.read_data(&node, offset, buffer)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, Errno> {
+        match self.kind {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Errno::EISDIR),
+            _ => return Err(Errno::EINVAL),
+        }
+
+        let mut state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        let result = self
+            .volume
+            .write_data(&mut state, self.ino, &mut node, offset, buffer);
+        self.volume.write_inode(self.ino, &node)?;
+        drop(node);
+        self.volume.commit(&mut state)?;
+        result
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), Errno> {
+        match self.kind {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Errno::EISDIR),
+            _ => return Err(Errno::EINVAL),
+        }
+
+        let mut state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        let result = self.volume.resize(&mut state, &mut node, size);
+        self.volume.write_inode(self.ino, &node)?;
+        drop(node);
+        self.volume.commit(&mut state)?;
+        result
+    }
+
+    fn set_times(&self, atime: Option<Timestamp>, mtime: Option<Timestamp>) -> Result<(), Errno> {
+        let _state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        if let Some(atime) = atime {
+            node.atime = unix_time(atime);
+        }
+        if let Some(mtime) = mtime {
+            node.mtime = unix_time(mtime);
+        }
+        node.touch_ctime();
+        self.volume.write_inode(self.ino, &node)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        if name == "." {
+            return Ok(self.this());
+        }
+
+        let _state = self.volume.state.lock();
+        let entry = {
+            let dir = self.node.lock();
+            if !self.is_directory() {
+                return Err(Errno::ENOTDIR);
+            }
+            self.volume.find(&dir, name)?.ok_or(Errno::ENOENT)?
+        };
+        Ok(self.volume.load(entry.ino)?)
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> Result<Arc<dyn Inode>, Errno> {
+        if kind == FileType::Symlink {
+            return Err(Errno::EINVAL);
+        }
+        self.create_node(name, RawInode::new(kind, mode), None)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        // Длинная цель занимает один блок данных.
+        if target.len() >= self.volume.block_size() {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        self.create_node(name, RawInode::new(FileType::Symlink, 0o777), Some(target))
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        let target = target
+            .as_any()
+            .downcast_ref::<Ext2Inode>()
+            .filter(|target| Arc::ptr_eq(&target.volume, &self.volume))
+            .ok_or(Errno::EXDEV)?
+            .this();
+        if target.is_directory() {
+            return Err(Errno::EPERM);
+        }
+
+        let mut state = self.lock_volume()?;
+        if self.volume.find(&*self.live_dir()?, name)?.is_some() {
+            return Err(Errno::EEXIST);
+        }
+        match target.node.lock().links {
+            0 => return Err(Errno::ENOENT),
+            LINK_MAX.. => return Err(Errno::EMLINK),
+            _ => {}
+        }
+
+        let mut dir = self.live_dir()?;
+        self.volume.add_entry(
+            &mut state,
+            self.ino,
+            &mut dir,
+            name,
+            target.ino,
+            type_code(target.kind),
+        )?;
+        drop(dir);
+
+        let mut node = target.node.lock();
+        node.links += 1;
+        node.touch_ctime();
+        self.volume.write_inode(target.ino, &node)?;
+        drop(node);
+        self.volume.commit(&mut state)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let mut state = self.lock_volume()?;
+        let entry = self.find(name)?;
+        if self.entry_kind(&entry)? == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+
+        let mut dir = self.live_dir()?;
+        self.volume.remove_entry(self.ino, &mut dir, &entry)?;
+        drop(dir);
+        self.volume.drop_link(&mut state, entry.ino)?;
+        self.volume.commit(&mut state)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        let mut state = self.lock_volume()?;
+        let entry = self.find(name)?;
+        if self.entry_kind(&entry)? != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        if !self
+            .volume
+            .is_empty_dir(&self.volume.read_inode(entry.ino)?)?
+        {
+            return Err(Errno::ENOTEMPTY);
+        }
+
+        let mut dir = self.live_dir()?;
+        self.volume.remove_entry(self.ino, &mut dir, &entry)?;
+        // Запись `..` удаленного каталога ссылалась на этот.
+        dir.links -= 1;
+        self.volume.write_inode(self.ino, &dir)?;
+        drop(dir);
+        self.volume.drop_link(&mut state, entry.ino)?;
+        self.volume.commit(&mut state)
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        new_parent: &Arc<dyn Inode>,
+        new_name: &str,
+    ) -> Result<(), Errno> {
+        let new_parent = new_parent
+            .as_any()
+            .downcast_ref::<Ext2Inode>()
+            .filter(|parent| Arc::ptr_eq(&parent.volume, &self.volume))
+            .ok_or(Errno::EXDEV)?
+            .this();
+        let volume = &self.volume;
+        let same_dir = new_parent.ino == self.ino;
+
+        let mut state = self.lock_volume()?;
+        let source = self.find(old_name)?;
+        let kind = self.entry_kind(&source)?;
+        let is_directory = kind == FileType::Directory;
+
+        // Каталог нельзя перенести в самого себя или своего потомка.
+        if is_directory && !same_dir {
+            let mut current = new_parent.ino;
+            while current != ROOT_INO {
+                if current == source.ino {
+                    return Err(Errno::EINVAL);
+                }
+                current = volume.parent_of(current)?;
+            }
+        }
+
+        let replaced = match volume.find(&*new_parent.live_dir()?, new_name)? {
+            // Та же запись или другая ссылка на тот же файл: ничего не делать.
+            Some(entry) if entry.ino == source.ino => return Ok(()),
+            entry => entry,
+        };
+        if let Some(replaced) = &replaced {
+            match (
+                is_directory,
+                new_parent.entry_kind(replaced)? == FileType::Directory,
+            ) {
+                (true, false) => return Err(Errno::ENOTDIR),
+                (false, true) => return Err(Errno::EISDIR),
+                (true, true) if !volume.is_empty_dir(&volume.read_inode(replaced.ino)?)? => {
+                    return Err(Errno::ENOTEMPTY);
+                }
+                _ => {}
+            }
+        }
+
+        // Сначала новая запись: если места нет, ничего не меняется.
+        {
+            let mut dir = new_parent.live_dir()?;
+            match &replaced {
+                Some(replaced) => volume.retarget_entry(
+                    new_parent.ino,
+                    &mut dir,
+                    replaced,
+                    source.ino,
+                    type_code(kind),
+                )?,
+                None => volume.add_entry(
+                    &mut state,
+                    new_parent.ino,
+                    &mut dir,
+                    new_name,
+                    source.ino,
+                    type_code(kind),
+                )?,
+            }
+        }
+
+        // Добавление могло сдвинуть записи того же каталога.
+        let source = self.find(old_name)?;
+        {
+            let mut dir = self.live_dir()?;
+            volume.remove_entry(self.ino, &mut dir, &source)?;
+            if is_directory && !same_dir {
+                dir.links -= 1;
+                volume.write_inode(self.ino, &dir)?;
+            }
+        }
+
+        if let Some(replaced) = &replaced {
+            if is_directory {
+                let mut dir = new_parent.live_dir()?;
+                dir.links -= 1;
+                volume.write_inode(new_parent.ino, &dir)?;
+            }
+            volume.drop_link(&mut state, replaced.ino)?;
+        }
+
+        if is_directory && !same_dir {
+            volume.update_inode(source.ino, |moved| {
+                let dot_dot = volume.find(moved, "..")?.ok_or(Errno::EIO)?;
+                volume.retarget_entry(source.ino, moved, &dot_dot, new_parent.ino, type_code(kind))
+            })?;
+
+            let mut dir = new_parent.live_dir()?;
+            dir.links += 1;
+            volume.write_inode(new_parent.ino, &dir)?;
+        }
+        volume.update_inode(source.ino, |moved| {
+            moved.touch_ctime();
+            Ok(())
+        })?;
+        volume.commit(&mut state)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        let _state = self.volume.state.lock();
+        let entries = {
+            let dir = self.node.lock();
+            if !self.is_directory() {
+                return Err(Errno::ENOTDIR);
+            }
+            self.volume.entries(&dir)?
+        };
+
+        entries
+            .into_iter()
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .map(|entry| {
+                Ok(DirEntry {
+                    kind: self.entry_kind(&entry)?,
+                    ino: entry.ino as u64,
+                    name: entry.name,
+                })
+            })
+            .collect()
+    }
+
+    fn read_link(&self) -> Result<String, Errno> {
+        if self.kind != FileType::Symlink {
+            return Err(Errno::EINVAL);
+        }
+
+        let _state = self.volume.state.lock();
+        let node = self.node.lock();
+        let size = node.size as usize;
+        let target = match node.is_fast_symlink(self.volume.block_size()) {
+            true => node.inline_data().get(..size).ok_or(Errno::EIO)?.to_vec(),
+            false => {
+                let mut target = vec![0; size];
+                self.volume.read_data(&node, 0, &mut target)?;
+                target
+            }
+        };
+        String::from_utf8(target).map_err(|_| Errno::EIO)
+    }
+}
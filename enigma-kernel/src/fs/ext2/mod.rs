@@ -0,0 +1,188 @@
+//! Драйвер ext2 (чтение и запись).
+//!
+//! Том читается и пишется через [`BlockDevice`]. Метаданные (битовые карты,
+//! дескрипторы групп, inode и каталоги) меняются под одной блокировкой тома
+//! и сразу пишутся на диск; суперблок со счетчиками свободного места
+//! переписывается в конце каждой операции.
+//!
+//! Поддерживаются ревизии 0 и 1 с возможностями `filetype`, `sparse_super`
+//! и `large_file`. Тома с другими возможностями, которые нельзя безопасно
+//! игнорировать при записи, монтируются только для чтения, а с
+//! несовместимыми (экстенты, 64-битные номера) не монтируются вовсе.
+
+mod bitmap;
+mod blocks;
+mod dir;
+mod inode;
+mod raw;
+mod superblock;
+
+use super::disk::Disk;
+use super::vfs::{FileSystem, Inode, Timestamp};
+use crate::drivers::block::BlockDevice;
+use crate::errno::Errno;
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use inode::Ext2Inode;
+use spin::Mutex;
+use superblock::{
+    DESCRIPTOR_SIZE, GroupDescriptor, ROOT_INO, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE, Superblock,
+};
+
+pub struct Ext2Fs {
+    volume: Arc<Volume>,
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2Fs {
+    /// Открывает том на `device`. Возвращает `EINVAL`, если на устройстве
+    /// нет правильного суперблока ext2 или том использует несовместимые
+    /// возможности.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, Errno> {
+        let disk = Disk::new(device);
+        let mut raw = [0; SUPERBLOCK_SIZE];
+        disk.read(SUPERBLOCK_OFFSET, &mut raw)?;
+        let superblock = Superblock::parse(&raw, disk.size())?;
+
+        let mut table = vec![0; superblock.group_count as usize * DESCRIPTOR_SIZE];
+        let table_offset = superblock.block_offset(superblock.descriptors_block());
+        disk.read(table_offset, &mut table)?;
+
+        let groups = table
+            .chunks_exact(DESCRIPTOR_SIZE)
+            .map(GroupDescriptor::parse)
+            .collect::<Vec<_>>();
+        for group in &groups {
+            group.check(&superblock)?;
+        }
+        let free_blocks = groups.iter().map(|group| group.free_blocks as u32).sum();
+        let free_inodes = groups.iter().map(|group| group.free_inodes as u32).sum();
+        if free_blocks > superblock.blocks_count || free_inodes > superblock.inodes_count {
+            return Err(Errno::EINVAL);
+        }
+
+        let volume = Arc::new(Volume {
+            disk,
+            read_only: superblock.read_only,
+            inode_tables: groups.iter().map(|group| group.inode_table).collect(),
+            state: Mutex::new(State {
+                ro_compat: superblock.ro_compat(),
+                groups,
+                free_blocks,
+                free_inodes,
+                dirty: false,
+            }),
+            superblock,
+            inodes: Mutex::new(BTreeMap::new()),
+            orphans: Mutex::new(Vec::new()),
+        });
+
+        let root = volume.load(ROOT_INO)?;
+        if !root.is_directory() {
+            return Err(Errno::EINVAL);
+        }
+        Ok(Arc::new(Self { volume, root }))
+    }
+
+    /// Имя тома из суперблока.
+    pub fn label(&self) -> String {
+        String::from_utf8_lossy(self.volume.superblock.label()).into()
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.volume.superblock.block_size
+    }
+
+    /// Количество свободных блоков.
+    pub fn free_blocks(&self) -> Result<u32, Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        Ok(state.free_blocks)
+    }
+
+    /// Количество свободных inode.
+    pub fn free_inodes(&self) -> Result<u32, Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        Ok(state.free_inodes)
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.volume.read_only || self.volume.disk.device.is_read_only()
+    }
+
+    fn sync(&self) -> Result<(), Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        self.volume.commit(&mut state)?;
+        self.volume.disk.device.flush()
+    }
+}
+
+/// Общие данные тома.
+struct Volume {
+    disk: Disk,
+    superblock: Superblock,
+    read_only: bool,
+    /// Первые блоки таблиц inode по группам (не меняются).
+    inode_tables: Vec<u32>,
+    /// Блокировка метаданных. Порядок блокировок: `state`, затем узлы
+    /// inode (не больше одного сразу); `inodes` и `orphans` берутся
+    /// последними и ненадолго.
+    state: Mutex<State>,
+    /// Загруженные inode по номерам.
+    inodes: Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+    /// Удаленные файлы, которые закрылись. Освобождаются при следующей
+    /// операции с томом: `Drop` не может ждать `state`.
+    orphans: Mutex<Vec<u32>>,
+}
+
+/// Изменяемая часть метаданных тома.
+struct State {
+    groups: Vec<GroupDescriptor>,
+    free_blocks: u32,
+    free_inodes: u32,
+    /// Поле `s_feature_ro_compat` (драйвер может включить `large_file`).
+    ro_compat: u32,
+    /// Суперблок нужно переписать.
+    dirty: bool,
+}
+
+impl Volume {
+    /// Записывает суперблок, если счетчики изменились.
+    fn commit(&self, state: &mut State) -> Result<(), Errno> {
+        if state.dirty {
+            let now = Timestamp::now().secs.clamp(0, u32::MAX as i64) as u32;
+            let raw =
+                self.superblock
+                    .encode(state.free_blocks, state.free_inodes, state.ro_compat, now);
+            self.disk.write(SUPERBLOCK_OFFSET, &raw)?;
+            state.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Смещение блока `block` на устройстве.
+    fn block_offset(&self, block: u32) -> u64 {
+        self.superblock.block_offset(block)
+    }
+
+    fn block_size(&self) -> usize {
+        self.superblock.block_size as usize
+    }
+}
@@ -0,0 +1,250 @@
+//! Inode на диске.
+
+use super::Volume;
+use crate::errno::Errno;
+use crate::fs::vfs::{FileType, Timestamp};
+use alloc::vec;
+
+/// Количество указателей на блоки в inode: 12 прямых и по одному
+/// одно-, двух- и трехкратно косвенному.
+pub(super) const BLOCK_POINTERS: usize = 15;
+/// Размер полей inode ревизии 0; остаток большего inode сохраняется.
+const BASE_SIZE: usize = 128;
+
+const S_IFMT: u16 = 0xF000;
+const S_IFSOCK: u16 = 0xC000;
+const S_IFLNK: u16 = 0xA000;
+const S_IFREG: u16 = 0x8000;
+const S_IFBLK: u16 = 0x6000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFIFO: u16 = 0x1000;
+
+/// Каталог проиндексирован хеш-деревом (`dir_index`).
+pub(super) const INDEX_FLAG: u32 = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct RawInode {
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    pub dtime: u32,
+    pub links: u16,
+    /// Занятое место в секторах по 512 байт, включая косвенные блоки.
+    pub sectors: u32,
+    pub flags: u32,
+    pub block: [u32; BLOCK_POINTERS],
+    /// Блок расширенных атрибутов.
+    pub file_acl: u32,
+}
+
+impl RawInode {
+    /// Новый inode типа `kind` с правами `mode`.
+    pub fn new(kind: FileType, mode: u16) -> Self {
+        let now = unix_time(Timestamp::now());
+        Self {
+            mode: type_bits(kind) | (mode & 0o7777),
+            uid: 0,
+            gid: 0,
+            size: 0,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+            links: 1,
+            sectors: 0,
+            flags: 0,
+            block: [0; BLOCK_POINTERS],
+            file_acl: 0,
+        }
+    }
+
+    fn parse(raw: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+
+        let mode = u16_at(0);
+        let mut size = u32_at(4) as u64;
+        // Старшая половина размера есть только у обычных файлов; у
+        // каталогов в этом поле исторически лежит `i_dir_acl`.
+        if mode & S_IFMT == S_IFREG {
+            size |= (u32_at(108) as u64) << 32;
+        }
+
+        Self {
+            mode,
+            uid: u16_at(2) as u32 | (u16_at(120) as u32) << 16,
+            gid: u16_at(24) as u32 | (u16_at(122) as u32) << 16,
+            size,
+            atime: u32_at(8),
+            ctime: u32_at(12),
+            mtime: u32_at(16),
+            dtime: u32_at(20),
+            links: u16_at(26),
+            sectors: u32_at(28),
+            flags: u32_at(32),
+            block: core::array::from_fn(|index| u32_at(40 + index * 4)),
+            file_acl: u32_at(104),
+        }
+    }
+
+    fn encode(&self, raw: &mut [u8]) {
+        let mut put = |offset: usize, bytes: &[u8]| {
+            raw[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        put(0, &self.mode.to_le_bytes());
+        put(2, &(self.uid as u16).to_le_bytes());
+        put(4, &(self.size as u32).to_le_bytes());
+        put(8, &self.atime.to_le_bytes());
+        put(12, &self.ctime.to_le_bytes());
+        put(16, &self.mtime.to_le_bytes());
+        put(20, &self.dtime.to_le_bytes());
+        put(24, &(self.gid as u16).to_le_bytes());
+        put(26, &self.links.to_le_bytes());
+        put(28, &self.sectors.to_le_bytes());
+        put(32, &self.flags.to_le_bytes());
+        for (index, block) in self.block.iter().enumerate() {
+            put(40 + index * 4, &block.to_le_bytes());
+        }
+        put(104, &self.file_acl.to_le_bytes());
+        if self.kind() == FileType::Regular {
+            put(108, &((self.size >> 32) as u32).to_le_bytes());
+        }
+        put(120, &((self.uid >> 16) as u16).to_le_bytes());
+        put(122, &((self.gid >> 16) as u16).to_le_bytes());
+    }
+
+    pub fn kind(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFIFO => FileType::Fifo,
+            S_IFSOCK => FileType::Socket,
+            _ => FileType::Regular,
+        }
+    }
+
+    /// Данные и косвенные блоки описаны полем `block` (у устройств там
+    /// номер устройства, у коротких ссылок - сама цель).
+    pub fn has_blocks(&self, block_size: usize) -> bool {
+        match self.kind() {
+            FileType::Regular | FileType::Directory => true,
+            FileType::Symlink => !self.is_fast_symlink(block_size),
+            _ => false,
+        }
+    }
+
+    /// Цель ссылки хранится прямо в поле `block`.
+    pub fn is_fast_symlink(&self, block_size: usize) -> bool {
+        let acl_sectors = match self.file_acl {
+            0 => 0,
+            _ => block_size as u32 / 512,
+        };
+        self.kind() == FileType::Symlink && self.sectors == acl_sectors
+    }
+
+    /// Байты поля `block` (для коротких ссылок).
+    pub fn inline_data(&self) -> [u8; BLOCK_POINTERS * 4] {
+        let mut data = [0; BLOCK_POINTERS * 4];
+        for (chunk, block) in data.chunks_exact_mut(4).zip(&self.block) {
+            chunk.copy_from_slice(&block.to_le_bytes());
+        }
+        data
+    }
+
+    pub fn set_inline_data(&mut self, data: &[u8]) {
+        let mut bytes = [0; BLOCK_POINTERS * 4];
+        bytes[..data.len()].copy_from_slice(data);
+        for (block, chunk) in self.block.iter_mut().zip(bytes.chunks_exact(4)) {
+            *block = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+    }
+
+    /// Отмечает изменение атрибутов.
+    pub fn touch_ctime(&mut self) {
+        self.ctime = unix_time(Timestamp::now());
+    }
+
+    /// Отмечает изменение содержимого.
+    pub fn touch_mtime(&mut self) {
+        self.mtime = unix_time(Timestamp::now());
+        self.ctime = self.mtime;
+    }
+}
+
+/// Код типа файла в записи каталога (`filetype`).
+pub(super) fn type_code(kind: FileType) -> u8 {
+    match kind {
+        FileType::Regular => 1,
+        FileType::Directory => 2,
+        FileType::CharDevice => 3,
+        FileType::BlockDevice => 4,
+        FileType::Fifo => 5,
+        FileType::Socket => 6,
+        FileType::Symlink => 7,
+    }
+}
+
+fn type_bits(kind: FileType) -> u16 {
+    match kind {
+        FileType::Regular => S_IFREG,
+        FileType::Directory => S_IFDIR,
+        FileType::CharDevice => S_IFCHR,
+        FileType::BlockDevice => S_IFBLK,
+        FileType::Fifo => S_IFIFO,
+        FileType::Socket => S_IFSOCK,
+        FileType::Symlink => S_IFLNK,
+    }
+}
+
+/// Время в формате ext2: знаковые 32-битные секунды.
+pub(super) fn unix_time(timestamp: Timestamp) -> u32 {
+    timestamp.secs.clamp(i32::MIN as i64, i32::MAX as i64) as i32 as u32
+}
+
+pub(super) fn timestamp(time: u32) -> Timestamp {
+    Timestamp::new(time as i32 as i64, 0)
+}
+
+impl Volume {
+    fn inode_offset(&self, ino: u32) -> Result<u64, Errno> {
+        let superblock = &self.superblock;
+        if ino == 0 || ino > superblock.inodes_count {
+            return Err(Errno::EIO);
+        }
+        let group = (ino - 1) / superblock.inodes_per_group;
+        let index = (ino - 1) % superblock.inodes_per_group;
+        let table = self.block_offset(self.inode_tables[group as usize]);
+        Ok(table + index as u64 * superblock.inode_size as u64)
+    }
+
+    pub(super) fn read_inode(&self, ino: u32) -> Result<RawInode, Errno> {
+        let mut raw = [0; BASE_SIZE];
+        self.disk.read(self.inode_offset(ino)?, &mut raw)?;
+        Ok(RawInode::parse(&raw))
+    }
+
+    pub(super) fn write_inode(&self, ino: u32, inode: &RawInode) -> Result<(), Errno> {
+        let offset = self.inode_offset(ino)?;
+        let mut raw = [0; BASE_SIZE];
+        self.disk.read(offset, &mut raw)?;
+        inode.encode(&mut raw);
+        self.disk.write(offset, &raw)
+    }
+
+    /// Записывает новый inode, обнуляя его место в таблице целиком.
+    pub(super) fn init_inode(&self, ino: u32, inode: &RawInode) -> Result<(), Errno> {
+        let offset = self.inode_offset(ino)?;
+        let mut raw = vec![0; self.superblock.inode_size as usize];
+        inode.encode(&mut raw);
+        self.disk.write(offset, &raw)
+    }
+}
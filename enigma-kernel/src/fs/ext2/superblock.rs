@@ -0,0 +1,233 @@
+//! Суперблок и дескрипторы групп блоков.
+
+use crate::errno::Errno;
+
+/// Суперблок всегда лежит по смещению 1024 от начала тома.
+pub(super) const SUPERBLOCK_OFFSET: u64 = 1024;
+pub(super) const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+pub(super) const DESCRIPTOR_SIZE: usize = 32;
+/// Номер inode корневого каталога.
+pub(super) const ROOT_INO: u32 = 2;
+
+/// Записи каталогов хранят тип файла.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Резервные копии суперблока есть только в группах 0, 1 и степенях 3, 5, 7.
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+/// Размер обычных файлов может превышать 2 ГиБ.
+pub(super) const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// Разобранный суперблок. Хранит исходные байты, чтобы при записи не
+/// потерять поля, которые драйвер не понимает.
+#[derive(Clone)]
+pub(super) struct Superblock {
+    raw: [u8; SUPERBLOCK_SIZE],
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub first_data_block: u32,
+    pub block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub inode_size: u32,
+    /// Первый inode, доступный для файлов (до него - служебные).
+    pub first_ino: u32,
+    pub group_count: u32,
+    /// Записи каталогов хранят тип файла.
+    pub file_types: bool,
+    /// Том содержит неизвестные драйверу возможности, мешающие записи.
+    pub read_only: bool,
+}
+
+impl Superblock {
+    /// Проверяет и разбирает суперблок тома размером `volume_size`.
+    pub fn parse(raw: &[u8; SUPERBLOCK_SIZE], volume_size: u64) -> Result<Self, Errno> {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+
+        let revision = u32_at(76);
+        if u16_at(56) != MAGIC || revision > 1 || u32_at(24) > 6 {
+            return Err(Errno::EINVAL);
+        }
+
+        let block_size = 1024 << u32_at(24);
+        let (first_ino, inode_size, incompat, ro_compat) = match revision {
+            0 => (11, 128, 0, 0),
+            _ => (u32_at(84), u16_at(88) as u32, u32_at(96), u32_at(100)),
+        };
+        // Остальные несовместимые возможности (экстенты, журнал, требующий
+        // восстановления, 64-битные номера) драйвер прочитать не может.
+        if incompat & !INCOMPAT_FILETYPE != 0 {
+            return Err(Errno::EINVAL);
+        }
+
+        let superblock = Self {
+            raw: *raw,
+            inodes_count: u32_at(0),
+            blocks_count: u32_at(4),
+            first_data_block: u32_at(20),
+            block_size,
+            blocks_per_group: u32_at(32),
+            inodes_per_group: u32_at(40),
+            inode_size,
+            first_ino,
+            group_count: 0,
+            file_types: incompat & INCOMPAT_FILETYPE != 0,
+            read_only: ro_compat & !(RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE) != 0,
+        };
+
+        let bits_per_block = block_size * 8;
+        let data_blocks = superblock
+            .blocks_count
+            .checked_sub(superblock.first_data_block)
+            .filter(|&blocks| blocks > 0)
+            .ok_or(Errno::EINVAL)?;
+        if !(1..=bits_per_block).contains(&superblock.blocks_per_group)
+            || !(1..=bits_per_block).contains(&superblock.inodes_per_group)
+            || !inode_size.is_power_of_two()
+            || !(128..=block_size).contains(&inode_size)
+            || superblock.first_data_block != (block_size == 1024) as u32
+            || superblock.first_ino <= ROOT_INO
+            || superblock.blocks_count as u64 * block_size as u64 > volume_size
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let group_count = data_blocks.div_ceil(superblock.blocks_per_group);
+        if group_count as u64 * superblock.inodes_per_group as u64 != superblock.inodes_count as u64
+            || superblock.first_ino >= superblock.inodes_count
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        Ok(Self {
+            group_count,
+            ..superblock
+        })
+    }
+
+    /// Байты суперблока с новыми счетчиками свободного места и флагами
+    /// `s_feature_ro_compat`.
+    pub fn encode(
+        &self,
+        free_blocks: u32,
+        free_inodes: u32,
+        ro_compat: u32,
+        write_time: u32,
+    ) -> [u8; SUPERBLOCK_SIZE] {
+        let mut raw = self.raw;
+        raw[12..16].copy_from_slice(&free_blocks.to_le_bytes());
+        raw[16..20].copy_from_slice(&free_inodes.to_le_bytes());
+        raw[48..52].copy_from_slice(&write_time.to_le_bytes());
+        if self.revision() > 0 {
+            raw[100..104].copy_from_slice(&ro_compat.to_le_bytes());
+        }
+        raw
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.u32_at(76)
+    }
+
+    pub fn ro_compat(&self) -> u32 {
+        match self.revision() {
+            0 => 0,
+            _ => self.u32_at(100),
+        }
+    }
+
+    /// Имя тома (без завершающих нулей).
+    pub fn label(&self) -> &[u8] {
+        let name = &self.raw[120..136];
+        let len = name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(name.len());
+        &name[..len]
+    }
+
+    pub fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.block_size as u64
+    }
+
+    /// Номер блока с таблицей дескрипторов групп.
+    pub fn descriptors_block(&self) -> u32 {
+        self.first_data_block + 1
+    }
+
+    /// Первый блок группы `group`.
+    pub fn group_first_block(&self, group: u32) -> u32 {
+        self.first_data_block + group * self.blocks_per_group
+    }
+
+    /// Количество блоков в группе `group` (последняя может быть короче).
+    pub fn blocks_in_group(&self, group: u32) -> u32 {
+        (self.blocks_count - self.group_first_block(group)).min(self.blocks_per_group)
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.raw[offset..offset + 4].try_into().unwrap())
+    }
+}
+
+/// Дескриптор группы блоков.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct GroupDescriptor {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks: u16,
+    pub free_inodes: u16,
+    pub used_dirs: u16,
+    /// Остальные байты дескриптора сохраняются как есть.
+    rest: [u8; 14],
+}
+
+impl GroupDescriptor {
+    pub fn parse(raw: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        Self {
+            block_bitmap: u32_at(0),
+            inode_bitmap: u32_at(4),
+            inode_table: u32_at(8),
+            free_blocks: u16_at(12),
+            free_inodes: u16_at(14),
+            used_dirs: u16_at(16),
+            rest: raw[18..DESCRIPTOR_SIZE].try_into().unwrap(),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; DESCRIPTOR_SIZE] {
+        let mut raw = [0; DESCRIPTOR_SIZE];
+        raw[0..4].copy_from_slice(&self.block_bitmap.to_le_bytes());
+        raw[4..8].copy_from_slice(&self.inode_bitmap.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.inode_table.to_le_bytes());
+        raw[12..14].copy_from_slice(&self.free_blocks.to_le_bytes());
+        raw[14..16].copy_from_slice(&self.free_inodes.to_le_bytes());
+        raw[16..18].copy_from_slice(&self.used_dirs.to_le_bytes());
+        raw[18..].copy_from_slice(&self.rest);
+        raw
+    }
+
+    /// Проверяет, что битовые карты и таблица inode лежат внутри тома.
+    pub fn check(&self, superblock: &Superblock) -> Result<(), Errno> {
+        // Оба множителя ограничены только размером блока, и произведение
+        // может не поместиться в u32.
+        let table_blocks = (superblock.inodes_per_group as u64 * superblock.inode_size as u64)
+            .div_ceil(superblock.block_size as u64);
+        let inside = |block: u32, count: u64| {
+            block >= superblock.first_data_block
+                && block as u64 + count <= superblock.blocks_count as u64
+        };
+
+        match inside(self.block_bitmap, 1)
+            && inside(self.inode_bitmap, 1)
+            && inside(self.inode_table, table_blocks)
+        {
+            true => Ok(()),
+            false => Err(Errno::EINVAL),
+        }
+    }
+}
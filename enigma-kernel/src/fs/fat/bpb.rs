@@ -0,0 +1,201 @@
+//! Загрузочный сектор (BPB) и геометрия тома.
+
+use crate::errno::Errno;
+
+/// Наибольшее число кластеров: номера FAT32 занимают 28 бит, а старшие
+/// значения зарезервированы.
+const MAX_CLUSTERS: u32 = 0x0FFF_FFF5;
+
+/// Разрядность таблицы FAT. Определяется, как требует спецификация, только
+/// количеством кластеров, а не полями загрузочного сектора.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    fn from_clusters(clusters: u32) -> Self {
+        match clusters {
+            0..4085 => Self::Fat12,
+            4085..65525 => Self::Fat16,
+            _ => Self::Fat32,
+        }
+    }
+}
+
+/// Где лежит корневой каталог.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RootDir {
+    /// FAT12/16: область фиксированного размера перед данными.
+    Fixed { offset: u64, entries: u32 },
+    /// FAT32: обычная цепочка кластеров.
+    Cluster(u32),
+}
+
+/// Разобранный загрузочный сектор. Все смещения - в байтах от начала тома.
+#[derive(Debug, Clone)]
+pub(super) struct Geometry {
+    pub fat_type: FatType,
+    pub cluster_size: u32,
+    pub fat_offset: u64,
+    /// Размер одной копии FAT.
+    pub fat_size: u64,
+    pub fat_count: u32,
+    /// Номер единственной используемой копии FAT, если зеркалирование
+    /// отключено (FAT32, бит 7 `BPB_ExtFlags`).
+    pub active_fat: Option<u32>,
+    pub root: RootDir,
+    pub data_offset: u64,
+    /// Количество кластеров данных (номера `2..cluster_count + 2`).
+    pub cluster_count: u32,
+    pub fsinfo_offset: Option<u64>,
+    pub label: [u8; 11],
+}
+
+impl Geometry {
+    /// Проверяет и разбирает загрузочный сектор тома размером `volume_size`.
+    pub fn parse(sector: &[u8], volume_size: u64) -> Result<Self, Errno> {
+        let u16_at = |offset: usize| u16::from_le_bytes([sector[offset], sector[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+
+        if sector.len() < 512
+            || !matches!(sector[0], 0xEB | 0xE9)
+            || sector[510..512] != [0x55, 0xAA]
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let bytes_per_sector = u16_at(11) as u32;
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = u16_at(14) as u32;
+        let fat_count = sector[16] as u32;
+        let root_entries = u16_at(17) as u32;
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            count => count as u32,
+        };
+        let fat_sectors = match u16_at(22) {
+            0 => u32_at(36),
+            count => count as u32,
+        };
+
+        if !bytes_per_sector.is_power_of_two()
+            || !(512..=4096).contains(&bytes_per_sector)
+            || !sectors_per_cluster.is_power_of_two()
+            || sectors_per_cluster > 128
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_sectors == 0
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let root_sectors = (root_entries * 32).div_ceil(bytes_per_sector);
+        // Поля берутся с диска как есть, поэтому сумма считается с
+        // проверкой переполнения.
+        let metadata_sectors = fat_count
+            .checked_mul(fat_sectors)
+            .and_then(|sectors| sectors.checked_add(reserved_sectors))
+            .and_then(|sectors| sectors.checked_add(root_sectors))
+            .ok_or(Errno::EINVAL)?;
+        let data_sectors = total_sectors
+            .checked_sub(metadata_sectors)
+            .ok_or(Errno::EINVAL)?;
+        let cluster_count = data_sectors / sectors_per_cluster;
+        if cluster_count > MAX_CLUSTERS {
+            return Err(Errno::EINVAL);
+        }
+        let fat_type = FatType::from_clusters(cluster_count);
+
+        let sector_offset = |sector: u32| sector as u64 * bytes_per_sector as u64;
+        let fat_offset = sector_offset(reserved_sectors);
+        let fat_size = sector_offset(fat_sectors);
+        let root_offset = fat_offset + fat_size * fat_count as u64;
+        let data_offset = root_offset + sector_offset(root_sectors);
+
+        let fat_bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        if (cluster_count as u64 + 2) * fat_bits > fat_size * 8
+            || sector_offset(total_sectors) > volume_size
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let (root, active_fat, fsinfo_offset, label) = if fat_type == FatType::Fat32 {
+            let ext_flags = u16_at(40);
+            let root_cluster = u32_at(44) & 0x0FFF_FFFF;
+            let fsinfo_sector = u16_at(48) as u32;
+            // Том FAT32 не может иметь корень фиксированного размера, а
+            // версии, отличные от 0.0, спецификация читать запрещает.
+            if root_entries != 0
+                || u16_at(22) != 0
+                || u16_at(42) != 0
+                || !(2..cluster_count + 2).contains(&root_cluster)
+            {
+                return Err(Errno::EINVAL);
+            }
+
+            let active_fat = (ext_flags & 0x80 != 0).then_some((ext_flags & 0x0F) as u32);
+            if active_fat.is_some_and(|fat| fat >= fat_count) {
+                return Err(Errno::EINVAL);
+            }
+            let fsinfo = (fsinfo_sector != 0 && fsinfo_sector < reserved_sectors)
+                .then(|| sector_offset(fsinfo_sector));
+            (
+                RootDir::Cluster(root_cluster),
+                active_fat,
+                fsinfo,
+                label_at(sector, 66),
+            )
+        } else {
+            if root_entries == 0 {
+                return Err(Errno::EINVAL);
+            }
+            let root = RootDir::Fixed {
+                offset: root_offset,
+                entries: root_entries,
+            };
+            (root, None, None, label_at(sector, 38))
+        };
+
+        Ok(Self {
+            fat_type,
+            cluster_size: bytes_per_sector * sectors_per_cluster,
+            fat_offset,
+            fat_size,
+            fat_count,
+            active_fat,
+            root,
+            data_offset,
+            cluster_count,
+            fsinfo_offset,
+            label,
+        })
+    }
+
+    /// Смещение начала кластера данных.
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + (cluster - 2) as u64 * self.cluster_size as u64
+    }
+
+    /// Номер последнего кластера данных.
+    pub fn last_cluster(&self) -> u32 {
+        self.cluster_count + 1
+    }
+}
+
+/// Метка тома из расширенного BPB, если его сигнатура (0x29) на месте.
+/// `offset` - смещение поля сигнатуры.
+fn label_at(sector: &[u8], offset: usize) -> [u8; 11] {
+    if sector[offset] == 0x29 {
+        sector[offset + 5..offset + 16].try_into().unwrap()
+    } else {
+        [b' '; 11]
+    }
+}
@@ -0,0 +1,499 @@
+//! Записи каталогов: короткие имена 8.3 и длинные имена VFAT.
+
+use super::Volume;
+use super::bpb::RootDir;
+use crate::errno::Errno;
+use crate::fs::dos_time::{from_dos, to_dos};
+use crate::fs::vfs::Timestamp;
+use alloc::{string::String, vec, vec::Vec};
+
+pub(super) const ENTRY_SIZE: usize = 32;
+
+pub(super) const ATTR_READ_ONLY: u8 = 0x01;
+pub(super) const ATTR_VOLUME_ID: u8 = 0x08;
+pub(super) const ATTR_DIRECTORY: u8 = 0x10;
+pub(super) const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// Первый байт свободной записи и записи, за которой записей больше нет.
+const DELETED: u8 = 0xE5;
+const END: u8 = 0x00;
+
+/// Флаги регистра Windows NT: имя или расширение короткой записи написаны
+/// строчными буквами.
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXTENSION: u8 = 0x10;
+
+/// Символов UCS-2 в одной записи длинного имени.
+const LFN_CHARS: usize = 13;
+/// Смещения символов внутри записи длинного имени.
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Максимальная длина длинного имени в символах UTF-16.
+const LFN_MAX: usize = 255;
+
+/// Короткая запись каталога.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct RawEntry {
+    pub name: [u8; 11],
+    pub attributes: u8,
+    pub case: u8,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+    pub cluster: u32,
+    pub size: u32,
+}
+
+impl RawEntry {
+    pub fn new(name: [u8; 11], attributes: u8, cluster: u32) -> Self {
+        let now = Timestamp::now();
+        Self {
+            name,
+            attributes,
+            case: 0,
+            created: now,
+            accessed: now,
+            modified: now,
+            cluster,
+            size: 0,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        Self {
+            name: bytes[..11].try_into().unwrap(),
+            attributes: bytes[11],
+            case: bytes[12],
+            created: from_dos(u16_at(16), u16_at(14), bytes[13]),
+            accessed: from_dos(u16_at(18), 0, 0),
+            modified: from_dos(u16_at(24), u16_at(22), 0),
+            cluster: (u16_at(20) as u32) << 16 | u16_at(26) as u32,
+            size: u32::from_le_bytes(bytes[28..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        let (created_date, created_time, created_tenths) = to_dos(self.created);
+        let (accessed_date, _, _) = to_dos(self.accessed);
+        let (modified_date, modified_time, _) = to_dos(self.modified);
+
+        bytes[..11].copy_from_slice(&self.name);
+        bytes[11] = self.attributes;
+        bytes[12] = self.case;
+        bytes[13] = created_tenths;
+        bytes[14..16].copy_from_slice(&created_time.to_le_bytes());
+        bytes[16..18].copy_from_slice(&created_date.to_le_bytes());
+        bytes[18..20].copy_from_slice(&accessed_date.to_le_bytes());
+        bytes[20..22].copy_from_slice(&((self.cluster >> 16) as u16).to_le_bytes());
+        bytes[22..24].copy_from_slice(&modified_time.to_le_bytes());
+        bytes[24..26].copy_from_slice(&modified_date.to_le_bytes());
+        bytes[26..28].copy_from_slice(&(self.cluster as u16).to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Короткое имя в виде `NAME.EXT` с учетом флагов регистра.
+    fn short_name(&self) -> String {
+        let mut name = self.name;
+        if name[0] == 0x05 {
+            // 0xE5 в начале имени хранится как 0x05.
+            name[0] = DELETED;
+        }
+
+        let part = |bytes: &[u8], lower: bool| -> String {
+            let text: String = bytes.iter().map(|&byte| oem_char(byte)).collect();
+            let text = text.trim_end_matches(' ');
+            if lower {
+                text.to_lowercase()
+            } else {
+                text.into()
+            }
+        };
+
+        let mut result = part(&name[..8], self.case & LOWER_BASE != 0);
+        let extension = part(&name[8..], self.case & LOWER_EXTENSION != 0);
+        if !extension.is_empty() {
+            result.push('.');
+            result.push_str(&extension);
+        }
+        result
+    }
+}
+
+/// Запись каталога вместе с длинным именем.
+#[derive(Debug, Clone)]
+pub(super) struct Found {
+    /// Длинное имя или короткое, если длинного нет.
+    pub name: String,
+    pub entry: RawEntry,
+    /// Смещение короткой записи на диске.
+    pub offset: u64,
+    /// Смещения всех записей (длинного имени и короткой).
+    pub slots: Vec<u64>,
+}
+
+impl Found {
+    /// Совпадает ли `name` с длинным или коротким именем записи.
+    pub fn matches(&self, name: &str) -> bool {
+        names_equal(&self.name, name) || names_equal(&self.entry.short_name(), name)
+    }
+}
+
+/// Содержимое каталога.
+pub(super) struct Listing {
+    /// Смещения всех записей каталога по порядку.
+    pub slots: Vec<u64>,
+    /// Для каждой записи - свободна ли она.
+    pub free: Vec<bool>,
+    pub entries: Vec<Found>,
+}
+
+impl Listing {
+    pub fn find(&self, name: &str) -> Option<&Found> {
+        self.entries.iter().find(|found| found.matches(name))
+    }
+
+    /// Занято ли короткое имя.
+    fn has_short_name(&self, name: &[u8; 11]) -> bool {
+        self.entries.iter().any(|found| &found.entry.name == name)
+    }
+
+    /// Индекс первой из `count` подряд идущих свободных записей.
+    pub fn free_run(&self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        for (index, &free) in self.free.iter().enumerate() {
+            run = if free { run + 1 } else { 0 };
+            if run == count {
+                return Some(index + 1 - count);
+            }
+        }
+        None
+    }
+}
+
+/// Каталог на томе.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum DirRef {
+    /// Корень FAT12/16.
+    Fixed {
+        offset: u64,
+        entries: u32,
+    },
+    Chain(u32),
+}
+
+impl DirRef {
+    pub fn root(root: RootDir) -> Self {
+        match root {
+            RootDir::Fixed { offset, entries } => Self::Fixed { offset, entries },
+            RootDir::Cluster(cluster) => Self::Chain(cluster),
+        }
+    }
+}
+
+impl Volume {
+    /// Читает каталог и разбирает его записи (без `.`, `..` и метки тома).
+    pub(super) fn list(&self, dir: DirRef) -> Result<Listing, Errno> {
+        let mut slots = Vec::new();
+        let mut data = Vec::new();
+        match dir {
+            DirRef::Fixed { offset, entries } => {
+                data.resize(entries as usize * ENTRY_SIZE, 0);
+                self.disk.read(offset, &mut data)?;
+                slots.extend((0..entries as u64).map(|index| offset + index * ENTRY_SIZE as u64));
+            }
+            DirRef::Chain(first) => {
+                let cluster_size = self.geometry.cluster_size as usize;
+                for cluster in self.chain(first)? {
+                    let start = data.len();
+                    data.resize(start + cluster_size, 0);
+                    let offset = self.geometry.cluster_offset(cluster);
+                    self.disk.read(offset, &mut data[start..])?;
+                    slots.extend(
+                        (0..(cluster_size / ENTRY_SIZE) as u64)
+                            .map(|index| offset + index * ENTRY_SIZE as u64),
+                    );
+                }
+            }
+        }
+
+        let mut free = vec![false; slots.len()];
+        let mut entries = Vec::new();
+        let mut long_name = LongName::default();
+        let mut ended = false;
+
+        for (index, bytes) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+            // После записи-терминатора все записи считаются свободными.
+            ended |= bytes[0] == END;
+            if ended || bytes[0] == DELETED {
+                free[index] = true;
+                long_name = LongName::default();
+                continue;
+            }
+
+            if bytes[11] & 0x3F == ATTR_LONG_NAME {
+                long_name.push(bytes, index);
+                continue;
+            }
+
+            let entry = RawEntry::parse(bytes);
+            let name = long_name.finish(&entry.name);
+            let first_slot = long_name.first.filter(|_| name.is_some()).unwrap_or(index);
+            long_name = LongName::default();
+
+            if entry.attributes & ATTR_VOLUME_ID != 0 || entry.name[0] == b'.' {
+                continue;
+            }
+            entries.push(Found {
+                name: name.unwrap_or_else(|| entry.short_name()),
+                entry,
+                offset: slots[index],
+                slots: slots[first_slot..=index].to_vec(),
+            });
+        }
+
+        Ok(Listing {
+            slots,
+            free,
+            entries,
+        })
+    }
+}
+
+/// Сборщик длинного имени из последовательности записей. Записи идут от
+/// последней части имени к первой; порядковый номер последней помечен
+/// битом 0x40.
+#[derive(Default)]
+struct LongName {
+    chars: Vec<u16>,
+    /// Ожидаемый номер следующей записи (0 - имя собрано).
+    next: u8,
+    checksum: u8,
+    first: Option<usize>,
+    broken: bool,
+}
+
+impl LongName {
+    fn push(&mut self, bytes: &[u8], index: usize) {
+        let order = bytes[0];
+        let number = order & 0x1F;
+
+        if order & 0x40 != 0 {
+            *self = Self {
+                chars: vec![0xFFFF; number as usize * LFN_CHARS],
+                next: number,
+                checksum: bytes[13],
+                first: Some(index),
+                broken: number == 0,
+            };
+        } else if self.first.is_none()
+            || number == 0
+            || number != self.next
+            || bytes[13] != self.checksum
+        {
+            self.broken = true;
+        }
+        if self.broken {
+            return;
+        }
+
+        let start = (number as usize - 1) * LFN_CHARS;
+        for (slot, &offset) in LFN_OFFSETS.iter().enumerate() {
+            self.chars[start + slot] = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        }
+        self.next = number - 1;
+    }
+
+    /// Длинное имя для короткой записи `short`, если оно собрано целиком и
+    /// контрольная сумма совпала.
+    fn finish(&self, short: &[u8; 11]) -> Option<String> {
+        if self.first.is_none() || self.broken || self.next != 0 || self.checksum != checksum(short)
+        {
+            return None;
+        }
+
+        let len = self
+            .chars
+            .iter()
+            .position(|&unit| unit == 0 || unit == 0xFFFF)
+            .unwrap_or(self.chars.len());
+        let name: String = char::decode_utf16(self.chars[..len].iter().copied())
+            .map(|unit| unit.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        (!name.is_empty()).then_some(name)
+    }
+}
+
+/// Записи, которыми имя `name` хранится в каталоге: записи длинного имени
+/// (если они нужны) и короткая запись `entry`, чье имя будет заполнено.
+pub(super) fn name_entries(
+    listing: &Listing,
+    name: &str,
+    entry: &mut RawEntry,
+) -> Result<Vec<[u8; ENTRY_SIZE]>, Errno> {
+    let units = check_long_name(name)?;
+
+    if let Some((short, case)) = exact_short_name(name) {
+        entry.name = short;
+        entry.case = case;
+        return Ok(vec![entry.encode()]);
+    }
+
+    entry.name = (1..=999_999)
+        .map(|number| numbered_alias(name, number))
+        .find(|alias| !listing.has_short_name(alias))
+        .ok_or(Errno::EEXIST)?;
+    entry.case = 0;
+
+    let checksum = checksum(&entry.name);
+    let count = units.len().div_ceil(LFN_CHARS);
+    let mut entries = Vec::with_capacity(count + 1);
+    for number in (1..=count).rev() {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0] = number as u8 | if number == count { 0x40 } else { 0 };
+        bytes[11] = ATTR_LONG_NAME;
+        bytes[13] = checksum;
+
+        for (slot, &offset) in LFN_OFFSETS.iter().enumerate() {
+            let index = (number - 1) * LFN_CHARS + slot;
+            // Имя завершается нулем, а остаток заполняется 0xFFFF.
+            let unit = match index.cmp(&units.len()) {
+                core::cmp::Ordering::Less => units[index],
+                core::cmp::Ordering::Equal => 0,
+                core::cmp::Ordering::Greater => 0xFFFF,
+            };
+            bytes[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entries.push(bytes);
+    }
+    entries.push(entry.encode());
+    Ok(entries)
+}
+
+/// Проверяет имя и возвращает его в UTF-16.
+fn check_long_name(name: &str) -> Result<Vec<u16>, Errno> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    // Windows молча отбрасывает точки и пробелы в конце имени, поэтому
+    // такие имена не создаются вовсе.
+    if name.chars().any(invalid) || name.ends_with(['.', ' ']) {
+        return Err(Errno::EINVAL);
+    }
+
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.len() > LFN_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    Ok(units)
+}
+
+/// Символы, допустимые в коротком имени (кроме букв и цифр).
+const SHORT_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+
+fn is_short_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || SHORT_SPECIAL.contains(&byte)
+}
+
+/// Короткое имя и флаги регистра, если `name` точно представимо в 8.3
+/// (имя и расширение целиком в одном регистре).
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+
+    let mut case = 0;
+    let mut short = [b' '; 11];
+    let (short_base, short_extension) = short.split_at_mut(8);
+    for (part, lower_flag, target) in [
+        (base, LOWER_BASE, short_base),
+        (extension, LOWER_EXTENSION, short_extension),
+    ] {
+        let lower = part.bytes().any(|byte| byte.is_ascii_lowercase());
+        let upper = part.bytes().any(|byte| byte.is_ascii_uppercase());
+        if lower && upper {
+            return None;
+        }
+        if lower {
+            case |= lower_flag;
+        }
+
+        for (slot, byte) in target.iter_mut().zip(part.bytes()) {
+            let byte = byte.to_ascii_uppercase();
+            if !is_short_char(byte) {
+                return None;
+            }
+            *slot = byte;
+        }
+    }
+    Some((short, case))
+}
+
+/// Короткий псевдоним длинного имени вида `BASE~N.EXT`.
+fn numbered_alias(name: &str, number: u32) -> [u8; 11] {
+    let convert = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.to_ascii_uppercase() {
+                c if c.is_ascii() && is_short_char(c as u8) => c as u8,
+                _ => b'_',
+            })
+            .collect()
+    };
+
+    let name = name.trim_start_matches('.');
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (convert(base), convert(extension)),
+        None => (convert(name), Vec::new()),
+    };
+
+    let mut suffix = [0; 8];
+    let mut suffix_len = 0;
+    let mut value = number;
+    while value != 0 {
+        suffix[suffix_len] = b'0' + (value % 10) as u8;
+        suffix_len += 1;
+        value /= 10;
+    }
+
+    let mut short = [b' '; 11];
+    let base_len = base.len().min(7 - suffix_len);
+    short[..base_len].copy_from_slice(&base[..base_len]);
+    short[base_len] = b'~';
+    for index in 0..suffix_len {
+        short[base_len + 1 + index] = suffix[suffix_len - 1 - index];
+    }
+    let extension_len = extension.len().min(3);
+    short[8..8 + extension_len].copy_from_slice(&extension[..extension_len]);
+    short
+}
+
+/// Контрольная сумма короткого имени, хранимая в записях длинного.
+fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Имена FAT не различают регистр.
+pub(super) fn names_equal(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_uppercase)
+        .eq(b.chars().flat_map(char::to_uppercase))
+}
+
+/// Символ кодовой страницы 437 (печатные ASCII как есть, остальное - `_`).
+fn oem_char(byte: u8) -> char {
+    match byte {
+        0x20..0x7F => byte as char,
+        _ => '_',
+    }
+}
@@ -0,0 +1,712 @@
+//! Файлы и каталоги тома FAT.
+
+use super::dir::{
+    ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, DirRef, ENTRY_SIZE, Found, Listing, RawEntry,
+    name_entries,
+};
+use super::{State, Volume};
+use crate::errno::Errno;
+use crate::fs::vfs::{DirEntry, FileType, Inode, Metadata, Timestamp};
+use alloc::{
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::Ordering;
+use spin::{Mutex, MutexGuard};
+
+/// Номера удаленных, но открытых файлов (не пересекаются с номерами по
+/// смещению записи).
+pub(super) const FIRST_ORPHAN_INO: u64 = 1 << 63;
+const ROOT_INO: u64 = 1;
+/// Больше записей в каталоге FAT быть не может.
+const MAX_DIR_ENTRIES: usize = 65536;
+/// После скольких загруженных inode кэш чистится от освобожденных.
+const CACHE_PRUNE: usize = 256;
+
+pub(super) struct FatInode {
+    volume: Arc<Volume>,
+    this: Weak<FatInode>,
+    kind: FileType,
+    is_root: bool,
+    node: Mutex<Node>,
+}
+
+struct Node {
+    ino: u64,
+    /// Смещение короткой записи (`None` у корня и удаленных файлов).
+    offset: Option<u64>,
+    parent: Option<Arc<FatInode>>,
+    /// Копия короткой записи. Изменения сразу пишутся на диск.
+    entry: RawEntry,
+}
+
+/// Номер inode файла, чья короткая запись лежит по смещению `offset`.
+fn ino_of(offset: u64) -> u64 {
+    offset / ENTRY_SIZE as u64 + 2
+}
+
+impl FatInode {
+    pub(super) fn root(volume: &Arc<Volume>) -> Arc<Self> {
+        let cluster = match DirRef::root(volume.geometry.root) {
+            DirRef::Chain(cluster) => cluster,
+            DirRef::Fixed { .. } => 0,
+        };
+        let mut entry = RawEntry::new([b' '; 11], ATTR_DIRECTORY, cluster);
+        entry.created = Timestamp::default();
+        entry.accessed = Timestamp::default();
+        entry.modified = Timestamp::default();
+
+        Self::new(volume, true, ROOT_INO, None, None, entry)
+    }
+
+    fn new(
+        volume: &Arc<Volume>,
+        is_root: bool,
+        ino: u64,
+        offset: Option<u64>,
+        parent: Option<Arc<FatInode>>,
+        entry: RawEntry,
+    ) -> Arc<Self> {
+        let kind = match entry.is_directory() {
+            true => FileType::Directory,
+            false => FileType::Regular,
+        };
+
+        Arc::new_cyclic(|this| Self {
+            volume: volume.clone(),
+            this: this.clone(),
+            kind,
+            is_root,
+            node: Mutex::new(Node {
+                ino,
+                offset,
+                parent,
+                entry,
+            }),
+        })
+    }
+
+    fn this(&self) -> Arc<FatInode> {
+        self.this.upgrade().unwrap()
+    }
+
+    /// Inode записи `found` этого каталога (из кэша или новый).
+    fn load(&self, found: &Found) -> Arc<FatInode> {
+        let volume = &self.volume;
+        if let Some(inode) = volume
+            .inodes
+            .lock()
+            .get(&found.offset)
+            .and_then(Weak::upgrade)
+        {
+            return inode;
+        }
+
+        let inode = Self::new(
+            volume,
+            false,
+            ino_of(found.offset),
+            Some(found.offset),
+            Some(self.this()),
+            found.entry,
+        );
+        let mut inodes = volume.inodes.lock();
+        if inodes.len() >= CACHE_PRUNE {
+            inodes.retain(|_, inode| inode.strong_count() > 0);
+        }
+        inodes.insert(found.offset, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Блокирует том и освобождает кластеры закрытых удаленных файлов.
+    fn lock_volume(&self) -> Result<MutexGuard<'_, State>, Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        Ok(state)
+    }
+
+    /// Содержимое каталога. Удаленный каталог пуст.
+    fn dir_ref(&self, node: &Node) -> Result<DirRef, Errno> {
+        if self.kind != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        if self.is_root {
+            return Ok(DirRef::root(self.volume.geometry.root));
+        }
+        match node.entry.cluster {
+            0 => Err(Errno::EIO),
+            cluster => Ok(DirRef::Chain(cluster)),
+        }
+    }
+
+    /// Каталог для добавления или удаления записей.
+    fn live_dir(&self) -> Result<DirRef, Errno> {
+        let node = self.node.lock();
+        if node.offset.is_none() && !self.is_root {
+            return Err(Errno::ENOENT);
+        }
+        self.dir_ref(&node)
+    }
+
+    /// Переписывает короткую запись на диске.
+    fn store(&self, node: &Node) -> Result<(), Errno> {
+        match node.offset {
+            Some(offset) => self.volume.disk.write(offset, &node.entry.encode()),
+            None => Ok(()),
+        }
+    }
+
+    /// Отмечает изменение содержимого каталога.
+    fn touch(&self) -> Result<(), Errno> {
+        let mut node = self.node.lock();
+        node.entry.modified = Timestamp::now();
+        self.store(&node)
+    }
+
+    /// Первый кластер каталога для записи `..` его подкаталогов.
+    fn parent_cluster(&self) -> u32 {
+        match self.is_root {
+            true => 0,
+            false => self.node.lock().entry.cluster,
+        }
+    }
+
+    /// Добавляет в каталог `dir` записи имени `name` и короткую запись
+    /// `entry`, расширяя каталог при необходимости. Возвращает смещение
+    /// короткой записи.
+    fn add_entry(
+        &self,
+        state: &mut State,
+        dir: DirRef,
+        mut listing: Listing,
+        name: &str,
+        entry: &mut RawEntry,
+    ) -> Result<u64, Errno> {
+        let entries = name_entries(&listing, name, entry)?;
+
+        let start = loop {
+            if let Some(start) = listing.free_run(entries.len()) {
+                break start;
+            }
+            let DirRef::Chain(first) = dir else {
+                return Err(Errno::ENOSPC);
+            };
+            if listing.slots.len() >= MAX_DIR_ENTRIES {
+                return Err(Errno::ENOSPC);
+            }
+
+            let last = *self.volume.chain(first)?.last().ok_or(Errno::EIO)?;
+            let cluster = self.volume.allocate_cluster(state, Some(last))?;
+            let offset = self.volume.geometry.cluster_offset(cluster);
+            let count = self.volume.geometry.cluster_size as usize / ENTRY_SIZE;
+            listing
+                .slots
+                .extend((0..count as u64).map(|index| offset + index * ENTRY_SIZE as u64));
+            listing.free.extend(core::iter::repeat_n(true, count));
+        };
+
+        for (index, bytes) in entries.iter().enumerate() {
+            self.volume
+                .disk
+                .write(listing.slots[start + index], bytes)?;
+        }
+        Ok(listing.slots[start + entries.len() - 1])
+    }
+
+    /// Помечает записи `found` свободными.
+    fn remove_entry(&self, found: &Found) -> Result<(), Errno> {
+        for &slot in &found.slots {
+            self.volume.disk.write(slot, &[0xE5])?;
+        }
+        Ok(())
+    }
+
+    /// Освобождает данные удаленной записи. Если файл открыт, он становится
+    /// сиротой, и кластеры освободятся после его закрытия.
+    fn release(&self, state: &mut State, found: &Found) -> Result<(), Errno> {
+        let volume = &self.volume;
+        let inode = volume
+            .inodes
+            .lock()
+            .remove(&found.offset)
+            .and_then(|inode| inode.upgrade());
+
+        match inode {
+            Some(inode) => {
+                let mut node = inode.node.lock();
+                node.offset = None;
+                node.ino = volume.next_orphan.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            None if found.entry.cluster != 0 => volume.free_chain(state, found.entry.cluster),
+            None => Ok(()),
+        }
+    }
+
+    /// Пишет `data` в кластеры `chain` начиная с позиции `offset` файла.
+    fn write_span(&self, chain: &[u32], offset: u64, data: &[u8]) -> Result<(), Errno> {
+        let cluster_size = self.volume.geometry.cluster_size as u64;
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let in_cluster = position % cluster_size;
+            let len = ((cluster_size - in_cluster) as usize).min(data.len() - done);
+            let cluster = chain[(position / cluster_size) as usize];
+
+            let target = self.volume.geometry.cluster_offset(cluster) + in_cluster;
+            self.volume.disk.write(target, &data[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Пишет данные файла, выделяя кластеры. Если место кончилось, пишет
+    /// сколько поместилось; ошибка - только если не поместилось ничего.
+    fn write_data(
+        &self,
+        state: &mut State,
+        node: &mut Node,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, Errno> {
+        let cluster_size = self.volume.geometry.cluster_size as u64;
+        let mut chain = self.volume.chain(node.entry.cluster)?;
+        let size = node.entry.size as u64;
+
+        // Хвост последнего кластера за концом файла может содержать мусор.
+        let capacity = chain.len() as u64 * cluster_size;
+        if offset > size && size < capacity {
+            let gap = vec![0; (offset.min(capacity) - size) as usize];
+            self.write_span(&chain, size, &gap)?;
+        }
+
+        let end = offset + data.len() as u64;
+        let needed = end.div_ceil(cluster_size) as usize;
+        while chain.len() < needed {
+            match self.volume.allocate_cluster(state, chain.last().copied()) {
+                Ok(cluster) => {
+                    if chain.is_empty() {
+                        node.entry.cluster = cluster;
+                    }
+                    chain.push(cluster);
+                }
+                Err(Errno::ENOSPC) => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        let capacity = chain.len() as u64 * cluster_size;
+        let len = capacity.saturating_sub(offset).min(data.len() as u64) as usize;
+        if len == 0 && !data.is_empty() {
+            return Err(Errno::ENOSPC);
+        }
+        self.write_span(&chain, offset, &data[..len])?;
+
+        node.entry.size = node.entry.size.max((offset + len as u64) as u32);
+        node.entry.modified = Timestamp::now();
+        node.entry.attributes |= ATTR_ARCHIVE;
+        self.store(node)?;
+        Ok(len)
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let node = self.node.lock();
+        if node.offset.is_none() && !self.is_root && node.entry.cluster != 0 {
+            self.volume.orphans.lock().push(node.entry.cluster);
+        }
+    }
+}
+
+impl Inode for FatInode {
+    fn ino(&self) -> u64 {
+        self.node.lock().ino
+    }
+
+    fn metadata(&self) -> Result<Metadata, Errno> {
+        let node = self.node.lock();
+        let entry = &node.entry;
+        let mut mode = match self.kind {
+            FileType::Directory => 0o755,
+            _ => 0o644,
+        };
+        if entry.attributes & ATTR_READ_ONLY != 0 {
+            mode &= !0o222;
+        }
+
+        Ok(Metadata {
+            ino: node.ino,
+            kind: self.kind,
+            mode,
+            // Подкаталоги не считаются: 1 у каталога означает "неизвестно".
+            nlink: match node.offset.is_some() || self.is_root {
+                true => 1,
+                false => 0,
+            },
+            uid: 0,
+            gid: 0,
+            size: match self.kind {
+                FileType::Directory => 0,
+                _ => entry.size as u64,
+            },
+            atime: entry.accessed,
+            mtime: entry.modified,
+            ctime: entry.created,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+        if self.kind == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+
+        // Время доступа не обновляется: иначе каждое чтение было бы записью.
+        let _state = self.volume.state.lock();
+        let node = self.node.lock();
+        let len = (node.entry.size as u64)
+            .saturating_sub(offset)
+            .min(buffer.len() as u64) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let geometry = &self.volume.geometry;
+        let cluster_size = geometry.cluster_size as u64;
+        let chain = self.volume.chain(node.entry.cluster)?;
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let in_cluster = position % cluster_size;
+            let chunk = ((cluster_size - in_cluster) as usize).min(len - done);
+            let cluster = *chain
+                .get((position / cluster_size) as usize)
+                .ok_or(Errno::EIO)?;
+
+            let source = geometry.cluster_offset(cluster) + in_cluster;
+            self.volume
+                .disk
+                .read(source, &mut buffer[done..done + chunk])?;
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, Errno> {
+        if self.kind == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+        match offset.checked_add(buffer.len() as u64) {
+            Some(end) if end <= u32::MAX as u64 => {}
+            _ => return Err(Errno::EFBIG),
+        }
+
+        let mut state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        let written = self.write_data(&mut state, &mut node, offset, buffer)?;
+        drop(node);
+        self.volume.commit(&mut state)?;
+        Ok(written)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), Errno> {
+        match self.kind {
+            FileType::Directory => return Err(Errno::EISDIR),
+            _ if size > u32::MAX as u64 => return Err(Errno::EFBIG),
+            _ => {}
+        }
+
+        let mut state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        let old_size = node.entry.size as u64;
+
+        if size > old_size {
+            let zeroes = [0; 4096];
+            let mut position = old_size;
+            while position < size {
+                let len = (size - position).min(zeroes.len() as u64) as usize;
+                let written = self.write_data(&mut state, &mut node, position, &zeroes[..len])?;
+                if written < len {
+                    return Err(Errno::ENOSPC);
+                }
+                position += len as u64;
+            }
+        } else if size < old_size {
+            let keep = size.div_ceil(self.volume.geometry.cluster_size as u64) as usize;
+            if keep == 0 && node.entry.cluster != 0 {
+                self.volume.free_chain(&mut state, node.entry.cluster)?;
+                node.entry.cluster = 0;
+            } else if keep > 0 {
+                self.volume
+                    .shrink_chain(&mut state, node.entry.cluster, keep)?;
+            }
+        }
+
+        node.entry.size = size as u32;
+        node.entry.modified = Timestamp::now();
+        node.entry.attributes |= ATTR_ARCHIVE;
+        self.store(&node)?;
+        drop(node);
+        self.volume.commit(&mut state)
+    }
+
+    fn set_times(&self, atime: Option<Timestamp>, mtime: Option<Timestamp>) -> Result<(), Errno> {
+        let _state = self.lock_volume()?;
+        let mut node = self.node.lock();
+        if let Some(atime) = atime {
+            node.entry.accessed = atime;
+        }
+        if let Some(mtime) = mtime {
+            node.entry.modified = mtime;
+        }
+        self.store(&node)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match name {
+            "." => return Ok(self.this()),
+            ".." => {
+                let parent = self.node.lock().parent.clone();
+                return Ok(parent.unwrap_or_else(|| self.this()));
+            }
+            _ => {}
+        }
+
+        let _state = self.volume.state.lock();
+        let dir = self.dir_ref(&self.node.lock())?;
+        let listing = self.volume.list(dir)?;
+        let found = listing.find(name).ok_or(Errno::ENOENT)?;
+        Ok(self.load(found))
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> Result<Arc<dyn Inode>, Errno> {
+        // FAT хранит только файлы и каталоги.
+        if !matches!(kind, FileType::Regular | FileType::Directory) {
+            return Err(Errno::EPERM);
+        }
+
+        let mut state = self.lock_volume()?;
+        let dir = self.live_dir()?;
+        let listing = self.volume.list(dir)?;
+        if listing.find(name).is_some() {
+            return Err(Errno::EEXIST);
+        }
+
+        let mut attributes = match kind {
+            FileType::Directory => ATTR_DIRECTORY,
+            _ => ATTR_ARCHIVE,
+        };
+        if mode & 0o222 == 0 {
+            attributes |= ATTR_READ_ONLY;
+        }
+
+        let cluster = match kind {
+            FileType::Directory => {
+                let cluster = self.volume.allocate_cluster(&mut state, None)?;
+                let dot = RawEntry::new(*b".          ", ATTR_DIRECTORY, cluster);
+                let dot_dot = RawEntry::new(*b"..         ", ATTR_DIRECTORY, self.parent_cluster());
+
+                let offset = self.volume.geometry.cluster_offset(cluster);
+                self.volume.disk.write(offset, &dot.encode())?;
+                self.volume
+                    .disk
+                    .write(offset + ENTRY_SIZE as u64, &dot_dot.encode())?;
+                cluster
+            }
+            _ => 0,
+        };
+
+        let mut entry = RawEntry::new([b' '; 11], attributes, cluster);
+        let offset = match self.add_entry(&mut state, dir, listing, name, &mut entry) {
+            Ok(offset) => offset,
+            Err(error) => {
+                if cluster != 0 {
+                    self.volume.free_chain(&mut state, cluster)?;
+                }
+                self.volume.commit(&mut state)?;
+                return Err(error);
+            }
+        };
+        self.touch()?;
+        self.volume.commit(&mut state)?;
+
+        Ok(self.load(&Found {
+            name: name.into(),
+            entry,
+            offset,
+            slots: Vec::new(),
+        }))
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let mut state = self.lock_volume()?;
+        let listing = self.volume.list(self.live_dir()?)?;
+        let found = listing.find(name).ok_or(Errno::ENOENT)?;
+        if found.entry.is_directory() {
+            return Err(Errno::EISDIR);
+        }
+
+        self.remove_entry(found)?;
+        self.release(&mut state, found)?;
+        self.touch()?;
+        self.volume.commit(&mut state)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        let mut state = self.lock_volume()?;
+        let listing = self.volume.list(self.live_dir()?)?;
+        let found = listing.find(name).ok_or(Errno::ENOENT)?;
+        if !found.entry.is_directory() {
+            return Err(Errno::ENOTDIR);
+        }
+        if !self
+            .volume
+            .list(DirRef::Chain(found.entry.cluster))?
+            .entries
+            .is_empty()
+        {
+            return Err(Errno::ENOTEMPTY);
+        }
+
+        self.remove_entry(found)?;
+        self.release(&mut state, found)?;
+        self.touch()?;
+        self.volume.commit(&mut state)
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        new_parent: &Arc<dyn Inode>,
+        new_name: &str,
+    ) -> Result<(), Errno> {
+        let new_parent = new_parent
+            .as_any()
+            .downcast_ref::<FatInode>()
+            .filter(|parent| Arc::ptr_eq(&parent.volume, &self.volume))
+            .ok_or(Errno::EXDEV)?
+            .this();
+
+        let mut state = self.lock_volume()?;
+        let old_dir = self.live_dir()?;
+        let new_dir = new_parent.live_dir()?;
+        let same_dir = old_dir == new_dir;
+
+        let old_listing = self.volume.list(old_dir)?;
+        let source = old_listing.find(old_name).ok_or(Errno::ENOENT)?.clone();
+        let is_directory = source.entry.is_directory();
+
+        // Каталог нельзя перенести в самого себя или своего потомка.
+        if is_directory {
+            let mut current = Some(new_parent.clone());
+            while let Some(dir) = current {
+                let node = dir.node.lock();
+                if node.offset == Some(source.offset) {
+                    return Err(Errno::EINVAL);
+                }
+                current = node.parent.clone();
+            }
+        }
+
+        let new_listing = match same_dir {
+            true => old_listing,
+            false => self.volume.list(new_dir)?,
+        };
+        let replaced = match new_listing.find(new_name).cloned() {
+            // Та же запись: смена регистра имени или ничего.
+            Some(found) if found.offset == source.offset => {
+                if found.name == new_name {
+                    return Ok(());
+                }
+                None
+            }
+            found => found,
+        };
+
+        if let Some(replaced) = &replaced {
+            match (is_directory, replaced.entry.is_directory()) {
+                (true, false) => return Err(Errno::ENOTDIR),
+                (false, true) => return Err(Errno::EISDIR),
+                (true, true) => {
+                    let entries = self.volume.list(DirRef::Chain(replaced.entry.cluster))?;
+                    if !entries.entries.is_empty() {
+                        return Err(Errno::ENOTEMPTY);
+                    }
+                }
+                (false, false) => {}
+            }
+        }
+
+        // Сначала новая запись: если места нет, ничего не меняется.
+        let mut entry = source.entry;
+        let new_offset =
+            new_parent.add_entry(&mut state, new_dir, new_listing, new_name, &mut entry)?;
+        if let Some(replaced) = &replaced {
+            self.remove_entry(replaced)?;
+            new_parent.release(&mut state, replaced)?;
+        }
+        self.remove_entry(&source)?;
+
+        let moved = self.volume.inodes.lock().remove(&source.offset);
+        if let Some(inode) = moved.as_ref().and_then(Weak::upgrade) {
+            let mut node = inode.node.lock();
+            node.offset = Some(new_offset);
+            node.ino = ino_of(new_offset);
+            node.entry.name = entry.name;
+            node.entry.case = entry.case;
+            node.parent = Some(new_parent.clone());
+        }
+        if let Some(moved) = moved {
+            self.volume.inodes.lock().insert(new_offset, moved);
+        }
+
+        if is_directory && !same_dir {
+            let dot_dot = self.volume.geometry.cluster_offset(entry.cluster) + ENTRY_SIZE as u64;
+            let mut bytes = [0; ENTRY_SIZE];
+            self.volume.disk.read(dot_dot, &mut bytes)?;
+            let cluster = new_parent.parent_cluster();
+            bytes[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+            bytes[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+            self.volume.disk.write(dot_dot, &bytes)?;
+        }
+
+        self.touch()?;
+        if !same_dir {
+            new_parent.touch()?;
+        }
+        self.volume.commit(&mut state)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        let _state = self.volume.state.lock();
+        let dir = self.dir_ref(&self.node.lock())?;
+        let listing = self.volume.list(dir)?;
+
+        Ok(listing
+            .entries
+            .into_iter()
+            .map(|found| DirEntry {
+                kind: match found.entry.is_directory() {
+                    true => FileType::Directory,
+                    false => FileType::Regular,
+                },
+                ino: ino_of(found.offset),
+                name: found.name,
+            })
+            .collect())
+    }
+}
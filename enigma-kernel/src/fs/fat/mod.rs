@@ -0,0 +1,145 @@
+//! Драйвер FAT12/16/32 с длинными именами (VFAT).
+//!
+//! Том читается и пишется через [`BlockDevice`]. Метаданные (таблица FAT,
+//! каталоги, FSInfo) меняются под одной блокировкой тома, а данные файлов
+//! пишутся прямо в кластеры.
+//!
+//! У FAT нет inode, поэтому номером файла служит положение его короткой
+//! записи в каталоге. Удаленный, но открытый файл теряет запись и получает
+//! отдельный номер, а его кластеры освобождаются после закрытия.
+
+mod bpb;
+mod dir;
+mod inode;
+mod table;
+
+pub use bpb::FatType;
+
+use super::disk::Disk;
+use super::vfs::{FileSystem, Inode};
+use crate::drivers::block::BlockDevice;
+use crate::errno::Errno;
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use bpb::Geometry;
+use core::sync::atomic::AtomicU64;
+use inode::FatInode;
+use spin::Mutex;
+
+pub struct FatFs {
+    volume: Arc<Volume>,
+    root: Arc<FatInode>,
+}
+
+impl FatFs {
+    /// Открывает том на `device`. Возвращает `EINVAL`, если на устройстве
+    /// нет правильного загрузочного сектора FAT.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, Errno> {
+        let disk = Disk::new(device);
+        let mut sector = vec![0; disk.block_size.max(512)];
+        disk.read(0, &mut sector)?;
+        let geometry = Geometry::parse(&sector, disk.size())?;
+
+        let volume = Arc::new(Volume {
+            disk,
+            geometry,
+            state: Mutex::new(State::default()),
+            inodes: Mutex::new(BTreeMap::new()),
+            orphans: Mutex::new(Vec::new()),
+            next_orphan: AtomicU64::new(inode::FIRST_ORPHAN_INO),
+        });
+        volume.load_fsinfo(&mut volume.state.lock())?;
+
+        let root = FatInode::root(&volume);
+        Ok(Arc::new(Self { volume, root }))
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.volume.geometry.fat_type
+    }
+
+    /// Метка тома из загрузочного сектора (без завершающих пробелов).
+    pub fn label(&self) -> String {
+        let label = &self.volume.geometry.label;
+        String::from_utf8_lossy(label).trim_end().into()
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        self.volume.geometry.cluster_size
+    }
+
+    /// Количество свободных кластеров. Если FSInfo его не хранит, таблица
+    /// просматривается целиком (один раз).
+    pub fn free_clusters(&self) -> Result<u32, Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        self.volume.count_free(&mut state)
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        match self.fat_type() {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.volume.disk.device.is_read_only()
+    }
+
+    fn sync(&self) -> Result<(), Errno> {
+        let mut state = self.volume.state.lock();
+        self.volume.free_orphans(&mut state)?;
+        self.volume.commit(&mut state)?;
+        self.volume.disk.device.flush()
+    }
+}
+
+/// Общие данные тома.
+struct Volume {
+    disk: Disk,
+    geometry: Geometry,
+    /// Блокировка метаданных. Порядок блокировок: `state`, затем узлы
+    /// inode; `inodes` и `orphans` берутся последними и ненадолго.
+    state: Mutex<State>,
+    /// Загруженные inode по смещению их короткой записи.
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+    /// Первые кластеры удаленных файлов, которые закрылись. Освобождаются
+    /// при следующей операции с томом: `Drop` не может ждать `state`.
+    orphans: Mutex<Vec<u32>>,
+    next_orphan: AtomicU64,
+}
+
+/// Состояние распределителя кластеров.
+#[derive(Debug, Default)]
+struct State {
+    /// Количество свободных кластеров (`None` - неизвестно).
+    free_clusters: Option<u32>,
+    /// С какого кластера искать свободный.
+    next_free: u32,
+    /// FSInfo нужно переписать.
+    dirty: bool,
+}
+
+impl Volume {
+    /// Записывает накопленные изменения FSInfo.
+    fn commit(&self, state: &mut State) -> Result<(), Errno> {
+        if state.dirty {
+            self.store_fsinfo(state)?;
+            state.dirty = false;
+        }
+        Ok(())
+    }
+}
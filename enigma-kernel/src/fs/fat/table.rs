@@ -0,0 +1,278 @@
+//! Таблица размещения файлов (FAT) и сектор FSInfo.
+
+use super::bpb::FatType;
+use super::{State, Volume};
+use crate::errno::Errno;
+use alloc::{vec, vec::Vec};
+
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+const FSINFO_TRAIL: u32 = 0xAA55_0000;
+/// "Неизвестно" в полях FSInfo.
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// Значение ячейки FAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Link {
+    Free,
+    Next(u32),
+    End,
+    Bad,
+}
+
+impl Volume {
+    /// Смещение и ширина (в байтах) ячейки кластера в первой копии FAT.
+    fn fat_slot(&self, cluster: u32) -> (u64, usize) {
+        let fat = self.geometry.fat_offset;
+        match self.geometry.fat_type {
+            FatType::Fat12 => (fat + (cluster + cluster / 2) as u64, 2),
+            FatType::Fat16 => (fat + cluster as u64 * 2, 2),
+            FatType::Fat32 => (fat + cluster as u64 * 4, 4),
+        }
+    }
+
+    /// Копии FAT, которые нужно обновлять при записи.
+    fn fat_copies(&self) -> impl Iterator<Item = u64> + '_ {
+        let geometry = &self.geometry;
+        let copies = match geometry.active_fat {
+            Some(fat) => fat..fat + 1,
+            None => 0..geometry.fat_count,
+        };
+        copies.map(|copy| copy as u64 * geometry.fat_size)
+    }
+
+    fn read_raw(&self, cluster: u32) -> Result<u32, Errno> {
+        let (offset, width) = self.fat_slot(cluster);
+        let offset = offset + self.geometry.active_fat.unwrap_or(0) as u64 * self.geometry.fat_size;
+        let mut bytes = [0; 4];
+        self.disk.read(offset, &mut bytes[..width])?;
+
+        let value = u32::from_le_bytes(bytes);
+        Ok(match self.geometry.fat_type {
+            FatType::Fat12 if cluster % 2 == 1 => value >> 4,
+            FatType::Fat12 => value & 0xFFF,
+            FatType::Fat16 => value,
+            FatType::Fat32 => value & 0x0FFF_FFFF,
+        })
+    }
+
+    /// Читает ячейку FAT кластера `cluster`.
+    pub(super) fn link(&self, cluster: u32) -> Result<Link, Errno> {
+        let value = self.read_raw(cluster)?;
+        let (bad, end) = match self.geometry.fat_type {
+            FatType::Fat12 => (0xFF7, 0xFF8),
+            FatType::Fat16 => (0xFFF7, 0xFFF8),
+            FatType::Fat32 => (0x0FFF_FFF7, 0x0FFF_FFF8),
+        };
+
+        match value {
+            0 => Ok(Link::Free),
+            value if value >= end => Ok(Link::End),
+            value if value == bad => Ok(Link::Bad),
+            value if (2..=self.geometry.last_cluster()).contains(&value) => Ok(Link::Next(value)),
+            // Ссылка за пределы тома: таблица повреждена.
+            _ => Err(Errno::EIO),
+        }
+    }
+
+    /// Записывает ячейку FAT во все используемые копии таблицы.
+    pub(super) fn set_link(&self, cluster: u32, link: Link) -> Result<(), Errno> {
+        let fat_type = self.geometry.fat_type;
+        let value = match (link, fat_type) {
+            (Link::Free, _) => 0,
+            (Link::Next(next), _) => next,
+            (Link::End, FatType::Fat12) => 0xFFF,
+            (Link::End, FatType::Fat16) => 0xFFFF,
+            (Link::End, FatType::Fat32) => 0x0FFF_FFFF,
+            (Link::Bad, FatType::Fat12) => 0xFF7,
+            (Link::Bad, FatType::Fat16) => 0xFFF7,
+            (Link::Bad, FatType::Fat32) => 0x0FFF_FFF7,
+        };
+
+        let (slot, width) = self.fat_slot(cluster);
+        for copy in self.fat_copies() {
+            let offset = slot + copy;
+            let mut bytes = [0; 4];
+            let new = match fat_type {
+                FatType::Fat12 => {
+                    self.disk.read(offset, &mut bytes[..2])?;
+                    let old = u16::from_le_bytes([bytes[0], bytes[1]]) as u32;
+                    if cluster % 2 == 1 {
+                        (old & 0x000F) | (value << 4)
+                    } else {
+                        (old & 0xF000) | value
+                    }
+                }
+                FatType::Fat16 => value,
+                FatType::Fat32 => {
+                    // Старшие 4 бита зарезервированы и сохраняются.
+                    self.disk.read(offset, &mut bytes)?;
+                    (u32::from_le_bytes(bytes) & 0xF000_0000) | value
+                }
+            };
+            self.disk.write(offset, &new.to_le_bytes()[..width])?;
+        }
+        Ok(())
+    }
+
+    /// Кластеры цепочки, начинающейся с `first` (0 - пустая цепочка).
+    pub(super) fn chain(&self, first: u32) -> Result<Vec<u32>, Errno> {
+        let mut clusters = Vec::new();
+        let mut current = first;
+
+        while current != 0 {
+            if !(2..=self.geometry.last_cluster()).contains(&current)
+                || clusters.len() > self.geometry.cluster_count as usize
+            {
+                // Цепочка ведет за пределы тома или зациклена.
+                return Err(Errno::EIO);
+            }
+            clusters.push(current);
+            current = match self.link(current)? {
+                Link::Next(next) => next,
+                Link::End => 0,
+                Link::Free | Link::Bad => return Err(Errno::EIO),
+            };
+        }
+        Ok(clusters)
+    }
+
+    /// Выделяет обнуленный кластер и, если задан `previous`, присоединяет
+    /// его к цепочке после `previous`.
+    pub(super) fn allocate_cluster(
+        &self,
+        state: &mut State,
+        previous: Option<u32>,
+    ) -> Result<u32, Errno> {
+        if state.free_clusters == Some(0) {
+            return Err(Errno::ENOSPC);
+        }
+
+        let last = self.geometry.last_cluster();
+        let count = self.geometry.cluster_count;
+        let start = match state.next_free {
+            next if (2..=last).contains(&next) => next,
+            _ => 2,
+        };
+
+        let mut found = None;
+        for step in 0..count {
+            let cluster = 2 + (start - 2 + step) % count;
+            if self.link(cluster)? == Link::Free {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(Errno::ENOSPC)?;
+
+        self.zero_cluster(cluster)?;
+        self.set_link(cluster, Link::End)?;
+        if let Some(previous) = previous {
+            self.set_link(previous, Link::Next(cluster))?;
+        }
+
+        state.free_clusters = state.free_clusters.map(|free| free.saturating_sub(1));
+        state.next_free = cluster + 1;
+        state.dirty = true;
+        Ok(cluster)
+    }
+
+    /// Освобождает цепочку начиная с `first`.
+    pub(super) fn free_chain(&self, state: &mut State, first: u32) -> Result<(), Errno> {
+        let clusters = self.chain(first)?;
+        for &cluster in &clusters {
+            self.set_link(cluster, Link::Free)?;
+        }
+
+        let freed = clusters.len() as u32;
+        state.free_clusters = state.free_clusters.map(|free| free + freed);
+        state.dirty = true;
+        Ok(())
+    }
+
+    /// Обрезает цепочку до `keep` кластеров (`keep > 0`).
+    pub(super) fn shrink_chain(
+        &self,
+        state: &mut State,
+        first: u32,
+        keep: usize,
+    ) -> Result<(), Errno> {
+        let clusters = self.chain(first)?;
+        if let Some(&rest) = clusters.get(keep) {
+            self.set_link(clusters[keep - 1], Link::End)?;
+            self.free_chain(state, rest)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn zero_cluster(&self, cluster: u32) -> Result<(), Errno> {
+        let zeroes = vec![0; self.geometry.cluster_size as usize];
+        self.disk
+            .write(self.geometry.cluster_offset(cluster), &zeroes)
+    }
+
+    /// Освобождает кластеры закрытых удаленных файлов.
+    pub(super) fn free_orphans(&self, state: &mut State) -> Result<(), Errno> {
+        let orphans = core::mem::take(&mut *self.orphans.lock());
+        for first in orphans {
+            self.free_chain(state, first)?;
+        }
+        Ok(())
+    }
+
+    /// Количество свободных кластеров (при необходимости считается).
+    pub(super) fn count_free(&self, state: &mut State) -> Result<u32, Errno> {
+        if let Some(free) = state.free_clusters {
+            return Ok(free);
+        }
+
+        let mut free = 0;
+        for cluster in 2..=self.geometry.last_cluster() {
+            if self.link(cluster)? == Link::Free {
+                free += 1;
+            }
+        }
+        state.free_clusters = Some(free);
+        state.dirty = true;
+        Ok(free)
+    }
+
+    /// Читает подсказки из FSInfo (только FAT32). Неправдоподобные значения
+    /// игнорируются: FSInfo - только подсказка, а не часть метаданных.
+    pub(super) fn load_fsinfo(&self, state: &mut State) -> Result<(), Errno> {
+        let Some(offset) = self.geometry.fsinfo_offset else {
+            return Ok(());
+        };
+
+        let mut sector = [0; 512];
+        self.disk.read(offset, &mut sector)?;
+        let u32_at = |at: usize| u32::from_le_bytes(sector[at..at + 4].try_into().unwrap());
+        if u32_at(0) != FSINFO_LEAD || u32_at(484) != FSINFO_STRUCT || u32_at(508) != FSINFO_TRAIL {
+            return Ok(());
+        }
+
+        let free = u32_at(488);
+        if free != FSINFO_UNKNOWN && free <= self.geometry.cluster_count {
+            state.free_clusters = Some(free);
+        }
+        state.next_free = u32_at(492);
+        Ok(())
+    }
+
+    /// Записывает количество свободных кластеров и подсказку в FSInfo.
+    pub(super) fn store_fsinfo(&self, state: &State) -> Result<(), Errno> {
+        let Some(offset) = self.geometry.fsinfo_offset else {
+            return Ok(());
+        };
+
+        let mut sector = [0; 512];
+        self.disk.read(offset, &mut sector)?;
+        sector[0..4].copy_from_slice(&FSINFO_LEAD.to_le_bytes());
+        sector[484..488].copy_from_slice(&FSINFO_STRUCT.to_le_bytes());
+        let free = state.free_clusters.unwrap_or(FSINFO_UNKNOWN);
+        sector[488..492].copy_from_slice(&free.to_le_bytes());
+        sector[492..496].copy_from_slice(&state.next_free.to_le_bytes());
+        sector[508..512].copy_from_slice(&FSINFO_TRAIL.to_le_bytes());
+        self.disk.write(offset, &sector)
+    }
+}
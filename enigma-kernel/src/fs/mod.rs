@@ -0,0 +1,34 @@
+//! Данный модуль содержит файловые системы ядра.
+
+mod disk;
+mod dos_time;
+pub mod exfat;
+pub mod ext2;
+pub mod fat;
+pub mod ramdisk;
+pub mod tmpfs;
+pub mod vfs;
+
+use crate::errno::Errno;
+use alloc::sync::Arc;
+use ramdisk::{Ramdisk, RamdiskFs};
+use tmpfs::Tmpfs;
+use vfs::MountFlags;
+
+/// Собирает начальное дерево файлов. Ramdisk (если он есть) монтируется
+/// только для чтения в `/`, а временные файлы живут в tmpfs на `/tmp`;
+/// без ramdisk корнем становится tmpfs.
+pub fn init(ramdisk: Option<&'static Ramdisk>) -> Result<(), Errno> {
+    let Some(ramdisk) = ramdisk else {
+        vfs::mount(Tmpfs::new(), "/", MountFlags::default())?;
+        return Ok(());
+    };
+
+    vfs::mount(
+        Arc::new(RamdiskFs::new(ramdisk)),
+        "/",
+        MountFlags::READ_ONLY,
+    )?;
+    vfs::mount(Tmpfs::new(), "/tmp", MountFlags::default())?;
+    Ok(())
+}
@@ -0,0 +1,451 @@
+//! Ramdisk - файловая система загрузки.
+//!
+//! Сборка упаковывает каталог `ramdisk/` в архив cpio (формат newc), а
+//! загрузчик кладет его в память и передает адрес через
+//! `BootInfo::ramdisk_addr`. Архив доступен только для чтения и не
+//! копируется: записи ссылаются прямо на память загрузчика.
+//!
+//! [`RamdiskFs`] позволяет смонтировать архив в VFS.
+
+use super::vfs::{DirEntry, FileSystem, FileType, Inode, Metadata, Timestamp};
+use crate::errno::Errno;
+use crate::process::fd::File;
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use conquer_once::spin::OnceCell;
+use core::any::Any;
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+static RAMDISK: OnceCell<Ramdisk> = OnceCell::uninit();
+
+const MAGIC: &[u8; 6] = b"070701";
+/// Вариант newc с контрольной суммой (она не проверяется).
+const MAGIC_CRC: &[u8; 6] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFSOCK: u32 = 0o140000;
+
+/// Номер inode корня (у остальных записей номер - смещение в архиве + 2).
+const ROOT_INO: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    /// Устройства, каналы и сокеты хранятся, но не используются.
+    Other,
+}
+
+/// Запись архива.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Номер inode (по смещению записи в архиве).
+    pub ino: u64,
+    /// Путь без начального `/` и `./` (корень - пустая строка).
+    pub path: &'static str,
+    pub mode: u32,
+    pub mtime: u32,
+    /// Содержимое файла или цель символической ссылки.
+    pub data: &'static [u8],
+}
+
+impl Entry {
+    pub fn kind(&self) -> EntryKind {
+        match self.mode & S_IFMT {
+            S_IFREG => EntryKind::File,
+            S_IFDIR => EntryKind::Directory,
+            S_IFLNK => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        }
+    }
+
+    /// Последний компонент пути.
+    pub fn name(&self) -> &'static str {
+        self.path.rsplit('/').next().unwrap_or(self.path)
+    }
+
+    /// Права доступа (младшие 12 бит режима).
+    pub fn permissions(&self) -> u32 {
+        self.mode & 0o7777
+    }
+}
+
+/// Проверенный архив cpio.
+#[derive(Debug, Clone, Copy)]
+pub struct Ramdisk {
+    data: &'static [u8],
+}
+
+impl Ramdisk {
+    /// Проверяет архив целиком. Возвращает `EINVAL`, если заголовки
+    /// повреждены или архив обрывается до записи `TRAILER!!!`.
+    pub fn new(data: &'static [u8]) -> Result<Self, Errno> {
+        let mut offset = 0;
+        loop {
+            match parse_entry(data, offset)? {
+                Some((_, next)) => offset = next,
+                None => return Ok(Self { data }),
+            }
+        }
+    }
+
+    /// Все записи архива в порядке хранения.
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        let mut offset = 0;
+        core::iter::from_fn(move || {
+            // Архив проверен в `new`, поэтому ошибок здесь нет.
+            let (entry, next) = parse_entry(self.data, offset).ok()??;
+            offset = next;
+            Some(entry)
+        })
+    }
+
+    /// Ищет запись по пути (`/etc/motd`, `etc/motd` и `./etc/motd`
+    /// равнозначны). Символические ссылки не разрешаются.
+    pub fn lookup(&self, path: &str) -> Option<Entry> {
+        let path = normalize(path);
+        if path.is_empty() {
+            return Some(Entry {
+                ino: ROOT_INO,
+                path: "",
+                mode: S_IFDIR | 0o555,
+                mtime: 0,
+                data: &[],
+            });
+        }
+
+        self.entries().find(|entry| entry.path == path)
+    }
+
+    /// Записи, непосредственно лежащие в каталоге `path`.
+    pub fn read_dir<'a>(&'a self, path: &'a str) -> impl Iterator<Item = Entry> + 'a {
+        let path = normalize(path);
+        self.entries().filter(move |entry| {
+            let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            !entry.path.is_empty() && parent == path
+        })
+    }
+
+    /// Открывает файл для чтения.
+    pub fn open(&self, path: &str) -> Result<Arc<RamdiskFile>, Errno> {
+        let entry = self.lookup(path).ok_or(Errno::ENOENT)?;
+        match entry.kind() {
+            EntryKind::File => Ok(Arc::new(RamdiskFile {
+                data: entry.data,
+                position: Mutex::new(0),
+            })),
+            EntryKind::Directory => Err(Errno::EISDIR),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
+
+/// Открытый файл ramdisk с собственной позицией чтения.
+pub struct RamdiskFile {
+    data: &'static [u8],
+    position: Mutex<usize>,
+}
+
+impl RamdiskFile {
+    /// Все содержимое файла (например, для загрузчика ELF).
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
+impl File for RamdiskFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Errno> {
+        let mut position = self.position.lock();
+        let rest = &self.data[*position..];
+        let len = rest.len().min(buffer.len());
+
+        buffer[..len].copy_from_slice(&rest[..len]);
+        *position += len;
+        Ok(len)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+}
+
+/// Ramdisk как файловая система только для чтения.
+pub struct RamdiskFs {
+    ramdisk: &'static Ramdisk,
+}
+
+impl RamdiskFs {
+    pub fn new(ramdisk: &'static Ramdisk) -> Self {
+        Self { ramdisk }
+    }
+}
+
+impl FileSystem for RamdiskFs {
+    fn name(&self) -> &'static str {
+        "ramdisk"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(RamdiskInode {
+            ramdisk: self.ramdisk,
+            entry: self.ramdisk.lookup("").unwrap(),
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+struct RamdiskInode {
+    ramdisk: &'static Ramdisk,
+    entry: Entry,
+}
+
+impl RamdiskInode {
+    fn kind(&self) -> FileType {
+        match self.entry.mode & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFIFO => FileType::Fifo,
+            S_IFSOCK => FileType::Socket,
+            _ => FileType::Regular,
+        }
+    }
+
+    fn inode(&self, entry: Entry) -> Arc<dyn Inode> {
+        Arc::new(RamdiskInode {
+            ramdisk: self.ramdisk,
+            entry,
+        })
+    }
+}
+
+impl Inode for RamdiskInode {
+    fn ino(&self) -> u64 {
+        self.entry.ino
+    }
+
+    fn metadata(&self) -> Result<Metadata, Errno> {
+        let kind = self.kind();
+        let time = Timestamp::new(i64::from(self.entry.mtime), 0);
+
+        Ok(Metadata {
+            ino: self.entry.ino,
+            kind,
+            mode: self.entry.permissions() as u16,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+            size: self.entry.data.len() as u64,
+            atime: time,
+            mtime: time,
+            ctime: time,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+        match self.kind() {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Errno::EISDIR),
+            _ => return Err(Errno::EINVAL),
+        }
+
+        let data = self.entry.data;
+        let start = usize::try_from(offset).map_or(data.len(), |offset| offset.min(data.len()));
+        let len = (data.len() - start).min(buffer.len());
+        buffer[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        if self.kind() != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        let path = self.entry.path;
+        let entry = match name {
+            "." => Some(self.entry),
+            ".." => {
+                let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+                self.ramdisk.lookup(parent)
+            }
+            name if path.is_empty() => self.ramdisk.lookup(name),
+            name => {
+                let mut child = String::with_capacity(path.len() + name.len() + 1);
+                child.push_str(path);
+                child.push('/');
+                child.push_str(name);
+                self.ramdisk.lookup(&child)
+            }
+        };
+
+        entry.map(|entry| self.inode(entry)).ok_or(Errno::ENOENT)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        if self.kind() != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        Ok(self
+            .ramdisk
+            .read_dir(self.entry.path)
+            .map(|entry| DirEntry {
+                name: entry.name().to_string(),
+                ino: entry.ino,
+                kind: RamdiskInode {
+                    ramdisk: self.ramdisk,
+                    entry,
+                }
+                .kind(),
+            })
+            .collect())
+    }
+
+    fn read_link(&self) -> Result<String, Errno> {
+        if self.kind() != FileType::Symlink {
+            return Err(Errno::EINVAL);
+        }
+        Ok(String::from_utf8_lossy(self.entry.data).into_owned())
+    }
+}
+
+/// Разбирает архив, переданный загрузчиком.
+///
+/// ## Safety
+///
+/// `[addr, addr + len)` должен быть отображен и не изменяться до конца
+/// работы ядра.
+pub unsafe fn init(addr: VirtAddr, len: u64) -> Result<&'static Ramdisk, Errno> {
+    let data = unsafe { core::slice::from_raw_parts(addr.as_ptr::<u8>(), len as usize) };
+    let ramdisk = Ramdisk::new(data)?;
+
+    RAMDISK
+        .try_init_once(|| ramdisk)
+        .map_err(|_| Errno::EBUSY)?;
+    Ok(RAMDISK.get().unwrap())
+}
+
+/// Ramdisk, если загрузчик его передал.
+pub fn get() -> Option<&'static Ramdisk> {
+    RAMDISK.get()
+}
+
+/// Разбирает запись по смещению `offset`. Возвращает запись и смещение
+/// следующей или `None` на записи `TRAILER!!!`.
+fn parse_entry(data: &'static [u8], offset: usize) -> Result<Option<(Entry, usize)>, Errno> {
+    let header = data
+        .get(offset..offset.checked_add(HEADER_SIZE).ok_or(Errno::EINVAL)?)
+        .ok_or(Errno::EINVAL)?;
+    if &header[..6] != MAGIC && &header[..6] != MAGIC_CRC {
+        return Err(Errno::EINVAL);
+    }
+
+    let field = |index: usize| parse_hex(&header[6 + index * 8..14 + index * 8]);
+    let mode = field(1)?;
+    let mtime = field(5)?;
+    let file_size = field(6)? as usize;
+    let name_size = field(11)? as usize;
+
+    // Имя заканчивается нулевым байтом, который входит в `name_size`.
+    let name_start = offset + HEADER_SIZE;
+    let name = name_start
+        .checked_add(name_size)
+        .and_then(|end| data.get(name_start..end))
+        .and_then(|name| name.split_last())
+        .filter(|(last, _)| **last == 0)
+        .and_then(|(_, name)| core::str::from_utf8(name).ok())
+        .ok_or(Errno::EINVAL)?;
+
+    if name == TRAILER {
+        return Ok(None);
+    }
+
+    let data_start = (name_start + name_size).next_multiple_of(4);
+    let data_end = data_start.checked_add(file_size).ok_or(Errno::EINVAL)?;
+    let contents = data.get(data_start..data_end).ok_or(Errno::EINVAL)?;
+
+    let entry = Entry {
+        ino: offset as u64 + 2,
+        path: normalize(name),
+        mode,
+        mtime,
+        data: contents,
+    };
+    Ok(Some((entry, data_end.next_multiple_of(4))))
+}
+
+fn parse_hex(digits: &[u8]) -> Result<u32, Errno> {
+    let digits = core::str::from_utf8(digits).map_err(|_| Errno::EINVAL)?;
+    u32::from_str_radix(digits, 16).map_err(|_| Errno::EINVAL)
+}
+
+/// Убирает начальные `/` и `./`, конечные `/` и путь `.`.
+fn normalize(path: &str) -> &str {
+    let mut path = path;
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else {
+            break;
+        }
+    }
+
+    match path.trim_end_matches('/') {
+        "." => "",
+        path => path,
+    }
+}
+
+// --- TEST ZONE --- //
+
+#[test_case]
+fn test_parse_archive() {
+    // Каталог `etc` и файл `etc/motd` с содержимым "hi\n".
+    static ARCHIVE: &[u8] = b"\
+07070100000002000041ED000000000000000000000002000000000000000000000000000000000000000000000000\
+0000000400000000etc\0\0\0\
+07070100000003000081A4000000000000000000000001000000000000000300000000000000000000000000000000\
+0000000900000000etc/motd\0\0hi\n\0\
+0707010000000000000000000000000000000000000001000000000000000000000000000000000000000000000000\
+0000000B00000000TRAILER!!!\0\0\0\0";
+
+    let ramdisk = Ramdisk::new(ARCHIVE).unwrap();
+    assert_eq!(ramdisk.entries().count(), 2);
+
+    let motd = ramdisk.lookup("/etc/motd").unwrap();
+    assert_eq!(motd.kind(), EntryKind::File);
+    assert_eq!(motd.data, b"hi\n");
+    assert_eq!(
+        ramdisk.lookup("./etc/").unwrap().kind(),
+        EntryKind::Directory
+    );
+    assert_eq!(
+        ramdisk.read_dir("etc").map(|entry| entry.name()).next(),
+        Some("motd")
+    );
+
+    assert_eq!(Ramdisk::new(&ARCHIVE[..200]).err(), Some(Errno::EINVAL));
+}
@@ -0,0 +1,702 @@
+//! Tmpfs - файловая система в оперативной памяти.
+//!
+//! Структура каталогов и атрибуты хранятся в куче ядра, а данные файлов -
+//! в отдельных физических кадрах (по странице на каждые 4 КиБ, дыры в
+//! файлах кадров не занимают). Размер данных и количество inode
+//! ограничиваются при создании системы.
+//!
+//! Inode освобождается, когда на него не остается ни записей каталогов, ни
+//! открытых файлов, поэтому удаленный, но открытый файл продолжает работать.
+
+use super::vfs::{DirEntry, FileSystem, FileType, Inode, Metadata, Timestamp};
+use crate::errno::Errno;
+use crate::memory::{self, GlobalFrameAllocator};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::{FrameDeallocator, PhysFrame};
+
+const PAGE_SIZE: u64 = 4096;
+/// Максимальное количество жестких ссылок на inode.
+const MAX_LINKS: u32 = u16::MAX as u32;
+
+/// Ограничения по умолчанию: 16 МиБ данных и 4096 inode.
+pub const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_INODES: u64 = 4096;
+
+/// Использование памяти системой.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TmpfsStats {
+    pub used_bytes: u64,
+    pub max_bytes: u64,
+    pub inodes: u64,
+    pub max_inodes: u64,
+}
+
+pub struct Tmpfs {
+    shared: Arc<Shared>,
+    root: Arc<TmpInode>,
+}
+
+/// Общие для всех inode данные системы.
+struct Shared {
+    max_pages: u64,
+    max_inodes: u64,
+    pages: AtomicU64,
+    inodes: AtomicU64,
+    next_ino: AtomicU64,
+    /// Переносы между каталогами выполняются по одному, чтобы проверка
+    /// "каталог не переносится в своего потомка" видела неизменное дерево
+    /// (ссылки на родителей меняются только под этой блокировкой).
+    rename_lock: Mutex<()>,
+}
+
+impl Shared {
+    fn reserve(counter: &AtomicU64, limit: u64, count: u64) -> Result<(), Errno> {
+        counter
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(count).filter(|&total| total <= limit)
+            })
+            .map(drop)
+            .map_err(|_| Errno::ENOSPC)
+    }
+
+    fn allocate_page(&self) -> Result<PhysFrame, Errno> {
+        Self::reserve(&self.pages, self.max_pages, 1)?;
+        memory::allocate_zeroed_frame().ok_or_else(|| {
+            self.pages.fetch_sub(1, Ordering::Relaxed);
+            Errno::ENOMEM
+        })
+    }
+
+    fn free_page(&self, frame: PhysFrame) {
+        unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+        self.pages.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Tmpfs {
+    /// Система с ограничениями по умолчанию.
+    pub fn new() -> Arc<Self> {
+        Self::with_limits(DEFAULT_MAX_BYTES, DEFAULT_MAX_INODES)
+    }
+
+    /// Система, хранящая не больше `max_bytes` данных (с округлением до
+    /// страниц) и `max_inodes` inode (включая корень).
+    pub fn with_limits(max_bytes: u64, max_inodes: u64) -> Arc<Self> {
+        let shared = Arc::new(Shared {
+            max_pages: max_bytes / PAGE_SIZE,
+            max_inodes: max_inodes.max(1),
+            pages: AtomicU64::new(0),
+            inodes: AtomicU64::new(1),
+            next_ino: AtomicU64::new(2),
+            rename_lock: Mutex::new(()),
+        });
+
+        let root = TmpInode::new(&shared, 1, FileType::Directory, 0o755, Weak::new());
+        Arc::new(Self { shared, root })
+    }
+
+    pub fn stats(&self) -> TmpfsStats {
+        TmpfsStats {
+            used_bytes: self.shared.pages.load(Ordering::Relaxed) * PAGE_SIZE,
+            max_bytes: self.shared.max_pages * PAGE_SIZE,
+            inodes: self.shared.inodes.load(Ordering::Relaxed),
+            max_inodes: self.shared.max_inodes,
+        }
+    }
+}
+
+impl FileSystem for Tmpfs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct TmpInode {
+    ino: u64,
+    kind: FileType,
+    shared: Arc<Shared>,
+    this: Weak<TmpInode>,
+    /// Родительский каталог (только у каталогов; у корня пусто).
+    parent: Mutex<Weak<TmpInode>>,
+    data: Mutex<InodeData>,
+}
+
+struct InodeData {
+    mode: u16,
+    nlink: u32,
+    atime: Timestamp,
+    mtime: Timestamp,
+    ctime: Timestamp,
+    content: Content,
+}
+
+enum Content {
+    File {
+        size: u64,
+        /// Страницы файла по номеру; отсутствующие читаются как нули.
+        pages: BTreeMap<u64, PhysFrame>,
+    },
+    Directory(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+    /// Устройства, каналы и сокеты хранят только атрибуты.
+    Special,
+}
+
+impl TmpInode {
+    fn new(
+        shared: &Arc<Shared>,
+        ino: u64,
+        kind: FileType,
+        mode: u16,
+        parent: Weak<TmpInode>,
+    ) -> Arc<Self> {
+        let now = Timestamp::now();
+        let (nlink, content) = match kind {
+            FileType::Regular => (
+                1,
+                Content::File {
+                    size: 0,
+                    pages: BTreeMap::new(),
+                },
+            ),
+            FileType::Directory => (2, Content::Directory(BTreeMap::new())),
+            FileType::Symlink => (1, Content::Symlink(String::new())),
+            _ => (1, Content::Special),
+        };
+
+        Arc::new_cyclic(|this| Self {
+            ino,
+            kind,
+            shared: shared.clone(),
+            this: this.clone(),
+            parent: Mutex::new(parent),
+            data: Mutex::new(InodeData {
+                mode: mode & 0o7777,
+                nlink,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                content,
+            }),
+        })
+    }
+
+    fn this(&self) -> Arc<TmpInode> {
+        self.this.upgrade().unwrap()
+    }
+
+    /// Выделяет номер и создает inode, если не превышен лимит.
+    fn allocate(&self, kind: FileType, mode: u16) -> Result<Arc<TmpInode>, Errno> {
+        let shared = &self.shared;
+        Shared::reserve(&shared.inodes, shared.max_inodes, 1)?;
+
+        let ino = shared.next_ino.fetch_add(1, Ordering::Relaxed);
+        let parent = match kind {
+            FileType::Directory => self.this.clone(),
+            _ => Weak::new(),
+        };
+        Ok(TmpInode::new(shared, ino, kind, mode, parent))
+    }
+
+    /// Добавляет новую запись `name`, созданную `make`, в этот каталог.
+    fn insert_new(
+        &self,
+        name: &str,
+        make: impl FnOnce() -> Result<Arc<TmpInode>, Errno>,
+    ) -> Result<Arc<TmpInode>, Errno> {
+        let mut data = self.data.lock();
+        let now = Timestamp::now();
+        let Content::Directory(entries) = &mut data.content else {
+            return Err(Errno::ENOTDIR);
+        };
+        if entries.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+
+        let inode = make()?;
+        entries.insert(name.to_string(), inode.clone());
+        if inode.kind == FileType::Directory {
+            data.nlink += 1;
+        }
+        data.mtime = now;
+        data.ctime = now;
+        Ok(inode)
+    }
+
+    fn same_fs(&self, other: &Arc<dyn Inode>) -> Result<Arc<TmpInode>, Errno> {
+        let other = other
+            .as_any()
+            .downcast_ref::<TmpInode>()
+            .ok_or(Errno::EXDEV)?;
+        if !Arc::ptr_eq(&self.shared, &other.shared) {
+            return Err(Errno::EXDEV);
+        }
+        Ok(other.this())
+    }
+
+    /// `ancestor` - это `self` или один из его родителей.
+    fn is_descendant_of(&self, ancestor: &TmpInode) -> bool {
+        let mut current = self.this();
+        loop {
+            if current.ino == ancestor.ino {
+                return true;
+            }
+            let parent = current.parent.lock().upgrade();
+            match parent {
+                Some(parent) => current = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn touch_changed(&self) {
+        self.data.lock().ctime = Timestamp::now();
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        if let Content::File { pages, .. } = &mut self.data.lock().content {
+            for (_, frame) in core::mem::take(pages) {
+                self.shared.free_page(frame);
+            }
+        }
+        self.shared.inodes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Inode for TmpInode {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    fn metadata(&self) -> Result<Metadata, Errno> {
+        let data = self.data.lock();
+        let size = match &data.content {
+            Content::File { size, .. } => *size,
+            Content::Directory(entries) => entries.len() as u64,
+            Content::Symlink(target) => target.len() as u64,
+            Content::Special => 0,
+        };
+
+        Ok(Metadata {
+            ino: self.ino,
+            kind: self.kind,
+            mode: data.mode,
+            nlink: data.nlink,
+            uid: 0,
+            gid: 0,
+            size,
+            atime: data.atime,
+            mtime: data.mtime,
+            ctime: data.ctime,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+        let mut data = self.data.lock();
+        let Content::File { size, pages } = &data.content else {
+            return Err(match self.kind {
+                FileType::Directory => Errno::EISDIR,
+                _ => Errno::EINVAL,
+            });
+        };
+
+        let len = size.saturating_sub(offset).min(buffer.len() as u64) as usize;
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let in_page = (position % PAGE_SIZE) as usize;
+            let chunk = (PAGE_SIZE as usize - in_page).min(len - done);
+            let target = &mut buffer[done..done + chunk];
+
+            match pages.get(&(position / PAGE_SIZE)) {
+                Some(frame) => unsafe {
+                    let source = memory::phys_to_virt(frame.start_address()).as_ptr::<u8>();
+                    core::ptr::copy_nonoverlapping(source.add(in_page), target.as_mut_ptr(), chunk);
+                },
+                None => target.fill(0),
+            }
+            done += chunk;
+        }
+
+        data.atime = Timestamp::now();
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, Errno> {
+        let mut data = self.data.lock();
+        let Content::File { size, pages } = &mut data.content else {
+            return Err(match self.kind {
+                FileType::Directory => Errno::EISDIR,
+                _ => Errno::EINVAL,
+            });
+        };
+        // Пустая запись не меняет ни размер, ни время изменения.
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let end = offset
+            .checked_add(buffer.len() as u64)
+            .filter(|&end| end <= i64::MAX as u64)
+            .ok_or(Errno::EFBIG)?;
+
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let in_page = (position % PAGE_SIZE) as usize;
+            let chunk = (PAGE_SIZE as usize - in_page).min(buffer.len() - done);
+
+            let frame = match pages.get(&(position / PAGE_SIZE)) {
+                Some(&frame) => frame,
+                None => match self.shared.allocate_page() {
+                    Ok(frame) => *pages.entry(position / PAGE_SIZE).or_insert(frame),
+                    // Частичная запись, как в `write(2)`: ошибка, только
+                    // если не записано ничего.
+                    Err(_) if done > 0 => break,
+                    Err(error) => return Err(error),
+                },
+            };
+
+            unsafe {
+                let target = memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+                core::ptr::copy_nonoverlapping(buffer[done..].as_ptr(), target.add(in_page), chunk);
+            }
+            done += chunk;
+        }
+
+        *size = (*size).max(if done == buffer.len() {
+            end
+        } else {
+            offset + done as u64
+        });
+        let now = Timestamp::now();
+        data.mtime = now;
+        data.ctime = now;
+        Ok(done)
+    }
+
+    fn truncate(&self, new_size: u64) -> Result<(), Errno> {
+        if new_size > i64::MAX as u64 {
+            return Err(Errno::EFBIG);
+        }
+
+        let mut data = self.data.lock();
+        let Content::File { size, pages } = &mut data.content else {
+            return Err(match self.kind {
+                FileType::Directory => Errno::EISDIR,
+                _ => Errno::EINVAL,
+            });
+        };
+
+        if new_size < *size {
+            // Страницы целиком за новым концом освобождаются, а хвост
+            // последней обнуляется, чтобы при росте файла там были нули.
+            let keep = new_size.div_ceil(PAGE_SIZE);
+            for (_, frame) in pages.split_off(&keep) {
+                self.shared.free_page(frame);
+            }
+
+            let in_page = (new_size % PAGE_SIZE) as usize;
+            if in_page != 0
+                && let Some(frame) = pages.get(&(new_size / PAGE_SIZE))
+            {
+                unsafe {
+                    let page = memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+                    page.add(in_page)
+                        .write_bytes(0, PAGE_SIZE as usize - in_page);
+                }
+            }
+        }
+
+        *size = new_size;
+        let now = Timestamp::now();
+        data.mtime = now;
+        data.ctime = now;
+        Ok(())
+    }
+
+    fn set_times(&self, atime: Option<Timestamp>, mtime: Option<Timestamp>) -> Result<(), Errno> {
+        let mut data = self.data.lock();
+        if let Some(atime) = atime {
+            data.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            data.mtime = mtime;
+        }
+        data.ctime = Timestamp::now();
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match name {
+            "." => return Ok(self.this()),
+            ".." => {
+                let parent = self.parent.lock().upgrade();
+                return Ok(parent.unwrap_or_else(|| self.this()));
+            }
+            _ => {}
+        }
+
+        let data = self.data.lock();
+        let Content::Directory(entries) = &data.content else {
+            return Err(Errno::ENOTDIR);
+        };
+        entries
+            .get(name)
+            .map(|inode| inode.clone() as Arc<dyn Inode>)
+            .ok_or(Errno::ENOENT)
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> Result<Arc<dyn Inode>, Errno> {
+        if kind == FileType::Symlink {
+            return Err(Errno::EINVAL);
+        }
+        Ok(self.insert_new(name, || self.allocate(kind, mode))?)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Ok(self.insert_new(name, || {
+            let inode = self.allocate(FileType::Symlink, 0o777)?;
+            inode.data.lock().content = Content::Symlink(target.to_string());
+            Ok(inode)
+        })?)
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        let target = self.same_fs(target)?;
+        if target.kind == FileType::Directory {
+            return Err(Errno::EPERM);
+        }
+
+        self.insert_new(name, || {
+            let mut data = target.data.lock();
+            // Удаленный файл (он еще открыт) нельзя вернуть в дерево.
+            if data.nlink == 0 {
+                return Err(Errno::ENOENT);
+            }
+            if data.nlink >= MAX_LINKS {
+                return Err(Errno::EMLINK);
+            }
+            data.nlink += 1;
+            data.ctime = Timestamp::now();
+            Ok(target.clone())
+        })?;
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let mut data = self.data.lock();
+        let Content::Directory(entries) = &mut data.content else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        let inode = entries.get(name).ok_or(Errno::ENOENT)?;
+        if inode.kind == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+
+        let inode = entries.remove(name).unwrap();
+        let now = Timestamp::now();
+        data.mtime = now;
+        data.ctime = now;
+        drop(data);
+
+        let mut target = inode.data.lock();
+        target.nlink -= 1;
+        target.ctime = now;
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        // Блокирует родителя и потомка, поэтому не должен пересекаться с
+        // переносом, который блокирует каталоги в другом порядке.
+        let _rename = self.shared.rename_lock.lock();
+        let mut data = self.data.lock();
+        let Content::Directory(entries) = &mut data.content else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        let inode = entries.get(name).ok_or(Errno::ENOENT)?;
+        if inode.kind != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        {
+            let mut child = inode.data.lock();
+            if matches!(&child.content, Content::Directory(entries) if !entries.is_empty()) {
+                return Err(Errno::ENOTEMPTY);
+            }
+            child.nlink = 0;
+        }
+
+        entries.remove(name);
+        data.nlink -= 1;
+        let now = Timestamp::now();
+        data.mtime = now;
+        data.ctime = now;
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        new_parent: &Arc<dyn Inode>,
+        new_name: &str,
+    ) -> Result<(), Errno> {
+        let new_parent = self.same_fs(new_parent)?;
+        let _rename = self.shared.rename_lock.lock();
+
+        let source = match self.lookup(old_name)?.as_any().downcast_ref::<TmpInode>() {
+            Some(source) => source.this(),
+            None => return Err(Errno::EXDEV),
+        };
+        if source.kind == FileType::Directory && new_parent.is_descendant_of(&source) {
+            return Err(Errno::EINVAL);
+        }
+
+        let same_dir = self.ino == new_parent.ino;
+        if same_dir && old_name == new_name {
+            return Ok(());
+        }
+
+        // Каталоги блокируются по возрастанию номера inode.
+        let (mut old_data, mut new_data) = if same_dir {
+            (self.data.lock(), None)
+        } else if self.ino < new_parent.ino {
+            let old = self.data.lock();
+            (old, Some(new_parent.data.lock()))
+        } else {
+            let new = new_parent.data.lock();
+            (self.data.lock(), Some(new))
+        };
+
+        // Запись могли удалить, пока каталоги не были заблокированы.
+        match &old_data.content {
+            Content::Directory(entries)
+                if entries
+                    .get(old_name)
+                    .is_some_and(|entry| entry.ino == source.ino) => {}
+            _ => return Err(Errno::ENOENT),
+        }
+
+        let replaced = {
+            let target = new_data.as_deref_mut().unwrap_or(&mut old_data);
+            let Content::Directory(entries) = &target.content else {
+                return Err(Errno::ENOTDIR);
+            };
+            entries.get(new_name).cloned()
+        };
+
+        if let Some(replaced) = &replaced {
+            if replaced.ino == source.ino {
+                // Две жесткие ссылки на один inode: ничего не делаем.
+                return Ok(());
+            }
+            match (
+                source.kind == FileType::Directory,
+                replaced.kind == FileType::Directory,
+            ) {
+                (true, false) => return Err(Errno::ENOTDIR),
+                (false, true) => return Err(Errno::EISDIR),
+                (true, true) => {
+                    // Заменяемый каталог не может быть старым родителем:
+                    // в нем лежит переносимая запись.
+                    if replaced.ino == self.ino {
+                        return Err(Errno::ENOTEMPTY);
+                    }
+                    let replaced_data = replaced.data.lock();
+                    if matches!(&replaced_data.content, Content::Directory(entries) if !entries.is_empty())
+                    {
+                        return Err(Errno::ENOTEMPTY);
+                    }
+                }
+                (false, false) => {}
+            }
+        }
+
+        let now = Timestamp::now();
+        let is_directory = source.kind == FileType::Directory;
+
+        if let Content::Directory(entries) = &mut old_data.content {
+            entries.remove(old_name);
+        }
+        old_data.mtime = now;
+        old_data.ctime = now;
+        if is_directory && !same_dir {
+            old_data.nlink -= 1;
+        }
+
+        let target = new_data.as_deref_mut().unwrap_or(&mut old_data);
+        if let Content::Directory(entries) = &mut target.content {
+            entries.insert(new_name.to_string(), source.clone());
+        }
+        target.mtime = now;
+        target.ctime = now;
+        if is_directory && !same_dir {
+            target.nlink += 1;
+        }
+        if let Some(replaced) = &replaced
+            && replaced.kind == FileType::Directory
+        {
+            // У заменяемого каталога была ссылка `..` на нового родителя.
+            target.nlink -= 1;
+        }
+
+        drop(new_data);
+        drop(old_data);
+
+        if let Some(replaced) = replaced {
+            let mut data = replaced.data.lock();
+            data.nlink = match replaced.kind {
+                FileType::Directory => 0,
+                _ => data.nlink - 1,
+            };
+            data.ctime = now;
+        }
+        if is_directory {
+            *source.parent.lock() = new_parent.this.clone();
+        }
+        source.touch_changed();
+        Ok(())
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        let data = self.data.lock();
+        let Content::Directory(entries) = &data.content else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        Ok(entries
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                ino: inode.ino,
+                kind: inode.kind,
+            })
+            .collect())
+    }
+
+    fn read_link(&self) -> Result<String, Errno> {
+        match &self.data.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
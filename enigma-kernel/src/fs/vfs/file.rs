@@ -0,0 +1,200 @@
+//! Открытые файлы.
+
+use super::path::{self, Location};
+use super::{DirEntry, FileType, Metadata, check_name};
+use crate::errno::Errno;
+use crate::process::fd::File;
+use alloc::{sync::Arc, vec::Vec};
+use core::ops::BitOr;
+use spin::Mutex;
+
+/// Флаги `open` (значения совпадают с Linux).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    pub const READ_ONLY: Self = Self(0);
+    pub const WRITE_ONLY: Self = Self(0o1);
+    pub const READ_WRITE: Self = Self(0o2);
+    pub const CREATE: Self = Self(0o100);
+    pub const EXCLUSIVE: Self = Self(0o200);
+    pub const TRUNCATE: Self = Self(0o1000);
+    pub const APPEND: Self = Self(0o2000);
+    pub const DIRECTORY: Self = Self(0o200000);
+    pub const NOFOLLOW: Self = Self(0o400000);
+
+    const ACCESS_MODE: u32 = 0o3;
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_readable(self) -> bool {
+        self.0 & Self::ACCESS_MODE != Self::WRITE_ONLY.0
+    }
+
+    pub const fn is_writable(self) -> bool {
+        self.0 & Self::ACCESS_MODE != Self::READ_ONLY.0
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Откуда отсчитывается позиция в [`OpenFile::seek`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Открытый файл VFS: inode, флаги и позиция. Файл держит свой inode,
+/// поэтому удаленный после открытия файл остается доступным.
+pub struct OpenFile {
+    location: Location,
+    kind: FileType,
+    flags: OpenFlags,
+    offset: Mutex<u64>,
+}
+
+impl OpenFile {
+    /// Открывает `path`. С `CREATE` отсутствующий файл создается с правами
+    /// `mode`, с `EXCLUSIVE` существующий файл считается ошибкой, а с
+    /// `TRUNCATE` файл, открытый на запись, обрезается до нуля.
+    pub fn open(path: &str, flags: OpenFlags, mode: u16) -> Result<Arc<Self>, Errno> {
+        if flags.bits() & OpenFlags::ACCESS_MODE == OpenFlags::ACCESS_MODE {
+            return Err(Errno::EINVAL);
+        }
+
+        let follow = !flags.contains(OpenFlags::NOFOLLOW);
+        let found = if follow {
+            path::resolve(path)
+        } else {
+            path::resolve_nofollow(path)
+        };
+
+        let location = match found {
+            Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => {
+                return Err(Errno::EEXIST);
+            }
+            Ok(location) => location,
+            Err(Errno::ENOENT) if flags.contains(OpenFlags::CREATE) => {
+                let (parent, name) = path::resolve_parent(path)?;
+                check_name(name).map_err(|_| Errno::EISDIR)?;
+                parent.check_writable()?;
+
+                let inode = parent.inode().create(name, FileType::Regular, mode)?;
+                Location::new(parent.mount(), inode)
+            }
+            Err(error) => return Err(error),
+        };
+
+        let kind = location.metadata()?.kind;
+        match kind {
+            FileType::Symlink => return Err(Errno::ELOOP),
+            FileType::Directory if flags.is_writable() => return Err(Errno::EISDIR),
+            FileType::Directory => {}
+            _ if flags.contains(OpenFlags::DIRECTORY) => return Err(Errno::ENOTDIR),
+            _ => {}
+        }
+
+        if flags.is_writable() {
+            location.check_writable()?;
+            if flags.contains(OpenFlags::TRUNCATE) && kind == FileType::Regular {
+                location.inode().truncate(0)?;
+            }
+        }
+
+        Ok(Arc::new(Self {
+            location,
+            kind,
+            flags,
+            offset: Mutex::new(0),
+        }))
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn metadata(&self) -> Result<Metadata, Errno> {
+        self.location.metadata()
+    }
+
+    /// Записи открытого каталога.
+    pub fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        self.location.inode().read_dir()
+    }
+
+    fn offset(&self) -> u64 {
+        *self.offset.lock()
+    }
+
+    fn advance(&self, from: u64, len: usize) {
+        *self.offset.lock() = from + len as u64;
+    }
+}
+
+impl File for OpenFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Errno> {
+        if !self.flags.is_readable() {
+            return Err(Errno::EBADF);
+        }
+        if self.kind == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+
+        // Позиция не блокируется на время чтения: устройство может спать.
+        let offset = self.offset();
+        let read = self.location.inode().read_at(offset, buffer)?;
+        self.advance(offset, read);
+        Ok(read)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, Errno> {
+        if !self.flags.is_writable() {
+            return Err(Errno::EBADF);
+        }
+
+        let offset = if self.flags.contains(OpenFlags::APPEND) {
+            self.metadata()?.size
+        } else {
+            self.offset()
+        };
+        let written = self.location.inode().write_at(offset, buffer)?;
+        self.advance(offset, written);
+        Ok(written)
+    }
+
+    fn seek(&self, from: SeekFrom) -> Result<u64, Errno> {
+        let offset = match from {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.offset().checked_add_signed(delta),
+            SeekFrom::End(delta) => self.metadata()?.size.checked_add_signed(delta),
+        };
+        // Смещение должно помещаться в `off_t` (i64), как в `lseek(2)`.
+        let offset = offset
+            .filter(|&offset| offset <= i64::MAX as u64)
+            .ok_or(Errno::EINVAL)?;
+        *self.offset.lock() = offset;
+        Ok(offset)
+    }
+}
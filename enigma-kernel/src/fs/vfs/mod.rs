@@ -0,0 +1,353 @@
+//! Виртуальная файловая система (VFS).
+//!
+//! Конкретные файловые системы реализуют [`FileSystem`] и [`Inode`] и
+//! монтируются в общее дерево через [`mount`]. VFS разбирает пути (с `.`,
+//! `..` и символическими ссылками, в том числе через точки монтирования),
+//! проверяет права на запись в смонтированные только для чтения системы и
+//! открывает файлы ([`OpenFile`]).
+//!
+//! Относительные пути считаются от текущего каталога процесса
+//! (`Process::cwd`), а для потоков ядра - от корня.
+
+mod file;
+mod mount;
+mod path;
+
+pub use file::{OpenFile, OpenFlags, SeekFrom};
+pub use mount::{MountFlags, MountId, MountInfo, mount, mounts, unmount};
+pub use path::{Location, chdir, getcwd, resolve, resolve_nofollow};
+
+use crate::errno::Errno;
+use crate::time;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+/// Максимальная длина имени файла.
+pub const NAME_MAX: usize = 255;
+/// Максимальная длина пути.
+pub const PATH_MAX: usize = 4096;
+/// Сколько символических ссылок можно пройти при разборе одного пути.
+pub const MAX_SYMLINKS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+/// Момент времени: секунды и наносекунды от эпохи Unix. Часов реального
+/// времени пока нет, поэтому [`Timestamp::now`] отсчитывает от загрузки.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    pub secs: i64,
+    pub nanos: u32,
+}
+
+impl Timestamp {
+    pub const fn new(secs: i64, nanos: u32) -> Self {
+        Self { secs, nanos }
+    }
+
+    pub fn now() -> Self {
+        let uptime = time::Instant::now().duration_since(time::Instant::from_ticks(0));
+        Self::new(uptime.as_secs() as i64, uptime.subsec_nanos())
+    }
+}
+
+/// Атрибуты inode (аналог `struct stat`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub ino: u64,
+    pub kind: FileType,
+    /// Права доступа (младшие 12 бит).
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime: Timestamp,
+    pub mtime: Timestamp,
+    pub ctime: Timestamp,
+}
+
+/// Запись каталога.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+/// Смонтированная файловая система.
+pub trait FileSystem: Send + Sync {
+    /// Короткое имя типа (`tmpfs`, `fat32`).
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Система не поддерживает запись (независимо от флагов монтирования).
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Записывает измененные данные на устройство.
+    fn sync(&self) -> Result<(), Errno> {
+        Ok(())
+    }
+}
+
+/// Файл, каталог или другой объект файловой системы.
+///
+/// Методы каталогов получают уже проверенные имена: не пустые, без `/`,
+/// не длиннее [`NAME_MAX`] и (кроме [`Inode::lookup`]) не `.` и `..`.
+/// Реализации по умолчанию возвращают ошибку, поэтому inode реализует
+/// только подходящие ему операции.
+pub trait Inode: Send + Sync {
+    /// Номер inode, уникальный в пределах файловой системы.
+    fn ino(&self) -> u64;
+
+    fn metadata(&self) -> Result<Metadata, Errno>;
+
+    /// Для [`Inode::link`] и [`Inode::rename`], которым нужен конкретный
+    /// тип второго inode.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Читает данные файла с позиции `offset`. Возвращает 0 в конце файла.
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Пишет данные файла с позиции `offset`, расширяя файл при необходимости.
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Меняет размер файла (новые байты равны нулю).
+    fn truncate(&self, _size: u64) -> Result<(), Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Меняет время доступа и изменения (`None` - не менять).
+    fn set_times(&self, _atime: Option<Timestamp>, _mtime: Option<Timestamp>) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    /// Ищет запись каталога, включая `.` и `..` (у корня `..` - он сам).
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Создает в каталоге файл, каталог или узел устройства.
+    fn create(&self, _name: &str, _kind: FileType, _mode: u16) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Создает символическую ссылку `name` на `target`.
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Создает жесткую ссылку `name` на `target` (той же файловой системы).
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Удаляет запись, не являющуюся каталогом.
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Удаляет пустой каталог.
+    fn rmdir(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Переносит запись `old_name` в каталог `new_parent` (той же файловой
+    /// системы) под именем `new_name`, заменяя существующую запись по
+    /// правилам `rename(2)`.
+    fn rename(
+        &self,
+        _old_name: &str,
+        _new_parent: &Arc<dyn Inode>,
+        _new_name: &str,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Записи каталога без `.` и `..`.
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Цель символической ссылки.
+    fn read_link(&self) -> Result<String, Errno> {
+        Err(Errno::EINVAL)
+    }
+}
+
+/// Проверяет имя последнего компонента пути перед созданием или удалением.
+fn check_name(name: &str) -> Result<(), Errno> {
+    match name {
+        "" => Err(Errno::ENOENT),
+        "." | ".." => Err(Errno::EINVAL),
+        name if name.len() > NAME_MAX => Err(Errno::ENAMETOOLONG),
+        _ => Ok(()),
+    }
+}
+
+/// Создает каталог.
+pub fn mkdir(path: &str, mode: u16) -> Result<(), Errno> {
+    let (parent, name) = path::resolve_parent(path)?;
+    check_name(name).map_err(exists_for_dots)?;
+    parent.check_writable()?;
+    parent.inode().create(name, FileType::Directory, mode)?;
+    Ok(())
+}
+
+/// Удаляет файл или символическую ссылку.
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    let (parent, name) = path::resolve_parent(path)?;
+    check_name(name).map_err(|error| match error {
+        Errno::EINVAL => Errno::EISDIR,
+        error => error,
+    })?;
+    parent.check_writable()?;
+
+    let target = parent.child(name)?;
+    if target.is_mountpoint() {
+        return Err(Errno::EBUSY);
+    }
+    parent.inode().unlink(name)
+}
+
+/// Удаляет пустой каталог.
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    let (parent, name) = path::resolve_parent(path)?;
+    check_name(name).map_err(|error| match (name, error) {
+        ("..", _) => Errno::ENOTEMPTY,
+        (_, error) => error,
+    })?;
+    parent.check_writable()?;
+
+    let target = parent.child(name)?;
+    if target.is_mountpoint() {
+        return Err(Errno::EBUSY);
+    }
+    parent.inode().rmdir(name)
+}
+
+/// Создает символическую ссылку `path` на `target`.
+pub fn symlink(target: &str, path: &str) -> Result<(), Errno> {
+    if target.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if target.len() > PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+
+    let (parent, name) = path::resolve_parent(path)?;
+    check_name(name).map_err(exists_for_dots)?;
+    parent.check_writable()?;
+    parent.inode().symlink(name, target)?;
+    Ok(())
+}
+
+/// Создает жесткую ссылку `new` на файл `old`.
+pub fn link(old: &str, new: &str) -> Result<(), Errno> {
+    let target = resolve_nofollow(old)?;
+    if target.metadata()?.kind == FileType::Directory {
+        return Err(Errno::EPERM);
+    }
+
+    let (parent, name) = path::resolve_parent(new)?;
+    check_name(name).map_err(exists_for_dots)?;
+    if parent.mount() != target.mount() {
+        return Err(Errno::EXDEV);
+    }
+    parent.check_writable()?;
+    parent.inode().link(name, target.inode())
+}
+
+/// Переименовывает (или переносит) `old` в `new`.
+pub fn rename(old: &str, new: &str) -> Result<(), Errno> {
+    let (old_parent, old_name) = path::resolve_parent(old)?;
+    let (new_parent, new_name) = path::resolve_parent(new)?;
+    check_name(old_name).map_err(busy_for_dots)?;
+    check_name(new_name).map_err(busy_for_dots)?;
+
+    if old_parent.mount() != new_parent.mount() {
+        return Err(Errno::EXDEV);
+    }
+    old_parent.check_writable()?;
+
+    if old_parent.child(old_name)?.is_mountpoint() {
+        return Err(Errno::EBUSY);
+    }
+    if let Ok(target) = new_parent.child(new_name)
+        && target.is_mountpoint()
+    {
+        return Err(Errno::EBUSY);
+    }
+
+    old_parent
+        .inode()
+        .rename(old_name, new_parent.inode(), new_name)
+}
+
+/// Возвращает цель символической ссылки.
+pub fn readlink(path: &str) -> Result<String, Errno> {
+    resolve_nofollow(path)?.inode().read_link()
+}
+
+/// Атрибуты файла (символические ссылки разрешаются).
+pub fn stat(path: &str) -> Result<Metadata, Errno> {
+    resolve(path)?.metadata()
+}
+
+/// Атрибуты файла (последняя символическая ссылка не разрешается).
+pub fn lstat(path: &str) -> Result<Metadata, Errno> {
+    resolve_nofollow(path)?.metadata()
+}
+
+/// Записи каталога без `.` и `..`.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, Errno> {
+    resolve(path)?.inode().read_dir()
+}
+
+/// Меняет размер файла.
+pub fn truncate(path: &str, size: u64) -> Result<(), Errno> {
+    let location = resolve(path)?;
+    match location.metadata()?.kind {
+        FileType::Regular => {}
+        FileType::Directory => return Err(Errno::EISDIR),
+        _ => return Err(Errno::EINVAL),
+    }
+
+    location.check_writable()?;
+    location.inode().truncate(size)
+}
+
+/// Открывает файл (см. [`OpenFile::open`]).
+pub fn open(path: &str, flags: OpenFlags, mode: u16) -> Result<Arc<OpenFile>, Errno> {
+    OpenFile::open(path, flags, mode)
+}
+
+fn exists_for_dots(error: Errno) -> Errno {
+    match error {
+        Errno::EINVAL => Errno::EEXIST,
+        error => error,
+    }
+}
+
+/// `.` и `..` переименовывать нельзя: они заняты каталогом.
+fn busy_for_dots(error: Errno) -> Errno {
+    match error {
+        Errno::EINVAL => Errno::EBUSY,
+        error => error,
+    }
+}
@@ -0,0 +1,190 @@
+//! Таблица монтирования.
+
+use super::path::{self, Location};
+use super::{FileSystem, FileType, Inode};
+use crate::errno::Errno;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use spin::RwLock;
+
+static MOUNTS: RwLock<MountTable> = RwLock::new(MountTable {
+    mounts: BTreeMap::new(),
+    root: None,
+    next_id: 1,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MountId(u32);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountFlags {
+    /// Запретить запись, даже если файловая система ее поддерживает.
+    pub read_only: bool,
+}
+
+impl MountFlags {
+    pub const READ_ONLY: Self = Self { read_only: true };
+}
+
+/// Описание точки монтирования для [`mounts`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub id: MountId,
+    pub path: String,
+    pub fs: &'static str,
+    pub flags: MountFlags,
+}
+
+struct Mount {
+    fs: Arc<dyn FileSystem>,
+    root: Arc<dyn Inode>,
+    flags: MountFlags,
+    /// Каталог, на который смонтирована система (`None` у корня).
+    mountpoint: Option<Location>,
+    path: String,
+}
+
+struct MountTable {
+    mounts: BTreeMap<MountId, Mount>,
+    root: Option<MountId>,
+    next_id: u32,
+}
+
+/// Монтирует `fs` в каталог `path`. Первой должна монтироваться система
+/// в `/`, а повторное монтирование в тот же каталог закрывает предыдущее.
+pub fn mount(fs: Arc<dyn FileSystem>, path: &str, flags: MountFlags) -> Result<MountId, Errno> {
+    let root = fs.root();
+    if root.metadata()?.kind != FileType::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+
+    // Путь разбирается до блокировки таблицы: разбор сам читает ее.
+    let mountpoint = if MOUNTS.read().root.is_some() {
+        let location = path::resolve(path)?;
+        if location.metadata()?.kind != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        Some(location)
+    } else if path == "/" {
+        None
+    } else {
+        return Err(Errno::ENOENT);
+    };
+    let path = match &mountpoint {
+        Some(location) => path::path_of(location)?,
+        None => String::from("/"),
+    };
+
+    let mut table = MOUNTS.write();
+    if mountpoint.is_none() && table.root.is_some() {
+        // Корень успели смонтировать, пока разбирался путь.
+        return Err(Errno::EBUSY);
+    }
+
+    let id = MountId(table.next_id);
+    table.next_id += 1;
+    if mountpoint.is_none() {
+        table.root = Some(id);
+    }
+    table.mounts.insert(
+        id,
+        Mount {
+            fs,
+            root,
+            flags,
+            mountpoint,
+            path,
+        },
+    );
+
+    Ok(id)
+}
+
+/// Отмонтирует систему, корень которой - `path`. Открытые файлы остаются
+/// рабочими: они держат свои inode.
+pub fn unmount(path: &str) -> Result<(), Errno> {
+    let location = path::resolve(path)?;
+    let mut table = MOUNTS.write();
+
+    let mount = table.mounts.get(&location.mount()).ok_or(Errno::EINVAL)?;
+    if mount.root.ino() != location.inode().ino() {
+        return Err(Errno::EINVAL);
+    }
+    if mount.mountpoint.is_none() {
+        return Err(Errno::EBUSY);
+    }
+
+    let busy = table.mounts.values().any(|other| {
+        other
+            .mountpoint
+            .as_ref()
+            .is_some_and(|mountpoint| mountpoint.mount() == location.mount())
+    });
+    if busy {
+        return Err(Errno::EBUSY);
+    }
+
+    table.mounts.remove(&location.mount());
+    Ok(())
+}
+
+/// Все смонтированные системы в порядке монтирования.
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS
+        .read()
+        .mounts
+        .iter()
+        .map(|(&id, mount)| MountInfo {
+            id,
+            path: mount.path.clone(),
+            fs: mount.fs.name(),
+            flags: mount.flags,
+        })
+        .collect()
+}
+
+/// Корень дерева.
+pub(super) fn root() -> Result<Location, Errno> {
+    let table = MOUNTS.read();
+    let id = table.root.ok_or(Errno::ENOENT)?;
+    Ok(Location::new(id, table.mounts[&id].root.clone()))
+}
+
+/// Если на `location` смонтирована система, возвращает ее корень (самой
+/// верхней из наложенных друг на друга).
+pub(super) fn covering(location: &Location) -> Option<Location> {
+    let table = MOUNTS.read();
+    let mut result = None;
+
+    loop {
+        let current = result.as_ref().unwrap_or(location);
+        let Some((&id, mount)) = table.mounts.iter().find(|(_, mount)| {
+            mount
+                .mountpoint
+                .as_ref()
+                .is_some_and(|mountpoint| mountpoint.is(current))
+        }) else {
+            return result;
+        };
+        result = Some(Location::new(id, mount.root.clone()));
+    }
+}
+
+/// Если `location` - корень смонтированной системы, возвращает каталог, на
+/// который она смонтирована. Для корня дерева возвращает `None`.
+pub(super) fn mountpoint(location: &Location) -> Option<Location> {
+    let table = MOUNTS.read();
+    let mount = table.mounts.get(&location.mount())?;
+    if mount.root.ino() != location.inode().ino() {
+        return None;
+    }
+    mount.mountpoint.clone()
+}
+
+/// Можно ли писать в систему `id`.
+pub(super) fn is_writable(id: MountId) -> bool {
+    MOUNTS
+        .read()
+        .mounts
+        .get(&id)
+        .is_some_and(|mount| !mount.flags.read_only && !mount.fs.is_read_only())
+}
@@ -0,0 +1,246 @@
+//! Разбор путей.
+
+use super::{FileType, Inode, MAX_SYMLINKS, Metadata, NAME_MAX, PATH_MAX, mount};
+use crate::errno::Errno;
+use crate::process;
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+/// Inode вместе с системой, в которой он найден. Номера inode уникальны
+/// только внутри одной системы, поэтому VFS всегда работает с парой.
+#[derive(Clone)]
+pub struct Location {
+    mount: mount::MountId,
+    inode: Arc<dyn Inode>,
+}
+
+impl Location {
+    pub(super) fn new(mount: mount::MountId, inode: Arc<dyn Inode>) -> Self {
+        Self { mount, inode }
+    }
+
+    pub fn mount(&self) -> mount::MountId {
+        self.mount
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn metadata(&self) -> Result<Metadata, Errno> {
+        self.inode.metadata()
+    }
+
+    /// Тот же inode той же системы.
+    pub fn is(&self, other: &Location) -> bool {
+        self.mount == other.mount && self.inode.ino() == other.inode.ino()
+    }
+
+    /// Запись `name` каталога (с переходом в смонтированную на неё систему,
+    /// но без разрешения символических ссылок).
+    pub(super) fn child(&self, name: &str) -> Result<Location, Errno> {
+        let child = Location::new(self.mount, self.inode.lookup(name)?);
+        Ok(mount::covering(&child).unwrap_or(child))
+    }
+
+    /// Родительский каталог (`..`). У корня дерева родитель - он сам.
+    pub(super) fn parent(&self) -> Result<Location, Errno> {
+        let mut location = self.clone();
+        // `..` корня смонтированной системы - это `..` каталога, на который
+        // она смонтирована.
+        while let Some(mountpoint) = mount::mountpoint(&location) {
+            location = mountpoint;
+        }
+
+        let parent = Location::new(location.mount, location.inode.lookup("..")?);
+        Ok(mount::covering(&parent).unwrap_or(parent))
+    }
+
+    /// Корень смонтированной системы (кроме корня дерева).
+    pub(super) fn is_mountpoint(&self) -> bool {
+        mount::mountpoint(self).is_some()
+    }
+
+    pub(super) fn check_writable(&self) -> Result<(), Errno> {
+        if mount::is_writable(self.mount) {
+            Ok(())
+        } else {
+            Err(Errno::EROFS)
+        }
+    }
+
+    fn is_directory(&self) -> Result<bool, Errno> {
+        Ok(self.metadata()?.kind == FileType::Directory)
+    }
+}
+
+/// Находит файл по пути, разрешая все символические ссылки.
+pub fn resolve(path: &str) -> Result<Location, Errno> {
+    walk(start()?, path, true)
+}
+
+/// Находит файл по пути, не разрешая символическую ссылку в последнем
+/// компоненте.
+pub fn resolve_nofollow(path: &str) -> Result<Location, Errno> {
+    walk(start()?, path, false)
+}
+
+/// Разбирает путь до последнего компонента: возвращает каталог и имя в нем.
+/// Для `/` имя равно `.`.
+pub(super) fn resolve_parent(path: &str) -> Result<(Location, &str), Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+
+    let trimmed = path.trim_end_matches('/');
+    let (directory, name) = match trimmed.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((directory, name)) => (directory, name),
+        // Путь состоял только из `/`.
+        None if trimmed.is_empty() => ("/", "."),
+        None => (".", trimmed),
+    };
+
+    let parent = walk(start()?, directory, true)?;
+    if !parent.is_directory()? {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok((parent, name))
+}
+
+/// Меняет текущий каталог текущего процесса.
+pub fn chdir(path: &str) -> Result<(), Errno> {
+    let location = resolve(path)?;
+    if !location.is_directory()? {
+        return Err(Errno::ENOTDIR);
+    }
+
+    let process = process::current().ok_or(Errno::ESRCH)?;
+    process.set_cwd(location);
+    Ok(())
+}
+
+/// Абсолютный путь текущего каталога (для потоков ядра - `/`).
+pub fn getcwd() -> Result<String, Errno> {
+    match process::current().and_then(|process| process.cwd()) {
+        Some(cwd) => path_of(&cwd),
+        None => Ok(String::from("/")),
+    }
+}
+
+/// Восстанавливает абсолютный путь каталога, поднимаясь по `..` и находя
+/// свое имя в каждом родителе. Для удаленного каталога возвращает `ENOENT`.
+pub(super) fn path_of(location: &Location) -> Result<String, Errno> {
+    let root = mount::root()?;
+    let mut names = Vec::new();
+    let mut current = location.clone();
+
+    loop {
+        while let Some(mountpoint) = mount::mountpoint(&current) {
+            current = mountpoint;
+        }
+        if current.is(&root) {
+            break;
+        }
+
+        let parent = Location::new(current.mount, current.inode.lookup("..")?);
+        let ino = current.inode.ino();
+        let entry = parent
+            .inode
+            .read_dir()?
+            .into_iter()
+            .find(|entry| entry.ino == ino)
+            .ok_or(Errno::ENOENT)?;
+
+        names.push(entry.name);
+        current = parent;
+    }
+
+    let mut path = String::new();
+    for name in names.iter().rev() {
+        path.push('/');
+        path.push_str(name);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+/// Каталог, от которого считаются относительные пути.
+fn start() -> Result<Location, Errno> {
+    match process::current().and_then(|process| process.cwd()) {
+        Some(cwd) => Ok(cwd),
+        None => mount::root(),
+    }
+}
+
+/// Проходит `path` от `current`. Символические ссылки раскрываются на месте:
+/// компоненты цели ставятся перед оставшимися компонентами пути, поэтому
+/// глубина вложенных ссылок не расходует стек ядра.
+fn walk(mut current: Location, path: &str, follow_last: bool) -> Result<Location, Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.len() > PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    if path.starts_with('/') {
+        current = mount::root()?;
+    }
+
+    // Путь с `/` на конце должен вести к каталогу.
+    let must_be_directory = path.ends_with('/');
+    let mut pending: Vec<String> = components(path);
+    let mut links = 0;
+
+    while let Some(name) = pending.pop() {
+        if !current.is_directory()? {
+            return Err(Errno::ENOTDIR);
+        }
+
+        match name.as_str() {
+            "." => continue,
+            ".." => {
+                current = current.parent()?;
+                continue;
+            }
+            name if name.len() > NAME_MAX => return Err(Errno::ENAMETOOLONG),
+            _ => {}
+        }
+
+        let next = current.child(&name)?;
+        let last = pending.is_empty();
+        if next.metadata()?.kind == FileType::Symlink && (!last || follow_last || must_be_directory)
+        {
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(Errno::ELOOP);
+            }
+
+            let target = next.inode.read_link()?;
+            if target.is_empty() {
+                return Err(Errno::ENOENT);
+            }
+            if target.starts_with('/') {
+                current = mount::root()?;
+            }
+            pending.extend(components(&target));
+            continue;
+        }
+
+        current = next;
+    }
+
+    if must_be_directory && !current.is_directory()? {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok(current)
+}
+
+/// Непустые компоненты пути в обратном порядке (последний - первый).
+fn components(path: &str) -> Vec<String> {
+    path.rsplit('/')
+        .filter(|component| !component.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -1,3 +1,4 @@
+use core::cell::UnsafeCell;
 use core::ptr::addr_of;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
@@ -5,9 +6,37 @@ use x86_64::{VirtAddr, structures::tss::TaskStateSegment};
 
 pub const DOUBLE_FAULT_IST_INDEXT: u16 = 0;
 
+/// TSS, который можно менять после загрузки (поле `privilege_stack_table`
+/// обновляется при каждом переключении потока).
+struct TssCell(UnsafeCell<TaskStateSegment>);
+
+// Планировщик рассчитан на одно ядро, а TSS меняется только с выключенными
+// прерываниями.
+unsafe impl Sync for TssCell {}
+
+/// Данные процессора, к которым точка входа `syscall` обращается через `gs`
+/// после `swapgs` (адрес структуры лежит в `KernelGsBase`).
+#[repr(C)]
+pub struct CpuLocal {
+    /// Вершина стека ядра текущего потока (совпадает с RSP0 в TSS).
+    pub kernel_stack: u64,
+    /// Стек пользователя, сохраненный на время переключения на стек ядра.
+    pub user_stack: u64,
+}
+
+struct CpuLocalCell(UnsafeCell<CpuLocal>);
+
+// Как и TSS: одно ядро, изменения только с выключенными прерываниями.
+unsafe impl Sync for CpuLocalCell {}
+
+static CPU_LOCAL: CpuLocalCell = CpuLocalCell(UnsafeCell::new(CpuLocal {
+    kernel_stack: 0,
+    user_stack: 0,
+}));
+
 lazy_static! {
     // Создаем TaskStateSegment.
-    static ref TSS: TaskStateSegment = {
+    static ref TSS: TssCell = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEXT as usize] = {
             const STACK_SIZE: usize = 4096 * 5;
@@ -17,31 +46,77 @@ lazy_static! {
             stack_start + STACK_SIZE as u64 // Return stack end.
         };
 
-        tss
+        TssCell(UnsafeCell::new(tss))
     };
 
     // Создаем GlobalDescriptorTable.
+    // Порядок сегментов пользователя (данные, затем код) требуется инструкцией `sysret`.
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
 
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
         let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+        let user_data_selector = gdt.append(Descriptor::user_data_segment());
+        let user_code_selector = gdt.append(Descriptor::user_code_segment());
+        let tss_selector = gdt.append(Descriptor::tss_segment(unsafe { &*TSS.0.get() }));
 
-        (gdt, Selectors { code_selector, data_selector, tss_selector })
+        (gdt, Selectors {
+            code_selector,
+            data_selector,
+            user_code_selector,
+            user_data_selector,
+            tss_selector,
+        })
     };
 }
 
 struct Selectors {
     code_selector: SegmentSelector,
     data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
 
+pub fn kernel_code_selector() -> SegmentSelector {
+    GDT.1.code_selector
+}
+
+pub fn kernel_data_selector() -> SegmentSelector {
+    GDT.1.data_selector
+}
+
+/// Селектор сегмента кода кольца 3 (с RPL = 3).
+pub fn user_code_selector() -> SegmentSelector {
+    GDT.1.user_code_selector
+}
+
+/// Селектор сегмента данных кольца 3 (с RPL = 3).
+pub fn user_data_selector() -> SegmentSelector {
+    GDT.1.user_data_selector
+}
+
+/// Задает стек ядра, на который процессор переключается при прерывании
+/// или исключении в кольце 3 (`privilege_stack_table[0]`, он же RSP0), и
+/// стек для системных вызовов через `syscall`.
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe {
+        let tss = TSS.0.get();
+        (*tss).privilege_stack_table[0] = top;
+        (*CPU_LOCAL.0.get()).kernel_stack = top.as_u64();
+    }
+}
+
+/// Возвращает текущее значение RSP0.
+pub fn kernel_stack() -> VirtAddr {
+    unsafe { (*TSS.0.get()).privilege_stack_table[0] }
+}
+
 /// Инициализирует idt.
 pub fn init() {
     use x86_64::instructions::segmentation::{CS, DS, ES, FS, GS, SS, Segment};
     use x86_64::instructions::tables::load_tss;
+    use x86_64::registers::model_specific::KernelGsBase;
 
     GDT.0.load();
     unsafe {
@@ -54,4 +129,8 @@ pub fn init() {
 
         load_tss(GDT.1.tss_selector);
     }
+
+    // В ядре `gs` указывает на пользовательскую базу, а `swapgs` на входе
+    // в системный вызов ненадолго подменяет её на `CPU_LOCAL`.
+    KernelGsBase::write(VirtAddr::from_ptr(CPU_LOCAL.0.get()));
 }
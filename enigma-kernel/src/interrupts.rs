@@ -1,7 +1,13 @@
 //! Данный модуль содержит логику для работы с прерываниями.
 
-use crate::{drivers::apic, gdt, hlt_loop, print, println};
+use crate::errno::Errno;
+use crate::{drivers::apic, gdt, hlt_loop, println, serial_println};
+use alloc::sync::Arc;
+use core::ops::RangeInclusive;
 use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::PrivilegeLevel;
+use x86_64::instructions::interrupts::without_interrupts;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
 lazy_static! {
@@ -9,11 +15,35 @@ lazy_static! {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.device_not_available
+            .set_handler_fn(device_not_available_handler);
 
         idt[InterruptIndex::Timer.as_usize() as u8].set_handler_fn(timer_interrupt_handler);
 
         idt[InterruptIndex::Keyboard.as_usize() as u8].set_handler_fn(keyboard_interrupt_handler);
 
+        // Векторы для драйверов: 0x30-0xEF (см. `allocate_vectors`).
+        macro_rules! dynamic_vectors {
+            ($($high:literal)*) => {
+                $(dynamic_vectors!(@row $high; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);)*
+            };
+            (@row $high:literal; $($low:literal)*) => {
+                $(idt[$high * 16 + $low]
+                    .set_handler_fn(dynamic_interrupt_handler::<{ $high * 16 + $low }>);)*
+            };
+        }
+        dynamic_vectors!(3 4 5 6 7 8 9 10 11 12 13 14);
+
+        // Шлюз доступен из кольца 3, иначе `int 0x80` вызовет #GP.
+        unsafe {
+            idt[crate::syscall::SYSCALL_VECTOR]
+                .set_handler_addr(crate::syscall::int80_handler())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
+
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_faulth_handler)
@@ -32,6 +62,10 @@ extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn device_not_available_handler(_stack_frame: InterruptStackFrame) {
+    crate::fpu::handle_device_not_available();
+}
+
 extern "x86-interrupt" fn double_faulth_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
@@ -45,6 +79,14 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
+    if from_user_mode(&stack_frame) {
+        kill_user_process(
+            SIGSEGV,
+            format_args!("page fault at {:?} ({:?})", Cr2::read(), error_code),
+            &stack_frame,
+        );
+    }
+
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
@@ -53,6 +95,60 @@ extern "x86-interrupt" fn page_fault_handler(
     hlt_loop();
 }
 
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    if from_user_mode(&stack_frame) {
+        kill_user_process(
+            SIGSEGV,
+            format_args!("general protection fault ({error_code:#x})"),
+            &stack_frame,
+        );
+    }
+
+    panic!(
+        "EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    if from_user_mode(&stack_frame) {
+        kill_user_process(SIGILL, format_args!("invalid opcode"), &stack_frame);
+    }
+
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+/// Номера сигналов, которыми завершаются процессы при исключениях.
+const SIGILL: i32 = 4;
+const SIGSEGV: i32 = 11;
+
+/// Произошло ли исключение в кольце 3.
+fn from_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+}
+
+/// Завершает текущий процесс после исключения в кольце 3. Код возврата,
+/// как в shell, равен `128 + номер сигнала`. Процессор уже находится на
+/// стеке ядра потока (из TSS), и сюда поток больше не вернется.
+fn kill_user_process(
+    signal: i32,
+    reason: core::fmt::Arguments,
+    stack_frame: &InterruptStackFrame,
+) -> ! {
+    let pid = crate::process::current().map(|process| process.pid().as_u32());
+    serial_println!(
+        "process {:?} killed: {} at {:?}",
+        pid,
+        reason,
+        stack_frame.instruction_pointer
+    );
+
+    crate::process::exit(128 + signal);
+}
+
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
@@ -74,10 +170,20 @@ impl InterruptIndex {
 
 // Обработчики прерываний для InterruptIndex.
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
-
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    // EOI отправляется до планировщика, так как он может переключиться
+    // на другой поток и вернуться сюда не скоро.
     apic::end_interrupt();
+    crate::time::tick();
+    crate::thread::tick();
+
+    // Поток, прерванный в кольце 3, не держит блокировок ядра, поэтому
+    // здесь он может завершиться, если процесс завершается. Память процесса
+    // освобождается уже с включенными прерываниями.
+    if from_user_mode(&stack_frame) && crate::thread::exit_requested() {
+        x86_64::instructions::interrupts::enable();
+        crate::process::exit_point();
+    }
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -90,6 +196,79 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     apic::end_interrupt();
 }
 
+/// Векторы, которые раздаются драйверам (MSI, MSI-X).
+pub const DYNAMIC_VECTORS: RangeInclusive<u8> = 0x30..=0xEF;
+
+/// Обработчик выделенного вектора. Вызывается в контексте прерывания с
+/// номером вектора; EOI отправляется после него.
+pub type VectorHandler = Arc<dyn Fn(u8) + Send + Sync>;
+
+static VECTORS: Mutex<[Option<VectorHandler>; 256]> = Mutex::new([const { None }; 256]);
+
+/// Выделяет вектор для `handler`.
+pub fn allocate_vector(handler: VectorHandler) -> Result<u8, Errno> {
+    allocate_vectors(1, handler)
+}
+
+/// Выделяет `count` (степень двойки, не больше 32) векторов подряд,
+/// выровненных на `count`, как требует MSI с несколькими сообщениями.
+/// Возвращает первый вектор или `ENOSPC`, если такого блока нет.
+pub fn allocate_vectors(count: u8, handler: VectorHandler) -> Result<u8, Errno> {
+    if !count.is_power_of_two() || count > 32 {
+        return Err(Errno::EINVAL);
+    }
+
+    without_interrupts(|| {
+        let mut vectors = VECTORS.lock();
+        let first = (*DYNAMIC_VECTORS.start()..=*DYNAMIC_VECTORS.end() + 1 - count)
+            .filter(|first| first % count == 0)
+            .find(|&first| {
+                (first..first + count).all(|vector| {
+                    vector != crate::syscall::SYSCALL_VECTOR && vectors[vector as usize].is_none()
+                })
+            })
+            .ok_or(Errno::ENOSPC)?;
+        for vector in first..first + count {
+            vectors[vector as usize] = Some(handler.clone());
+        }
+        Ok(first)
+    })
+}
+
+/// Освобождает `count` векторов начиная с `first`.
+pub fn free_vectors(first: u8, count: u8) {
+    without_interrupts(|| {
+        let mut vectors = VECTORS.lock();
+        for vector in first..first + count {
+            debug_assert!(DYNAMIC_VECTORS.contains(&vector), "static vector {vector}");
+            vectors[vector as usize] = None;
+        }
+    });
+}
+
+/// Сколько векторов сейчас свободно.
+pub fn free_vector_count() -> usize {
+    without_interrupts(|| {
+        let vectors = VECTORS.lock();
+        DYNAMIC_VECTORS
+            .filter(|&vector| {
+                vector != crate::syscall::SYSCALL_VECTOR && vectors[vector as usize].is_none()
+            })
+            .count()
+    })
+}
+
+extern "x86-interrupt" fn dynamic_interrupt_handler<const VECTOR: u8>(
+    _stack_frame: InterruptStackFrame,
+) {
+    // Обработчик вызывается без блокировки: он может освобождать векторы.
+    let handler = VECTORS.lock()[VECTOR as usize].clone();
+    if let Some(handler) = handler {
+        handler(VECTOR);
+    }
+    apic::end_interrupt();
+}
+
 // --- TEST ZONE --- //
 
 #[test_case]
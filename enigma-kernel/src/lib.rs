@@ -16,15 +16,28 @@
 extern crate alloc;
 
 pub mod allocator;
+pub mod deferred;
 pub mod drivers;
+pub mod errno;
+pub mod fpu;
 pub mod framebuffer;
+pub mod fs;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod process;
+pub mod sync;
+pub mod syscall;
 pub mod task;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod thread;
+pub mod time;
 
 pub fn init() {
     gdt::init();
+    fpu::init();
+    syscall::init();
     // x86_64::instructions::interrupts::enable();
 }
 
@@ -1,12 +1,35 @@
 //! В данном модуле находится реализация работы с памятью, а именно работа
 //! со страницами и их инициализация (Paging).
 
+use alloc::vec::Vec;
 use bootloader_api::info::{MemoryRegionKind::Usable, MemoryRegions};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
 use x86_64::{
     PhysAddr, VirtAddr,
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    instructions::interrupts::without_interrupts,
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB, mapper::MapToError,
+    },
 };
 
+/// Начало области ядра в нижней половине адресного пространства (куча,
+/// MMIO). Всё, что ниже, принадлежит пользовательским процессам.
+pub const KERNEL_SPACE_START: u64 = 0x0000_4000_0000_0000;
+/// Начало окна для отображения регистров устройств (MMIO).
+pub const MMIO_START: u64 = 0x0000_4800_0000_0000;
+/// Размер окна MMIO (одна запись таблицы 4 уровня).
+pub const MMIO_SIZE: u64 = 0x0000_0080_0000_0000;
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Таблица 4 уровня, созданная загрузчиком (таблица страниц ядра).
+static KERNEL_PAGE_TABLE: AtomicU64 = AtomicU64::new(0);
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+/// Следующий свободный адрес в окне MMIO.
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
 /// Инициализирует новую таблицу OffsetPageTable.
 ///
 /// ## Safety
@@ -18,17 +41,114 @@ use x86_64::{
 /// избежать наложения псевдонимов на ссылки "&mut"
 /// (что является неопределенным поведением).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    KERNEL_PAGE_TABLE.store(Cr3::read().0.start_address().as_u64(), Ordering::Relaxed);
+
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
     }
 }
 
+/// Делает `frame_allocator` общим распределителем кадров ядра, после чего
+/// кадры выделяются через [`GlobalFrameAllocator`].
+pub fn init_frame_allocator(frame_allocator: BootInfoFrameAllocator) {
+    without_interrupts(|| *FRAME_ALLOCATOR.lock() = Some(frame_allocator));
+}
+
+/// Возвращает смещение, по которому загрузчик отобразил всю физическую память.
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Переводит физический адрес в виртуальный через отображение всей физической памяти.
+pub fn phys_to_virt(address: PhysAddr) -> VirtAddr {
+    physical_memory_offset() + address.as_u64()
+}
+
+/// Возвращает кадр таблицы 4 уровня ядра.
+pub fn kernel_page_table() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_PAGE_TABLE.load(Ordering::Relaxed)))
+}
+
+/// Возвращает отображение таблицы страниц ядра.
+///
+/// ## Safety
+///
+/// Вызывающий не должен одновременно держать другую изменяемую ссылку на
+/// таблицу страниц ядра (в том числе полученную из [`init`]).
+pub unsafe fn kernel_mapper() -> OffsetPageTable<'static> {
+    let offset = physical_memory_offset();
+    let table = phys_to_virt(kernel_page_table().start_address()).as_mut_ptr::<PageTable>();
+
+    unsafe { OffsetPageTable::new(&mut *table, offset) }
+}
+
+/// Отображает `size` байт регистров устройства с физического адреса
+/// `address` в окно MMIO без кэширования и возвращает виртуальный адрес.
+pub fn map_mmio(
+    address: PhysAddr,
+    size: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(address);
+    let last = PhysFrame::<Size4KiB>::containing_address(address + size.max(1) - 1u64);
+    let pages = (last.start_address() - first.start_address()) / 4096 + 1;
+
+    let start = NEXT_MMIO.fetch_add(pages * 4096, Ordering::Relaxed);
+    if start + pages * 4096 > MMIO_START + MMIO_SIZE {
+        return Err(MapToError::FrameAllocationFailed);
+    }
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    for (index, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+        let page = Page::containing_address(VirtAddr::new(start + index as u64 * 4096));
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    Ok(VirtAddr::new(start) + (address - first.start_address()))
+}
+
+/// Распределитель кадров, работающий через общий распределитель ядра.
+/// Освобожденные кадры используются повторно.
+pub struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        without_interrupts(|| FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame())
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for GlobalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        without_interrupts(|| {
+            if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+                unsafe { allocator.deallocate_frame(frame) };
+            }
+        });
+    }
+}
+
+/// Выделяет кадр и заполняет его нулями.
+pub fn allocate_zeroed_frame() -> Option<PhysFrame> {
+    let frame = GlobalFrameAllocator.allocate_frame()?;
+    unsafe {
+        phys_to_virt(frame.start_address())
+            .as_mut_ptr::<u8>()
+            .write_bytes(0, 4096);
+    }
+
+    Some(frame)
+}
+
 /// FrameworAllocator, который возвращает используемые
 /// кадры из карты памяти загрузчика.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryRegions,
     next: usize,
+    /// Освобожденные кадры.
+    free: Vec<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
@@ -44,6 +164,7 @@ impl BootInfoFrameAllocator {
         Self {
             memory_map,
             next: 0,
+            free: Vec::new(),
         }
     }
 
@@ -61,12 +182,22 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free.pop() {
+            return Some(frame);
+        }
+
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
 }
 
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free.push(frame);
+    }
+}
+
 /// Возвращает изменяемую ссылку на таблицу активного уровня 4.
 ///
 /// ## Safety
@@ -77,7 +208,6 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 /// один раз, чтобы избежать наложения псевдонимов на ссылки "&mut"
 /// (что является неопределенным поведением).
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
     let (level_4_table_frame, _) = Cr3::read();
 
     let phys = level_4_table_frame.start_address();
@@ -0,0 +1,261 @@
+//! Адресное пространство процесса.
+//!
+//! Нижняя часть адресов (до [`USER_END`]) принадлежит процессу и у каждого
+//! своя. Записи таблицы 4 уровня выше неё копируются из таблицы ядра при
+//! создании, поэтому ядро видно во всех адресных пространствах. Из-за этого
+//! новые отображения ядра должны попадать в уже существующие записи 4 уровня
+//! (куча и окно MMIO создаются при загрузке).
+
+use crate::errno::Errno;
+use crate::memory::{self, GlobalFrameAllocator};
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{
+        FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, Translate,
+        mapper::{MapToError, TranslateResult},
+    },
+};
+
+/// Первый адрес, доступный процессу (нулевая страница не отображается).
+pub const USER_START: u64 = 0x1000;
+/// Конец пользовательской части адресного пространства.
+pub const USER_END: u64 = memory::KERNEL_SPACE_START;
+/// Количество записей таблицы 4 уровня, принадлежащих процессу.
+const USER_ENTRIES: usize = (USER_END >> 39) as usize;
+
+pub struct AddressSpace {
+    page_table: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Создает пустое адресное пространство с отображением ядра.
+    pub fn new() -> Result<Self, Errno> {
+        let page_table = memory::allocate_zeroed_frame().ok_or(Errno::ENOMEM)?;
+
+        let table = unsafe { table_mut(page_table) };
+        let kernel = unsafe { table_mut(memory::kernel_page_table()) };
+        for index in USER_ENTRIES..512 {
+            table[index] = kernel[index].clone();
+        }
+
+        Ok(Self { page_table })
+    }
+
+    /// Возвращает кадр таблицы 4 уровня (значение для CR3).
+    pub fn page_table(&self) -> PhysFrame {
+        self.page_table
+    }
+
+    /// Проверяет, что `[start, start + len)` лежит в пользовательской части.
+    pub fn is_user_range(start: VirtAddr, len: u64) -> bool {
+        let start = start.as_u64();
+        start >= USER_START && start.checked_add(len).is_some_and(|end| end <= USER_END)
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let table = unsafe { table_mut(self.page_table) };
+        unsafe { OffsetPageTable::new(table, memory::physical_memory_offset()) }
+    }
+
+    /// Отображает обнуленную память на `[start, start + len)` с флагами `flags`
+    /// (`PRESENT` и `USER_ACCESSIBLE` добавляются сами). Уже отображенные
+    /// страницы сохраняются, а их флаги объединяются с `flags`.
+    pub fn map(&mut self, start: VirtAddr, len: u64, flags: PageTableFlags) -> Result<(), Errno> {
+        if len == 0 {
+            return Ok(());
+        }
+        if !Self::is_user_range(start, len) {
+            return Err(Errno::EFAULT);
+        }
+
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let parent_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+        let first = Page::<Size4KiB>::containing_address(start);
+        let last = Page::<Size4KiB>::containing_address(start + (len - 1));
+        let mut mapper = self.mapper();
+
+        for page in Page::range_inclusive(first, last) {
+            if let TranslateResult::Mapped { flags: old, .. } =
+                mapper.translate(page.start_address())
+            {
+                // Страница общая для двух сегментов: права объединяются, а
+                // NO_EXECUTE остается, только если его просят оба.
+                let mut merged = old | flags;
+                if !(old & flags).contains(PageTableFlags::NO_EXECUTE) {
+                    merged.remove(PageTableFlags::NO_EXECUTE);
+                }
+                unsafe {
+                    mapper
+                        .update_flags(page, merged)
+                        .map_err(|_| Errno::EFAULT)?
+                        .flush()
+                };
+                continue;
+            }
+
+            let frame = memory::allocate_zeroed_frame().ok_or(Errno::ENOMEM)?;
+            let result = unsafe {
+                mapper.map_to_with_table_flags(
+                    page,
+                    frame,
+                    flags,
+                    parent_flags,
+                    &mut GlobalFrameAllocator,
+                )
+            };
+
+            match result {
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+                    return Err(match error {
+                        MapToError::FrameAllocationFailed => Errno::ENOMEM,
+                        _ => Errno::EFAULT,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Снимает отображение `[start, start + len)` и освобождает память.
+    pub fn unmap(&mut self, start: VirtAddr, len: u64) -> Result<(), Errno> {
+        if len == 0 {
+            return Ok(());
+        }
+        if !Self::is_user_range(start, len) {
+            return Err(Errno::EFAULT);
+        }
+
+        let first = Page::<Size4KiB>::containing_address(start);
+        let last = Page::<Size4KiB>::containing_address(start + (len - 1));
+        let mut mapper = self.mapper();
+
+        for page in Page::range_inclusive(first, last) {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Возвращает физический адрес и флаги страницы по адресу `address`.
+    pub fn translate(&mut self, address: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+        if !Self::is_user_range(address, 1) {
+            return None;
+        }
+
+        match self.mapper().translate(address) {
+            TranslateResult::Mapped {
+                frame,
+                offset,
+                flags,
+            } => Some((frame.start_address() + offset, flags)),
+            _ => None,
+        }
+    }
+
+    /// Копирует `data` в память процесса по адресу `address` (независимо от
+    /// прав страниц, но только в уже отображенные страницы).
+    pub fn write(&mut self, address: VirtAddr, data: &[u8]) -> Result<(), Errno> {
+        self.for_each_chunk(address, data.len(), |virt, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(data[offset..].as_ptr(), virt, len);
+        })
+    }
+
+    /// Копирует память процесса по адресу `address` в `buffer`.
+    pub fn read(&mut self, address: VirtAddr, buffer: &mut [u8]) -> Result<(), Errno> {
+        let target = buffer.as_mut_ptr();
+        self.for_each_chunk(address, buffer.len(), |virt, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(virt, target.add(offset), len);
+        })
+    }
+
+    /// Обнуляет `len` байт памяти процесса, начиная с `address`.
+    pub fn zero(&mut self, address: VirtAddr, len: usize) -> Result<(), Errno> {
+        self.for_each_chunk(address, len, |virt, _, len| unsafe {
+            virt.write_bytes(0, len);
+        })
+    }
+
+    /// Вызывает `f(указатель ядра, смещение, длина)` для каждого куска
+    /// `[address, address + len)`, не пересекающего границу страницы.
+    fn for_each_chunk(
+        &mut self,
+        address: VirtAddr,
+        len: usize,
+        mut f: impl FnMut(*mut u8, usize, usize),
+    ) -> Result<(), Errno> {
+        if !Self::is_user_range(address, len as u64) {
+            return Err(Errno::EFAULT);
+        }
+
+        // Сначала проверяем весь диапазон, чтобы не выполнить запись частично.
+        let mut offset = 0;
+        while offset < len {
+            let current = address + offset as u64;
+            self.translate(current).ok_or(Errno::EFAULT)?;
+            offset += (4096 - usize::from(current.page_offset())).min(len - offset);
+        }
+
+        let mut offset = 0;
+        while offset < len {
+            let current = address + offset as u64;
+            let (physical, _) = self.translate(current).ok_or(Errno::EFAULT)?;
+            let chunk = (4096 - usize::from(current.page_offset())).min(len - offset);
+
+            f(memory::phys_to_virt(physical).as_mut_ptr(), offset, chunk);
+            offset += chunk;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Освобождает всю память процесса и таблицы страниц. Адресное
+    /// пространство не должно быть активным ни на одном процессоре.
+    fn drop(&mut self) {
+        let table = unsafe { table_mut(self.page_table) };
+        for entry in table.iter_mut().take(USER_ENTRIES) {
+            if !entry.is_unused() {
+                unsafe { free_table(entry.frame().unwrap(), 3) };
+                entry.set_unused();
+            }
+        }
+
+        unsafe { GlobalFrameAllocator.deallocate_frame(self.page_table) };
+    }
+}
+
+/// Рекурсивно освобождает таблицу уровня `level` вместе с отображенными кадрами.
+unsafe fn free_table(frame: PhysFrame, level: u8) {
+    let table = unsafe { table_mut(frame) };
+
+    for entry in table.iter() {
+        if entry.is_unused() {
+            continue;
+        }
+
+        let huge = entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        match entry.frame() {
+            Ok(child) if level > 1 && !huge => unsafe { free_table(child, level - 1) },
+            Ok(child) if level == 1 => unsafe { GlobalFrameAllocator.deallocate_frame(child) },
+            // Большие страницы процессам не выделяются.
+            _ => {}
+        }
+    }
+
+    unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+}
+
+unsafe fn table_mut(frame: PhysFrame) -> &'static mut PageTable {
+    let virt = memory::phys_to_virt(frame.start_address());
+    unsafe { &mut *virt.as_mut_ptr::<PageTable>() }
+}
@@ -0,0 +1,374 @@
+//! Загрузчик статических исполняемых файлов ELF64 (x86_64).
+//!
+//! Поддерживаются `ET_EXEC` и статические PIE (`ET_DYN` без `PT_INTERP`),
+//! которые загружаются по адресу [`PIE_BASE`]. Сегменты `PT_LOAD` копируются
+//! в адресное пространство процесса с правами из `p_flags`, а стек
+//! заполняется по System V ABI: `argc`, `argv`, `envp` и вспомогательный
+//! вектор (auxv).
+//!
+//! Файл полностью проверяется перед использованием: любая ошибка в
+//! заголовках возвращается как `ENOEXEC`, а не приводит к панике.
+
+use super::address_space::{AddressSpace, USER_END};
+use crate::errno::Errno;
+use crate::time;
+use alloc::vec::Vec;
+use x86_64::{VirtAddr, structures::paging::PageTableFlags};
+
+/// Адрес загрузки статических PIE.
+pub const PIE_BASE: u64 = 0x5555_5555_4000;
+/// Вершина стека процесса (последняя страница перед ядром не используется).
+pub const STACK_TOP: u64 = USER_END - 0x1000;
+/// Размер стека процесса.
+pub const STACK_SIZE: u64 = 128 * 1024;
+/// Максимальный суммарный размер строк `argv` и `envp`.
+pub const ARG_MAX: usize = 32 * 1024;
+
+const PAGE_SIZE: u64 = 4096;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7FELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_X86_64: u16 = 62;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+/// Ограничение на количество заголовков программы (как в Linux: таблица
+/// должна помещаться в 64 КиБ).
+const MAX_PHNUM: usize = 65536 / PHDR_SIZE;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_FLAGS: u64 = 8;
+const AT_ENTRY: u64 = 9;
+const AT_UID: u64 = 11;
+const AT_EUID: u64 = 12;
+const AT_GID: u64 = 13;
+const AT_EGID: u64 = 14;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+
+/// Результат загрузки: с этими значениями процесс можно запускать через
+/// [`super::Process::spawn_user_thread`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadedImage {
+    pub entry: VirtAddr,
+    /// Указатель стека, указывающий на `argc`.
+    pub stack_pointer: VirtAddr,
+}
+
+/// Заголовок программы (только используемые поля).
+#[derive(Debug, Clone, Copy)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+}
+
+/// Разобранный и проверенный файл.
+struct Elf<'a> {
+    data: &'a [u8],
+    base: u64,
+    entry: u64,
+    phoff: u64,
+    headers: Vec<ProgramHeader>,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, Errno> {
+        if data.len() < EHDR_SIZE
+            || data[..4] != ELF_MAGIC
+            || data[4] != ELFCLASS64
+            || data[5] != ELFDATA2LSB
+            || data[6] != EV_CURRENT
+        {
+            return Err(Errno::ENOEXEC);
+        }
+
+        let kind = read_u16(data, 16)?;
+        let machine = read_u16(data, 18)?;
+        let version = read_u32(data, 20)?;
+        if machine != EM_X86_64 || version != u32::from(EV_CURRENT) {
+            return Err(Errno::ENOEXEC);
+        }
+
+        let base = match kind {
+            ET_EXEC => 0,
+            ET_DYN => PIE_BASE,
+            _ => return Err(Errno::ENOEXEC),
+        };
+
+        let entry = read_u64(data, 24)?;
+        let phoff = read_u64(data, 32)?;
+        let phentsize = usize::from(read_u16(data, 54)?);
+        let phnum = usize::from(read_u16(data, 56)?);
+        if phentsize != PHDR_SIZE || phnum == 0 || phnum > MAX_PHNUM {
+            return Err(Errno::ENOEXEC);
+        }
+
+        let mut headers = Vec::with_capacity(phnum);
+        for index in 0..phnum {
+            let offset = usize::try_from(phoff)
+                .ok()
+                .and_then(|phoff| phoff.checked_add(index * PHDR_SIZE))
+                .ok_or(Errno::ENOEXEC)?;
+
+            headers.push(ProgramHeader {
+                kind: read_u32(data, offset)?,
+                flags: read_u32(data, offset + 4)?,
+                offset: read_u64(data, offset + 8)?,
+                vaddr: read_u64(data, offset + 16)?,
+                filesz: read_u64(data, offset + 32)?,
+                memsz: read_u64(data, offset + 40)?,
+            });
+        }
+
+        let elf = Self {
+            data,
+            base,
+            entry: entry.checked_add(base).ok_or(Errno::ENOEXEC)?,
+            phoff,
+            headers,
+        };
+        elf.validate()?;
+        Ok(elf)
+    }
+
+    fn loads(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.headers.iter().filter(|header| header.kind == PT_LOAD)
+    }
+
+    /// Проверяет сегменты: данные внутри файла, адреса внутри
+    /// пользовательской части, точка входа в исполняемом сегменте.
+    fn validate(&self) -> Result<(), Errno> {
+        // Динамическая компоновка пока не поддерживается.
+        if self.headers.iter().any(|header| header.kind == PT_INTERP) {
+            return Err(Errno::ENOEXEC);
+        }
+
+        let mut has_entry = false;
+        let mut has_load = false;
+        for header in self.loads() {
+            has_load = true;
+
+            let file_end = header.offset.checked_add(header.filesz);
+            if header.filesz > header.memsz
+                || file_end.is_none_or(|end| end > self.data.len() as u64)
+            {
+                return Err(Errno::ENOEXEC);
+            }
+
+            let start = header.vaddr.checked_add(self.base).ok_or(Errno::ENOEXEC)?;
+            let start = VirtAddr::try_new(start).map_err(|_| Errno::ENOEXEC)?;
+            if !AddressSpace::is_user_range(start, header.memsz)
+                // Сегмент не должен пересекаться со стеком.
+                || start.as_u64() + header.memsz > STACK_TOP - STACK_SIZE
+            {
+                return Err(Errno::ENOEXEC);
+            }
+
+            let end = start.as_u64() + header.memsz;
+            if header.flags & PF_X != 0 && (start.as_u64()..end).contains(&self.entry) {
+                has_entry = true;
+            }
+        }
+
+        if has_load && has_entry {
+            Ok(())
+        } else {
+            Err(Errno::ENOEXEC)
+        }
+    }
+
+    /// Адрес таблицы заголовков программы в памяти процесса (для `AT_PHDR`).
+    fn phdr_address(&self) -> u64 {
+        if let Some(phdr) = self.headers.iter().find(|header| header.kind == PT_PHDR) {
+            return phdr.vaddr.wrapping_add(self.base);
+        }
+
+        // Обычно таблица попадает в первый сегмент вместе с заголовком ELF.
+        self.loads()
+            .find(|header| (header.offset..header.offset + header.filesz).contains(&self.phoff))
+            .map_or(0, |header| {
+                header.vaddr + self.base + (self.phoff - header.offset)
+            })
+    }
+}
+
+/// Загружает исполняемый файл `data` в пустое адресное пространство `space`
+/// и готовит стек с аргументами `argv` и окружением `envp`.
+///
+/// Ошибки: `ENOEXEC` для некорректного или неподдерживаемого файла,
+/// `E2BIG` для слишком длинных аргументов, `EINVAL` для строк с нулевым
+/// байтом, `ENOMEM` при нехватке памяти. При ошибке `space` может быть
+/// заполнено частично.
+pub fn load(
+    space: &mut AddressSpace,
+    data: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<LoadedImage, Errno> {
+    let elf = Elf::parse(data)?;
+
+    for header in elf.loads() {
+        let start = VirtAddr::new(header.vaddr + elf.base);
+
+        let mut flags = PageTableFlags::empty();
+        if header.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if header.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+
+        let file_start = header.offset as usize;
+        let file_end = file_start + header.filesz as usize;
+
+        space.map(start, header.memsz, flags)?;
+        space.write(start, &data[file_start..file_end])?;
+        // Страница могла достаться от предыдущего сегмента, поэтому .bss
+        // обнуляется явно.
+        space.zero(
+            start + header.filesz,
+            (header.memsz - header.filesz) as usize,
+        )?;
+    }
+
+    let entry = VirtAddr::new(elf.entry);
+    let auxv = [
+        (AT_PHDR, elf.phdr_address()),
+        (AT_PHENT, PHDR_SIZE as u64),
+        (AT_PHNUM, elf.headers.len() as u64),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, entry.as_u64()),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_SECURE, 0),
+    ];
+    let stack_pointer = build_stack(space, argv, envp, &auxv)?;
+
+    Ok(LoadedImage {
+        entry,
+        stack_pointer,
+    })
+}
+
+/// Отображает стек и раскладывает на нем (сверху вниз) строки, 16 байт для
+/// `AT_RANDOM`, вспомогательный вектор, `envp`, `argv` и `argc`.
+fn build_stack(
+    space: &mut AddressSpace,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<VirtAddr, Errno> {
+    let strings_len: usize = argv.iter().chain(envp).map(|string| string.len() + 1).sum();
+    if strings_len > ARG_MAX {
+        return Err(Errno::E2BIG);
+    }
+    if argv.iter().chain(envp).any(|string| string.contains('\0')) {
+        return Err(Errno::EINVAL);
+    }
+
+    let stack_bottom = VirtAddr::new(STACK_TOP - STACK_SIZE);
+    space.map(
+        stack_bottom,
+        STACK_SIZE,
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    )?;
+
+    // Строки и случайные байты лежат в самом верху стека.
+    let mut strings = Vec::with_capacity(strings_len + 16);
+    let strings_start = STACK_TOP - (strings_len as u64 + 16);
+    let mut pointers = Vec::with_capacity(argv.len() + envp.len());
+    for string in argv.iter().chain(envp) {
+        pointers.push(strings_start + strings.len() as u64);
+        strings.extend_from_slice(string.as_bytes());
+        strings.push(0);
+    }
+    let random = strings_start + strings.len() as u64;
+    strings.extend_from_slice(&random_bytes());
+
+    // argc, argv + NULL, envp + NULL, auxv с AT_RANDOM и AT_NULL.
+    let mut words = Vec::with_capacity(pointers.len() + 2 * auxv.len() + 8);
+    words.push(argv.len() as u64);
+    words.extend_from_slice(&pointers[..argv.len()]);
+    words.push(0);
+    words.extend_from_slice(&pointers[argv.len()..]);
+    words.push(0);
+    for &(key, value) in auxv.iter().chain(&[(AT_RANDOM, random), (AT_NULL, 0)]) {
+        words.push(key);
+        words.push(value);
+    }
+
+    // По ABI на входе в программу `rsp` (указывающий на argc) выровнен по 16.
+    let stack_pointer = (strings_start - words.len() as u64 * 8) & !0xF;
+    if stack_pointer < stack_bottom.as_u64() {
+        return Err(Errno::E2BIG);
+    }
+
+    let mut table = Vec::with_capacity(words.len() * 8);
+    for word in words {
+        table.extend_from_slice(&word.to_le_bytes());
+    }
+
+    space.write(VirtAddr::new(stack_pointer), &table)?;
+    space.write(VirtAddr::new(strings_start), &strings)?;
+    Ok(VirtAddr::new(stack_pointer))
+}
+
+/// Байты для `AT_RANDOM` (из TSC - пригодны для канареек стека, но не для
+/// криптографии).
+fn random_bytes() -> [u8; 16] {
+    let mut state = time::tsc() ^ 0x9E37_79B9_7F4A_7C15;
+    let mut bytes = [0; 16];
+    for chunk in bytes.chunks_mut(8) {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        chunk.copy_from_slice(&(value ^ (value >> 31)).to_le_bytes());
+    }
+    bytes
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Errno> {
+    Ok(u16::from_le_bytes(read_array(data, offset)?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Errno> {
+    Ok(u32::from_le_bytes(read_array(data, offset)?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, Errno> {
+    Ok(u64::from_le_bytes(read_array(data, offset)?))
+}
+
+fn read_array<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], Errno> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .map(|bytes| bytes.try_into().unwrap())
+        .ok_or(Errno::ENOEXEC)
+}
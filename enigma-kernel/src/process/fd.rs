@@ -0,0 +1,103 @@
+//! Таблица файловых дескрипторов процесса.
+
+use crate::errno::Errno;
+use crate::fs::vfs::SeekFrom;
+use alloc::{sync::Arc, vec::Vec};
+
+/// Максимальное количество открытых дескрипторов в одном процессе.
+pub const MAX_FDS: usize = 256;
+
+/// Открытый файл, на который ссылается дескриптор.
+pub trait File: Send + Sync {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Errno>;
+    fn write(&self, buffer: &[u8]) -> Result<usize, Errno>;
+
+    /// Меняет позицию и возвращает новую. Файлы без позиции (консоль,
+    /// каналы) возвращают `ESPIPE`.
+    fn seek(&self, _from: SeekFrom) -> Result<u64, Errno> {
+        Err(Errno::ESPIPE)
+    }
+}
+
+/// Номер файлового дескриптора.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fd(pub u32);
+
+#[derive(Default)]
+pub struct FdTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// Добавляет файл под наименьшим свободным номером.
+    pub fn insert(&mut self, file: Arc<dyn File>) -> Result<Fd, Errno> {
+        let index = match self.files.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.files.len() < MAX_FDS => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(Errno::EMFILE),
+        };
+
+        self.files[index] = Some(file);
+        Ok(Fd(index as u32))
+    }
+
+    /// Помещает файл под номером `fd`, закрывая прежний файл (как `dup2`).
+    pub fn insert_at(&mut self, fd: Fd, file: Arc<dyn File>) -> Result<(), Errno> {
+        let index = fd.0 as usize;
+        if index >= MAX_FDS {
+            return Err(Errno::EBADF);
+        }
+
+        if self.files.len() <= index {
+            self.files.resize_with(index + 1, || None);
+        }
+        self.files[index] = Some(file);
+        Ok(())
+    }
+
+    pub fn get(&self, fd: Fd) -> Result<Arc<dyn File>, Errno> {
+        self.files
+            .get(fd.0 as usize)
+            .and_then(Option::clone)
+            .ok_or(Errno::EBADF)
+    }
+
+    pub fn close(&mut self, fd: Fd) -> Result<(), Errno> {
+        let slot = self.files.get_mut(fd.0 as usize).ok_or(Errno::EBADF)?;
+        slot.take().map(drop).ok_or(Errno::EBADF)
+    }
+
+    /// Дублирует дескриптор под наименьшим свободным номером.
+    pub fn dup(&mut self, fd: Fd) -> Result<Fd, Errno> {
+        let file = self.get(fd)?;
+        self.insert(file)
+    }
+
+    /// Копия таблицы, ссылающаяся на те же открытые файлы (для `fork`).
+    pub fn duplicate(&self) -> Self {
+        Self {
+            files: self.files.clone(),
+        }
+    }
+
+    /// Закрывает все дескрипторы.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    /// Количество открытых дескрипторов.
+    pub fn len(&self) -> usize {
+        self.files.iter().filter(|file| file.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
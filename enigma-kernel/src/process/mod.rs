@@ -0,0 +1,539 @@
+//! Данный модуль содержит процессы: адресное пространство, таблицу файловых
+//! дескрипторов, потоки процесса и связи родитель-потомок.
+//!
+//! Процесс живет в глобальной таблице процессов, пока его не заберет родитель
+//! через [`wait`]. При завершении (через [`exit`] или [`terminate`]) остальные
+//! потоки процесса останавливаются не сразу, а в безопасной точке
+//! ([`exit_point`]), где они не держат блокировок ядра. Когда выходит
+//! последний поток, процесс освобождает память и файлы и становится зомби,
+//! храня только код возврата. Потомки завершившегося процесса передаются
+//! процессу init ([`INIT_PID`]), а если его нет - ядру.
+//!
+//! Процессы без родителя (созданные ядром) ждет ядро: [`wait`] из потока
+//! ядра (не принадлежащего процессу) ждет именно их.
+
+pub mod address_space;
+pub mod elf;
+pub mod fd;
+pub mod user;
+
+use crate::drivers::console::Console;
+use crate::errno::Errno;
+use crate::fs::vfs::Location;
+use crate::thread::{self, ThreadId};
+use address_space::AddressSpace;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use fd::FdTable;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// PID процесса init, которому передаются осиротевшие процессы.
+pub const INIT_PID: Pid = Pid(1);
+
+static TABLE: Mutex<ProcessTable> = Mutex::new(ProcessTable {
+    entries: BTreeMap::new(),
+    threads: BTreeMap::new(),
+    waiters: Vec::new(),
+    next_pid: 1,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(u32);
+
+impl Pid {
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+
+    pub const fn from_u32(pid: u32) -> Self {
+        Self(pid)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Alive,
+    /// Процесс завершился с кодом возврата и ждет родителя.
+    Zombie(i32),
+}
+
+/// Какого потомка ждет [`wait`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitTarget {
+    Any,
+    Pid(Pid),
+}
+
+pub struct Process {
+    pid: Pid,
+    name: String,
+    /// `None` после завершения процесса.
+    address_space: Mutex<Option<AddressSpace>>,
+    files: Mutex<FdTable>,
+    /// Текущий каталог (`None` - корень).
+    cwd: Mutex<Option<Location>>,
+}
+
+impl Process {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Родитель процесса (`None`, если процесс принадлежит ядру).
+    pub fn parent(&self) -> Option<Pid> {
+        locked(|table| table.entries.get(&self.pid).and_then(|entry| entry.parent))
+    }
+
+    pub fn children(&self) -> Vec<Pid> {
+        locked(|table| {
+            table
+                .entries
+                .get(&self.pid)
+                .map(|entry| entry.children.clone())
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn threads(&self) -> Vec<ThreadId> {
+        locked(|table| {
+            table
+                .entries
+                .get(&self.pid)
+                .map(|entry| entry.threads.clone())
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn state(&self) -> ProcessState {
+        locked(|table| {
+            table
+                .entries
+                .get(&self.pid)
+                .map_or(ProcessState::Zombie(0), |entry| entry.state)
+        })
+    }
+
+    /// Выполняет `f` с адресным пространством процесса.
+    pub fn with_address_space<R>(
+        &self,
+        f: impl FnOnce(&mut AddressSpace) -> R,
+    ) -> Result<R, Errno> {
+        let mut address_space = self.address_space.lock();
+        address_space.as_mut().map(f).ok_or(Errno::ESRCH)
+    }
+
+    /// Выполняет `f` с таблицей файловых дескрипторов процесса.
+    pub fn with_files<R>(&self, f: impl FnOnce(&mut FdTable) -> R) -> R {
+        f(&mut self.files.lock())
+    }
+
+    /// Текущий каталог процесса (`None`, если это корень).
+    pub fn cwd(&self) -> Option<Location> {
+        self.cwd.lock().clone()
+    }
+
+    pub fn set_cwd(&self, cwd: Location) {
+        *self.cwd.lock() = Some(cwd);
+    }
+
+    /// Создает поток процесса, который выполняется в его адресном
+    /// пространстве. Когда завершается последний поток, завершается и
+    /// процесс (с кодом 0).
+    pub fn spawn_thread<F>(self: &Arc<Self>, name: &'static str, f: F) -> Result<ThreadId, Errno>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let page_table = self.with_address_space(|space| space.page_table())?;
+        let pid = self.pid;
+
+        // Прерывания выключены, пока поток не добавлен в процесс, поэтому
+        // он не может начать выполняться (и завершиться) раньше.
+        without_interrupts(|| {
+            let id = thread::spawn_with_page_table(name, page_table, move || {
+                // Процесс мог начать завершаться до первого запуска потока.
+                if !thread::exit_requested() {
+                    f();
+                }
+                thread_finished(pid);
+            });
+
+            let mut table = TABLE.lock();
+            match table.entries.get_mut(&pid) {
+                Some(entry) if entry.state == ProcessState::Alive => {
+                    entry.threads.push(id);
+                    table.threads.insert(id, pid);
+                    Ok(id)
+                }
+                _ => {
+                    thread::request_exit(id);
+                    Err(Errno::ESRCH)
+                }
+            }
+        })
+    }
+
+    /// Создает поток процесса, который сразу переходит в кольцо 3 на адрес
+    /// `entry` со стеком `stack` (оба должны быть отображены в адресном
+    /// пространстве процесса).
+    pub fn spawn_user_thread(
+        self: &Arc<Self>,
+        entry: VirtAddr,
+        stack: VirtAddr,
+    ) -> Result<ThreadId, Errno> {
+        let mapped = self.with_address_space(|space| {
+            space.translate(entry).is_some() && space.translate(stack - 1u64).is_some()
+        })?;
+        if !mapped {
+            return Err(Errno::EFAULT);
+        }
+
+        self.spawn_thread("user", move || unsafe {
+            user::enter_user_mode(entry, stack)
+        })
+    }
+}
+
+struct Entry {
+    process: Arc<Process>,
+    parent: Option<Pid>,
+    children: Vec<Pid>,
+    threads: Vec<ThreadId>,
+    state: ProcessState,
+    /// Код возврата, пока потоки завершающегося процесса доходят до
+    /// безопасной точки.
+    exit_status: Option<i32>,
+}
+
+struct ProcessTable {
+    entries: BTreeMap<Pid, Entry>,
+    /// Процесс, которому принадлежит поток.
+    threads: BTreeMap<ThreadId, Pid>,
+    /// Потоки, ждущие завершения потомков процесса (`None` - ядра).
+    waiters: Vec<(Option<Pid>, ThreadId)>,
+    next_pid: u32,
+}
+
+impl ProcessTable {
+    fn wake_waiters(&mut self, parent: Option<Pid>) {
+        self.waiters.retain(|&(waiting_for, thread)| {
+            if waiting_for == parent {
+                thread::unpark(thread);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+fn locked<R>(f: impl FnOnce(&mut ProcessTable) -> R) -> R {
+    without_interrupts(|| f(&mut TABLE.lock()))
+}
+
+/// Создает процесс с пустым адресным пространством и без потоков.
+/// Родитель - текущий процесс (или ядро, если вызвано из потока ядра), от
+/// него же наследуется текущий каталог.
+pub fn create(name: &str) -> Result<Arc<Process>, Errno> {
+    Ok(create_with(name, AddressSpace::new()?))
+}
+
+fn create_with(name: &str, address_space: AddressSpace) -> Arc<Process> {
+    let parent = current();
+    let cwd = parent.as_ref().and_then(|parent| parent.cwd());
+    let parent = parent.map(|process| process.pid);
+
+    locked(|table| {
+        let pid = Pid(table.next_pid);
+        table.next_pid += 1;
+
+        let process = Arc::new(Process {
+            pid,
+            name: name.into(),
+            address_space: Mutex::new(Some(address_space)),
+            files: Mutex::new(FdTable::new()),
+            cwd: Mutex::new(cwd),
+        });
+
+        if let Some(parent) = parent.and_then(|parent| table.entries.get_mut(&parent)) {
+            parent.children.push(pid);
+        }
+        table.entries.insert(
+            pid,
+            Entry {
+                process: process.clone(),
+                parent,
+                children: Vec::new(),
+                threads: Vec::new(),
+                state: ProcessState::Alive,
+                exit_status: None,
+            },
+        );
+
+        process
+    })
+}
+
+/// Создает процесс из статического исполняемого файла ELF `data` и
+/// запускает его главный поток. Дескрипторы 0, 1 и 2 ссылаются на консоль.
+///
+/// Ошибки загрузки (см. [`elf::load`]) возвращаются до создания процесса.
+pub fn spawn_elf(
+    name: &str,
+    data: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<Arc<Process>, Errno> {
+    let mut address_space = AddressSpace::new()?;
+    let image = elf::load(&mut address_space, data, argv, envp)?;
+
+    let process = create_with(name, address_space);
+    process.with_files(|files| {
+        let console: Arc<dyn fd::File> = Arc::new(Console);
+        (0..3).try_for_each(|fd| files.insert_at(fd::Fd(fd), console.clone()))
+    })?;
+
+    if let Err(error) = process.spawn_user_thread(image.entry, image.stack_pointer) {
+        // Процесс уже в таблице, поэтому его, как и любой другой, забирает
+        // родитель через `wait`.
+        let _ = terminate(process.pid, 127);
+        return Err(error);
+    }
+
+    Ok(process)
+}
+
+/// Возвращает процесс по PID (в том числе зомби).
+pub fn get(pid: Pid) -> Option<Arc<Process>> {
+    locked(|table| table.entries.get(&pid).map(|entry| entry.process.clone()))
+}
+
+/// Возвращает процесс, которому принадлежит текущий поток.
+pub fn current() -> Option<Arc<Process>> {
+    let thread = thread::current()?;
+    locked(|table| {
+        let pid = table.threads.get(&thread)?;
+        table.entries.get(pid).map(|entry| entry.process.clone())
+    })
+}
+
+/// Возвращает PID всех процессов.
+pub fn pids() -> Vec<Pid> {
+    locked(|table| table.entries.keys().copied().collect())
+}
+
+/// Вызывается, когда поток процесса заканчивает работу: функция потока
+/// вернула управление или поток дошел до [`exit_point`]. Последний поток
+/// завершает процесс.
+fn thread_finished(pid: Pid) {
+    let thread = thread::current();
+    // Адресное пространство может освободиться раньше, чем поток перестанет
+    // выполняться, поэтому поток переходит на таблицу страниц ядра.
+    if let Some(thread) = thread {
+        thread::set_page_table(thread, None);
+    }
+
+    let process = locked(|table| {
+        if let Some(thread) = thread {
+            table.threads.remove(&thread);
+        }
+
+        let entry = table.entries.get_mut(&pid)?;
+        let count = entry.threads.len();
+        entry.threads.retain(|&id| Some(id) != thread);
+        let last = entry.threads.len() < count && entry.threads.is_empty();
+        if !last || entry.state != ProcessState::Alive {
+            return None;
+        }
+
+        let status = entry.exit_status.unwrap_or(0);
+        Some(finish(table, pid, status))
+    });
+
+    if let Some(process) = process {
+        release(&process);
+    }
+}
+
+/// Завершает процесс `pid` с кодом `status`: его потоки (кроме текущего)
+/// завершаются в ближайшей безопасной точке (см. [`exit_point`]), а когда
+/// выходит последний из них, процесс освобождает память и файлы и будит
+/// родителя. Процесс без потоков завершается сразу.
+///
+/// Возвращает `Err(ESRCH)`, если процесса нет или он уже завершается.
+pub fn terminate(pid: Pid, status: i32) -> Result<(), Errno> {
+    let current_thread = thread::current();
+
+    let process = locked(|table| {
+        let entry = table.entries.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        if entry.state != ProcessState::Alive || entry.exit_status.is_some() {
+            return Err(Errno::ESRCH);
+        }
+
+        entry.exit_status = Some(status);
+        for &thread in &entry.threads {
+            if Some(thread) != current_thread {
+                thread::request_exit(thread);
+            }
+        }
+
+        Ok(entry.threads.is_empty().then(|| finish(table, pid, status)))
+    })?;
+
+    if let Some(process) = process {
+        release(&process);
+    }
+    Ok(())
+}
+
+/// Делает процесс `pid` зомби, передает его потомков и будит родителя.
+/// Память и файлы освобождает [`release`] уже вне блокировки таблицы.
+fn finish(table: &mut ProcessTable, pid: Pid, status: i32) -> Arc<Process> {
+    let entry = table.entries.get_mut(&pid).unwrap();
+    entry.state = ProcessState::Zombie(status);
+    let children = core::mem::take(&mut entry.children);
+    let parent = entry.parent;
+    let process = entry.process.clone();
+
+    // Осиротевшие потомки переходят к init (или к ядру).
+    let adopter = Some(INIT_PID).filter(|&init| {
+        init != pid
+            && table
+                .entries
+                .get(&init)
+                .is_some_and(|init| init.state == ProcessState::Alive)
+    });
+    for &child in &children {
+        if let Some(child) = table.entries.get_mut(&child) {
+            child.parent = adopter;
+        }
+    }
+    let has_zombies = children.iter().any(|child| {
+        table
+            .entries
+            .get(child)
+            .is_some_and(|child| child.state != ProcessState::Alive)
+    });
+    if let Some(adopter) = adopter.and_then(|adopter| table.entries.get_mut(&adopter)) {
+        adopter.children.extend(children);
+    }
+    if has_zombies {
+        table.wake_waiters(adopter);
+    }
+
+    table.wake_waiters(parent);
+    process
+}
+
+fn release(process: &Process) {
+    let address_space = process.address_space.lock().take();
+    drop(address_space);
+    let files = core::mem::take(&mut *process.files.lock());
+    drop(files);
+}
+
+/// Безопасная точка потока процесса: если процесс завершается (см.
+/// [`terminate`]), то текущий поток завершается здесь. Вызывается только
+/// там, где поток не держит блокировок ядра: перед возвратом в кольцо 3 из
+/// системного вызова или прерывания.
+pub fn exit_point() {
+    if thread::exit_requested() {
+        exit_thread();
+    }
+}
+
+/// Завершает текущий поток (и процесс, если поток в нём последний).
+fn exit_thread() -> ! {
+    if let Some(process) = current() {
+        thread_finished(process.pid);
+    }
+
+    thread::exit();
+}
+
+/// Завершает текущий процесс с кодом `status` и текущий поток.
+pub fn exit(status: i32) -> ! {
+    if let Some(process) = current() {
+        let _ = terminate(process.pid, status);
+    }
+
+    exit_thread();
+}
+
+/// Забирает завершившегося потомка без ожидания. Возвращает `Ok(None)`,
+/// если подходящие потомки есть, но ещё не завершились.
+pub fn try_wait(target: WaitTarget) -> Result<Option<(Pid, i32)>, Errno> {
+    let parent = current().map(|process| process.pid);
+    locked(|table| reap(table, parent, target))
+}
+
+/// Ждет завершения потомка текущего процесса (или процесса ядра, если
+/// вызвано из потока ядра) и забирает его. Возвращает PID и код возврата.
+pub fn wait(target: WaitTarget) -> Result<(Pid, i32), Errno> {
+    let parent = current().map(|process| process.pid);
+    let thread = thread::current().ok_or(Errno::ECHILD)?;
+
+    loop {
+        let reaped = locked(|table| {
+            let reaped = reap(table, parent, target);
+            // После ложного пробуждения поток может быть ещё в списке, а
+            // вернувшемуся из `wait` потоку там делать нечего.
+            let waiter = (parent, thread);
+            match reaped {
+                Ok(None) if !table.waiters.contains(&waiter) => table.waiters.push(waiter),
+                Ok(None) => {}
+                _ => table.waiters.retain(|&entry| entry != waiter),
+            }
+            reaped
+        })?;
+
+        match reaped {
+            Some(result) => return Ok(result),
+            // Поток завершающегося процесса не ждет, а идет к безопасной точке.
+            None if thread::exit_requested() => {
+                locked(|table| table.waiters.retain(|&entry| entry != (parent, thread)));
+                return Err(Errno::EINTR);
+            }
+            // Если потомок завершится до `park`, то `unpark` не потеряется.
+            None => thread::park(),
+        }
+    }
+}
+
+/// Удаляет из таблицы подходящего потомка-зомби `parent`.
+fn reap(
+    table: &mut ProcessTable,
+    parent: Option<Pid>,
+    target: WaitTarget,
+) -> Result<Option<(Pid, i32)>, Errno> {
+    let mut candidates = table.entries.iter().filter(|(pid, entry)| {
+        entry.parent == parent
+            && match target {
+                WaitTarget::Any => true,
+                WaitTarget::Pid(target) => **pid == target,
+            }
+    });
+
+    let mut found = false;
+    let zombie = candidates.find_map(|(&pid, entry)| {
+        found = true;
+        match entry.state {
+            ProcessState::Zombie(status) => Some((pid, status)),
+            ProcessState::Alive => None,
+        }
+    });
+
+    let Some((pid, status)) = zombie else {
+        return if found { Ok(None) } else { Err(Errno::ECHILD) };
+    };
+
+    table.entries.remove(&pid);
+    if let Some(parent) = parent.and_then(|parent| table.entries.get_mut(&parent)) {
+        parent.children.retain(|&child| child != pid);
+    }
+    Ok(Some((pid, status)))
+}
@@ -0,0 +1,61 @@
+//! Переход потока в пользовательский режим (кольцо 3).
+
+use crate::gdt;
+use core::arch::asm;
+use x86_64::VirtAddr;
+use x86_64::registers::rflags::RFlags;
+
+/// Переводит текущий поток в кольцо 3: выполнение продолжится с адреса
+/// `entry` со стеком `stack` и включенными прерываниями. Все регистры общего
+/// назначения обнуляются, чтобы не передать процессу данные ядра.
+///
+/// Обратно в ядро поток попадает только через прерывания, исключения и
+/// системные вызовы, которые используют стек ядра потока из TSS (RSP0).
+/// Кадры, оставшиеся на стеке ядра на момент вызова, больше не используются.
+///
+/// ## Safety
+///
+/// Активная таблица страниц должна отображать `entry` и `stack` с флагом
+/// `USER_ACCESSIBLE`, а текущий поток должен иметь собственный стек ядра.
+pub unsafe fn enter_user_mode(entry: VirtAddr, stack: VirtAddr) -> ! {
+    let code = u64::from(gdt::user_code_selector().0);
+    let data = u64::from(gdt::user_data_selector().0);
+    // Бит 1 RFLAGS зарезервирован и всегда равен 1.
+    let rflags = RFlags::INTERRUPT_FLAG.bits() | 0x2;
+
+    unsafe {
+        asm!(
+            "cli",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            // Кадр для `iretq`: SS, RSP, RFLAGS, CS, RIP.
+            "push {data}",
+            "push {stack}",
+            "push {rflags}",
+            "push {code}",
+            "push {entry}",
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            data = in(reg) data,
+            stack = in(reg) stack.as_u64(),
+            rflags = in(reg) rflags,
+            code = in(reg) code,
+            entry = in(reg) entry.as_u64(),
+            options(noreturn),
+        );
+    }
+}
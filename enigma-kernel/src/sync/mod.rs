@@ -0,0 +1,27 @@
+//! Данный модуль содержит ассинхронные примитивы синхронизации для задач
+//! исполнителя. В отличие от `spin::Mutex`, ожидание в них не крутит
+//! процессор: задача засыпает и будится через свой `Waker`.
+//!
+//! Внутреннее состояние всех примитивов защищено `spin::Mutex`, который
+//! берется с выключенными прерываниями, поэтому их можно будить (например,
+//! [`Notify::notify_one`] или [`mpsc::Sender::try_send`]) из обработчиков прерываний.
+
+use spin::Mutex as SpinMutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub mod mpsc;
+pub mod mutex;
+pub mod notify;
+pub mod oneshot;
+pub mod rwlock;
+pub mod semaphore;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Event, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+
+/// Выполняет `f` над состоянием, захваченным с выключенными прерываниями.
+fn locked<T, R>(mutex: &SpinMutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    without_interrupts(|| f(&mut mutex.lock()))
+}
@@ -0,0 +1,241 @@
+//! Многопроизводительный канал с одним получателем (mpsc).
+//!
+//! Ограниченный канал ([`channel`]) заставляет отправителей ждать, пока в
+//! нем не появится место. Его очередь выделяется сразу на всю емкость, так
+//! что [`Sender::try_send`] не выделяет память и годится для прерываний.
+
+use super::{locked, semaphore::Semaphore};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    fmt,
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use futures_util::stream::Stream;
+use spin::Mutex as SpinMutex;
+
+/// Количество разрешений, которое добавляется при закрытии канала, чтобы
+/// разбудить всех ждущих отправителей.
+const CLOSED_PERMITS: usize = usize::MAX >> 1;
+
+/// Ошибка отправки: получатель закрыт. Содержит неотправленное значение.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// В канале нет места.
+    Full(T),
+    /// Получатель закрыт.
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Канал пуст, но отправители ещё есть.
+    Empty,
+    /// Канал пуст и все отправители удалены.
+    Disconnected,
+}
+
+struct Chan<T> {
+    queue: VecDeque<T>,
+    receiver_waker: Option<Waker>,
+    senders: usize,
+    closed: bool,
+}
+
+struct Shared<T> {
+    chan: SpinMutex<Chan<T>>,
+    /// Свободные места ограниченного канала.
+    capacity: Option<Semaphore>,
+}
+
+impl<T> Shared<T> {
+    fn new(capacity: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            chan: SpinMutex::new(Chan {
+                queue: VecDeque::with_capacity(capacity.unwrap_or(0)),
+                receiver_waker: None,
+                senders: 1,
+                closed: false,
+            }),
+            capacity: capacity.map(Semaphore::new),
+        })
+    }
+
+    fn push(&self, value: T) -> Result<(), T> {
+        locked(&self.chan, |chan| {
+            if chan.closed {
+                return Err(value);
+            }
+
+            chan.queue.push_back(value);
+            if let Some(waker) = chan.receiver_waker.take() {
+                waker.wake();
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Создает ограниченный канал емкостью `capacity` сообщений.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be non-zero");
+
+    let shared = Shared::new(Some(capacity));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// Создает неограниченный канал.
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Shared::new(None);
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Отправляет значение, дожидаясь свободного места.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if let Some(capacity) = &self.shared.capacity {
+            capacity.acquire().await.forget();
+        }
+
+        self.shared.push(value).map_err(SendError)
+    }
+
+    /// Отправляет значение без ожидания.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(value));
+        }
+
+        if let Some(capacity) = &self.shared.capacity {
+            match capacity.try_acquire() {
+                Some(permit) => permit.forget(),
+                None => return Err(TrySendError::Full(value)),
+            }
+        }
+
+        self.shared.push(value).map_err(TrySendError::Closed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        locked(&self.shared.chan, |chan| chan.closed)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        locked(&self.shared.chan, |chan| chan.senders += 1);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        locked(&self.shared.chan, |chan| {
+            chan.senders -= 1;
+            if chan.senders == 0
+                && let Some(waker) = chan.receiver_waker.take()
+            {
+                waker.wake();
+            }
+        });
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Ждет следующего значения. Возвращает `None`, когда канал пуст и
+    /// все отправители удалены.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let value = locked(&self.shared.chan, |chan| match chan.queue.pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if chan.senders == 0 => Poll::Ready(None),
+            None => {
+                chan.receiver_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        });
+
+        if let Poll::Ready(Some(_)) = value {
+            self.release_slot();
+        }
+        value
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let value = locked(&self.shared.chan, |chan| match chan.queue.pop_front() {
+            Some(value) => Ok(value),
+            None if chan.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        });
+
+        if value.is_ok() {
+            self.release_slot();
+        }
+        value
+    }
+
+    /// Закрывает канал: новые отправки будут возвращать ошибку, а уже
+    /// отправленные значения всё ещё можно получить.
+    pub fn close(&mut self) {
+        let was_closed = locked(&self.shared.chan, |chan| {
+            core::mem::replace(&mut chan.closed, true)
+        });
+
+        if !was_closed && let Some(capacity) = &self.shared.capacity {
+            capacity.add_permits(CLOSED_PERMITS);
+        }
+    }
+
+    fn release_slot(&self) {
+        if let Some(capacity) = &self.shared.capacity {
+            capacity.add_permits(1);
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,228 @@
+//! Примитивы уведомления задач: [`Notify`] (разбудить одну или все
+//! ждущие задачи) и [`Event`] (событие с ручным сбросом).
+
+use super::locked;
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex as SpinMutex;
+
+/// Ожидающий ещё не уведомлен.
+const WAITING: u8 = 0;
+/// Ожидающий уведомлен через `notify_one`.
+const NOTIFIED_ONE: u8 = 1;
+/// Ожидающий уведомлен через `notify_waiters`.
+const NOTIFIED_ALL: u8 = 2;
+
+struct Waiter {
+    state: AtomicU8,
+    waker: SpinMutex<Option<Waker>>,
+}
+
+struct State {
+    /// Сохраненное уведомление `notify_one`, когда никто не ждал.
+    permit: bool,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        match self.waiters.pop_front() {
+            Some(waiter) => wake(&waiter, NOTIFIED_ONE),
+            None => self.permit = true,
+        }
+    }
+}
+
+fn wake(waiter: &Waiter, kind: u8) {
+    waiter.state.store(kind, Ordering::Release);
+    if let Some(waker) = waiter.waker.lock().take() {
+        waker.wake();
+    }
+}
+
+pub struct Notify {
+    state: SpinMutex<State>,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            state: SpinMutex::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Будит первую ждущую задачу. Если никто не ждет, то уведомление
+    /// сохраняется, и следующий [`Notify::notified`] завершится сразу.
+    pub fn notify_one(&self) {
+        locked(&self.state, State::notify_one);
+    }
+
+    /// Будит все задачи, которые ждут в данный момент (без сохранения уведомления).
+    pub fn notify_waiters(&self) {
+        locked(&self.state, |state| {
+            for waiter in state.waiters.drain(..) {
+                wake(&waiter, NOTIFIED_ALL);
+            }
+        });
+    }
+
+    /// Ждет уведомления.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future, возвращаемый [`Notify::notified`].
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(waiter) = self.waiter.as_ref() {
+            locked(&waiter.waker, |waker| *waker = Some(cx.waker().clone()));
+
+            if waiter.state.load(Ordering::Acquire) != WAITING {
+                self.waiter = None;
+                return Poll::Ready(());
+            }
+            return Poll::Pending;
+        }
+
+        let waiter = locked(&self.notify.state, |state| {
+            if state.permit {
+                state.permit = false;
+                return None;
+            }
+
+            let waiter = Arc::new(Waiter {
+                state: AtomicU8::new(WAITING),
+                waker: SpinMutex::new(Some(cx.waker().clone())),
+            });
+            state.waiters.push_back(waiter.clone());
+            Some(waiter)
+        });
+
+        match waiter {
+            Some(waiter) => {
+                self.waiter = Some(waiter);
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+
+        locked(&self.notify.state, |state| {
+            match waiter.state.load(Ordering::Acquire) {
+                // Уведомление `notify_one` не должно потеряться: передадим его дальше.
+                NOTIFIED_ONE => state.notify_one(),
+                NOTIFIED_ALL => {}
+                _ => state.waiters.retain(|other| !Arc::ptr_eq(other, &waiter)),
+            }
+        });
+    }
+}
+
+struct EventState {
+    set: bool,
+    waiters: Vec<Waker>,
+}
+
+/// Событие с ручным сбросом: пока оно установлено, [`Event::wait`]
+/// завершается сразу.
+pub struct Event {
+    state: SpinMutex<EventState>,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Self {
+            state: SpinMutex::new(EventState {
+                set: false,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Устанавливает событие и будит всех ждущих.
+    pub fn set(&self) {
+        locked(&self.state, |state| {
+            state.set = true;
+            for waker in state.waiters.drain(..) {
+                waker.wake();
+            }
+        });
+    }
+
+    pub fn reset(&self) {
+        locked(&self.state, |state| state.set = false);
+    }
+
+    pub fn is_set(&self) -> bool {
+        locked(&self.state, |state| state.set)
+    }
+
+    /// Ждет установки события.
+    pub fn wait(&self) -> EventWait<'_> {
+        EventWait { event: self }
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future, возвращаемый [`Event::wait`].
+pub struct EventWait<'a> {
+    event: &'a Event,
+}
+
+impl Future for EventWait<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        locked(&self.event.state, |state| {
+            if state.set {
+                return Poll::Ready(());
+            }
+
+            if !state
+                .waiters
+                .iter()
+                .any(|waker| waker.will_wake(cx.waker()))
+            {
+                state.waiters.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+}
@@ -0,0 +1,119 @@
+//! Канал для передачи одного значения от одной задачи другой.
+
+use super::locked;
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex as SpinMutex;
+
+/// Ошибка получения: отправитель был удален без отправки значения.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("oneshot sender dropped")
+    }
+}
+
+struct Inner<T> {
+    value: Option<T>,
+    receiver_waker: Option<Waker>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+}
+
+/// Создает канал из отправителя и получателя.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(SpinMutex::new(Inner {
+        value: None,
+        receiver_waker: None,
+        sender_dropped: false,
+        receiver_dropped: false,
+    }));
+
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+pub struct Sender<T> {
+    inner: Arc<SpinMutex<Inner<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Отправляет значение. Если получатель уже удален, то значение возвращается.
+    pub fn send(self, value: T) -> Result<(), T> {
+        locked(&self.inner, |inner| {
+            if inner.receiver_dropped {
+                return Err(value);
+            }
+
+            inner.value = Some(value);
+            if let Some(waker) = inner.receiver_waker.take() {
+                waker.wake();
+            }
+            Ok(())
+        })
+    }
+
+    /// Возвращает `true`, если получатель удален.
+    pub fn is_closed(&self) -> bool {
+        locked(&self.inner, |inner| inner.receiver_dropped)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        locked(&self.inner, |inner| {
+            inner.sender_dropped = true;
+            if let Some(waker) = inner.receiver_waker.take() {
+                waker.wake();
+            }
+        });
+    }
+}
+
+/// Получатель, является future для ожидания значения.
+pub struct Receiver<T> {
+    inner: Arc<SpinMutex<Inner<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Забирает значение, если оно уже отправлено.
+    pub fn try_recv(&mut self) -> Option<Result<T, RecvError>> {
+        locked(&self.inner, |inner| match inner.value.take() {
+            Some(value) => Some(Ok(value)),
+            None if inner.sender_dropped => Some(Err(RecvError)),
+            None => None,
+        })
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        locked(&self.inner, |inner| match inner.value.take() {
+            Some(value) => Poll::Ready(Ok(value)),
+            None if inner.sender_dropped => Poll::Ready(Err(RecvError)),
+            None => {
+                inner.receiver_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        locked(&self.inner, |inner| inner.receiver_dropped = true);
+    }
+}
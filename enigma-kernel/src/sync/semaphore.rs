@@ -0,0 +1,190 @@
+//! Ассинхронный семафор со строгим порядком FIFO. На нем построены
+//! [`Mutex`](super::Mutex) и [`RwLock`](super::RwLock).
+
+use super::locked;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex as SpinMutex;
+
+struct Waiter {
+    permits: usize,
+    granted: AtomicBool,
+    waker: SpinMutex<Option<Waker>>,
+}
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+impl State {
+    /// Раздает свободные разрешения ожидающим в порядке очереди.
+    fn grant(&mut self) {
+        while let Some(waiter) = self.waiters.front()
+            && waiter.permits <= self.permits
+        {
+            let waiter = self.waiters.pop_front().unwrap();
+            self.permits -= waiter.permits;
+            waiter.granted.store(true, Ordering::Release);
+
+            if let Some(waker) = waiter.waker.lock().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+pub struct Semaphore {
+    state: SpinMutex<State>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: SpinMutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Возвращает количество свободных разрешений.
+    pub fn available_permits(&self) -> usize {
+        locked(&self.state, |state| state.permits)
+    }
+
+    /// Ждет одно разрешение.
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Ждет `permits` разрешений (они выдаются все сразу).
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    /// Пытается получить разрешение без ожидания.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, permits: usize) -> Option<SemaphorePermit<'_>> {
+        locked(&self.state, |state| {
+            // Очередь ожидающих не обгоняем.
+            if state.waiters.is_empty() && state.permits >= permits {
+                state.permits -= permits;
+                Some(SemaphorePermit {
+                    semaphore: self,
+                    permits,
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Возвращает `permits` разрешений семафору.
+    pub fn add_permits(&self, permits: usize) {
+        locked(&self.state, |state| {
+            state.permits += permits;
+            state.grant();
+        });
+    }
+}
+
+/// Разрешения семафора, которые возвращаются при удалении.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Забывает разрешения, не возвращая их семафору.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+/// Future, возвращаемый [`Semaphore::acquire`].
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let permits = self.permits;
+
+        if let Some(waiter) = self.waiter.as_ref() {
+            locked(&waiter.waker, |waker| *waker = Some(cx.waker().clone()));
+
+            if waiter.granted.load(Ordering::Acquire) {
+                self.waiter = None;
+                return Poll::Ready(SemaphorePermit { semaphore, permits });
+            }
+            return Poll::Pending;
+        }
+
+        if let Some(permit) = semaphore.try_acquire_many(permits) {
+            return Poll::Ready(permit);
+        }
+
+        let waiter = Arc::new(Waiter {
+            permits,
+            granted: AtomicBool::new(false),
+            waker: SpinMutex::new(Some(cx.waker().clone())),
+        });
+        let granted = locked(&semaphore.state, |state| {
+            state.waiters.push_back(waiter.clone());
+            // Разрешения могли освободиться после `try_acquire_many`.
+            state.grant();
+            waiter.granted.load(Ordering::Acquire)
+        });
+
+        if granted {
+            Poll::Ready(SemaphorePermit { semaphore, permits })
+        } else {
+            self.waiter = Some(waiter);
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+
+        locked(&self.semaphore.state, |state| {
+            if waiter.granted.load(Ordering::Acquire) {
+                // Разрешения уже выданы, но future удален: вернем их.
+                state.permits += waiter.permits;
+            } else {
+                state.waiters.retain(|other| !Arc::ptr_eq(other, &waiter));
+            }
+            state.grant();
+        });
+    }
+}
@@ -0,0 +1,117 @@
+//! Точки входа в ядро из кольца 3: `syscall` и `int 0x80`.
+//!
+//! Обе точки сохраняют регистры пользователя в [`SyscallFrame`] на стеке ядра
+//! потока, вызывают [`super::dispatch`] с включенными прерываниями и
+//! возвращают результат в `rax`. Остальные регистры (кроме `rcx` и `r11`,
+//! которые портит сама инструкция `syscall`) сохраняются.
+
+use super::dispatch;
+use crate::gdt::CpuLocal;
+use core::arch::naked_asm;
+use core::mem::offset_of;
+
+/// Регистры пользователя в том порядке, в котором их кладут точки входа.
+#[repr(C)]
+pub(super) struct SyscallFrame {
+    r9: u64,
+    r8: u64,
+    r10: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rax: u64,
+}
+
+extern "C" fn handle(frame: &mut SyscallFrame) {
+    let args = [
+        frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9,
+    ];
+    frame.rax = dispatch(frame.rax, args) as u64;
+    // Перед возвратом в кольцо 3 вызов уже отпустил все блокировки.
+    crate::process::exit_point();
+}
+
+/// Точка входа инструкции `syscall` (записана в LSTAR).
+///
+/// Процессор не меняет стек, поэтому после `swapgs` стек пользователя
+/// сохраняется в [`CpuLocal`], а стек ядра берется оттуда же. `gs` сразу
+/// возвращается к пользовательской базе: поток может уснуть внутри вызова, а
+/// прерывания из кольца 3 `swapgs` не выполняют.
+///
+/// `sysretq` с неканоническим `rcx` вызвал бы #GP уже в кольце 0 на стеке
+/// пользователя. Здесь `rcx` - адрес после инструкции `syscall`, а процессу
+/// доступна только нижняя половина до `USER_END`, так что он всегда канонический.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "swapgs",
+        "mov gs:[{user_stack}], rsp",
+        "mov rsp, gs:[{kernel_stack}]",
+        "push qword ptr gs:[{user_stack}]",
+        "swapgs",
+        // RIP и RFLAGS пользователя.
+        "push rcx",
+        "push r11",
+        "push rax",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        // 10 значений на выровненном стеке ядра - стек выровнен по 16 байт.
+        "mov rdi, rsp",
+        "sti",
+        "call {handle}",
+        "cli",
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop rax",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "sysretq",
+        user_stack = const offset_of!(CpuLocal, user_stack),
+        kernel_stack = const offset_of!(CpuLocal, kernel_stack),
+        handle = sym handle,
+    );
+}
+
+/// Обработчик `int 0x80` для отладки: то же соглашение о регистрах, что и у
+/// `syscall` (а не 32-битное соглашение Linux). Работает на стеке ядра из
+/// TSS, поэтому `swapgs` не нужен.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn int80_entry() {
+    naked_asm!(
+        "push rcx",
+        "push r11",
+        "push rax",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        // Процессор выравнивает стек перед кадром прерывания (5 значений),
+        // вместе с 9 регистрами стек снова выровнен по 16 байт.
+        "mov rdi, rsp",
+        "sti",
+        "call {handle}",
+        "cli",
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop rax",
+        "pop r11",
+        "pop rcx",
+        "iretq",
+        handle = sym handle,
+    );
+}
@@ -0,0 +1,157 @@
+//! Обработчики системных вызовов.
+
+use super::{SyscallResult, UserPtr, copy_from_user, copy_path_from_user, copy_to_user};
+use crate::errno::Errno;
+use crate::fs::vfs::{self, OpenFlags, SeekFrom};
+use crate::process::{self, Pid, WaitTarget, fd::Fd};
+use crate::thread;
+use alloc::vec;
+use core::time::Duration;
+
+/// Максимальный размер одного `read`/`write`: больше за раз не копируется,
+/// а вызов возвращает, сколько успел (как частичное чтение или запись).
+const MAX_IO: usize = 64 * 1024;
+
+/// Флаг `wait4`: не ждать, если ни один потомок еще не завершился.
+const WNOHANG: i32 = 1;
+
+pub fn read(fd: Fd, buffer: UserPtr, len: usize) -> SyscallResult {
+    let file = current()?.with_files(|files| files.get(fd))?;
+
+    let mut data = vec![0; len.min(MAX_IO)];
+    let read = file.read(&mut data)?;
+    copy_to_user(buffer, &data[..read])?;
+    Ok(read as u64)
+}
+
+pub fn write(fd: Fd, buffer: UserPtr, len: usize) -> SyscallResult {
+    let file = current()?.with_files(|files| files.get(fd))?;
+
+    let mut data = vec![0; len.min(MAX_IO)];
+    copy_from_user(buffer, &mut data)?;
+    Ok(file.write(&data)? as u64)
+}
+
+pub fn open(path: UserPtr, flags: u32, mode: u32) -> SyscallResult {
+    let path = copy_path_from_user(path)?;
+    let file = vfs::open(&path, OpenFlags::from_bits(flags), (mode & 0o7777) as u16)?;
+
+    let fd = current()?.with_files(|files| files.insert(file))?;
+    Ok(fd.0.into())
+}
+
+pub fn close(fd: Fd) -> SyscallResult {
+    current()?.with_files(|files| files.close(fd))?;
+    Ok(0)
+}
+
+pub fn lseek(fd: Fd, offset: i64, whence: i32) -> SyscallResult {
+    let from = match whence {
+        0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| Errno::EINVAL)?),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return Err(Errno::EINVAL),
+    };
+
+    let file = current()?.with_files(|files| files.get(fd))?;
+    file.seek(from)
+}
+
+/// `getcwd(buf, size)`: возвращает длину пути вместе с нулевым байтом.
+pub fn getcwd(buffer: UserPtr, size: usize) -> SyscallResult {
+    let mut path = vfs::getcwd()?.into_bytes();
+    path.push(0);
+    if path.len() > size {
+        return Err(Errno::ERANGE);
+    }
+
+    copy_to_user(buffer, &path)?;
+    Ok(path.len() as u64)
+}
+
+pub fn chdir(path: UserPtr) -> SyscallResult {
+    vfs::chdir(&copy_path_from_user(path)?)?;
+    Ok(0)
+}
+
+pub fn mkdir(path: UserPtr, mode: u32) -> SyscallResult {
+    vfs::mkdir(&copy_path_from_user(path)?, (mode & 0o7777) as u16)?;
+    Ok(0)
+}
+
+pub fn rmdir(path: UserPtr) -> SyscallResult {
+    vfs::rmdir(&copy_path_from_user(path)?)?;
+    Ok(0)
+}
+
+pub fn unlink(path: UserPtr) -> SyscallResult {
+    vfs::unlink(&copy_path_from_user(path)?)?;
+    Ok(0)
+}
+
+pub fn sched_yield() -> SyscallResult {
+    thread::yield_now();
+    Ok(0)
+}
+
+/// `nanosleep(req, rem)`: `rem` не заполняется, так как сон не прерывается.
+pub fn nanosleep(request: UserPtr, _remaining: UserPtr) -> SyscallResult {
+    let mut timespec = [0; 16];
+    copy_from_user(request, &mut timespec)?;
+
+    let seconds = i64::from_le_bytes(timespec[..8].try_into().unwrap());
+    let nanos = i64::from_le_bytes(timespec[8..].try_into().unwrap());
+    if seconds < 0 || !(0..1_000_000_000).contains(&nanos) {
+        return Err(Errno::EINVAL);
+    }
+
+    thread::sleep(Duration::new(seconds as u64, nanos as u32));
+    Ok(0)
+}
+
+pub fn getpid() -> SyscallResult {
+    Ok(current()?.pid().as_u32().into())
+}
+
+/// Родитель процесса (0, если процесс принадлежит ядру).
+pub fn getppid() -> SyscallResult {
+    Ok(current()?.parent().map_or(0, |pid| pid.as_u32().into()))
+}
+
+/// `exit` и `exit_group`: потоков в смысле Linux у процесса пока нет, поэтому
+/// оба завершают весь процесс.
+pub fn exit(status: i32) -> SyscallResult {
+    process::exit(status & 0xFF)
+}
+
+/// `wait4(pid, status, options, rusage)`: поддерживаются `pid == -1` и
+/// `pid > 0`, а из флагов только `WNOHANG`. `rusage` не заполняется.
+pub fn wait4(pid: i32, status: UserPtr, options: i32, _rusage: UserPtr) -> SyscallResult {
+    let target = match pid {
+        -1 => WaitTarget::Any,
+        pid if pid > 0 => WaitTarget::Pid(Pid::from_u32(pid as u32)),
+        _ => return Err(Errno::EINVAL),
+    };
+    if options & !WNOHANG != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let result = if options & WNOHANG != 0 {
+        process::try_wait(target)?
+    } else {
+        Some(process::wait(target)?)
+    };
+
+    let Some((pid, code)) = result else {
+        return Ok(0);
+    };
+    if !status.is_null() {
+        // Кодировка как у `WEXITSTATUS`: код завершения во втором байте.
+        copy_to_user(status, &((code & 0xFF) << 8).to_le_bytes())?;
+    }
+    Ok(pid.as_u32().into())
+}
+
+fn current() -> Result<alloc::sync::Arc<process::Process>, Errno> {
+    process::current().ok_or(Errno::ESRCH)
+}
@@ -0,0 +1,237 @@
+//! Системные вызовы.
+//!
+//! Процесс входит в ядро инструкцией `syscall`: номер вызова в `rax`,
+//! аргументы в `rdi`, `rsi`, `rdx`, `r10`, `r8`, `r9`, результат в `rax`.
+//! Номера и соглашение совпадают с Linux x86_64, ошибки возвращаются как
+//! `-errno`. Для отладки тот же вызов можно сделать через `int 0x80`.
+//!
+//! Обработчики принимают типизированные аргументы ([`FromArg`]), а таблица
+//! [`TABLE`] собирается макросом `syscall!`, который разбирает сырые значения
+//! регистров.
+
+mod entry;
+mod handlers;
+pub mod number;
+
+use crate::errno::Errno;
+use crate::fs::vfs::PATH_MAX;
+use crate::gdt;
+use crate::process::{self, fd::Fd};
+use alloc::{string::String, vec::Vec};
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+
+/// Вектор прерывания для системных вызовов через `int 0x80`.
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+/// Количество записей в таблице системных вызовов.
+const MAX_SYSCALLS: usize = 512;
+
+pub type SyscallResult = Result<u64, Errno>;
+
+/// Преобразование сырого значения регистра в аргумент обработчика.
+pub trait FromArg: Sized {
+    fn from_arg(value: u64) -> Result<Self, Errno>;
+}
+
+impl FromArg for u64 {
+    fn from_arg(value: u64) -> Result<Self, Errno> {
+        Ok(value)
+    }
+}
+
+impl FromArg for usize {
+    fn from_arg(value: u64) -> Result<Self, Errno> {
+        Ok(value as usize)
+    }
+}
+
+impl FromArg for i64 {
+    fn from_arg(value: u64) -> Result<Self, Errno> {
+        Ok(value as i64)
+    }
+}
+
+/// Аргументы типа `int` занимают младшие 32 бита регистра.
+impl FromArg for i32 {
+    fn from_arg(value: u64) -> Result<Self, Errno> {
+        Ok(value as u32 as i32)
+    }
+}
+
+impl FromArg for u32 {
+    fn from_arg(value: u64) -> Result<Self, Errno> {
+        Ok(value as u32)
+    }
+}
+
+impl FromArg for Fd {
+    fn from_arg(value: u64) -> Result<Self, Errno> {
+        u32::try_from(value as u32 as i32)
+            .map(Fd)
+            .map_err(|_| Errno::EBADF)
+    }
+}
+
+/// Адрес в памяти процесса. Сам по себе он не проверяется на отображение:
+/// это делают [`copy_from_user`] и [`copy_to_user`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserPtr(VirtAddr);
+
+impl UserPtr {
+    pub fn addr(self) -> VirtAddr {
+        self.0
+    }
+
+    pub fn is_null(self) -> bool {
+        self.0.is_null()
+    }
+}
+
+impl FromArg for UserPtr {
+    fn from_arg(value: u64) -> Result<Self, Errno> {
+        VirtAddr::try_new(value)
+            .map(UserPtr)
+            .map_err(|_| Errno::EFAULT)
+    }
+}
+
+/// Копирует память текущего процесса по адресу `ptr` в `buffer`.
+pub fn copy_from_user(ptr: UserPtr, buffer: &mut [u8]) -> Result<(), Errno> {
+    let process = process::current().ok_or(Errno::ESRCH)?;
+    process.with_address_space(|space| space.read(ptr.0, buffer))?
+}
+
+/// Копирует `data` в память текущего процесса по адресу `ptr`.
+pub fn copy_to_user(ptr: UserPtr, data: &[u8]) -> Result<(), Errno> {
+    let process = process::current().ok_or(Errno::ESRCH)?;
+    process.with_address_space(|space| space.write(ptr.0, data))?
+}
+
+/// Копирует из памяти текущего процесса строку с нулевым байтом на конце
+/// (путь). Строки длиннее [`PATH_MAX`] дают `ENAMETOOLONG`.
+pub fn copy_path_from_user(ptr: UserPtr) -> Result<String, Errno> {
+    let mut path = Vec::new();
+    let mut address = ptr.0;
+
+    // Читаем до границы страницы, чтобы не задеть следующую, если она не
+    // отображена, а строка уже закончилась.
+    while path.len() <= PATH_MAX {
+        let chunk = (4096 - usize::from(address.page_offset())).min(PATH_MAX + 1 - path.len());
+        let start = path.len();
+        path.resize(start + chunk, 0);
+        copy_from_user(UserPtr(address), &mut path[start..])?;
+
+        if let Some(end) = path[start..].iter().position(|&byte| byte == 0) {
+            path.truncate(start + end);
+            return String::from_utf8(path).map_err(|_| Errno::EINVAL);
+        }
+        address += chunk as u64;
+    }
+
+    Err(Errno::ENAMETOOLONG)
+}
+
+/// Запись таблицы системных вызовов.
+#[derive(Clone, Copy)]
+struct Syscall {
+    number: u64,
+    name: &'static str,
+    handler: fn(&[u64; 6]) -> SyscallResult,
+}
+
+/// Создает запись таблицы: `syscall!(номер, обработчик, (индекс: тип, ...))`.
+/// Каждый аргумент берется из регистра с указанным индексом и
+/// преобразуется через [`FromArg`]; ошибка преобразования сразу становится
+/// результатом вызова.
+macro_rules! syscall {
+    ($number:expr, $handler:path, ($($index:tt: $ty:ty),* $(,)?)) => {
+        Syscall {
+            number: $number,
+            name: stringify!($handler),
+            handler: |_args| $handler($(<$ty as FromArg>::from_arg(_args[$index])?),*),
+        }
+    };
+}
+
+static TABLE: [Option<Syscall>; MAX_SYSCALLS] = build_table(&[
+    syscall!(number::READ, handlers::read, (0: Fd, 1: UserPtr, 2: usize)),
+    syscall!(number::WRITE, handlers::write, (0: Fd, 1: UserPtr, 2: usize)),
+    syscall!(number::OPEN, handlers::open, (0: UserPtr, 1: u32, 2: u32)),
+    syscall!(number::CLOSE, handlers::close, (0: Fd)),
+    syscall!(number::LSEEK, handlers::lseek, (0: Fd, 1: i64, 2: i32)),
+    syscall!(number::SCHED_YIELD, handlers::sched_yield, ()),
+    syscall!(number::NANOSLEEP, handlers::nanosleep, (0: UserPtr, 1: UserPtr)),
+    syscall!(number::GETPID, handlers::getpid, ()),
+    syscall!(number::EXIT, handlers::exit, (0: i32)),
+    syscall!(number::WAIT4, handlers::wait4, (0: i32, 1: UserPtr, 2: i32, 3: UserPtr)),
+    syscall!(number::GETCWD, handlers::getcwd, (0: UserPtr, 1: usize)),
+    syscall!(number::CHDIR, handlers::chdir, (0: UserPtr)),
+    syscall!(number::MKDIR, handlers::mkdir, (0: UserPtr, 1: u32)),
+    syscall!(number::RMDIR, handlers::rmdir, (0: UserPtr)),
+    syscall!(number::UNLINK, handlers::unlink, (0: UserPtr)),
+    syscall!(number::GETPPID, handlers::getppid, ()),
+    syscall!(number::EXIT_GROUP, handlers::exit, (0: i32)),
+]);
+
+const fn build_table(entries: &[Syscall]) -> [Option<Syscall>; MAX_SYSCALLS] {
+    let mut table = [None; MAX_SYSCALLS];
+    let mut index = 0;
+    while index < entries.len() {
+        let entry = entries[index];
+        assert!(table[entry.number as usize].is_none(), "duplicate syscall");
+        table[entry.number as usize] = Some(entry);
+        index += 1;
+    }
+    table
+}
+
+/// Выполняет системный вызов `number` и возвращает значение для `rax`.
+pub fn dispatch(number: u64, args: [u64; 6]) -> i64 {
+    let result = usize::try_from(number)
+        .ok()
+        .and_then(|number| TABLE.get(number).copied().flatten())
+        .map_or(Err(Errno::ENOSYS), |syscall| (syscall.handler)(&args));
+
+    match result {
+        Ok(value) => value as i64,
+        Err(errno) => errno.as_syscall_return(),
+    }
+}
+
+/// Возвращает имя системного вызова по номеру (для отладки).
+pub fn name(number: u64) -> Option<&'static str> {
+    let syscall = TABLE.get(usize::try_from(number).ok()?)?.as_ref()?;
+    debug_assert_eq!(syscall.number, number);
+    Some(syscall.name)
+}
+
+/// Включает инструкции `syscall`/`sysret`. Вызывается после [`gdt::init`].
+pub fn init() {
+    Star::write(
+        gdt::user_code_selector(),
+        gdt::user_data_selector(),
+        gdt::kernel_code_selector(),
+        gdt::kernel_data_selector(),
+    )
+    .expect("GDT layout is incompatible with sysret");
+
+    LStar::write(VirtAddr::new(entry::syscall_entry as usize as u64));
+    // Точка входа начинает работу с выключенными прерываниями и сброшенными
+    // флагами направления и трассировки.
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK
+            | RFlags::NESTED_TASK,
+    );
+
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Адрес обработчика `int 0x80` для таблицы прерываний.
+pub(crate) fn int80_handler() -> VirtAddr {
+    VirtAddr::new(entry::int80_entry as usize as u64)
+}
@@ -0,0 +1,19 @@
+//! Номера системных вызовов (совпадают с Linux x86_64).
+
+pub const READ: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const OPEN: u64 = 2;
+pub const CLOSE: u64 = 3;
+pub const LSEEK: u64 = 8;
+pub const SCHED_YIELD: u64 = 24;
+pub const NANOSLEEP: u64 = 35;
+pub const GETPID: u64 = 39;
+pub const EXIT: u64 = 60;
+pub const WAIT4: u64 = 61;
+pub const GETCWD: u64 = 79;
+pub const CHDIR: u64 = 80;
+pub const MKDIR: u64 = 83;
+pub const RMDIR: u64 = 84;
+pub const UNLINK: u64 = 87;
+pub const GETPPID: u64 = 110;
+pub const EXIT_GROUP: u64 = 231;
@@ -1,70 +1,367 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+//! Данный модуль содержит исполнитель ассинхронных задач.
+//!
+//! Исполнитель состоит из нескольких обработчиков (worker'ов), каждый из
+//! которых работает в своем потоке ядра. У каждого обработчика своя очередь
+//! готовых задач; обработчик без работы забирает задачи из чужих очередей
+//! (work stealing), а если работы нет совсем, то засыпает до `unpark`.
+//!
+//! Ядро пока работает на одном процессоре (планировщик потоков, GDT и TSS
+//! общие), поэтому обработчики делят его между собой через потоки.
+//!
+//! Внутри очереди обработчика задачи разделены по [`Priority`]: сначала
+//! выполняются более приоритетные, с защитой низких уровней от голодания.
+//!
+//! Для каждой задачи ведется статистика опросов и пробуждений, которую можно
+//! получить через [`Executor::tasks`] или вывести таблицей через [`dump_tasks`].
+
+use super::join::{self, JoinHandle};
+use super::run_queue::PriorityQueues;
+use super::stats::{self, TaskInfo, TaskState, TaskStats};
+use super::{Priority, Task, TaskId};
+use crate::thread::{self, ThreadId};
+use crate::time;
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::future::Future;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use core::task::{Context, Poll, Waker};
-use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+
+/// Значение `Worker::thread`, когда обработчик работает не в потоке.
+const NO_THREAD: u64 = u64::MAX;
+
+/// Все созданные исполнители (для [`dump_tasks`]).
+static RUNTIMES: Mutex<Vec<Weak<Runtime>>> = Mutex::new(Vec::new());
+
+/// Состояние одного обработчика.
+struct Worker {
+    queue: PriorityQueues,
+    /// Поток, в котором работает обработчик.
+    thread: AtomicU64,
+    /// Запущен ли цикл обработчика.
+    running: AtomicBool,
+    /// Спит ли обработчик в ожидании работы.
+    parked: AtomicBool,
+}
+
+impl Worker {
+    fn new() -> Self {
+        Self {
+            queue: PriorityQueues::new(),
+            thread: AtomicU64::new(NO_THREAD),
+            running: AtomicBool::new(false),
+            parked: AtomicBool::new(false),
+        }
+    }
+
+    /// Будит поток обработчика. Обработчик без потока ждет в `hlt`, и его
+    /// будит само прерывание, из которого пришло пробуждение.
+    fn unpark(&self) {
+        let thread = self.thread.load(Ordering::Acquire);
+        if thread != NO_THREAD {
+            thread::unpark(ThreadId::from_u64(thread));
+        }
+    }
+}
+
+/// Задача вместе с её waker'ом.
+struct TaskSlot {
+    /// `None`, когда задача уже завершилась.
+    task: Mutex<Option<Task>>,
+    name: Option<Cow<'static, str>>,
+    location: &'static Location<'static>,
+    task_waker: Arc<TaskWaker>,
+    waker: Waker,
+}
+
+/// Общее состояние исполнителя.
+struct Runtime {
+    workers: Box<[Worker]>,
+    tasks: Mutex<BTreeMap<TaskId, Arc<TaskSlot>>>,
+    /// Количество живых задач (емкость каждой очереди не меньше этого числа).
+    task_count: AtomicUsize,
+}
+
+impl Runtime {
+    fn spawn(self: &Arc<Self>, task: Task, local: usize) {
+        let task_id = task.id;
+        let priority = task.priority;
+        let home = task.affinity.unwrap_or(local) % self.workers.len();
+
+        // Новая задача сразу стоит в очереди.
+        let task_waker = Arc::new(TaskWaker {
+            task_id,
+            runtime: Arc::downgrade(self),
+            queued: AtomicBool::new(true),
+            home: AtomicUsize::new(home),
+            pinned: task.affinity.is_some(),
+            priority,
+            stats: TaskStats::new(),
+        });
+        let slot = Arc::new(TaskSlot {
+            name: task.name.clone(),
+            location: task.location,
+            task: Mutex::new(Some(task)),
+            waker: Waker::from(task_waker.clone()),
+            task_waker,
+        });
+
+        if self.tasks.lock().insert(task_id, slot).is_some() {
+            panic!("task with same ID already in tasks")
+        }
+
+        // Задача может оказаться в любой очереди (из-за пробуждения на
+        // другом обработчике), поэтому расширяются все очереди.
+        let tasks = self.task_count.fetch_add(1, Ordering::Relaxed) + 1;
+        for worker in self.workers.iter() {
+            worker.queue.reserve(tasks);
+        }
+
+        self.enqueue(task_id, priority, home);
+    }
+
+    /// Ставит задачу в очередь обработчика `home` и будит того, кто её выполнит.
+    /// Может вызываться из прерываний.
+    fn enqueue(&self, task_id: TaskId, priority: Priority, home: usize) {
+        let worker = &self.workers[home];
+        if !worker.queue.push(task_id, priority) {
+            // Недостижимо, пока соблюдается инвариант очереди, но из
+            // прерывания лучше потерять пробуждение, чем паниковать.
+            return;
+        }
+
+        if worker.parked.load(Ordering::Acquire) {
+            worker.unpark();
+        } else if let Some(idle) = self
+            .workers
+            .iter()
+            .find(|worker| worker.parked.load(Ordering::Acquire))
+        {
+            // Свой обработчик занят - пусть задачу украдет простаивающий.
+            idle.unpark();
+        }
+    }
+
+    /// Возвращает индекс обработчика, работающего в текущем потоке
+    /// (или 0, если текущий поток не обработчик этого исполнителя).
+    fn local_worker(&self) -> usize {
+        let Some(thread) = thread::current() else {
+            return 0;
+        };
+
+        self.workers
+            .iter()
+            .position(|worker| worker.thread.load(Ordering::Acquire) == thread.as_u64())
+            .unwrap_or(0)
+    }
+
+    /// Берет самую приоритетную задачу из своих очередей или крадет из чужих.
+    fn find_task(&self, index: usize) -> Option<TaskId> {
+        if let Some(task_id) = self.workers[index].queue.pop() {
+            return Some(task_id);
+        }
+
+        let count = self.workers.len();
+        (1..count).find_map(|offset| self.workers[(index + offset) % count].queue.pop())
+    }
+
+    fn has_work(&self) -> bool {
+        self.workers.iter().any(|worker| !worker.queue.is_empty())
+    }
+
+    fn task_infos(&self) -> Vec<TaskInfo> {
+        let slots: Vec<_> = self.tasks.lock().values().cloned().collect();
+
+        slots
+            .iter()
+            .map(|slot| {
+                let waker = &slot.task_waker;
+                let state = if slot.task.try_lock().is_none() {
+                    TaskState::Running
+                } else if waker.queued.load(Ordering::Acquire) {
+                    TaskState::Queued
+                } else {
+                    TaskState::Idle
+                };
+
+                TaskInfo::new(
+                    waker.task_id.as_u64(),
+                    slot.name.clone(),
+                    slot.location,
+                    waker.priority,
+                    state,
+                    &waker.stats,
+                )
+            })
+            .collect()
+    }
+}
 
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    runtime: Arc<Runtime>,
+    /// Индекс обработчика, которым является данный исполнитель.
+    index: usize,
 }
 
 impl Executor {
+    /// Создает исполнитель с одним обработчиком.
     pub fn new() -> Self {
-        Self {
-            tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
+        Self::with_workers(1)
+    }
+
+    /// Создает исполнитель с `workers` обработчиками. Возвращается обработчик 0,
+    /// остальные можно получить через [`Executor::worker`] и запустить в
+    /// других потоках.
+    pub fn with_workers(workers: usize) -> Self {
+        assert!(workers > 0, "executor needs at least one worker");
+
+        let runtime = Arc::new(Runtime {
+            workers: (0..workers)
+                .map(|_| Worker::new())
+                .collect::<Vec<_>>()
+                .into(),
+            tasks: Mutex::new(BTreeMap::new()),
+            task_count: AtomicUsize::new(0),
+        });
+
+        let mut runtimes = RUNTIMES.lock();
+        runtimes.retain(|runtime| runtime.strong_count() > 0);
+        runtimes.push(Arc::downgrade(&runtime));
+
+        Self { runtime, index: 0 }
+    }
+
+    /// Возвращает обработчик с индексом `index` того же исполнителя.
+    pub fn worker(&self, index: usize) -> Executor {
+        assert!(
+            index < self.runtime.workers.len(),
+            "worker index out of range"
+        );
+
+        Executor {
+            runtime: self.runtime.clone(),
+            index,
         }
     }
 
+    pub fn worker_count(&self) -> usize {
+        self.runtime.workers.len()
+    }
+
     pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task_id, task).is_some() {
-            panic!("task with same ID already in tasks")
+        self.runtime.spawn(task, self.index);
+    }
+
+    /// Возвращает снимок сведений обо всех живых задачах исполнителя.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.runtime.task_infos()
+    }
+
+    /// Возвращает [`Spawner`], через который задачи могут создавать новые задачи.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            runtime: self.runtime.clone(),
         }
-        self.task_queue.push(task_id).expect("queue full");
     }
 
+    /// Запускает цикл обработчика в текущем потоке.
     pub fn run(&mut self) -> ! {
+        let worker = &self.runtime.workers[self.index];
+        assert!(
+            !worker.running.swap(true, Ordering::AcqRel),
+            "executor worker is already running"
+        );
+
+        if let Some(thread) = thread::current() {
+            worker.thread.store(thread.as_u64(), Ordering::Release);
+        }
+
         loop {
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
     }
 
+    /// Если работы нет ни в одной очереди, то блокирует поток обработчика
+    /// (или останавливает процессор, если потоков ещё нет) до пробуждения.
     fn sleep_if_idle(&self) {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
+        let worker = &self.runtime.workers[self.index];
+
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
-        } else {
-            interrupts::enable();
+        worker.parked.store(true, Ordering::SeqCst);
+
+        if !self.runtime.has_work() {
+            if thread::current().is_some() {
+                thread::park();
+            } else {
+                enable_and_hlt();
+                interrupts::disable();
+            }
         }
+
+        worker.parked.store(false, Ordering::SeqCst);
+        interrupts::enable();
     }
 
     fn run_ready_tasks(&mut self) {
-        while let Some(task_id) = self.task_queue.pop() {
-            let task = match self.tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // Задача больше не существует.
-            };
-
-            let waker = self
-                .waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new_waker(task_id, self.task_queue.clone()));
-
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    // Задача выполнена -> удалите её и кэшированный waker.
-                    self.tasks.remove(&task_id);
-                    self.waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+        while let Some(task_id) = self.runtime.find_task(self.index) {
+            self.poll_task(task_id);
+        }
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
+        let slot = match self.runtime.tasks.lock().get(&task_id) {
+            Some(slot) => slot.clone(),
+            None => return, // Задача больше не существует.
+        };
+
+        // Задачу опрашивает другой обработчик: вернем её в очередь.
+        let Some(mut guard) = slot.task.try_lock() else {
+            self.runtime.workers[self.index]
+                .queue
+                .push(task_id, slot.task_waker.priority);
+            return;
+        };
+        let Some(task) = guard.as_mut() else {
+            return;
+        };
+
+        // Снимаем флаг до опроса, чтобы пробуждения во время опроса
+        // снова поставили задачу в очередь.
+        slot.task_waker.queued.store(false, Ordering::Release);
+        if !slot.task_waker.pinned {
+            slot.task_waker.home.store(self.index, Ordering::Relaxed);
+        }
+
+        let mut context = Context::from_waker(&slot.waker);
+        let start = time::tsc();
+        let poll = task.poll(&mut context);
+        slot.task_waker
+            .stats
+            .record_poll(time::tsc().wrapping_sub(start));
+
+        match poll {
+            Poll::Ready(()) => {
+                // Задача выполнена -> удалите её и её waker. Флаг очереди
+                // остается поднятым, и оставшиеся копии waker ничего не
+                // делают.
+                slot.task_waker.queued.store(true, Ordering::Release);
+                *guard = None;
+                drop(guard);
+
+                self.runtime.tasks.lock().remove(&task_id);
+                self.runtime.task_count.fetch_sub(1, Ordering::Relaxed);
             }
+            Poll::Pending => {}
         }
     }
 }
@@ -75,21 +372,119 @@ impl Default for Executor {
     }
 }
 
+/// Клонируемый дескриптор исполнителя, через который можно создавать задачи,
+/// в том числе изнутри уже работающих задач и из других потоков.
+#[derive(Clone)]
+pub struct Spawner {
+    runtime: Arc<Runtime>,
+}
+
+impl Spawner {
+    /// Создает задачу и возвращает [`JoinHandle`] для получения её результата.
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with(None, Priority::Normal, future)
+    }
+
+    /// То же, что [`Spawner::spawn`], но с заданным приоритетом задачи.
+    #[track_caller]
+    pub fn spawn_with_priority<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with(None, priority, future)
+    }
+
+    /// То же, что [`Spawner::spawn`], но задача получает имя.
+    #[track_caller]
+    pub fn spawn_named<F>(
+        &self,
+        name: impl Into<Cow<'static, str>>,
+        future: F,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with(Some(name.into()), Priority::Normal, future)
+    }
+
+    #[track_caller]
+    fn spawn_with<F>(
+        &self,
+        name: Option<Cow<'static, str>>,
+        priority: Priority,
+        future: F,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (future, handle) = join::pair(future);
+
+        let mut task = Task::new(future).with_priority(priority);
+        task.name = name;
+        self.spawn_task(task);
+
+        handle
+    }
+
+    /// Передает готовую задачу исполнителю (обработчику текущего потока,
+    /// если у задачи нет подсказки о привязке).
+    pub fn spawn_task(&self, task: Task) {
+        self.runtime.spawn(task, self.runtime.local_worker());
+    }
+
+    /// Возвращает снимок сведений обо всех живых задачах исполнителя.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.runtime.task_infos()
+    }
+}
+
+/// Выводит таблицу задач всех исполнителей в последовательный порт.
+pub fn dump_tasks() {
+    let runtimes: Vec<_> = RUNTIMES.lock().iter().filter_map(Weak::upgrade).collect();
+
+    let mut tasks: Vec<_> = runtimes
+        .iter()
+        .flat_map(|runtime| runtime.task_infos())
+        .collect();
+    stats::print_table(&mut tasks);
+}
+
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    /// Слабая ссылка: исполнитель хранит waker'ы своих задач, и сильная
+    /// ссылка не дала бы освободить исполнитель с незавершенными задачами.
+    runtime: Weak<Runtime>,
+    /// Стоит ли задача уже в очереди.
+    queued: AtomicBool,
+    /// Обработчик, в очередь которого ставится задача при пробуждении.
+    home: AtomicUsize,
+    /// Задан ли `home` подсказкой о привязке (тогда он не меняется).
+    pinned: bool,
+    priority: Priority,
+    stats: TaskStats,
 }
 
 impl TaskWaker {
-    fn new_waker(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue,
-        }))
-    }
-
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        self.stats.record_wake();
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // Исполнитель уже освобожден вместе с задачей.
+        let Some(runtime) = self.runtime.upgrade() else {
+            return;
+        };
+
+        let home = self.home.load(Ordering::Relaxed);
+        runtime.enqueue(self.task_id, self.priority, home);
     }
 }
 
@@ -0,0 +1,168 @@
+//! Данный модуль содержит [`JoinHandle`], который позволяет дождаться
+//! результата задачи или отменить её.
+
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Ошибка ожидания задачи.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// Задача была отменена через [`JoinHandle::abort`].
+    Cancelled,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => f.write_str("task was cancelled"),
+        }
+    }
+}
+
+struct JoinState<T> {
+    output: Option<Result<T, JoinError>>,
+    finished: bool,
+    aborted: bool,
+    /// Waker того, кто ждет JoinHandle.
+    join_waker: Option<Waker>,
+    /// Waker самой задачи, чтобы разбудить её при отмене.
+    task_waker: Option<Waker>,
+}
+
+type SharedState<T> = Arc<Mutex<JoinState<T>>>;
+
+fn lock<T, R>(state: &SharedState<T>, f: impl FnOnce(&mut JoinState<T>) -> R) -> R {
+    without_interrupts(|| f(&mut state.lock()))
+}
+
+/// Создает пару из future, который нужно запустить как задачу, и
+/// [`JoinHandle`] для ожидания его результата.
+pub(crate) fn pair<F>(future: F) -> (JoinFuture<F>, JoinHandle<F::Output>)
+where
+    F: Future,
+{
+    let state = Arc::new(Mutex::new(JoinState {
+        output: None,
+        finished: false,
+        aborted: false,
+        join_waker: None,
+        task_waker: None,
+    }));
+
+    let future = JoinFuture {
+        future: Some(future),
+        state: state.clone(),
+    };
+
+    (future, JoinHandle { state })
+}
+
+/// Обертка над future задачи, которая сохраняет результат и проверяет отмену.
+pub(crate) struct JoinFuture<F: Future> {
+    future: Option<F>,
+    state: SharedState<F::Output>,
+}
+
+impl<F: Future> JoinFuture<F> {
+    fn finish(&mut self, output: Result<F::Output, JoinError>) {
+        let waker = lock(&self.state, |state| {
+            state.output = Some(output);
+            state.finished = true;
+            state.task_waker = None;
+            state.join_waker.take()
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<F: Future> Future for JoinFuture<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: внутренний future не перемещается, пока он `Some`.
+        let this = unsafe { self.get_unchecked_mut() };
+
+        let aborted = lock(&this.state, |state| {
+            if !state.aborted {
+                state.task_waker = Some(cx.waker().clone());
+            }
+            state.aborted
+        });
+        if aborted {
+            this.future = None;
+            this.finish(Err(JoinError::Cancelled));
+            return Poll::Ready(());
+        }
+
+        let future = match this.future.as_mut() {
+            Some(future) => unsafe { Pin::new_unchecked(future) },
+            None => return Poll::Ready(()),
+        };
+
+        match future.poll(cx) {
+            Poll::Ready(output) => {
+                this.future = None;
+                this.finish(Ok(output));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Дескриптор задачи, созданной через [`Spawner::spawn`](super::executor::Spawner::spawn).
+///
+/// Сам является future, который возвращает результат задачи. Удаление
+/// дескриптора не отменяет задачу (она продолжает работать отдельно).
+pub struct JoinHandle<T> {
+    state: SharedState<T>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Отменяет задачу. Её future будет удален при следующем опросе.
+    pub fn abort(&self) {
+        let waker = lock(&self.state, |state| {
+            if state.finished {
+                return None;
+            }
+            state.aborted = true;
+            state.task_waker.take()
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Возвращает `true`, если задача завершилась (или была отменена).
+    pub fn is_finished(&self) -> bool {
+        lock(&self.state, |state| state.finished)
+    }
+
+    /// Отсоединяет задачу: она продолжит работать, а её результат будет отброшен.
+    pub fn detach(self) {}
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        lock(&self.state, |state| match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.join_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+}
@@ -1,7 +1,9 @@
 // Данный модуль содержит логику для ассинхронной обработки клавиатуры.
 
+use crate::deferred::Work;
 use crate::{print, println};
 use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::{
     pin::Pin,
     task::{Context, Poll},
@@ -11,27 +13,39 @@ use futures_util::{
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
-use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts};
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1, layouts};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
+/// Количество скан-кодов, потерянных из-за переполненной или неинициализированной очереди.
+static DROPPED_SCANCODES: AtomicU64 = AtomicU64::new(0);
+
+/// Нижняя половина прерывания клавиатуры: будит задачу, читающую скан-коды.
+static KEYBOARD_WORK: Work = Work::new("keyboard", || WAKER.wake());
 
 /// Вызывается обработчиком прерываний с клавиатуры.
 /// Не должен блокировать или выделять.
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if queue.push(scancode).is_err() {
-            println!("WARNING: scancode queue full; dropping keyboard input");
-        } else {
-            WAKER.wake();
-        }
-    } else {
-        println!("WARNING: scancode queue uninitialized");
+    let pushed = matches!(SCANCODE_QUEUE.try_get(), Ok(queue) if queue.push(scancode).is_ok());
+    if !pushed {
+        // Предупреждение выводится позже из ScancodeStream, так как
+        // печать из прерывания требует блокировки фреймбуфера.
+        DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
     }
+
+    // Задача, читающая клавиатуру, печатает через println! и берет блокировку
+    // фреймбуфера, поэтому будится из kworker, а не из прерывания.
+    KEYBOARD_WORK.schedule();
+}
+
+/// Возвращает общее количество потерянных скан-кодов.
+pub fn dropped_scancodes() -> u64 {
+    DROPPED_SCANCODES.load(Ordering::Relaxed)
 }
 
 pub struct ScancodeStream {
-    _private: (),
+    /// Значение `DROPPED_SCANCODES`, о котором уже было сообщено.
+    reported_dropped: u64,
 }
 
 impl ScancodeStream {
@@ -40,15 +54,29 @@ impl ScancodeStream {
             .try_init_once(|| ArrayQueue::new(100))
             .expect("ScancodeStream::new should only be called once");
 
-        Self { _private: () }
+        Self {
+            reported_dropped: 0,
+        }
+    }
+
+    fn report_dropped(&mut self) {
+        let dropped = dropped_scancodes();
+        if dropped != self.reported_dropped {
+            println!(
+                "WARNING: scancode queue full; dropped {} keyboard scancodes",
+                dropped - self.reported_dropped
+            );
+            self.reported_dropped = dropped;
+        }
     }
 }
 
 impl Stream for ScancodeStream {
     type Item = u8;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let queue = SCANCODE_QUEUE.try_get().expect("not initialized");
+        self.report_dropped();
 
         // Быстрый путь.
         if let Some(scancode) = queue.pop() {
@@ -85,6 +113,11 @@ pub async fn print_keypresses() {
             && let Some(key) = keyboard.process_keyevent(key_event)
         {
             match key {
+                // F12 - вывести таблицу задач и отложенных работ в последовательный порт.
+                DecodedKey::RawKey(KeyCode::F12) => {
+                    super::executor::dump_tasks();
+                    crate::deferred::dump_stats();
+                }
                 DecodedKey::Unicode(character) => print!("{}", character),
                 DecodedKey::RawKey(key) => print!("{:?}", key),
             }
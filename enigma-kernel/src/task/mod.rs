@@ -1,32 +1,87 @@
-use alloc::boxed::Box;
+use alloc::{borrow::Cow, boxed::Box};
 use core::sync::atomic::{AtomicU64, Ordering};
 use core::{
     future::Future,
+    panic::Location,
     pin::Pin,
     task::{Context, Poll},
 };
 
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+mod run_queue;
+pub mod stats;
+pub mod timer;
 
 pub struct Task {
-    future: Pin<Box<dyn Future<Output = ()>>>,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
     id: TaskId,
+    name: Option<Cow<'static, str>>,
+    /// Место в коде, где была создана задача.
+    location: &'static Location<'static>,
+    /// Предпочтительный обработчик (worker) исполнителя.
+    affinity: Option<usize>,
+    priority: Priority,
 }
 
 impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+    #[track_caller]
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
         Self {
             future: Box::pin(future),
             id: TaskId::new(),
+            name: None,
+            location: Location::caller(),
+            affinity: None,
+            priority: Priority::Normal,
         }
     }
 
+    /// Задает имя задачи, которое видно в таблице задач исполнителя.
+    pub fn with_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Задает приоритет задачи (по умолчанию [`Priority::Normal`]).
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Подсказывает исполнителю, на каком обработчике лучше выполнять задачу.
+    /// Это только подсказка: простаивающие обработчики всё равно могут её забрать.
+    pub fn with_affinity(mut self, worker: usize) -> Self {
+        self.affinity = Some(worker);
+        self
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
 }
 
+/// Приоритет задачи. Исполнитель всегда выбирает готовую задачу с самым
+/// высоким приоритетом, но задача, которую долго пропускали ради более
+/// приоритетных, выполняется вне очереди, поэтому низкие уровни не голодают.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Фоновая работа.
+    Low = 0,
+    #[default]
+    Normal = 1,
+    /// Задачи, чувствительные к задержкам (ввод, звук).
+    High = 2,
+    /// Задачи, которые должны выполняться раньше всех остальных.
+    Realtime = 3,
+}
+
+impl Priority {
+    /// Количество уровней приоритета.
+    pub(crate) const COUNT: usize = 4;
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
@@ -35,4 +90,8 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    fn as_u64(self) -> u64 {
+        self.0
+    }
 }
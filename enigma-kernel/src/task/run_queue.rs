@@ -0,0 +1,156 @@
+//! Данный модуль содержит очередь готовых задач исполнителя.
+//!
+//! Каждая задача находится в очереди не более одного раза (повторные
+//! пробуждения уже стоящей в очереди задачи отбрасываются через флаг
+//! `queued` в её waker'е). Поэтому емкости, равной числу живых задач,
+//! всегда достаточно: очередь расширяется при создании задач (где можно
+//! выделять память), а `push` из прерывания никогда не выделяет память
+//! и не паникует.
+//!
+//! У каждого обработчика по одной такой очереди на каждый [`Priority`],
+//! они объединены в [`PriorityQueues`].
+
+use super::{Priority, TaskId};
+use core::sync::atomic::{AtomicU32, Ordering};
+use crossbeam_queue::ArrayQueue;
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Начальная емкость очереди.
+const INITIAL_CAPACITY: usize = 64;
+
+/// Сколько раз подряд непустой уровень может быть пропущен ради более
+/// приоритетных задач, прежде чем его задача будет выполнена вне очереди.
+pub(crate) const STARVATION_LIMIT: u32 = 16;
+
+pub(crate) struct RunQueue {
+    queue: RwLock<ArrayQueue<TaskId>>,
+}
+
+impl RunQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            queue: RwLock::new(ArrayQueue::new(INITIAL_CAPACITY)),
+        }
+    }
+
+    /// Добавляет задачу в очередь. Может вызываться из прерываний.
+    ///
+    /// Возвращает `false`, если места нет, что возможно только при
+    /// нарушении инварианта "одна задача - одно место в очереди".
+    pub(crate) fn push(&self, task_id: TaskId) -> bool {
+        self.with_queue(|queue| queue.push(task_id).is_ok())
+    }
+
+    pub(crate) fn pop(&self) -> Option<TaskId> {
+        self.with_queue(ArrayQueue::pop)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.with_queue(ArrayQueue::is_empty)
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.with_queue(ArrayQueue::capacity)
+    }
+
+    /// Выполняет `f` под блокировкой на чтение. Прерывания выключены, чтобы
+    /// обработчик не был вытеснен с блокировкой: иначе `reserve` в другом
+    /// потоке ждал бы её с выключенными прерываниями вечно.
+    fn with_queue<R>(&self, f: impl FnOnce(&ArrayQueue<TaskId>) -> R) -> R {
+        without_interrupts(|| f(&self.queue.read()))
+    }
+
+    /// Гарантирует, что в очереди хватит места для `tasks` задач.
+    /// Не должна вызываться из прерываний, так как выделяет память.
+    pub(crate) fn reserve(&self, tasks: usize) {
+        if tasks <= self.capacity() {
+            return;
+        }
+
+        let mut capacity = self.capacity();
+        while capacity < tasks {
+            capacity *= 2;
+        }
+        let grown = ArrayQueue::new(capacity);
+
+        // Прерывания выключены, чтобы waker из прерывания не ждал
+        // блокировку, которую держит прерванный им код.
+        without_interrupts(|| {
+            let mut queue = self.queue.write();
+            if queue.capacity() >= capacity {
+                return;
+            }
+            while let Some(task_id) = queue.pop() {
+                let _ = grown.push(task_id);
+            }
+            *queue = grown;
+        });
+    }
+}
+
+/// Очереди готовых задач одного обработчика, по одной на приоритет.
+///
+/// `pop` всегда берет задачу с самого высокого непустого уровня, но каждый
+/// пропуск непустого нижнего уровня увеличивает его счетчик "голодания".
+/// Когда счетчик достигает [`STARVATION_LIMIT`], задача нижнего уровня
+/// выполняется вне очереди, поэтому фоновые задачи не голодают бесконечно.
+pub(crate) struct PriorityQueues {
+    levels: [RunQueue; Priority::COUNT],
+    starved: [AtomicU32; Priority::COUNT],
+}
+
+impl PriorityQueues {
+    pub(crate) fn new() -> Self {
+        Self {
+            levels: core::array::from_fn(|_| RunQueue::new()),
+            starved: core::array::from_fn(|_| AtomicU32::new(0)),
+        }
+    }
+
+    /// Добавляет задачу в очередь её приоритета. Может вызываться из прерываний.
+    pub(crate) fn push(&self, task_id: TaskId, priority: Priority) -> bool {
+        self.levels[priority as usize].push(task_id)
+    }
+
+    pub(crate) fn pop(&self) -> Option<TaskId> {
+        let top = (0..Priority::COUNT)
+            .rev()
+            .find(|&level| !self.levels[level].is_empty())?;
+
+        // Уровни ниже `top` пропускаются: самый долго ждущий из них
+        // (начиная с нижнего) получает ход, если исчерпал лимит.
+        let mut chosen = top;
+        for level in 0..top {
+            if self.levels[level].is_empty() {
+                self.starved[level].store(0, Ordering::Relaxed);
+                continue;
+            }
+
+            let starved = self.starved[level].fetch_add(1, Ordering::Relaxed) + 1;
+            if starved >= STARVATION_LIMIT && chosen == top {
+                chosen = level;
+            }
+        }
+
+        match self.levels[chosen].pop() {
+            Some(task_id) => {
+                self.starved[chosen].store(0, Ordering::Relaxed);
+                Some(task_id)
+            }
+            // Очередь опустошил другой обработчик: ищем заново.
+            None => self.levels.iter().rev().find_map(RunQueue::pop),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.levels.iter().all(RunQueue::is_empty)
+    }
+
+    /// Гарантирует, что на каждом уровне хватит места для `tasks` задач.
+    pub(crate) fn reserve(&self, tasks: usize) {
+        for level in &self.levels {
+            level.reserve(tasks);
+        }
+    }
+}
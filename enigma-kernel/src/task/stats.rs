@@ -0,0 +1,139 @@
+//! Данный модуль содержит статистику задач исполнителя и вывод таблицы
+//! задач (аналог `top`) в последовательный порт.
+
+use super::Priority;
+use crate::serial_println;
+use crate::time::{self, Instant};
+use alloc::{borrow::Cow, format};
+use core::panic::Location;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// Значение `TaskStats::last_woken`, пока задачу ни разу не будили.
+const NEVER: u64 = u64::MAX;
+
+/// Счетчики одной задачи. Обновляются исполнителем и waker'ом задачи
+/// (в том числе из прерываний), поэтому все поля атомарные.
+pub(crate) struct TaskStats {
+    polls: AtomicU64,
+    /// Суммарное время опросов в тактах TSC.
+    total_poll: AtomicU64,
+    /// Самый долгий опрос в тактах TSC.
+    max_poll: AtomicU64,
+    /// Тик последнего пробуждения.
+    last_woken: AtomicU64,
+}
+
+impl TaskStats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            polls: AtomicU64::new(0),
+            total_poll: AtomicU64::new(0),
+            max_poll: AtomicU64::new(0),
+            last_woken: AtomicU64::new(NEVER),
+        }
+    }
+
+    /// Учитывает один опрос длительностью `cycles` тактов TSC.
+    pub(crate) fn record_poll(&self, cycles: u64) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.total_poll.fetch_add(cycles, Ordering::Relaxed);
+        self.max_poll.fetch_max(cycles, Ordering::Relaxed);
+    }
+
+    /// Запоминает время пробуждения. Может вызываться из прерываний.
+    pub(crate) fn record_wake(&self) {
+        self.last_woken.store(time::ticks(), Ordering::Relaxed);
+    }
+}
+
+/// Состояние задачи на момент снимка.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Задачу сейчас опрашивает один из обработчиков.
+    Running,
+    /// Задача стоит в очереди готовых задач.
+    Queued,
+    /// Задача ждет пробуждения.
+    Idle,
+}
+
+/// Снимок сведений об одной задаче.
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: Option<Cow<'static, str>>,
+    /// Место в коде, где была создана задача.
+    pub location: &'static Location<'static>,
+    pub priority: Priority,
+    pub state: TaskState,
+    pub polls: u64,
+    pub total_poll_time: Duration,
+    pub max_poll_time: Duration,
+    pub last_woken: Option<Instant>,
+}
+
+impl TaskInfo {
+    pub(crate) fn new(
+        id: u64,
+        name: Option<Cow<'static, str>>,
+        location: &'static Location<'static>,
+        priority: Priority,
+        state: TaskState,
+        stats: &TaskStats,
+    ) -> Self {
+        let last_woken = stats.last_woken.load(Ordering::Relaxed);
+
+        Self {
+            id,
+            name,
+            location,
+            priority,
+            state,
+            polls: stats.polls.load(Ordering::Relaxed),
+            total_poll_time: time::tsc_to_duration(stats.total_poll.load(Ordering::Relaxed)),
+            max_poll_time: time::tsc_to_duration(stats.max_poll.load(Ordering::Relaxed)),
+            last_woken: (last_woken != NEVER).then(|| Instant::from_ticks(last_woken)),
+        }
+    }
+}
+
+/// Печатает таблицу задач в последовательный порт, сортируя их по
+/// суммарному времени опросов (самые "тяжелые" сверху).
+pub(crate) fn print_table(tasks: &mut [TaskInfo]) {
+    tasks.sort_by(|a, b| b.total_poll_time.cmp(&a.total_poll_time));
+
+    serial_println!(
+        "{:>5} {:<20} {:<8} {:<7} {:>9} {:>11} {:>10} {:>9}  SPAWNED AT",
+        "ID",
+        "NAME",
+        "PRIO",
+        "STATE",
+        "POLLS",
+        "TOTAL(us)",
+        "MAX(us)",
+        "WOKEN(ms)"
+    );
+
+    let now = Instant::now();
+    for task in tasks.iter() {
+        let woken = match task.last_woken {
+            Some(instant) => format!("{}", (now - instant).as_millis()),
+            None => "never".into(),
+        };
+
+        serial_println!(
+            "{:>5} {:<20} {:<8} {:<7} {:>9} {:>11} {:>10} {:>9}  {}",
+            task.id,
+            task.name.as_deref().unwrap_or("-"),
+            format!("{:?}", task.priority),
+            format!("{:?}", task.state),
+            task.polls,
+            task.total_poll_time.as_micros(),
+            task.max_poll_time.as_micros(),
+            woken,
+            task.location
+        );
+    }
+    serial_println!("{} tasks", tasks.len());
+}
@@ -0,0 +1,297 @@
+//! Данный модуль содержит ассинхронные таймеры для исполнителя: [`sleep`],
+//! [`sleep_until`], [`timeout`] и [`interval`].
+//!
+//! Все таймеры хранятся в одной куче, упорядоченной по сроку срабатывания.
+//! Прерывание таймера продвигает её через [`process`] и будит задачи через
+//! их `Waker`. Отмененные таймеры удаляются, когда наступает их срок, а
+//! если их становится больше половины кучи, она очищается от них сразу.
+
+use crate::time::Instant;
+use alloc::{collections::BinaryHeap, sync::Arc};
+use core::cmp::{Ordering as CmpOrdering, Reverse};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::{
+    fmt,
+    future::{Future, poll_fn},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use futures_util::{stream::Stream, task::AtomicWaker};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+static TIMERS: Mutex<Timers> = Mutex::new(Timers {
+    heap: BinaryHeap::new(),
+    cancelled: 0,
+});
+
+struct Timers {
+    heap: BinaryHeap<Reverse<TimerEntry>>,
+    /// Отмененные таймеры, которые ещё лежат в куче.
+    cancelled: usize,
+}
+
+impl Timers {
+    /// Отмечает таймер отмененным. Сработавший таймер в куче уже не лежит.
+    fn cancel(&mut self, state: &TimerState) {
+        if state.fired.load(Ordering::Acquire) || state.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.cancelled += 1;
+        if self.cancelled * 2 > self.heap.len() {
+            self.heap
+                .retain(|Reverse(entry)| !entry.state.cancelled.load(Ordering::Acquire));
+            self.cancelled = 0;
+        }
+    }
+}
+
+struct TimerState {
+    fired: AtomicBool,
+    cancelled: AtomicBool,
+    waker: AtomicWaker,
+}
+
+struct TimerEntry {
+    deadline: u64,
+    /// Порядковый номер для стабильного порядка таймеров с одинаковым сроком.
+    seq: u64,
+    state: Arc<TimerState>,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+fn register(deadline: Instant) -> Arc<TimerState> {
+    static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+    let state = Arc::new(TimerState {
+        fired: AtomicBool::new(false),
+        cancelled: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+    let entry = TimerEntry {
+        deadline: deadline.ticks(),
+        seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+        state: state.clone(),
+    };
+
+    without_interrupts(|| TIMERS.lock().heap.push(Reverse(entry)));
+    state
+}
+
+/// Будит все таймеры, срок которых наступил к тику `now`.
+/// Вызывается из прерывания таймера.
+pub(crate) fn process(now: u64) {
+    let mut timers = TIMERS.lock();
+
+    while let Some(Reverse(entry)) = timers.heap.peek()
+        && entry.deadline <= now
+    {
+        let Reverse(entry) = timers.heap.pop().unwrap();
+        if entry.state.cancelled.load(Ordering::Acquire) {
+            timers.cancelled -= 1;
+        } else {
+            entry.state.fired.store(true, Ordering::Release);
+            entry.state.waker.wake();
+        }
+    }
+}
+
+/// Возвращает количество зарегистрированных таймеров (включая отмененные,
+/// но ещё не удаленные).
+pub fn pending_timers() -> usize {
+    without_interrupts(|| TIMERS.lock().heap.len())
+}
+
+/// Future, который завершается по наступлении указанного момента.
+pub struct Sleep {
+    deadline: Instant,
+    state: Option<Arc<TimerState>>,
+}
+
+/// Ждет, пока пройдет `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Ждет наступления момента `deadline`.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        state: None,
+    }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Переустанавливает таймер на новый срок.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    fn cancel(&mut self) {
+        if let Some(state) = self.state.take() {
+            without_interrupts(|| TIMERS.lock().cancel(&state));
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_elapsed() {
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let state = self.state.get_or_insert_with(|| register(deadline));
+        state.waker.register(cx.waker());
+
+        // Таймер мог сработать между регистрацией и сохранением waker'а.
+        if state.fired.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Ошибка, возвращаемая [`Timeout`], если время ожидания истекло.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+/// Future, ограничивающий время выполнения другого future.
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Ждет завершения `future`, но не дольше `duration`.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+impl<F> Timeout<F> {
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `future` никогда не перемещается из закрепленного `Timeout`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Периодический таймер. Если тики были пропущены, то следующий тик
+/// переносится на период вперед от текущего момента, а не выдается пачкой.
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+/// Создает периодический таймер, первый тик которого происходит сразу.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Instant::now(), period)
+}
+
+/// Создает периодический таймер, первый тик которого происходит в `start`.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+
+    Interval {
+        period,
+        sleep: sleep_until(start),
+    }
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Ждет следующего тика и возвращает его плановое время.
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let fired = self.sleep.deadline();
+        let now = Instant::now();
+        let mut next = fired + self.period;
+        if next <= now {
+            next = now + self.period;
+        }
+
+        self.sleep.reset(next);
+        Poll::Ready(fired)
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
@@ -0,0 +1,185 @@
+//! Общие помощники интеграционных тестов (`tests/`): точка входа теста
+//! ([`test_entry!`](crate::test_entry)), диски из разреженных образов,
+//! которые программа запуска тестов передает в ramdisk, и короткие обертки
+//! над VFS.
+//!
+//! Модуль собирается только с feature `test-util`, которую тесты включают
+//! через dev-зависимость пакета на самого себя.
+
+use crate::drivers::block::{BlockDevice, RamBlockDevice};
+use crate::drivers::{ahci, apic, pci};
+use crate::errno::Errno;
+use crate::fs::ramdisk;
+use crate::fs::vfs::{self, FileSystem, MountFlags, OpenFlags};
+use crate::memory::{self, BootInfoFrameAllocator};
+use crate::process::fd::File;
+use crate::{allocator, deferred, fs, interrupts, thread};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use bootloader_api::{BootInfo, BootloaderConfig, config::Mapping};
+use x86_64::VirtAddr;
+
+/// Конфигурация загрузчика для тестов: вся физическая память отображена
+/// в старшую половину адресного пространства.
+pub const BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(Mapping::Dynamic);
+    config.mappings.dynamic_range_start = Some(0xFFFF_8000_0000_0000);
+    config
+};
+
+/// Подсистемы, которые тест поднимает поверх памяти, кучи и потоков.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Setup {
+    /// Загрузить IDT без LAPIC (исключения из ring 3).
+    pub idt: bool,
+    /// Включить LAPIC и его таймер (вытеснение потоков и таймеры).
+    pub apic: bool,
+    /// Запустить поток `kworker` для нижних половин прерываний.
+    pub deferred: bool,
+    /// Перечислить устройства PCI (хотя бы одно должно найтись).
+    pub pci: bool,
+    /// Запустить драйвер AHCI (нужны `apic`, `deferred` и `pci`).
+    pub ahci: bool,
+    /// Смонтировать корневую tmpfs.
+    pub fs: bool,
+}
+
+/// Инициализирует ядро для интеграционного теста: память, кучу, потоки и
+/// подсистемы из `setup`. Вызывается один раз из точки входа теста.
+pub fn boot(boot_info: &'static mut BootInfo, setup: Setup) {
+    crate::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.take().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    // Образы дисков для тестов (см. [`fixture`]); ramdisk не монтируется.
+    if let Some(addr) = boot_info.ramdisk_addr.into_option() {
+        unsafe { ramdisk::init(VirtAddr::new(addr), boot_info.ramdisk_len) }
+            .expect("test images ramdisk is corrupted");
+    }
+    if setup.idt {
+        interrupts::init_idt();
+    }
+    thread::init();
+    if setup.deferred {
+        deferred::init();
+    }
+
+    let rsdp = boot_info.rsdp_addr.into_option().map(|rsdp| rsdp as usize);
+    if setup.apic {
+        let rsdp = rsdp.expect("no RSDP for APIC");
+        unsafe { apic::init(rsdp, phys_mem_offset, &mut mapper, &mut frame_allocator) };
+    }
+    memory::init_frame_allocator(frame_allocator);
+
+    if setup.pci {
+        assert!(unsafe { pci::init(rsdp) } > 0, "no PCI devices found");
+    }
+    if setup.ahci {
+        ahci::init();
+    }
+    if setup.fs {
+        fs::init(None).unwrap();
+    }
+}
+
+/// Объявляет точку входа и обработчик паники интеграционного теста.
+///
+/// Точка входа вызывает [`boot`] с [`Setup`] из первого аргумента (по
+/// умолчанию пустым), затем необязательную функцию подготовки и `test_main`.
+#[macro_export]
+macro_rules! test_entry {
+    () => {
+        $crate::test_entry!($crate::test_util::Setup::default());
+    };
+    ($setup:expr $(, $prepare:expr)? $(,)?) => {
+        bootloader_api::entry_point!(
+            __test_entry,
+            config = &$crate::test_util::BOOTLOADER_CONFIG
+        );
+
+        fn __test_entry(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+            $crate::test_util::boot(boot_info, $setup);
+            $($prepare();)?
+            test_main();
+            $crate::hlt_loop();
+        }
+
+        #[panic_handler]
+        fn panic(info: &core::panic::PanicInfo) -> ! {
+            $crate::test_panic_handler(info)
+        }
+    };
+}
+
+/// Открытие на чтение и запись с созданием файла.
+pub const CREATE: OpenFlags =
+    OpenFlags::from_bits(OpenFlags::READ_WRITE.bits() | OpenFlags::CREATE.bits());
+
+/// Возвращает образ диска `name` (например, `fat12.sparse`) из ramdisk,
+/// который собирает программа запуска тестов (крейт `enigma-test-images`).
+pub fn fixture(name: &str) -> &'static [u8] {
+    let Some(entry) = ramdisk::get().and_then(|ramdisk| ramdisk.lookup(name)) else {
+        panic!("test image {name} is missing: run the tests through the QEMU runner");
+    };
+    entry.data
+}
+
+/// Разворачивает разреженный образ в диск в памяти. Образ начинается с
+/// числа секторов по 512 байт, за которым идут записи "номер сектора,
+/// содержимое" для ненулевых секторов.
+pub fn sparse_device(sparse: &[u8]) -> Arc<RamBlockDevice> {
+    let u64_at = |offset: usize| u64::from_le_bytes(sparse[offset..offset + 8].try_into().unwrap());
+    let device = RamBlockDevice::new(512, u64_at(0));
+    for record in sparse[8..].chunks_exact(8 + 512) {
+        let lba = u64::from_le_bytes(record[..8].try_into().unwrap());
+        device.write_blocks(lba, &record[8..]).unwrap();
+    }
+    Arc::new(device)
+}
+
+/// Открывает том на `device` конструктором `open` (например, `FatFs::new`)
+/// и монтирует его в каталог `path`, создавая каталог при необходимости.
+pub fn mount<D, F>(
+    open: fn(Arc<dyn BlockDevice>) -> Result<Arc<F>, Errno>,
+    device: &Arc<D>,
+    path: &str,
+) -> Arc<F>
+where
+    D: BlockDevice + 'static,
+    F: FileSystem + 'static,
+{
+    let fs = open(device.clone()).unwrap();
+    if vfs::stat(path).is_err() {
+        vfs::mkdir(path, 0o755).unwrap();
+    }
+    vfs::mount(fs.clone(), path, MountFlags::default()).unwrap();
+    fs
+}
+
+/// Читает файл целиком.
+pub fn read(path: &str) -> Result<Vec<u8>, Errno> {
+    let file = vfs::open(path, OpenFlags::READ_ONLY, 0)?;
+    let mut data = vec![0; file.metadata()?.size as usize];
+    let len = file.read(&mut data)?;
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Создает или перезаписывает файл содержимым `data`.
+pub fn write(path: &str, data: &[u8]) -> Result<usize, Errno> {
+    vfs::open(path, CREATE | OpenFlags::TRUNCATE, 0o644)?.write(data)
+}
+
+/// Отсортированные имена записей каталога.
+pub fn names(path: &str) -> Vec<String> {
+    let mut names: Vec<_> = vfs::read_dir(path)
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    names.sort();
+    names
+}
@@ -0,0 +1,560 @@
+//! Данный модуль содержит вытесняющую многопоточность ядра: потоки со своим
+//! стеком, сохраненным контекстом и состоянием, а также планировщик
+//! round-robin, который вызывается прерыванием таймера LAPIC.
+//!
+//! Планировщик рассчитан на одно ядро: его блокировка берется только с
+//! выключенными прерываниями, поэтому обработчик прерывания не может
+//! застать её занятой.
+
+use crate::fpu::{self, FpuState};
+use crate::gdt;
+use crate::time;
+use alloc::{boxed::Box, collections::BTreeMap, collections::VecDeque, sync::Arc, task::Wake, vec};
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PhysFrame;
+
+mod switch;
+
+/// Размер стека потока по умолчанию.
+pub const DEFAULT_STACK_SIZE: usize = 4096 * 4;
+/// Размер стека потока простоя.
+const IDLE_STACK_SIZE: usize = 4096 * 2;
+
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+type ThreadMain = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub(crate) fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Поток готов к выполнению и стоит в очереди.
+    Ready,
+    /// Поток выполняется в данный момент.
+    Running,
+    /// Поток ждет вызова [`unpark`].
+    Blocked,
+    /// Поток спит до указанного тика.
+    Sleeping { until: u64 },
+    /// Поток завершился и ждет освобождения ресурсов.
+    Dead,
+}
+
+struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    state: ThreadState,
+    /// Сохраненный указатель стека (валиден, пока поток не выполняется).
+    rsp: u64,
+    /// Стек потока, `None` для потока, унаследованного от загрузчика.
+    stack: Option<Box<[u8]>>,
+    fpu: Box<FpuState>,
+    /// Таблица страниц 4 уровня, с которой выполняется поток.
+    page_table: PhysFrame,
+    /// Был ли вызван [`unpark`] до того, как поток заблокировался.
+    unpark_token: bool,
+    /// Был ли вызван [`request_exit`].
+    exit_requested: bool,
+}
+
+struct Scheduler {
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    ready: VecDeque<ThreadId>,
+    current: ThreadId,
+    idle: Option<ThreadId>,
+    /// Таблица страниц, унаследованная от загрузчика (для потоков ядра).
+    kernel_page_table: PhysFrame,
+}
+
+impl Scheduler {
+    fn thread_ptr(&mut self, id: ThreadId) -> *mut Thread {
+        &mut **self.threads.get_mut(&id).expect("unknown thread") as *mut Thread
+    }
+
+    fn make_ready(&mut self, id: ThreadId) {
+        if let Some(thread) = self.threads.get_mut(&id) {
+            thread.state = ThreadState::Ready;
+            if Some(id) != self.idle {
+                self.ready.push_back(id);
+            }
+        }
+    }
+
+    /// Освобождает завершившиеся потоки, кроме текущего (мы можем
+    /// всё ещё находиться на его стеке).
+    fn reap(&mut self) {
+        let current = self.current;
+        self.threads
+            .retain(|&id, thread| thread.state != ThreadState::Dead || id == current);
+    }
+
+    /// Выбирает следующий поток и возвращает аргументы для переключения.
+    fn pick_next(&mut self) -> Option<(*mut u64, u64)> {
+        let prev_id = self.current;
+        let prev = self.thread_ptr(prev_id);
+        let prev_running = unsafe { (*prev).state == ThreadState::Running };
+
+        let next_id = match self.ready.pop_front() {
+            Some(id) => id,
+            None if prev_running => return None,
+            None => self.idle.expect("no runnable threads and no idle thread"),
+        };
+
+        if prev_running {
+            self.make_ready(prev_id);
+        }
+
+        let next = self.thread_ptr(next_id);
+        self.current = next_id;
+
+        unsafe {
+            (*next).state = ThreadState::Running;
+            fpu::switch_to(&mut *(*next).fpu);
+
+            // Прерывание из кольца 3 должно попасть на стек ядра этого потока.
+            if let Some(stack) = &(*next).stack {
+                gdt::set_kernel_stack(stack_top(stack));
+            }
+
+            let (active, flags) = Cr3::read();
+            if active != (*next).page_table {
+                Cr3::write((*next).page_table, flags);
+            }
+
+            Some((&mut (*prev).rsp as *mut u64, (*next).rsp))
+        }
+    }
+}
+
+/// Превращает текущий поток выполнения в поток `kernel-main` и запускает
+/// поток простоя. Должна вызываться один раз, после инициализации кучи.
+pub fn init() {
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        assert!(guard.is_none(), "thread::init should only be called once");
+
+        let kernel_page_table = Cr3::read().0;
+        let mut main = Box::new(Thread {
+            id: ThreadId::new(),
+            name: "kernel-main",
+            state: ThreadState::Running,
+            rsp: 0,
+            stack: None,
+            fpu: Box::new(FpuState::new()),
+            page_table: kernel_page_table,
+            unpark_token: false,
+            exit_requested: false,
+        });
+        unsafe { fpu::switch_to(&mut *main.fpu) };
+
+        let current = main.id;
+        let mut threads = BTreeMap::new();
+        threads.insert(current, main);
+
+        *guard = Some(Scheduler {
+            threads,
+            ready: VecDeque::new(),
+            current,
+            idle: None,
+            kernel_page_table,
+        });
+    });
+
+    let idle = create("idle", IDLE_STACK_SIZE, Box::new(idle_main), Cr3::read().0);
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().unwrap();
+        scheduler.idle = Some(idle.id);
+        scheduler.threads.insert(idle.id, idle);
+    });
+}
+
+/// Возвращает `true`, если планировщик уже инициализирован.
+pub fn is_initialized() -> bool {
+    without_interrupts(|| SCHEDULER.lock().is_some())
+}
+
+/// Создает новый поток ядра со стеком размера [`DEFAULT_STACK_SIZE`].
+pub fn spawn<F>(name: &'static str, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_stack(name, DEFAULT_STACK_SIZE, f)
+}
+
+/// Создает новый поток ядра с указанным размером стека.
+pub fn spawn_with_stack<F>(name: &'static str, stack_size: usize, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_inner(name, stack_size, None, Box::new(f))
+}
+
+/// Создает поток, который выполняется с таблицей страниц `page_table`
+/// (например, поток пользовательского процесса).
+pub fn spawn_with_page_table<F>(name: &'static str, page_table: PhysFrame, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_inner(name, DEFAULT_STACK_SIZE, Some(page_table), Box::new(f))
+}
+
+fn spawn_inner(
+    name: &'static str,
+    stack_size: usize,
+    page_table: Option<PhysFrame>,
+    main: ThreadMain,
+) -> ThreadId {
+    let kernel_page_table = without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        let scheduler = guard.as_ref().expect("scheduler is not initialized");
+        scheduler.kernel_page_table
+    });
+
+    let thread = create(
+        name,
+        stack_size,
+        main,
+        page_table.unwrap_or(kernel_page_table),
+    );
+    let id = thread.id;
+
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("scheduler is not initialized");
+
+        scheduler.threads.insert(id, thread);
+        scheduler.make_ready(id);
+    });
+
+    id
+}
+
+fn create(
+    name: &'static str,
+    stack_size: usize,
+    main: ThreadMain,
+    page_table: PhysFrame,
+) -> Box<Thread> {
+    let mut stack = vec![0u8; stack_size].into_boxed_slice();
+    let arg = Box::into_raw(Box::new(main)) as u64;
+    let rsp = switch::prepare_stack(&mut stack, arg);
+
+    Box::new(Thread {
+        id: ThreadId::new(),
+        name,
+        state: ThreadState::Ready,
+        rsp,
+        stack: Some(stack),
+        fpu: Box::new(FpuState::new()),
+        page_table,
+        unpark_token: false,
+        exit_requested: false,
+    })
+}
+
+/// Возвращает вершину стека, выровненную на 16 байт.
+fn stack_top(stack: &[u8]) -> VirtAddr {
+    VirtAddr::new(stack.as_ptr() as u64 + stack.len() as u64).align_down(16u64)
+}
+
+/// Первая Rust функция нового потока, вызывается из трамплина.
+extern "C" fn thread_entry(main: *mut ThreadMain) -> ! {
+    let main = unsafe { Box::from_raw(main) };
+
+    // Поток стартует изнутри `schedule`, где прерывания выключены.
+    interrupts::enable();
+    main();
+
+    exit();
+}
+
+fn idle_main() {
+    loop {
+        interrupts::disable();
+        let has_ready = SCHEDULER
+            .lock()
+            .as_ref()
+            .is_some_and(|scheduler| !scheduler.ready.is_empty());
+
+        if has_ready {
+            schedule();
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+/// Переключается на следующий готовый поток (если он есть).
+///
+/// Должна вызываться с выключенными прерываниями.
+fn schedule() {
+    let switch = {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+
+        scheduler.reap();
+        scheduler.pick_next()
+    };
+
+    if let Some((old_rsp, new_rsp)) = switch {
+        unsafe { switch::switch_context(old_rsp, new_rsp) };
+    }
+}
+
+/// Вызывается обработчиком прерывания таймера после отправки EOI.
+pub(crate) fn tick() {
+    {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+
+        let now = time::ticks();
+
+        while let Some(id) = scheduler
+            .threads
+            .values()
+            .find(|thread| matches!(thread.state, ThreadState::Sleeping { until } if until <= now))
+            .map(|thread| thread.id)
+        {
+            scheduler.make_ready(id);
+        }
+    }
+
+    schedule();
+}
+
+/// Возвращает идентификатор текущего потока.
+pub fn current() -> Option<ThreadId> {
+    without_interrupts(|| SCHEDULER.lock().as_ref().map(|scheduler| scheduler.current))
+}
+
+/// Возвращает имя потока.
+pub fn name(id: ThreadId) -> Option<&'static str> {
+    without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        guard.as_ref()?.threads.get(&id).map(|thread| thread.name)
+    })
+}
+
+/// Возвращает состояние потока.
+pub fn state(id: ThreadId) -> Option<ThreadState> {
+    without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        guard.as_ref()?.threads.get(&id).map(|thread| thread.state)
+    })
+}
+
+/// Добровольно уступает процессор следующему готовому потоку.
+pub fn yield_now() {
+    without_interrupts(schedule);
+}
+
+/// Усыпляет текущий поток на `ticks` тиков таймера.
+pub fn sleep_ticks(ticks: u64) {
+    without_interrupts(|| {
+        {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = guard.as_mut().expect("scheduler is not initialized");
+            let until = time::ticks() + ticks;
+
+            let current = scheduler.current;
+            scheduler.threads.get_mut(&current).unwrap().state = ThreadState::Sleeping { until };
+        }
+
+        schedule();
+    });
+}
+
+/// Усыпляет текущий поток как минимум на `duration`.
+pub fn sleep(duration: Duration) {
+    sleep_ticks(time::duration_to_ticks(duration));
+}
+
+/// Блокирует текущий поток до вызова [`unpark`].
+///
+/// Если [`unpark`] уже был вызван, возвращается сразу.
+pub fn park() {
+    without_interrupts(|| {
+        {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = guard.as_mut().expect("scheduler is not initialized");
+
+            let current = scheduler.current;
+            let thread = scheduler.threads.get_mut(&current).unwrap();
+            if thread.unpark_token {
+                thread.unpark_token = false;
+                return;
+            }
+            thread.state = ThreadState::Blocked;
+        }
+
+        schedule();
+    });
+}
+
+/// Пробуждает поток, заблокированный в [`park`]. Может вызываться из прерываний.
+pub fn unpark(id: ThreadId) {
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+
+        let Some(thread) = scheduler.threads.get_mut(&id) else {
+            return;
+        };
+        match thread.state {
+            ThreadState::Blocked => scheduler.make_ready(id),
+            ThreadState::Dead => {}
+            _ => thread.unpark_token = true,
+        }
+    });
+}
+
+/// Выполняет `future` до завершения, блокируя текущий поток, пока future
+/// ждет пробуждения. Так синхронный код ядра дожидается асинхронных событий.
+///
+/// Вызов из задачи исполнителя блокирует весь исполнитель. До запуска
+/// планировщика поток не блокируется, а ждет пробуждения в цикле.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Arc::new(ThreadWaker {
+        thread: current(),
+        woken: AtomicBool::new(false),
+    });
+    let task_waker = Waker::from(waker.clone());
+    let mut context = Context::from_waker(&task_waker);
+    let mut future = pin!(future);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        match waker.thread {
+            Some(_) => park(),
+            None => {
+                while !waker.woken.swap(false, Ordering::Acquire) {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+/// Waker потока, ждущего в [`block_on`].
+struct ThreadWaker {
+    thread: Option<ThreadId>,
+    woken: AtomicBool,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        match self.thread {
+            Some(thread) => unpark(thread),
+            None => self.woken.store(true, Ordering::Release),
+        }
+    }
+}
+
+/// Меняет таблицу страниц потока. Если это текущий поток, то новая
+/// таблица сразу загружается в CR3; `None` означает таблицу ядра.
+pub fn set_page_table(id: ThreadId, page_table: Option<PhysFrame>) {
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+
+        let page_table = page_table.unwrap_or(scheduler.kernel_page_table);
+        let current = scheduler.current;
+        let Some(thread) = scheduler.threads.get_mut(&id) else {
+            return;
+        };
+
+        thread.page_table = page_table;
+        if id == current {
+            let (_, flags) = Cr3::read();
+            unsafe { Cr3::write(page_table, flags) };
+        }
+    });
+}
+
+/// Просит поток `id` завершиться. Поток не останавливается сразу (он может
+/// держать блокировки ядра), а сам проверяет [`exit_requested`] в безопасных
+/// точках и завершается там. Заблокированный или спящий поток будится,
+/// чтобы дойти до такой точки.
+pub fn request_exit(id: ThreadId) {
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+
+        let Some(thread) = scheduler.threads.get_mut(&id) else {
+            return;
+        };
+        thread.exit_requested = true;
+        match thread.state {
+            ThreadState::Blocked | ThreadState::Sleeping { .. } => scheduler.make_ready(id),
+            _ => thread.unpark_token = true,
+        }
+    });
+}
+
+/// Возвращает `true`, если текущий поток попросили завершиться через
+/// [`request_exit`].
+pub fn exit_requested() -> bool {
+    without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        guard
+            .as_ref()
+            .is_some_and(|scheduler| scheduler.threads[&scheduler.current].exit_requested)
+    })
+}
+
+/// Завершает текущий поток.
+pub fn exit() -> ! {
+    interrupts::disable();
+    {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("scheduler is not initialized");
+
+        let current = scheduler.current;
+        scheduler.threads.get_mut(&current).unwrap().state = ThreadState::Dead;
+    }
+
+    schedule();
+    unreachable!("dead thread was scheduled again");
+}
@@ -0,0 +1,72 @@
+//! Низкоуровневое переключение контекста между потоками ядра.
+
+use core::arch::naked_asm;
+
+/// Количество регистров, сохраняемых [`switch_context`] на стеке.
+const SAVED_REGISTERS: usize = 6;
+
+/// Сохраняет callee-saved регистры текущего потока, записывает его `rsp`
+/// в `old_rsp` и переключается на стек `new_rsp`.
+///
+/// ## Safety
+///
+/// `new_rsp` должен указывать на стек, подготовленный [`prepare_stack`] или
+/// сохраненный предыдущим вызовом этой функции. Прерывания должны быть выключены.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn switch_context(old_rsp: *mut u64, new_rsp: u64) {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    );
+}
+
+/// Точка входа нового потока: передает значение `r12` в `thread_entry`.
+#[unsafe(naked)]
+unsafe extern "C" fn thread_trampoline() -> ! {
+    naked_asm!(
+        "mov rdi, r12",
+        "call {entry}",
+        "ud2",
+        entry = sym super::thread_entry,
+    );
+}
+
+/// Подготавливает стек нового потока так, чтобы первый [`switch_context`]
+/// на него "вернулся" в `thread_trampoline` с аргументом `arg` в `r12`.
+///
+/// Возвращает начальное значение `rsp`.
+pub(super) fn prepare_stack(stack: &mut [u8], arg: u64) -> u64 {
+    let top = (stack.as_mut_ptr() as u64 + stack.len() as u64) & !0xF;
+
+    // После `ret` в трамплин `rsp` должен быть выровнен на 16 байт,
+    // чтобы `call` передал в `thread_entry` корректно выровненный стек.
+    let return_address = top - 24;
+    let rsp = return_address - (SAVED_REGISTERS as u64) * 8;
+
+    unsafe {
+        let frame = rsp as *mut u64;
+        // Порядок: r15, r14, r13, r12, rbx, rbp, адрес возврата.
+        for i in 0..SAVED_REGISTERS {
+            frame.add(i).write(0);
+        }
+        frame.add(3).write(arg);
+        frame
+            .add(SAVED_REGISTERS)
+            .write(thread_trampoline as usize as u64);
+    }
+
+    rsp
+}
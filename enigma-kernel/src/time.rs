@@ -0,0 +1,116 @@
+//! Данный модуль содержит системное время ядра, которое отсчитывается
+//! периодическим таймером LAPIC.
+
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// Частота прерываний таймера (тиков в секунду).
+pub const TICK_HZ: u64 = 1000;
+/// Длительность одного тика в наносекундах.
+pub const TICK_NANOS: u64 = 1_000_000_000 / TICK_HZ;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Частота счетчика TSC в герцах (0, пока не откалибрована).
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Вызывается обработчиком прерывания таймера.
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    crate::task::timer::process(now);
+}
+
+/// Возвращает количество тиков с момента запуска таймера.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Возвращает значение счетчика тактов процессора (TSC). Используется для
+/// измерения коротких интервалов, которые меньше одного тика.
+pub fn tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Сохраняет частоту TSC, измеренную при калибровке таймера LAPIC.
+pub(crate) fn set_tsc_hz(hz: u64) {
+    TSC_HZ.store(hz, Ordering::Relaxed);
+}
+
+/// Переводит количество тактов TSC в длительность (ноль, если частота
+/// TSC ещё не известна).
+pub fn tsc_to_duration(cycles: u64) -> Duration {
+    match TSC_HZ.load(Ordering::Relaxed) {
+        0 => Duration::ZERO,
+        hz => Duration::from_nanos((cycles as u128 * 1_000_000_000 / hz as u128) as u64),
+    }
+}
+
+/// Переводит длительность в количество тиков (с округлением вверх).
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos();
+    nanos.div_ceil(TICK_NANOS as u128).min(u64::MAX as u128) as u64
+}
+
+/// Момент времени с точностью до одного тика.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(ticks())
+    }
+
+    /// Создает момент времени из номера тика.
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    pub const fn ticks(self) -> u64 {
+        self.0
+    }
+
+    /// Возвращает время, прошедшее с `earlier` (или ноль, если `earlier` позже).
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0) * TICK_NANOS)
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Instant::now().duration_since(self)
+    }
+
+    pub fn checked_add(self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_to_ticks(duration)).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration_to_ticks(rhs)))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+// --- TEST ZONE --- //
+
+#[test_case]
+fn test_duration_to_ticks_rounds_up() {
+    assert_eq!(duration_to_ticks(Duration::ZERO), 0);
+    assert_eq!(duration_to_ticks(Duration::from_nanos(1)), 1);
+    assert_eq!(duration_to_ticks(Duration::from_millis(1)), TICK_HZ / 1000);
+    assert_eq!(duration_to_ticks(Duration::from_secs(2)), 2 * TICK_HZ);
+}
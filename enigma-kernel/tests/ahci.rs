@@ -0,0 +1,114 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec;
+use enigma_kernel::drivers::ahci::{self, Identify, identify::IDENTIFY_SIZE};
+use enigma_kernel::drivers::block::{self, BlockDevice};
+use enigma_kernel::test_util::Setup;
+use enigma_kernel::thread;
+
+// Завершение команд приходит прерыванием через LAPIC.
+enigma_kernel::test_entry!(Setup {
+    apic: true,
+    deferred: true,
+    pci: true,
+    ahci: true,
+    ..Setup::default()
+});
+
+/// Записывает строку ATA (байты в словах переставлены) в слова с `word`.
+fn put_string(data: &mut [u8], word: usize, text: &str) {
+    for (index, pair) in text.as_bytes().chunks(2).enumerate() {
+        let offset = (word + index) * 2;
+        data[offset + 1] = pair[0];
+        data[offset] = pair.get(1).copied().unwrap_or(b' ');
+    }
+}
+
+fn put_word(data: &mut [u8], word: usize, value: u16) {
+    data[word * 2..word * 2 + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+#[test_case]
+fn parses_identify() {
+    let mut data = vec![0; IDENTIFY_SIZE];
+    data[20..40].fill(b' ');
+    data[54..94].fill(b' ');
+    put_string(&mut data, 10, "QM00001");
+    put_string(&mut data, 27, "QEMU HARDDISK");
+    put_word(&mut data, 60, 0xFFFF);
+    put_word(&mut data, 61, 0x0FFF);
+    // Без LBA диск не поддерживается.
+    assert_eq!(Identify::parse(&data), None);
+
+    put_word(&mut data, 49, 1 << 9);
+    let identify = Identify::parse(&data).unwrap();
+    assert_eq!(identify.model, "QEMU HARDDISK");
+    assert_eq!(identify.serial, "QM00001");
+    assert_eq!(identify.sectors, 0x0FFF_FFFF);
+    assert!(!identify.lba48 && !identify.ncq);
+    assert_eq!(identify.queue_depth, 1);
+
+    // LBA48, NCQ глубиной 32 и секторы по 4 КиБ.
+    put_word(&mut data, 83, 1 << 10);
+    put_word(&mut data, 101, 0x1234);
+    put_word(&mut data, 102, 0x1);
+    put_word(&mut data, 75, 31);
+    put_word(&mut data, 76, 1 << 8);
+    put_word(&mut data, 106, 0x4000 | 1 << 12);
+    put_word(&mut data, 117, 2048);
+    let identify = Identify::parse(&data).unwrap();
+    assert_eq!(identify.sectors, 0x1_1234_0000);
+    assert!(identify.lba48 && identify.ncq);
+    assert_eq!(identify.queue_depth, 32);
+    assert_eq!(identify.sector_size, 4096);
+    assert_eq!(identify.capacity(), Some(0x1_1234_0000 * 4096));
+
+    // Слово 103 в адрес не входит, а огромный сектор переполняет емкость.
+    put_word(&mut data, 103, 0xFFFF);
+    put_word(&mut data, 102, 0xFFFF);
+    put_word(&mut data, 118, 0xFFFF);
+    let identify = Identify::parse(&data).unwrap();
+    assert_eq!(identify.sectors, 0xFFFF_1234_0000);
+    assert_eq!(identify.capacity(), None);
+}
+
+#[test_case]
+fn reads_disks() {
+    // Тесты запускаются на `q35` (`src/runner.rs` корневого пакета): к
+    // встроенному контроллеру AHCI подключен загрузочный диск. Диск только
+    // читается.
+    let disks = ahci::disks();
+    assert!(
+        !disks.is_empty(),
+        "no AHCI disks: run the tests through the q35 runner"
+    );
+    for disk in disks {
+        let registered = block::get(disk.name()).unwrap();
+        assert_eq!(registered.block_count(), disk.identify().sectors);
+        assert!(disk.depth() >= 1);
+
+        // Чтение больше наибольшей команды делится на несколько.
+        let size = disk.block_size();
+        let mut first = vec![0; 256 * 1024];
+        disk.read_blocks(0, &mut first).unwrap();
+        let mut second = vec![0; 256 * 1024];
+        thread::block_on(disk.read_async(0, &mut second)).unwrap();
+        assert_eq!(first, second);
+
+        let mut block = vec![0; size];
+        disk.read_blocks(1, &mut block).unwrap();
+        assert_eq!(block, first[size..2 * size]);
+        disk.flush().unwrap();
+
+        let last = disk.block_count() - 1;
+        disk.read_blocks(last, &mut block).unwrap();
+        assert!(disk.read_blocks(last, &mut first).is_err());
+        assert!(disk.queue_stats().completed >= 3);
+    }
+}
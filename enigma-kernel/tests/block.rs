@@ -0,0 +1,172 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use enigma_kernel::drivers::block::queue::{Operation, QueueStats};
+use enigma_kernel::drivers::block::{BlockDevice, BufferCache, RamBlockDevice, RequestQueue};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::fs::exfat::ExFatFs;
+use enigma_kernel::fs::vfs::{FileSystem, FileType};
+use enigma_kernel::test_util::{fixture, sparse_device};
+use enigma_kernel::thread::block_on;
+
+enigma_kernel::test_entry!();
+
+/// Образ exFAT из крейта `enigma-test-images`.
+const EXFAT: &str = "exfat.sparse";
+
+fn blocks(count: usize, byte: u8) -> Vec<u8> {
+    vec![byte; count * 512]
+}
+
+fn read(device: &dyn BlockDevice, lba: u64, count: usize) -> Vec<u8> {
+    let mut data = blocks(count, 0);
+    device.read_blocks(lba, &mut data).unwrap();
+    data
+}
+
+#[test_case]
+fn cache_defers_writes_until_flush() {
+    let disk = Arc::new(RamBlockDevice::new(512, 64));
+    let cache = BufferCache::new(disk.clone(), 16);
+
+    cache.write_blocks(4, &blocks(4, 0xAB)).unwrap();
+    assert_eq!(read(&*disk, 4, 4), blocks(4, 0));
+    assert_eq!(read(&cache, 4, 4), blocks(4, 0xAB));
+    assert_eq!(cache.stats().dirty, 4);
+
+    block_on(cache.flush_async()).unwrap();
+    assert_eq!(read(&*disk, 4, 4), blocks(4, 0xAB));
+    let stats = cache.stats();
+    assert_eq!((stats.dirty, stats.written, stats.hits), (0, 4, 4));
+    assert_eq!(
+        cache.write_blocks(63, &blocks(2, 1)),
+        Err(Errno::EIO),
+        "запись за концом устройства"
+    );
+}
+
+#[test_case]
+fn cache_evicts_least_recently_used() {
+    let disk = Arc::new(RamBlockDevice::new(512, 64));
+    for lba in 0..8 {
+        disk.write_blocks(lba, &blocks(1, lba as u8)).unwrap();
+    }
+    let cache = BufferCache::new(disk.clone(), 4);
+
+    read(&cache, 0, 4);
+    read(&cache, 0, 1);
+    // Блок 4 вытесняет блок 1: блок 0 только что использовали.
+    read(&cache, 4, 1);
+    let stats = cache.stats();
+    assert_eq!((stats.misses, stats.hits, stats.evictions), (5, 1, 1));
+    read(&cache, 0, 1);
+    assert_eq!(cache.stats().hits, 2);
+    assert_eq!(read(&cache, 1, 1), blocks(1, 1));
+    assert_eq!(cache.stats().misses, 6);
+
+    // Кэш, полный грязных блоков, пишет на устройство самые старые.
+    cache.write_blocks(10, &blocks(4, 0xCD)).unwrap();
+    cache.write_blocks(20, &blocks(2, 0xEF)).unwrap();
+    assert_eq!(read(&*disk, 10, 2), blocks(2, 0xCD));
+    assert_eq!(read(&*disk, 12, 2), blocks(2, 0));
+    let stats = cache.stats();
+    assert_eq!((stats.cached, stats.dirty, stats.written), (4, 4, 2));
+}
+
+#[test_case]
+fn queue_merges_adjacent_requests() {
+    let disk = RamBlockDevice::new(512, 64);
+    disk.write_blocks(8, &blocks(6, 0x11)).unwrap();
+    let queue = RequestQueue::new(512, 8);
+
+    let middle = queue.read(10, 2);
+    let back = queue.read(12, 2);
+    let front = queue.read(8, 2);
+    // Пересекается с чтением и не сливается с ним.
+    let write = queue.write(11, blocks(1, 0x22));
+    let flush = queue.flush();
+    let again = queue.flush();
+    // Сброс - граница: чтение после него не переезжает вперед.
+    let late = queue.read(14, 1);
+    let stats = queue.stats();
+    assert_eq!((stats.submitted, stats.merged, stats.depth), (7, 3, 4));
+
+    let mut served = Vec::new();
+    while let Some(mut request) = queue.pop() {
+        served.push((request.operation(), request.lba(), request.count()));
+        let lba = request.lba();
+        let result = match request.operation() {
+            Operation::Read => disk.read_blocks(lba, request.data_mut()),
+            Operation::Write => disk.write_blocks(lba, request.data()),
+            Operation::Flush => disk.flush(),
+        };
+        request.complete(result);
+    }
+    assert_eq!(
+        served,
+        [
+            (Operation::Read, 8, 6),
+            (Operation::Write, 11, 1),
+            (Operation::Flush, 0, 0),
+            (Operation::Read, 14, 1),
+        ]
+    );
+
+    for (completion, count) in [(front, 2), (middle, 2), (back, 2)] {
+        assert_eq!(block_on(completion), Ok(blocks(count, 0x11)));
+    }
+    assert_eq!(block_on(write), Ok(Vec::new()));
+    assert_eq!(block_on(flush), Ok(Vec::new()));
+    assert_eq!(block_on(again), Ok(Vec::new()));
+    assert_eq!(block_on(late), Ok(blocks(1, 0)));
+    assert_eq!(read(&disk, 11, 1), blocks(1, 0x22));
+
+    // Запрос, брошенный драйвером, завершается ошибкой.
+    let lost = queue.read(0, 1);
+    drop(queue.pop());
+    assert_eq!(block_on(lost), Err(Errno::EIO));
+    let stats = queue.stats();
+    assert_eq!(
+        stats,
+        QueueStats {
+            submitted: 8,
+            merged: 3,
+            dispatched: 5,
+            completed: 4,
+            errors: 1,
+            depth: 0,
+            max_depth: 4,
+        }
+    );
+}
+
+#[test_case]
+fn file_system_runs_on_cache() {
+    let disk = sparse_device(fixture(EXFAT));
+
+    let cache = Arc::new(BufferCache::new(disk.clone(), 64));
+    let fs = ExFatFs::new(cache.clone()).unwrap();
+    let file = fs
+        .root()
+        .create("cached", FileType::Regular, 0o644)
+        .unwrap();
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 253) as u8).collect();
+    assert_eq!(file.write_at(0, &data), Ok(data.len()));
+    drop(file);
+    fs.sync().unwrap();
+    assert_eq!(cache.stats().dirty, 0);
+    assert!(cache.stats().evictions > 0);
+
+    // Все дошло до устройства под кэшем.
+    let fs = ExFatFs::new(disk).unwrap();
+    let file = fs.root().lookup("cached").unwrap();
+    let mut buffer = vec![0; data.len()];
+    assert_eq!(file.read_at(0, &mut buffer), Ok(data.len()));
+    assert_eq!(buffer, data);
+}
@@ -0,0 +1,127 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use enigma_kernel::errno::Errno;
+use enigma_kernel::process::{self, WaitTarget};
+use enigma_kernel::test_util::Setup;
+
+enigma_kernel::test_entry!(Setup {
+    idt: true,
+    ..Setup::default()
+});
+
+const BASE: u64 = 0x40_0000;
+const HEADERS: usize = 64 + 56;
+
+/// Собирает минимальный ELF: один сегмент R+X, покрывающий весь файл, с
+/// точкой входа сразу после заголовков.
+fn elf(code: &[u8]) -> Vec<u8> {
+    let size = (HEADERS + code.len()) as u64;
+    let mut file = Vec::new();
+
+    file.extend_from_slice(b"\x7FELF");
+    file.extend_from_slice(&[2, 1, 1, 0]);
+    file.resize(16, 0);
+    file.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    file.extend_from_slice(&62u16.to_le_bytes()); // EM_X86_64
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&(BASE + HEADERS as u64).to_le_bytes()); // e_entry
+    file.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+    file.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&64u16.to_le_bytes());
+    file.extend_from_slice(&56u16.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    file.extend_from_slice(&[0; 6]);
+
+    file.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    file.extend_from_slice(&5u32.to_le_bytes()); // PF_R | PF_X
+    file.extend_from_slice(&0u64.to_le_bytes());
+    file.extend_from_slice(&BASE.to_le_bytes());
+    file.extend_from_slice(&BASE.to_le_bytes());
+    file.extend_from_slice(&size.to_le_bytes());
+    file.extend_from_slice(&size.to_le_bytes());
+    file.extend_from_slice(&4096u64.to_le_bytes());
+
+    file.extend_from_slice(code);
+    file
+}
+
+/// exit(argc + envp[0][0] + (rsp & 15))
+const PROGRAM: [u8; 29] = [
+    0x48, 0x8B, 0x3C, 0x24, 0x48, 0x8B, 0x44, 0x24, 0x20, 0x0F, 0xB6, 0x00, 0x01, 0xC7, 0x48, 0x89,
+    0xE1, 0x83, 0xE1, 0x0F, 0x01, 0xCF, 0xB8, 0x3C, 0x00, 0x00, 0x00, 0x0F, 0x05,
+];
+
+fn spawn(file: &[u8]) -> Result<i32, Errno> {
+    let process = process::spawn_elf("elf", file, &["elf", "arg"], &["X=1"])?;
+    let (pid, status) = process::wait(WaitTarget::Pid(process.pid())).unwrap();
+    assert_eq!(pid, process.pid());
+    Ok(status)
+}
+
+#[test_case]
+fn runs_program_with_arguments() {
+    assert_eq!(spawn(&elf(&PROGRAM)), Ok(2 + i32::from(b'X')));
+}
+
+#[test_case]
+fn rejects_truncated_file() {
+    let file = elf(&PROGRAM);
+    for len in [0, 4, 63, 100] {
+        assert_eq!(spawn(&file[..len]), Err(Errno::ENOEXEC));
+    }
+}
+
+#[test_case]
+fn rejects_bad_header() {
+    let mut file = elf(&PROGRAM);
+    file[4] = 1; // ELFCLASS32
+    assert_eq!(spawn(&file), Err(Errno::ENOEXEC));
+
+    let mut file = elf(&PROGRAM);
+    file[18] = 3; // EM_386
+    assert_eq!(spawn(&file), Err(Errno::ENOEXEC));
+
+    let mut file = elf(&PROGRAM);
+    file[56] = 0xFF; // e_phnum за пределами файла
+    assert_eq!(spawn(&file), Err(Errno::ENOEXEC));
+}
+
+#[test_case]
+fn rejects_bad_segments() {
+    // p_filesz больше размера файла.
+    let mut file = elf(&PROGRAM);
+    file[64 + 32..64 + 40].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(spawn(&file), Err(Errno::ENOEXEC));
+
+    // Сегмент в памяти ядра.
+    let mut file = elf(&PROGRAM);
+    file[64 + 16..64 + 24].copy_from_slice(&0xFFFF_8000_0000_0000u64.to_le_bytes());
+    assert_eq!(spawn(&file), Err(Errno::ENOEXEC));
+
+    // Точка входа вне исполняемого сегмента.
+    let mut file = elf(&PROGRAM);
+    file[64 + 4] = 4; // PF_R
+    assert_eq!(spawn(&file), Err(Errno::ENOEXEC));
+
+    // Динамический исполняемый файл (PT_INTERP).
+    let mut file = elf(&PROGRAM);
+    file[64] = 3;
+    assert_eq!(spawn(&file), Err(Errno::ENOEXEC));
+}
+
+#[test_case]
+fn rejects_huge_arguments() {
+    let huge = "x".repeat(64 * 1024);
+    assert_eq!(
+        process::spawn_elf("elf", &elf(&PROGRAM), &[&huge], &[]).err(),
+        Some(Errno::E2BIG)
+    );
+}
@@ -0,0 +1,189 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::Poll;
+use enigma_kernel::sync::mpsc;
+use enigma_kernel::task::Priority;
+use enigma_kernel::task::executor::Executor;
+use enigma_kernel::test_util::Setup;
+use enigma_kernel::thread::{self, block_on};
+
+// Прерывание таймера LAPIC вытесняет обработчики посреди работы с очередью.
+enigma_kernel::test_entry!(Setup {
+    apic: true,
+    ..Setup::default()
+});
+
+/// Запускает обработчик `index` исполнителя в отдельном потоке.
+fn start_worker(executor: &Executor, index: usize) {
+    let mut worker = executor.worker(index);
+    thread::spawn("worker", move || worker.run());
+}
+
+/// Отдает ход другим задачам исполнителя.
+async fn yield_task() {
+    let mut yielded = false;
+    poll_fn(|context| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[test_case]
+fn idle_worker_steals_tasks() {
+    let executor = Executor::with_workers(2);
+    let spawner = executor.spawner();
+    // Обработчик 0 не запущен, и его задачи выполняет обработчик 1.
+    start_worker(&executor, 1);
+
+    // Текущий поток не обработчик, поэтому задача попадает в очередь 0.
+    assert_eq!(block_on(spawner.spawn(async { 1 + 1 })), Ok(2));
+    // Теперь обработчик 1 спит, и новая задача его будит.
+    assert_eq!(block_on(spawner.spawn(async { 2 + 2 })), Ok(4));
+}
+
+#[test_case]
+fn tasks_spawned_by_workers_complete() {
+    let executor = Executor::with_workers(2);
+    let spawner = executor.spawner();
+    start_worker(&executor, 0);
+    start_worker(&executor, 1);
+
+    let (sender, mut receiver) = mpsc::unbounded();
+    let inner = spawner.clone();
+    let sum = spawner.spawn(async move {
+        // Дочерние задачи ставятся в очередь обработчика, который их создал,
+        // и могут быть украдены другим.
+        let children: Vec<_> = (0..8)
+            .map(|value| inner.spawn(async move { value * 2 }))
+            .collect();
+        let mut sum = 0;
+        for child in children {
+            sum += child.await.unwrap();
+        }
+        // Пробуждение приходит из другого потока.
+        while let Some(value) = receiver.recv().await {
+            sum += value;
+        }
+        sum
+    });
+
+    block_on(async {
+        for value in 0..10 {
+            sender.send(value).await.unwrap();
+        }
+    });
+    drop(sender);
+    assert_eq!(block_on(sum), Ok(56 + 45));
+}
+
+#[test_case]
+fn low_priority_task_is_not_starved() {
+    let executor = Executor::with_workers(1);
+    let spawner = executor.spawner();
+    start_worker(&executor, 0);
+
+    // Две задачи высокого приоритета всегда готовы и не дают очереди
+    // опустеть, пока их не остановят.
+    let stop = Arc::new(AtomicBool::new(false));
+    let polls = Arc::new(AtomicUsize::new(0));
+    let busy: Vec<_> = (0..2)
+        .map(|_| {
+            let (stop, polls) = (stop.clone(), polls.clone());
+            spawner.spawn_with_priority(Priority::High, async move {
+                while !stop.load(Ordering::Acquire) {
+                    polls.fetch_add(1, Ordering::Relaxed);
+                    yield_task().await;
+                }
+            })
+        })
+        .collect();
+
+    // Обработчик вытесняемый и мог начать опрос задач раньше.
+    let before = polls.load(Ordering::Relaxed);
+    let observed = polls.clone();
+    let low =
+        spawner.spawn_with_priority(
+            Priority::Low,
+            async move { observed.load(Ordering::Relaxed) },
+        );
+
+    // Низкий уровень пропускается не больше STARVATION_LIMIT (16) раз.
+    let skipped = block_on(low).unwrap() - before;
+    assert!(skipped > 0, "high priority tasks should run first");
+    assert!(skipped <= 16, "low priority task waited {skipped} polls");
+
+    stop.store(true, Ordering::Release);
+    for task in busy {
+        assert_eq!(block_on(task), Ok(()));
+    }
+}
+
+#[test_case]
+fn run_queue_grows_while_workers_run() {
+    let executor = Executor::with_workers(3);
+    let spawner = executor.spawner();
+    for index in 0..3 {
+        start_worker(&executor, index);
+    }
+
+    // Очереди начинаются с 64 мест и расширяются, пока обработчики
+    // вытесняются таймером посреди push и pop.
+    let handles: Vec<_> = (0..200usize)
+        .map(|value| {
+            spawner.spawn(async move {
+                for _ in 0..3 {
+                    yield_task().await;
+                }
+                value
+            })
+        })
+        .collect();
+
+    let sum: usize = handles
+        .into_iter()
+        .map(|task| block_on(task).unwrap())
+        .sum();
+    assert_eq!(sum, (0..200).sum());
+}
+
+#[test_case]
+fn dropped_executor_releases_pending_tasks() {
+    /// Отмечает уничтожение будущего.
+    struct Guard(Arc<AtomicBool>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Release);
+        }
+    }
+
+    let dropped = Arc::new(AtomicBool::new(false));
+    let executor = Executor::with_workers(1);
+    let spawner = executor.spawner();
+    let guard = Guard(dropped.clone());
+    // Задача никогда не выполнится: обработчик не запущен.
+    let task = spawner.spawn(async move {
+        let _guard = guard;
+    });
+
+    // Будильник задачи не удерживает исполнитель, и вместе с ним
+    // освобождается незавершенная задача.
+    drop(task);
+    drop(spawner);
+    drop(executor);
+    assert!(dropped.load(Ordering::Acquire));
+}
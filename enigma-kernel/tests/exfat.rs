@@ -0,0 +1,258 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, sync::Arc, vec, vec::Vec};
+use enigma_kernel::drivers::block::{BlockDevice, RamBlockDevice};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::fs::exfat::ExFatFs;
+use enigma_kernel::fs::vfs::{self, FileSystem, FileType, OpenFlags};
+use enigma_kernel::process::fd::File;
+use enigma_kernel::test_util::{CREATE, Setup, fixture, mount, read, sparse_device, write};
+
+enigma_kernel::test_entry!(Setup {
+    fs: true,
+    ..Setup::default()
+});
+
+/// Образ из крейта `enigma-test-images`: кластер 4 КиБ, битовая
+/// карта в первом кластере области данных.
+const EXFAT: &str = "exfat.sparse";
+
+const CLUSTER: usize = 4096;
+
+/// Разворачивает образ в диск в памяти.
+fn device() -> Arc<RamBlockDevice> {
+    sparse_device(fixture(EXFAT))
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed))
+        .collect()
+}
+
+/// Сверяет записанный на диск том с ожидаемым числом свободных кластеров:
+/// флаг `VolumeDirty` снят, а битовая карта считает столько же.
+fn check_volume(device: &RamBlockDevice, free: u32) {
+    let mut boot = [0; 512];
+    device.read_blocks(0, &mut boot).unwrap();
+    let u32_at = |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap());
+    assert_eq!(boot[106] & 0x02, 0, "том помечен грязным");
+
+    let heap = u32_at(88) as u64;
+    let cluster_count = u32_at(92) as usize;
+    let mut bitmap = vec![0; CLUSTER];
+    device.read_blocks(heap, &mut bitmap).unwrap();
+    let zeros = (0..cluster_count)
+        .filter(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0)
+        .count();
+    assert_eq!(zeros as u32, free);
+}
+
+#[test_case]
+fn reads_generated_image() {
+    let disk = device();
+    let fs = mount(ExFatFs::new, &disk, "/read");
+    assert_eq!(fs.label(), "ENIGMA");
+    assert_eq!(fs.cluster_size() as usize, CLUSTER);
+
+    // Имена сравниваются по таблице регистра тома, в том числе кириллица.
+    assert_eq!(read("/read/HELLO.TXT").unwrap(), b"Hello, exFAT!");
+    assert_eq!(read("/read/ПРИВЕТ.txt").unwrap(), "мир".as_bytes());
+    assert_eq!(
+        read("/read/A file with a rather long name that needs several name entries.txt").unwrap(),
+        b"long names work"
+    );
+
+    // big.bin лежит цепочкой FAT через кластер, deep.bin - подряд.
+    for (path, len) in [
+        ("/read/big.bin", 100000),
+        ("/read/dir/nested/deep.bin", 10000),
+    ] {
+        let data = read(path).unwrap();
+        assert_eq!(data.len(), len);
+        assert!(
+            data.iter()
+                .enumerate()
+                .all(|(i, &byte)| byte == (i % 251) as u8)
+        );
+    }
+
+    // За ValidDataLength на диске мусор, а читаться должны нули.
+    let sparse = read("/read/sparse.bin").unwrap();
+    assert_eq!(sparse.len(), 10000);
+    assert!(sparse[..100].iter().all(|&byte| byte == b'v'));
+    assert!(sparse[100..].iter().all(|&byte| byte == 0));
+
+    assert_eq!(vfs::read_dir("/read/many").unwrap().len(), 100);
+    assert_eq!(read("/read/many/file-42").unwrap(), b"42");
+    assert_eq!(vfs::stat("/read/dir").unwrap().kind, FileType::Directory);
+    vfs::unmount("/read").unwrap();
+}
+
+#[test_case]
+fn files_grow_contiguously_until_they_cannot() {
+    let disk = device();
+    let fs = mount(ExFatFs::new, &disk, "/grow");
+    let free = fs.free_clusters().unwrap();
+
+    let first = pattern(3 * CLUSTER + 10, 1);
+    let second = pattern(2 * CLUSTER, 2);
+    let tail = pattern(5 * CLUSTER, 3);
+    write("/grow/first", &first).unwrap();
+    write("/grow/second", &second).unwrap();
+    // За first уже лежит second: дописанная часть уйдет в цепочку FAT.
+    let file = vfs::open("/grow/first", OpenFlags::READ_WRITE | OpenFlags::APPEND, 0).unwrap();
+    assert_eq!(file.write(&tail), Ok(tail.len()));
+    drop(file);
+
+    // Запись за концом файла оставляет дыру из нулей.
+    let file = vfs::open("/grow/hole", CREATE, 0o644).unwrap();
+    file.seek(vfs::SeekFrom::Start(3 * CLUSTER as u64 + 5))
+        .unwrap();
+    file.write(b"tail").unwrap();
+    drop(file);
+
+    assert_eq!(fs.free_clusters(), Ok(free - 15));
+    vfs::unmount("/grow").unwrap();
+    fs.sync().unwrap();
+    check_volume(&disk, free - 15);
+
+    mount(ExFatFs::new, &disk, "/grow");
+    let mut expected = first;
+    expected.extend_from_slice(&tail);
+    assert_eq!(read("/grow/FIRST").unwrap(), expected);
+    assert_eq!(read("/grow/second").unwrap(), second);
+    let hole = read("/grow/hole").unwrap();
+    assert!(hole[..3 * CLUSTER + 5].iter().all(|&byte| byte == 0));
+    assert_eq!(&hole[3 * CLUSTER + 5..], b"tail");
+    vfs::unmount("/grow").unwrap();
+}
+
+#[test_case]
+fn directories_grow_and_move() {
+    let disk = device();
+    let fs = mount(ExFatFs::new, &disk, "/tree");
+    let free = fs.free_clusters().unwrap();
+
+    // Каждое имя занимает пять записей: каталог вырастет на несколько
+    // кластеров.
+    vfs::mkdir("/tree/Sub", 0o755).unwrap();
+    for i in 0..200 {
+        write(&format!("/tree/Sub/some fairly long file name {i}"), b"").unwrap();
+    }
+    assert!(vfs::stat("/tree/sub").unwrap().size > CLUSTER as u64);
+    assert_eq!(vfs::mkdir("/tree/SUB", 0o755), Err(Errno::EEXIST));
+    assert_eq!(vfs::symlink("x", "/tree/link"), Err(Errno::EPERM));
+    assert_eq!(vfs::rmdir("/tree/Sub"), Err(Errno::ENOTEMPTY));
+
+    vfs::rename("/tree/Sub", "/tree/dir/moved").unwrap();
+    assert_eq!(vfs::read_dir("/tree/dir/moved").unwrap().len(), 200);
+    assert_eq!(
+        vfs::rename("/tree/dir", "/tree/dir/moved/x"),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        vfs::rename("/tree/dir", "/tree/hello.txt"),
+        Err(Errno::ENOTDIR)
+    );
+    assert_eq!(
+        vfs::rename("/tree/hello.txt", "/tree/dir"),
+        Err(Errno::EISDIR)
+    );
+    // Переименование, меняющее только регистр.
+    vfs::rename("/tree/hello.txt", "/tree/HELLO.TXT").unwrap();
+    assert!(
+        vfs::read_dir("/tree")
+            .unwrap()
+            .iter()
+            .any(|entry| entry.name == "HELLO.TXT")
+    );
+    vfs::unmount("/tree").unwrap();
+
+    mount(ExFatFs::new, &disk, "/tree");
+    assert_eq!(
+        read("/tree/dir/moved/some fairly long file name 199").unwrap(),
+        b""
+    );
+    for i in 0..200 {
+        vfs::unlink(&format!("/tree/dir/moved/some fairly long file name {i}")).unwrap();
+    }
+    vfs::rmdir("/tree/dir/moved").unwrap();
+    assert_eq!(fs.free_clusters(), Ok(free));
+    vfs::unmount("/tree").unwrap();
+}
+
+#[test_case]
+fn truncate_extends_with_zeros() {
+    let disk = device();
+    let fs = mount(ExFatFs::new, &disk, "/size");
+    let free = fs.free_clusters().unwrap();
+
+    write("/size/file", b"abc").unwrap();
+    vfs::truncate("/size/file", 10 * CLUSTER as u64).unwrap();
+    assert_eq!(fs.free_clusters(), Ok(free - 10));
+    let data = read("/size/file").unwrap();
+    assert_eq!(&data[..3], b"abc");
+    assert!(data[3..].iter().all(|&byte| byte == 0));
+
+    vfs::truncate("/size/file", CLUSTER as u64 + 1).unwrap();
+    assert_eq!(fs.free_clusters(), Ok(free - 2));
+    vfs::unmount("/size").unwrap();
+    fs.sync().unwrap();
+    check_volume(&disk, free - 2);
+}
+
+#[test_case]
+fn unlinked_file_keeps_clusters_while_open() {
+    let disk = device();
+    let fs = mount(ExFatFs::new, &disk, "/orphan");
+    let free = fs.free_clusters().unwrap();
+
+    let file = vfs::open("/orphan/big.bin", OpenFlags::READ_ONLY, 0).unwrap();
+    vfs::unlink("/orphan/big.bin").unwrap();
+    assert_eq!(fs.free_clusters(), Ok(free));
+    let mut buffer = vec![0; 100000];
+    assert_eq!(file.read(&mut buffer), Ok(100000));
+
+    drop(file);
+    assert_eq!(fs.free_clusters(), Ok(free + 25));
+    vfs::unmount("/orphan").unwrap();
+}
+
+#[test_case]
+fn rejects_corrupted_volumes() {
+    let empty = Arc::new(RamBlockDevice::new(512, 64));
+    assert_eq!(ExFatFs::new(empty).err(), Some(Errno::EINVAL));
+
+    // Испорченный загрузочный код не сходится с контрольной суммой области.
+    let disk = device();
+    let mut sector = [0; 512];
+    disk.read_blocks(0, &mut sector).unwrap();
+    sector[200] ^= 1;
+    disk.write_blocks(0, &sector).unwrap();
+    assert_eq!(ExFatFs::new(disk).err(), Some(Errno::EINVAL));
+
+    // Длина тома в байтах не помещается в u64, хотя контрольная сумма верна.
+    let disk = device();
+    let mut region = vec![0; 12 * 512];
+    disk.read_blocks(0, &mut region).unwrap();
+    region[72..80].copy_from_slice(&(u64::MAX / 256).to_le_bytes());
+    let checksum = region[..11 * 512]
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| !matches!(index, 106 | 107 | 112))
+        .fold(0u32, |sum, (_, &byte)| {
+            sum.rotate_right(1).wrapping_add(byte as u32)
+        });
+    for word in region[11 * 512..].chunks_exact_mut(4) {
+        word.copy_from_slice(&checksum.to_le_bytes());
+    }
+    disk.write_blocks(0, &region).unwrap();
+    assert_eq!(ExFatFs::new(disk).err(), Some(Errno::EINVAL));
+}
@@ -0,0 +1,341 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, sync::Arc, vec, vec::Vec};
+use enigma_kernel::drivers::ahci;
+use enigma_kernel::drivers::block::{BlockDevice, RamBlockDevice};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::fs::ext2::Ext2Fs;
+use enigma_kernel::fs::vfs::{self, FileSystem, FileType, MountFlags, OpenFlags};
+use enigma_kernel::process::fd::File;
+use enigma_kernel::test_util::{CREATE, Setup, fixture, mount, read, sparse_device, write};
+
+// Диск `scratch` подключен к контроллеру AHCI.
+enigma_kernel::test_entry!(Setup {
+    apic: true,
+    deferred: true,
+    pci: true,
+    ahci: true,
+    fs: true,
+    ..Setup::default()
+});
+
+/// Образ, созданный крейтом `enigma-test-images` утилитой `mke2fs`: блоки
+/// по 1 КиБ, 4 группы. Без e2fsprogs образа в ramdisk нет, и тесты падают.
+const EXT2: &str = "ext2.sparse";
+
+/// Разворачивает образ в диск в памяти.
+fn device() -> Arc<RamBlockDevice> {
+    sparse_device(fixture(EXT2))
+}
+
+fn read_disk(device: &RamBlockDevice, offset: u64, len: usize) -> Vec<u8> {
+    let start = offset / 512;
+    let end = (offset + len as u64).div_ceil(512);
+    let mut data = vec![0; ((end - start) * 512) as usize];
+    device.read_blocks(start, &mut data).unwrap();
+    let skip = (offset % 512) as usize;
+    data[skip..skip + len].to_vec()
+}
+
+/// Сверяет битовые карты со счетчиками свободного места в дескрипторах
+/// групп и суперблоке (как пятый проход `e2fsck`).
+fn check_accounting(device: &RamBlockDevice) {
+    let superblock = read_disk(device, 1024, 1024);
+    let u32_at = |data: &[u8], offset: usize| {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    };
+    let u16_at = |data: &[u8], offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+
+    let blocks = u32_at(&superblock, 4);
+    let first_data_block = u32_at(&superblock, 20);
+    let block_size = 1024 << u32_at(&superblock, 24);
+    let blocks_per_group = u32_at(&superblock, 32);
+    let inodes_per_group = u32_at(&superblock, 40);
+    let groups = (blocks - first_data_block).div_ceil(blocks_per_group);
+    let descriptors = read_disk(
+        device,
+        (first_data_block as u64 + 1) * block_size as u64,
+        groups as usize * 32,
+    );
+
+    let free_bits = |block: u32, count: u32| {
+        let bitmap = read_disk(device, block as u64 * block_size as u64, block_size);
+        (0..count)
+            .filter(|&bit| bitmap[bit as usize / 8] & (1 << (bit % 8)) == 0)
+            .count() as u32
+    };
+
+    let (mut free_blocks, mut free_inodes) = (0, 0);
+    for (group, descriptor) in descriptors.chunks_exact(32).enumerate() {
+        let group_blocks =
+            (blocks - first_data_block - group as u32 * blocks_per_group).min(blocks_per_group);
+        let group_free_blocks = free_bits(u32_at(descriptor, 0), group_blocks);
+        let group_free_inodes = free_bits(u32_at(descriptor, 4), inodes_per_group);
+        assert_eq!(u16_at(descriptor, 12) as u32, group_free_blocks);
+        assert_eq!(u16_at(descriptor, 14) as u32, group_free_inodes);
+        free_blocks += group_free_blocks;
+        free_inodes += group_free_inodes;
+    }
+    assert_eq!(u32_at(&superblock, 12), free_blocks);
+    assert_eq!(u32_at(&superblock, 16), free_inodes);
+}
+
+#[test_case]
+fn reads_generated_image() {
+    let disk = device();
+    let fs = mount(Ext2Fs::new, &disk, "/read");
+    assert_eq!(fs.label(), "ENIGMA");
+    assert_eq!(fs.block_size(), 1024);
+
+    assert_eq!(read("/read/hello.txt").unwrap(), b"Hello, ext2!");
+    assert_eq!(read("/read/link").unwrap(), b"Hello, ext2!");
+    assert_eq!(vfs::readlink("/read/link").as_deref(), Ok("hello.txt"));
+    assert_eq!(vfs::lstat("/read/link").unwrap().kind, FileType::Symlink);
+
+    let hello = vfs::stat("/read/hello.txt").unwrap();
+    let hard = vfs::stat("/read/hard.txt").unwrap();
+    assert_eq!((hello.ino, hello.nlink), (hard.ino, 2));
+    assert_eq!(hello.mode & 0o777, 0o644);
+
+    // 300000 байт: прямые блоки, косвенный и двойной косвенный.
+    let big = read("/read/dir/nested/big.bin").unwrap();
+    assert_eq!(big.len(), 300_000);
+    assert!(
+        big.iter()
+            .enumerate()
+            .all(|(i, &byte)| byte == (i % 251) as u8)
+    );
+
+    assert_eq!(vfs::read_dir("/read/many").unwrap().len(), 100);
+    assert_eq!(read("/read/many/file-99").unwrap(), b"99");
+    assert_eq!(vfs::stat("/read/dir").unwrap().nlink, 3);
+    assert_eq!(
+        vfs::stat("/read/dir/nested/..").unwrap().ino,
+        vfs::stat("/read/dir").unwrap().ino
+    );
+    vfs::unmount("/read").unwrap();
+}
+
+#[test_case]
+fn writes_all_indirection_levels() {
+    let disk = device();
+    let fs = mount(Ext2Fs::new, &disk, "/levels");
+    let free = fs.free_blocks().unwrap();
+
+    // Первые блоки прямой, косвенной, двойной и тройной адресации при
+    // блоке 1 КиБ (256 указателей в блоке).
+    let offsets = [0u64, 12, 12 + 256, 12 + 256 + 65536].map(|block| block * 1024 + 10);
+    let file = vfs::open("/levels/sparse", CREATE, 0o644).unwrap();
+    for (index, &offset) in offsets.iter().enumerate() {
+        file.seek(vfs::SeekFrom::Start(offset)).unwrap();
+        assert_eq!(file.write(format!("level {index}").as_bytes()), Ok(7));
+    }
+    // 4 блока данных и 1 + 2 + 3 косвенных.
+    assert_eq!(fs.free_blocks(), Ok(free - 10));
+    drop(file);
+    vfs::unmount("/levels").unwrap();
+    fs.sync().unwrap();
+
+    let fs = mount(Ext2Fs::new, &disk, "/levels");
+    let file = vfs::open("/levels/sparse", OpenFlags::READ_WRITE, 0).unwrap();
+    assert_eq!(file.metadata().unwrap().size, offsets[3] + 7);
+    for (index, &offset) in offsets.iter().enumerate() {
+        let mut buffer = [0; 7];
+        file.seek(vfs::SeekFrom::Start(offset)).unwrap();
+        assert_eq!(file.read(&mut buffer), Ok(7));
+        assert_eq!(&buffer, format!("level {index}").as_bytes());
+    }
+    // Дыры читаются нулями.
+    let mut buffer = [1; 64];
+    file.seek(vfs::SeekFrom::Start(5000)).unwrap();
+    assert_eq!(file.read(&mut buffer), Ok(64));
+    assert_eq!(buffer, [0; 64]);
+
+    // Усечение внутри косвенной области освобождает двойное и тройное
+    // деревья целиком.
+    vfs::truncate("/levels/sparse", offsets[1] + 3).unwrap();
+    assert_eq!(fs.free_blocks(), Ok(free - 3));
+    vfs::truncate("/levels/sparse", offsets[1] + 100).unwrap();
+    assert_eq!(
+        read("/levels/sparse").unwrap()[offsets[1] as usize + 3..],
+        [0; 97]
+    );
+    vfs::truncate("/levels/sparse", 0).unwrap();
+    assert_eq!(fs.free_blocks(), Ok(free));
+    check_accounting(&disk);
+    vfs::unmount("/levels").unwrap();
+}
+
+#[test_case]
+fn directories_and_links_keep_bitmaps_consistent() {
+    let disk = device();
+    let fs = mount(Ext2Fs::new, &disk, "/tree");
+    let free = (fs.free_blocks().unwrap(), fs.free_inodes().unwrap());
+
+    vfs::mkdir("/tree/a", 0o755).unwrap();
+    vfs::mkdir("/tree/a/b", 0o700).unwrap();
+    assert_eq!(vfs::stat("/tree/a").unwrap().nlink, 3);
+    // Длинные имена не помещаются в один блок каталога.
+    for i in 0..60 {
+        write(
+            &format!("/tree/a/b/a rather long file name {i:03}"),
+            b"data",
+        )
+        .unwrap();
+    }
+    assert!(vfs::stat("/tree/a/b").unwrap().size > 1024);
+    for i in (0..60).step_by(2) {
+        vfs::unlink(&format!("/tree/a/b/a rather long file name {i:03}")).unwrap();
+    }
+    assert_eq!(vfs::read_dir("/tree/a/b").unwrap().len(), 30);
+
+    vfs::rename("/tree/a/b", "/tree/dir/moved").unwrap();
+    assert_eq!(
+        vfs::stat("/tree/dir/moved/..").unwrap().ino,
+        vfs::stat("/tree/dir").unwrap().ino
+    );
+    assert_eq!(vfs::stat("/tree/a").unwrap().nlink, 2);
+    assert_eq!(vfs::stat("/tree/dir").unwrap().nlink, 4);
+    assert_eq!(
+        vfs::rename("/tree/dir", "/tree/dir/moved/x"),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(vfs::rmdir("/tree/dir/moved"), Err(Errno::ENOTEMPTY));
+
+    vfs::rename("/tree/hello.txt", "/tree/many/file-0").unwrap();
+    assert_eq!(read("/tree/many/file-0").unwrap(), b"Hello, ext2!");
+    vfs::link("/tree/many/file-0", "/tree/a/third").unwrap();
+    assert_eq!(vfs::stat("/tree/hard.txt").unwrap().nlink, 3);
+    vfs::symlink("../many/file-0", "/tree/a/short").unwrap();
+    let long = "long/".repeat(40);
+    vfs::symlink(&long, "/tree/a/long").unwrap();
+    assert_eq!(read("/tree/a/short").unwrap(), b"Hello, ext2!");
+    assert_eq!(vfs::readlink("/tree/a/long"), Ok(long));
+    check_accounting(&disk);
+
+    vfs::unmount("/tree").unwrap();
+    let fs = mount(Ext2Fs::new, &disk, "/tree");
+    for entry in vfs::read_dir("/tree/dir/moved").unwrap() {
+        vfs::unlink(&format!("/tree/dir/moved/{}", entry.name)).unwrap();
+    }
+    vfs::rmdir("/tree/dir/moved").unwrap();
+    for name in ["third", "short", "long"] {
+        vfs::unlink(&format!("/tree/a/{name}")).unwrap();
+    }
+    vfs::rmdir("/tree/a").unwrap();
+    // Осталось только удаление прежнего many/file-0.
+    assert_eq!(fs.free_blocks(), Ok(free.0 + 1));
+    assert_eq!(fs.free_inodes(), Ok(free.1 + 1));
+    check_accounting(&disk);
+    vfs::unmount("/tree").unwrap();
+}
+
+#[test_case]
+fn unlinked_file_stays_open() {
+    let disk = device();
+    let fs = mount(Ext2Fs::new, &disk, "/orphan");
+    let free = fs.free_inodes().unwrap();
+
+    let file = vfs::open("/orphan/open", CREATE, 0o644).unwrap();
+    file.write(&[7; 4096]).unwrap();
+    vfs::unlink("/orphan/open").unwrap();
+    assert_eq!(file.metadata().unwrap().nlink, 0);
+    assert_eq!(fs.free_inodes(), Ok(free - 1));
+
+    let mut buffer = [0; 4096];
+    file.seek(vfs::SeekFrom::Start(0)).unwrap();
+    assert_eq!(file.read(&mut buffer), Ok(4096));
+    assert!(buffer.iter().all(|&byte| byte == 7));
+
+    drop(file);
+    assert_eq!(fs.free_inodes(), Ok(free));
+    check_accounting(&disk);
+    vfs::unmount("/orphan").unwrap();
+}
+
+#[test_case]
+fn rejects_unsupported_volumes() {
+    let disk = device();
+    let superblock = |change: fn(&mut [u8])| {
+        let copy = Arc::new(RamBlockDevice::new(512, disk.block_count()));
+        let mut block = vec![0; 512];
+        for lba in 0..disk.block_count() {
+            disk.read_blocks(lba, &mut block).unwrap();
+            if lba == 2 {
+                change(&mut block);
+            }
+            copy.write_blocks(lba, &block).unwrap();
+        }
+        copy
+    };
+
+    let empty = Arc::new(RamBlockDevice::new(512, 64));
+    assert_eq!(Ext2Fs::new(empty).err(), Some(Errno::EINVAL));
+    // Экстенты (ext4) драйвер не читает.
+    let extents = superblock(|raw| raw[96] |= 0x40);
+    assert_eq!(Ext2Fs::new(extents).err(), Some(Errno::EINVAL));
+
+    // Неизвестная возможность из `ro_compat`: только чтение.
+    let fs = Ext2Fs::new(superblock(|raw| raw[103] |= 0x80)).unwrap();
+    assert!(fs.is_read_only());
+    vfs::mkdir("/ro", 0o755).unwrap();
+    vfs::mount(fs, "/ro", MountFlags::default()).unwrap();
+    assert_eq!(read("/ro/hello.txt").unwrap(), b"Hello, ext2!");
+    assert_eq!(write("/ro/new", b"x"), Err(Errno::EROFS));
+    vfs::unmount("/ro").unwrap();
+}
+
+/// Диск `scratch` подключает программа запуска тестов (`src/runner.rs`
+/// корневого пакета): пустой том ext2, который после теста проверяется
+/// `e2fsck -n`. Тест оставляет на нем файлы всех уровней адресации, каталоги
+/// на несколько блоков, жесткие и символические ссылки.
+#[test_case]
+fn leaves_volume_consistent_for_e2fsck() {
+    let disk = ahci::disks()
+        .into_iter()
+        .find(|disk| disk.identify().serial == "scratch")
+        .expect("no scratch disk: run the tests through the q35 runner");
+    let fs = mount(Ext2Fs::new, &disk, "/scratch");
+
+    let big: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+    write("/scratch/big.bin", &big).unwrap();
+    let file = vfs::open("/scratch/sparse", CREATE, 0o644).unwrap();
+    file.seek(vfs::SeekFrom::Start((12 + 256 + 65536) * 1024))
+        .unwrap();
+    file.write(b"triple").unwrap();
+    drop(file);
+    vfs::truncate("/scratch/big.bin", 100_000).unwrap();
+
+    vfs::mkdir("/scratch/a", 0o755).unwrap();
+    vfs::mkdir("/scratch/a/b", 0o700).unwrap();
+    for i in 0..60 {
+        write(
+            &format!("/scratch/a/b/a rather long file name {i:03}"),
+            b"data",
+        )
+        .unwrap();
+    }
+    for i in (0..60).step_by(3) {
+        vfs::unlink(&format!("/scratch/a/b/a rather long file name {i:03}")).unwrap();
+    }
+    vfs::mkdir("/scratch/c", 0o755).unwrap();
+    vfs::rename("/scratch/a/b", "/scratch/c/moved").unwrap();
+    vfs::rename("/scratch/sparse", "/scratch/c/sparse").unwrap();
+    vfs::link("/scratch/big.bin", "/scratch/a/hard").unwrap();
+    vfs::symlink("../big.bin", "/scratch/a/short").unwrap();
+    vfs::symlink(&"long/".repeat(40), "/scratch/a/long").unwrap();
+
+    vfs::mkdir("/scratch/gone", 0o755).unwrap();
+    write("/scratch/gone/file", &[1; 5000]).unwrap();
+    vfs::unlink("/scratch/gone/file").unwrap();
+    vfs::rmdir("/scratch/gone").unwrap();
+
+    vfs::unmount("/scratch").unwrap();
+    fs.sync().unwrap();
+}
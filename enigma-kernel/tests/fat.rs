@@ -0,0 +1,215 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, sync::Arc, vec::Vec};
+use enigma_kernel::drivers::block::{BlockDevice, RamBlockDevice};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::fs::fat::{FatFs, FatType};
+use enigma_kernel::fs::vfs::{self, FileSystem, FileType};
+use enigma_kernel::process::fd::File;
+use enigma_kernel::test_util::{CREATE, Setup, fixture, mount, names, read, sparse_device, write};
+
+enigma_kernel::test_entry!(Setup {
+    fs: true,
+    ..Setup::default()
+});
+
+/// Образы, созданные крейтом `enigma-test-images` библиотекой `fatfs`.
+const FAT12: &str = "fat12.sparse";
+const FAT16: &str = "fat16.sparse";
+const FAT32: &str = "fat32.sparse";
+
+#[test_case]
+fn reads_generated_images() {
+    let images = [
+        (FAT12, FatType::Fat12, "/read12"),
+        (FAT16, FatType::Fat16, "/read16"),
+        (FAT32, FatType::Fat32, "/read32"),
+    ];
+
+    for (image, fat_type, path) in images {
+        let fs = mount(FatFs::new, &sparse_device(fixture(image)), path);
+        assert_eq!(fs.fat_type(), fat_type);
+        assert_eq!(fs.label(), "ENIGMA");
+
+        let file = |name: &str| format!("{path}/{name}");
+        assert_eq!(read(&file("HELLO.TXT")).unwrap(), b"Hello, FAT!");
+        // Имена не различают регистр.
+        assert_eq!(read(&file("hello.txt")).unwrap(), b"Hello, FAT!");
+        assert_eq!(
+            read(&file("A long file name.text")).unwrap(),
+            b"long names work"
+        );
+        assert_eq!(read(&file("Кириллица.txt")).unwrap(), "привет".as_bytes());
+
+        let deep = read(&file("dir/nested/deep.bin")).unwrap();
+        assert_eq!(deep.len(), 10000);
+        assert!(
+            deep.iter()
+                .enumerate()
+                .all(|(i, &byte)| byte == (i % 251) as u8)
+        );
+
+        assert_eq!(
+            names(path),
+            [
+                "A long file name.text",
+                "HELLO.TXT",
+                "dir",
+                "many",
+                "Кириллица.txt"
+            ]
+        );
+        assert_eq!(vfs::read_dir(&file("many")).unwrap().len(), 40);
+        assert_eq!(read(&file("many/file-39")).unwrap(), b"39");
+        assert_eq!(vfs::stat(&file("dir")).unwrap().kind, FileType::Directory);
+        assert_eq!(vfs::getcwd().as_deref(), Ok("/"));
+    }
+}
+
+#[test_case]
+fn changes_survive_remount() {
+    let disk = sparse_device(fixture(FAT16));
+    let fs = mount(FatFs::new, &disk, "/write");
+    let free = fs.free_clusters().unwrap();
+
+    vfs::mkdir("/write/New Directory", 0o755).unwrap();
+    let data: Vec<u8> = (0..20000).map(|i| (i * 7) as u8).collect();
+    assert_eq!(
+        write("/write/New Directory/data file.bin", &data),
+        Ok(data.len())
+    );
+    write("/write/short.txt", b"x").unwrap();
+    vfs::rename("/write/short.txt", "/write/New Directory/Renamed.txt").unwrap();
+    vfs::unlink("/write/HELLO.TXT").unwrap();
+    assert_eq!(
+        vfs::mkdir("/write/new directory", 0o755),
+        Err(Errno::EEXIST)
+    );
+    assert_eq!(vfs::symlink("x", "/write/link"), Err(Errno::EPERM));
+    vfs::unmount("/write").unwrap();
+    fs.sync().unwrap();
+
+    let fs = mount(FatFs::new, &disk, "/write");
+    assert_eq!(read("/write/New Directory/data file.bin").unwrap(), data);
+    assert_eq!(read("/write/new directory/renamed.txt").unwrap(), b"x");
+    assert_eq!(vfs::stat("/write/HELLO.TXT"), Err(Errno::ENOENT));
+    assert_eq!(
+        vfs::stat("/write/New Directory/..").unwrap().ino,
+        vfs::stat("/write").unwrap().ino
+    );
+
+    assert_eq!(vfs::rmdir("/write/New Directory"), Err(Errno::ENOTEMPTY));
+    vfs::unlink("/write/New Directory/data file.bin").unwrap();
+    vfs::unlink("/write/New Directory/Renamed.txt").unwrap();
+    vfs::rmdir("/write/New Directory").unwrap();
+    // Освобождены все кластеры, кроме кластера удаленного HELLO.TXT.
+    assert_eq!(fs.free_clusters(), Ok(free + 1));
+    vfs::unmount("/write").unwrap();
+}
+
+#[test_case]
+fn directories_grow_and_move() {
+    let disk = sparse_device(fixture(FAT12));
+    mount(FatFs::new, &disk, "/grow");
+
+    // В кластере FAT12-образа 16 записей, а у каждого имени есть длинная
+    // запись: каталог займет несколько кластеров.
+    vfs::mkdir("/grow/many/more", 0o755).unwrap();
+    for i in 0..50 {
+        write(&format!("/grow/many/more/entry number {i}"), b"").unwrap();
+    }
+    assert_eq!(vfs::read_dir("/grow/many/more").unwrap().len(), 50);
+
+    vfs::rename("/grow/many/more", "/grow/dir/moved").unwrap();
+    assert_eq!(vfs::read_dir("/grow/dir/moved").unwrap().len(), 50);
+    assert_eq!(
+        vfs::stat("/grow/dir/moved/..").unwrap().ino,
+        vfs::stat("/grow/dir").unwrap().ino
+    );
+    assert_eq!(
+        vfs::rename("/grow/dir", "/grow/dir/moved/x"),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        vfs::rename("/grow/dir", "/grow/HELLO.TXT"),
+        Err(Errno::ENOTDIR)
+    );
+    assert_eq!(
+        vfs::rename("/grow/HELLO.TXT", "/grow/dir"),
+        Err(Errno::EISDIR)
+    );
+
+    vfs::unmount("/grow").unwrap();
+    mount(FatFs::new, &disk, "/grow");
+    assert_eq!(read("/grow/dir/moved/entry number 49").unwrap(), b"");
+    assert_eq!(vfs::getcwd().as_deref(), Ok("/"));
+    vfs::unmount("/grow").unwrap();
+}
+
+#[test_case]
+fn fsinfo_tracks_free_clusters() {
+    let disk = sparse_device(fixture(FAT32));
+    let fs = mount(FatFs::new, &disk, "/info");
+    let free = fs.free_clusters().unwrap();
+
+    write("/info/three", &[1; 3 * 512]).unwrap();
+    assert_eq!(fs.free_clusters(), Ok(free - 3));
+    vfs::truncate("/info/three", 512).unwrap();
+    assert_eq!(fs.free_clusters(), Ok(free - 1));
+    vfs::unmount("/info").unwrap();
+
+    // Новый экземпляр берет счетчик из FSInfo.
+    let fs = mount(FatFs::new, &disk, "/info");
+    assert_eq!(fs.free_clusters(), Ok(free - 1));
+    vfs::unmount("/info").unwrap();
+}
+
+#[test_case]
+fn unlinked_file_keeps_clusters_while_open() {
+    let disk = sparse_device(fixture(FAT32));
+    let fs = mount(FatFs::new, &disk, "/orphan");
+    let free = fs.free_clusters().unwrap();
+
+    let file = vfs::open("/orphan/open", CREATE, 0o644).unwrap();
+    file.write(&[7; 2048]).unwrap();
+    vfs::unlink("/orphan/open").unwrap();
+    write("/orphan/other", &[8; 2048]).unwrap();
+
+    assert_eq!(fs.free_clusters(), Ok(free - 8));
+    let mut buffer = [0; 2048];
+    file.seek(vfs::SeekFrom::Start(0)).unwrap();
+    assert_eq!(file.read(&mut buffer), Ok(2048));
+    assert!(buffer.iter().all(|&byte| byte == 7));
+
+    drop(file);
+    assert_eq!(fs.free_clusters(), Ok(free - 4));
+    vfs::unmount("/orphan").unwrap();
+}
+
+#[test_case]
+fn rejects_corrupted_volumes() {
+    let empty = Arc::new(RamBlockDevice::new(512, 64));
+    assert_eq!(FatFs::new(empty).err(), Some(Errno::EINVAL));
+
+    // Образ FAT16 на диске меньше, чем записано в загрузочном секторе.
+    let disk = sparse_device(fixture(FAT16));
+    let truncated = Arc::new(RamBlockDevice::new(512, 1024));
+    let mut sector = [0; 512];
+    disk.read_blocks(0, &mut sector).unwrap();
+    truncated.write_blocks(0, &sector).unwrap();
+    assert_eq!(FatFs::new(truncated).err(), Some(Errno::EINVAL));
+
+    // Размер FAT, при котором сумма служебных секторов переполняется.
+    let disk = sparse_device(fixture(FAT32));
+    disk.read_blocks(0, &mut sector).unwrap();
+    sector[16] = 2;
+    sector[36..40].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    disk.write_blocks(0, &sector).unwrap();
+    assert_eq!(FatFs::new(disk).err(), Some(Errno::EINVAL));
+}
@@ -0,0 +1,97 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use enigma_kernel::fpu::{self, FpuMode};
+use enigma_kernel::test_util::Setup;
+use enigma_kernel::{thread, time};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+// Потоки переключает прерывание таймера LAPIC.
+enigma_kernel::test_entry!(Setup {
+    apic: true,
+    ..Setup::default()
+});
+
+/// Сколько раз каждый поток проверяет свои регистры.
+const ROUNDS: usize = 8;
+
+/// Включен ли компонент AVX (регистры YMM).
+fn avx_enabled() -> bool {
+    fpu::xsave_enabled() && XCr0::read().contains(XCr0Flags::AVX)
+}
+
+/// Загружает `pattern` в XMM0 и, если есть AVX, в YMM0.
+fn load_vector(pattern: &[u8; 32]) {
+    unsafe {
+        if avx_enabled() {
+            asm!("vmovdqu ymm0, [{}]", in(reg) pattern.as_ptr(), options(nostack, readonly));
+        } else {
+            asm!("movdqu xmm0, [{}]", in(reg) pattern.as_ptr(), options(nostack, readonly));
+        }
+    }
+}
+
+/// Читает XMM0 и, если есть AVX, YMM0.
+fn store_vector() -> [u8; 32] {
+    let mut value = [0; 32];
+    unsafe {
+        if avx_enabled() {
+            asm!("vmovdqu [{}], ymm0", in(reg) value.as_mut_ptr(), options(nostack));
+        } else {
+            asm!("movdqu [{}], xmm0", in(reg) value.as_mut_ptr(), options(nostack));
+        }
+    }
+    value
+}
+
+/// Запускает два потока с разными значениями векторных регистров и ждет,
+/// пока каждый несколько раз переживет вытеснение таймером.
+fn run_threads() {
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+    DONE.store(0, Ordering::Release);
+
+    for seed in [0x11u8, 0xA7] {
+        thread::spawn("fpu", move || {
+            let mut pattern = [0; 32];
+            for (index, byte) in pattern.iter_mut().enumerate() {
+                *byte = seed.wrapping_add(index as u8);
+            }
+            let len = if avx_enabled() { 32 } else { 16 };
+
+            for _ in 0..ROUNDS {
+                load_vector(&pattern);
+                // Ждем без уступки процессора, чтобы переключение вызвал таймер.
+                let start = time::ticks();
+                while time::ticks() < start + 2 {
+                    core::hint::spin_loop();
+                }
+                assert_eq!(store_vector()[..len], pattern[..len]);
+            }
+            DONE.fetch_add(1, Ordering::AcqRel);
+        });
+    }
+
+    while DONE.load(Ordering::Acquire) < 2 {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn eager_switch_preserves_vector_registers() {
+    fpu::set_mode(FpuMode::Eager);
+    run_threads();
+}
+
+#[test_case]
+fn lazy_switch_preserves_vector_registers() {
+    fpu::set_mode(FpuMode::Lazy);
+    run_threads();
+    fpu::set_mode(FpuMode::Eager);
+}
@@ -0,0 +1,230 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use enigma_kernel::drivers::block::partition::{self, Guid, PartitionKind, PartitionTable, Scheme};
+use enigma_kernel::drivers::block::{self, BlockDevice, RamBlockDevice};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::fs::fat::FatFs;
+use enigma_kernel::fs::vfs::FileSystem;
+use enigma_kernel::test_util::{fixture, sparse_device};
+
+enigma_kernel::test_entry!();
+
+/// Диски, размеченные крейтом `enigma-test-images` библиотеками `gpt` и
+/// `mbrman`.
+const GPT: &str = "gpt.sparse";
+const MBR: &str = "mbr.sparse";
+
+const LINUX_FS: Guid = Guid::new(
+    0x0FC6_3DAF,
+    0x8483,
+    0x4772,
+    [0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4],
+);
+const BASIC_DATA: Guid = Guid::new(
+    0xEBD0_A0A2,
+    0xB9E5,
+    0x4433,
+    [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7],
+);
+
+/// Метка, которую `enigma-test-images` записал в начало раздела.
+fn marker(device: &dyn BlockDevice) -> Vec<u8> {
+    let mut block = vec![0; 512];
+    device.read_blocks(0, &mut block).unwrap();
+    let len = block.iter().position(|&byte| byte == 0).unwrap();
+    block.truncate(len);
+    block
+}
+
+/// Портит байт `offset` блока `lba`.
+fn corrupt(device: &RamBlockDevice, lba: u64, offset: usize) {
+    let mut block = vec![0; 512];
+    device.read_blocks(lba, &mut block).unwrap();
+    block[offset] ^= 0xFF;
+    device.write_blocks(lba, &block).unwrap();
+}
+
+fn ranges(table: &PartitionTable) -> Vec<(u32, u64, u64)> {
+    table
+        .partitions
+        .iter()
+        .map(|info| (info.number, info.start, info.count))
+        .collect()
+}
+
+#[test_case]
+fn reads_gpt_disk() {
+    let disk = sparse_device(fixture(GPT));
+    let table = partition::scan(&*disk).unwrap().unwrap();
+    assert_eq!(
+        table.scheme,
+        Scheme::Gpt {
+            disk_guid: Guid::new(
+                0x0123_4567,
+                0x89AB,
+                0xCDEF,
+                [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]
+            ),
+            primary_valid: true,
+            backup_valid: true,
+        }
+    );
+    assert_eq!(
+        ranges(&table),
+        [(1, 2048, 2880), (2, 6144, 4096), (3, 10240, 2048)]
+    );
+
+    let kinds: Vec<_> = table.partitions.iter().map(|info| info.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            PartitionKind::Gpt(Guid::ESP),
+            PartitionKind::Gpt(LINUX_FS),
+            PartitionKind::Gpt(BASIC_DATA),
+        ]
+    );
+    let names: Vec<_> = table.partitions.iter().map(|info| &*info.name).collect();
+    assert_eq!(names, ["EFI System", "data", "Рабочий"]);
+    assert!(table.partitions.iter().all(|info| info.guid.is_some()));
+    assert_eq!(
+        alloc::format!("{}", Guid::ESP),
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+    );
+}
+
+#[test_case]
+fn registers_gpt_partitions() {
+    let disk: Arc<dyn BlockDevice> = sparse_device(fixture(GPT));
+    block::register("gpt0", disk.clone()).unwrap();
+    let partitions = partition::register_partitions("gpt0", &disk).unwrap();
+    let names: Vec<_> = partitions.iter().map(|part| part.name()).collect();
+    assert_eq!(names, ["gpt0p1", "gpt0p2", "gpt0p3"]);
+    for name in ["gpt0", "gpt0p1", "gpt0p2", "gpt0p3"] {
+        assert!(block::devices().iter().any(|device| device == name));
+    }
+    assert_eq!(
+        partition::register_partitions("gpt0", &disk).map(|_| ()),
+        Err(Errno::EEXIST),
+        "разделы уже зарегистрированы"
+    );
+
+    let data = block::get("gpt0p2").unwrap();
+    assert_eq!(data.block_count(), 4096);
+    assert_eq!(marker(&*data), b"partition 2");
+    assert_eq!(marker(&*partitions[2]), b"partition 3");
+    let mut buffer = vec![0; 1024];
+    assert_eq!(data.read_blocks(4095, &mut buffer), Err(Errno::EIO));
+
+    // Системный раздел EFI найден сам и читается как FAT.
+    let esp = partition::esp().unwrap();
+    assert_eq!(esp.name(), "gpt0p1");
+    assert!(esp.info().is_esp());
+    let fs = FatFs::new(esp).unwrap();
+    let file = fs.root().lookup("HELLO.TXT").unwrap();
+    let mut text = vec![0; 11];
+    assert_eq!(file.read_at(0, &mut text), Ok(11));
+    assert_eq!(text, b"Hello, FAT!");
+}
+
+#[test_case]
+fn falls_back_to_backup_gpt() {
+    let expected = partition::scan(&*sparse_device(fixture(GPT)))
+        .unwrap()
+        .unwrap();
+    let valid = |table: &PartitionTable| match table.scheme {
+        Scheme::Gpt {
+            primary_valid,
+            backup_valid,
+            ..
+        } => (primary_valid, backup_valid),
+        Scheme::Mbr => panic!("GPT read as MBR"),
+    };
+
+    let disk = sparse_device(fixture(GPT));
+    corrupt(&disk, 32767, 24);
+    let table = partition::scan(&*disk).unwrap().unwrap();
+    assert_eq!(valid(&table), (true, false));
+    assert_eq!(table.partitions, expected.partitions);
+
+    let disk = sparse_device(fixture(GPT));
+    corrupt(&disk, 1, 24);
+    let table = partition::scan(&*disk).unwrap().unwrap();
+    assert_eq!(valid(&table), (false, true));
+    assert_eq!(table.partitions, expected.partitions);
+
+    // Испорчены записи, а не заголовок: не сходится CRC массива.
+    let disk = sparse_device(fixture(GPT));
+    corrupt(&disk, 2, 0);
+    assert_eq!(
+        valid(&partition::scan(&*disk).unwrap().unwrap()),
+        (false, true)
+    );
+
+    corrupt(&disk, 32767, 24);
+    assert_eq!(partition::scan(&*disk), Err(Errno::EINVAL));
+}
+
+#[test_case]
+fn reads_mbr_disk() {
+    let disk: Arc<dyn BlockDevice> = sparse_device(fixture(MBR));
+    let table = partition::scan(&*disk).unwrap().unwrap();
+    assert_eq!(table.scheme, Scheme::Mbr);
+    assert_eq!(
+        ranges(&table),
+        [
+            (1, 2048, 2048),
+            (2, 4096, 4096),
+            (5, 10240, 2048),
+            (6, 14336, 2048),
+        ]
+    );
+    let kinds: Vec<_> = table.partitions.iter().map(|info| info.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            PartitionKind::Mbr(0x0C),
+            PartitionKind::Mbr(0x83),
+            PartitionKind::Mbr(0x83),
+            PartitionKind::Mbr(0x07),
+        ]
+    );
+    let bootable: Vec<_> = table.partitions.iter().map(|info| info.bootable).collect();
+    assert_eq!(bootable, [true, false, false, false]);
+
+    let partitions = partition::register_partitions("sdb", &disk).unwrap();
+    for part in &partitions {
+        let number = part.info().number;
+        assert_eq!(part.name(), alloc::format!("sdb{number}"));
+        assert_eq!(
+            marker(&**part),
+            alloc::format!("partition {number}").as_bytes()
+        );
+    }
+    for part in &partitions {
+        block::unregister(part.name());
+    }
+    assert!(block::get("sdb5").is_none());
+}
+
+#[test_case]
+fn ignores_blank_disk() {
+    let disk = RamBlockDevice::new(512, 1024);
+    assert_eq!(partition::scan(&disk), Ok(None));
+    let disk: Arc<dyn BlockDevice> = Arc::new(disk);
+    assert!(
+        partition::register_partitions("blank", &disk)
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        partition::partition_name("nvme0n1", 2),
+        String::from("nvme0n1p2")
+    );
+}
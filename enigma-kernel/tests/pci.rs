@@ -0,0 +1,210 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use enigma_kernel::drivers::pci::{self, Address, Bar, Device, DeviceId, Driver, device, msi};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::interrupts::{self, VectorHandler};
+use enigma_kernel::test_util::Setup;
+
+enigma_kernel::test_entry!(Setup {
+    pci: true,
+    ..Setup::default()
+});
+
+/// Хост-мост есть и у `pc` (i440FX), и у `q35`.
+const HOST_BRIDGE: DeviceId = DeviceId::class(0x06, 0x00, None);
+/// Мост ISA (PIIX3) или LPC (ICH9).
+const ISA_BRIDGE: DeviceId = DeviceId::class(0x06, 0x01, None);
+
+static PROBED: AtomicUsize = AtomicUsize::new(0);
+
+fn probe_host(device: &Arc<Device>) -> Result<(), Errno> {
+    assert_eq!(device.address(), Address::new(0, 0, 0, 0));
+    PROBED.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+fn refuse(_device: &Arc<Device>) -> Result<(), Errno> {
+    Err(Errno::ENODEV)
+}
+
+static HOST_DRIVER: Driver = Driver {
+    name: "test-host",
+    ids: &[HOST_BRIDGE],
+    probe: probe_host,
+};
+static SECOND_HOST_DRIVER: Driver = Driver {
+    name: "test-host-2",
+    ids: &[HOST_BRIDGE],
+    probe: probe_host,
+};
+static REFUSING_DRIVER: Driver = Driver {
+    name: "test-refuse",
+    ids: &[ISA_BRIDGE],
+    probe: refuse,
+};
+
+#[test_case]
+fn finds_chipset_devices() {
+    let host = pci::get(Address::new(0, 0, 0, 0)).unwrap();
+    assert_eq!(host.vendor_id(), 0x8086);
+    assert!(HOST_BRIDGE.matches(&host));
+    assert_eq!(host.read_u16(device::VENDOR_ID), 0x8086);
+    assert_eq!(pci::find(ISA_BRIDGE).len(), 1);
+
+    let devices = pci::devices();
+    assert!(devices.is_sorted_by_key(|device| device.address()));
+    for device in &devices {
+        assert_eq!(device.read_u16(device::VENDOR_ID + 2), device.device_id());
+        for capability in device.capabilities() {
+            assert_eq!(capability.offset % 4, 0);
+            assert!(capability.offset >= 0x40);
+        }
+    }
+}
+
+#[test_case]
+fn sizes_bars() {
+    // Стандартная видеокарта QEMU: кадровый буфер в BAR 0.
+    let vga = pci::find(DeviceId::class(0x03, 0x00, None));
+    let Some(Bar::Memory {
+        address,
+        size,
+        prefetchable,
+        ..
+    }) = vga[0].bar(0)
+    else {
+        panic!("no framebuffer BAR");
+    };
+    assert!(prefetchable);
+    assert!(size >= 16 << 20);
+
+    for device in pci::devices() {
+        for (_, bar) in device.bars() {
+            let size = bar.size();
+            assert!(size.is_power_of_two(), "{device}: {bar:?}");
+            let address = match bar {
+                Bar::Memory { address, .. } => address,
+                Bar::Io { port, .. } => port as u64,
+            };
+            assert_eq!(address % size, 0, "{device}: {bar:?}");
+        }
+    }
+
+    // Определение размера не сдвигает BAR и не выключает декодирование.
+    assert_eq!(vga[0].read_u32(device::BAR0) & !0xF, address as u32 & !0xF);
+    assert_ne!(vga[0].command() & device::COMMAND_MEMORY, 0);
+}
+
+#[test_case]
+fn binds_drivers() {
+    assert_eq!(pci::register_driver(&HOST_DRIVER), 1);
+    assert_eq!(PROBED.load(Ordering::Relaxed), 1);
+    let host = pci::get(Address::new(0, 0, 0, 0)).unwrap();
+    assert_eq!(host.driver(), Some("test-host"));
+
+    // Устройство уже занято.
+    assert_eq!(pci::register_driver(&SECOND_HOST_DRIVER), 0);
+    assert_eq!(PROBED.load(Ordering::Relaxed), 1);
+
+    // Драйвер отказался: устройство остается свободным.
+    assert_eq!(pci::register_driver(&REFUSING_DRIVER), 0);
+    assert_eq!(pci::find(ISA_BRIDGE)[0].driver(), None);
+}
+
+fn ignore() -> VectorHandler {
+    Arc::new(|_vector| {})
+}
+
+#[test_case]
+fn allocates_vectors() {
+    let free = interrupts::free_vector_count();
+    let single = interrupts::allocate_vector(ignore()).unwrap();
+    assert!(interrupts::DYNAMIC_VECTORS.contains(&single));
+
+    // Блок для MSI выровнен на свой размер.
+    let block = interrupts::allocate_vectors(8, ignore()).unwrap();
+    assert_eq!(block % 8, 0);
+    assert!(!(block..block + 8).contains(&single));
+    assert_eq!(interrupts::free_vector_count(), free - 9);
+    assert_eq!(
+        interrupts::allocate_vectors(3, ignore()),
+        Err(Errno::EINVAL)
+    );
+
+    interrupts::free_vectors(block, 8);
+    interrupts::free_vectors(single, 1);
+    assert_eq!(interrupts::free_vector_count(), free);
+
+    // Кончились векторы - ENOSPC, а не чужой вектор.
+    let mut taken = alloc::vec::Vec::new();
+    while let Ok(vector) = interrupts::allocate_vector(ignore()) {
+        assert_ne!(vector, enigma_kernel::syscall::SYSCALL_VECTOR);
+        taken.push(vector);
+    }
+    assert_eq!(taken.len(), free);
+    assert_eq!(interrupts::allocate_vector(ignore()), Err(Errno::ENOSPC));
+    for vector in taken {
+        interrupts::free_vectors(vector, 1);
+    }
+}
+
+#[test_case]
+fn programs_msi() {
+    let free = interrupts::free_vector_count();
+    // Тесты запускаются на `q35` (`src/runner.rs` корневого пакета), где
+    // MSI есть у AHCI.
+    let device = pci::devices()
+        .into_iter()
+        .find(|device| device.capability(device::CAP_MSI).is_some())
+        .expect("no MSI capable device: run the tests through the q35 runner");
+    let offset = device.capability(device::CAP_MSI).unwrap();
+
+    let vectors = msi::enable_msi(&device, 1, 0, ignore()).unwrap();
+    assert_eq!(vectors.kind(), msi::Kind::Msi);
+    assert_eq!(device.read_u16(offset + 2) & 1, 1);
+    assert_eq!(device.read_u32(offset + 4), 0xFEE0_0000);
+    assert_ne!(device.command() & device::COMMAND_INTX_DISABLE, 0);
+    assert_eq!(
+        msi::enable_msi(&device, 3, 0, ignore()).err(),
+        Some(Errno::EINVAL)
+    );
+    // Повторное включение не перезаписывает чужие векторы.
+    assert_eq!(
+        msi::enable_msi(&device, 1, 0, ignore()).err(),
+        Some(Errno::EBUSY)
+    );
+    assert_eq!(interrupts::free_vector_count(), free - 1);
+    assert_eq!(vectors.set_target(0, 0x100), Err(Errno::EINVAL));
+    vectors.set_target(0, 1).unwrap();
+    assert_eq!(device.read_u32(offset + 4), 0xFEE0_1000);
+    assert_eq!(interrupts::free_vector_count(), free - 1);
+
+    drop(vectors);
+    assert_eq!(device.read_u16(offset + 2) & 1, 0);
+    assert_eq!(device.command() & device::COMMAND_INTX_DISABLE, 0);
+    assert_eq!(interrupts::free_vector_count(), free);
+}
+
+#[test_case]
+fn maps_bar_once() {
+    let (device, index) = pci::devices()
+        .into_iter()
+        .find_map(|device| {
+            let index = device
+                .bars()
+                .find(|(_, bar)| matches!(bar, Bar::Memory { .. }))?
+                .0;
+            Some((device, index))
+        })
+        .expect("no device with a memory BAR");
+    let first = device.map_bar(index).unwrap();
+    assert_eq!(device.map_bar(index), Ok(first));
+}
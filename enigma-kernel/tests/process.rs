@@ -0,0 +1,120 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use enigma_kernel::errno::Errno;
+use enigma_kernel::process::{self, ProcessState, WaitTarget};
+use x86_64::{VirtAddr, structures::paging::PageTableFlags};
+
+enigma_kernel::test_entry!();
+
+#[test_case]
+fn exit_status_is_returned_by_wait() {
+    let child = process::create("child").unwrap();
+    child.spawn_thread("child", || process::exit(42)).unwrap();
+
+    let pid = child.pid();
+    assert_eq!(process::wait(WaitTarget::Pid(pid)), Ok((pid, 42)));
+    assert!(process::get(pid).is_none());
+}
+
+#[test_case]
+fn wait_survives_spurious_wakeups() {
+    use enigma_kernel::thread;
+
+    let waiter = thread::current().unwrap();
+    let child = process::create("child").unwrap();
+    child
+        .spawn_thread("child", move || {
+            // Каждое пробуждение возвращает `wait` на новый круг.
+            for _ in 0..3 {
+                thread::unpark(waiter);
+                thread::yield_now();
+            }
+            process::exit(7)
+        })
+        .unwrap();
+
+    let pid = child.pid();
+    assert_eq!(process::wait(WaitTarget::Pid(pid)), Ok((pid, 7)));
+    assert_eq!(process::try_wait(WaitTarget::Any), Err(Errno::ECHILD));
+}
+
+#[test_case]
+fn terminate_stops_threads_at_safe_point() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use enigma_kernel::thread;
+
+    static LOCK: spin::Mutex<()> = spin::Mutex::new(());
+    static LOCKED: AtomicBool = AtomicBool::new(false);
+
+    let child = process::create("child").unwrap();
+    child
+        .spawn_thread("child", || {
+            let guard = LOCK.lock();
+            LOCKED.store(true, Ordering::Release);
+            while !thread::exit_requested() {
+                thread::yield_now();
+            }
+            // Блокировка отпускается до безопасной точки.
+            drop(guard);
+            process::exit_point();
+            unreachable!("the process is exiting");
+        })
+        .unwrap();
+    while !LOCKED.load(Ordering::Acquire) {
+        thread::yield_now();
+    }
+
+    let pid = child.pid();
+    process::terminate(pid, 9).unwrap();
+    assert_eq!(process::terminate(pid, 10), Err(Errno::ESRCH));
+    assert_eq!(process::wait(WaitTarget::Pid(pid)), Ok((pid, 9)));
+    assert!(LOCK.try_lock().is_some());
+}
+
+#[test_case]
+fn last_thread_exit_makes_zombie() {
+    let child = process::create("child").unwrap();
+    child.spawn_thread("child", || {}).unwrap();
+
+    while child.state() == ProcessState::Alive {
+        enigma_kernel::thread::yield_now();
+    }
+    assert_eq!(child.state(), ProcessState::Zombie(0));
+    assert_eq!(
+        process::try_wait(WaitTarget::Any),
+        Ok(Some((child.pid(), 0)))
+    );
+}
+
+#[test_case]
+fn wait_without_children_fails() {
+    assert_eq!(process::try_wait(WaitTarget::Any), Err(Errno::ECHILD));
+}
+
+#[test_case]
+fn address_space_copies_user_memory() {
+    let child = process::create("memory").unwrap();
+    let address = VirtAddr::new(0x40_0000);
+
+    child
+        .with_address_space(|space| {
+            space.map(address, 8192, PageTableFlags::WRITABLE).unwrap();
+            space.write(address + 4090u64, b"enigma wave").unwrap();
+
+            let mut buffer = [0u8; 11];
+            space.read(address + 4090u64, &mut buffer).unwrap();
+            assert_eq!(&buffer, b"enigma wave");
+
+            assert_eq!(space.write(address + 8190u64, b"abc"), Err(Errno::EFAULT));
+        })
+        .unwrap();
+
+    process::terminate(child.pid(), 1).unwrap();
+    assert_eq!(process::wait(WaitTarget::Any), Ok((child.pid(), 1)));
+}
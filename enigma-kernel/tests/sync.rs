@@ -0,0 +1,224 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc, task::Wake};
+use core::future::Future;
+use core::pin::{Pin, pin};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use enigma_kernel::sync::mpsc::{self, SendError, TryRecvError, TrySendError};
+use enigma_kernel::sync::{Event, Mutex, Notify, Semaphore};
+use enigma_kernel::thread::block_on;
+
+enigma_kernel::test_entry!();
+
+/// Waker, который считает свои пробуждения.
+struct Counter(AtomicUsize);
+
+impl Wake for Counter {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn counter() -> (Arc<Counter>, Waker) {
+    let counter = Arc::new(Counter(AtomicUsize::new(0)));
+    (counter.clone(), Waker::from(counter))
+}
+
+fn wakes(counter: &Counter) -> usize {
+    counter.0.load(Ordering::Relaxed)
+}
+
+fn poll<F: Future + ?Sized>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(waker))
+}
+
+#[test_case]
+fn notify_wakes_waiters_in_order() {
+    let notify = Notify::new();
+    let (first_wakes, first_waker) = counter();
+    let (second_wakes, second_waker) = counter();
+    let mut first = pin!(notify.notified());
+    let mut second = pin!(notify.notified());
+    assert!(poll(first.as_mut(), &first_waker).is_pending());
+    assert!(poll(second.as_mut(), &second_waker).is_pending());
+
+    notify.notify_one();
+    assert_eq!((wakes(&first_wakes), wakes(&second_wakes)), (1, 0));
+    assert!(poll(first.as_mut(), &first_waker).is_ready());
+    notify.notify_waiters();
+    assert_eq!(wakes(&second_wakes), 1);
+    assert!(poll(second.as_mut(), &second_waker).is_ready());
+
+    // Без ждущих `notify_one` сохраняется (но только одно), а
+    // `notify_waiters` - нет.
+    notify.notify_waiters();
+    notify.notify_one();
+    notify.notify_one();
+    let (_, waker) = counter();
+    assert!(poll(pin!(notify.notified()), &waker).is_ready());
+    assert!(poll(pin!(notify.notified()), &waker).is_pending());
+}
+
+#[test_case]
+fn dropped_notified_passes_notification_on() {
+    let notify = Notify::new();
+    let (_, first_waker) = counter();
+    let (second_wakes, second_waker) = counter();
+    let mut first = Box::pin(notify.notified());
+    let mut second = pin!(notify.notified());
+    assert!(poll(first.as_mut(), &first_waker).is_pending());
+    assert!(poll(second.as_mut(), &second_waker).is_pending());
+
+    notify.notify_one();
+    assert_eq!(wakes(&second_wakes), 0);
+    drop(first);
+    assert_eq!(wakes(&second_wakes), 1);
+    assert!(poll(second.as_mut(), &second_waker).is_ready());
+}
+
+#[test_case]
+fn event_stays_set_until_reset() {
+    let event = Event::new();
+    let (count, waker) = counter();
+    let mut wait = pin!(event.wait());
+    assert!(poll(wait.as_mut(), &waker).is_pending());
+    // Повторный опрос тем же waker не добавляет его второй раз.
+    assert!(poll(wait.as_mut(), &waker).is_pending());
+
+    event.set();
+    assert_eq!(wakes(&count), 1);
+    assert!(event.is_set());
+    assert!(poll(wait.as_mut(), &waker).is_ready());
+    assert!(poll(pin!(event.wait()), &waker).is_ready());
+
+    event.reset();
+    assert!(!event.is_set());
+    assert!(poll(pin!(event.wait()), &waker).is_pending());
+}
+
+#[test_case]
+fn semaphore_grants_permits_in_fifo_order() {
+    let semaphore = Semaphore::new(3);
+    let permit = semaphore.try_acquire_many(2).unwrap();
+    assert_eq!(semaphore.available_permits(), 1);
+
+    let (big_wakes, big_waker) = counter();
+    let mut big = pin!(semaphore.acquire_many(3));
+    assert!(poll(big.as_mut(), &big_waker).is_pending());
+    // Свободное разрешение не обгоняет очередь.
+    assert!(semaphore.try_acquire().is_none());
+    let (small_wakes, small_waker) = counter();
+    let mut small = Box::pin(semaphore.acquire());
+    assert!(poll(small.as_mut(), &small_waker).is_pending());
+
+    drop(permit);
+    assert_eq!((wakes(&big_wakes), wakes(&small_wakes)), (1, 0));
+    let Poll::Ready(big_permit) = poll(big.as_mut(), &big_waker) else {
+        panic!("permits were granted");
+    };
+    assert_eq!(semaphore.available_permits(), 0);
+
+    drop(big_permit);
+    assert_eq!(wakes(&small_wakes), 1);
+    assert_eq!(semaphore.available_permits(), 2);
+    // Future удален после выдачи разрешения: оно возвращается.
+    drop(small);
+    assert_eq!(semaphore.available_permits(), 3);
+
+    // Удаленный ждущий уходит из очереди.
+    let mut huge = Box::pin(semaphore.acquire_many(5));
+    assert!(poll(huge.as_mut(), &small_waker).is_pending());
+    drop(huge);
+    semaphore.try_acquire().unwrap().forget();
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[test_case]
+fn mutex_hands_lock_to_waiter() {
+    let mutex = Mutex::new(0);
+    let guard = mutex.try_lock().unwrap();
+    assert!(mutex.try_lock().is_none());
+
+    let (count, waker) = counter();
+    let mut lock = pin!(mutex.lock());
+    assert!(poll(lock.as_mut(), &waker).is_pending());
+    drop(guard);
+    assert_eq!(wakes(&count), 1);
+    // Пока ждущий не опрошен, мьютекс уже принадлежит ему.
+    assert!(mutex.try_lock().is_none());
+
+    let Poll::Ready(mut guard) = poll(lock.as_mut(), &waker) else {
+        panic!("the lock was handed over");
+    };
+    *guard += 1;
+    drop(guard);
+    assert_eq!(*mutex.try_lock().unwrap(), 1);
+}
+
+#[test_case]
+fn bounded_channel_waits_for_space() {
+    let (sender, mut receiver) = mpsc::channel(2);
+    assert_eq!(sender.try_send(1), Ok(()));
+    assert_eq!(sender.try_send(2), Ok(()));
+    assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+
+    {
+        let (count, waker) = counter();
+        let mut send = pin!(sender.send(3));
+        assert!(poll(send.as_mut(), &waker).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(wakes(&count), 1);
+        assert_eq!(poll(send.as_mut(), &waker), Poll::Ready(Ok(())));
+    }
+
+    let second = sender.clone();
+    drop(sender);
+    assert_eq!(block_on(receiver.recv()), Some(2));
+    assert_eq!(receiver.try_recv(), Ok(3));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+
+    // Получатель будится, когда уходит последний отправитель.
+    let (count, waker) = counter();
+    assert!(
+        receiver
+            .poll_recv(&mut Context::from_waker(&waker))
+            .is_pending()
+    );
+    drop(second);
+    assert_eq!(wakes(&count), 1);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+}
+
+#[test_case]
+fn closed_channel_rejects_senders() {
+    let (sender, mut receiver) = mpsc::channel(1);
+    sender.try_send(1).unwrap();
+    let (count, waker) = counter();
+    let mut send = pin!(sender.send(2));
+    assert!(poll(send.as_mut(), &waker).is_pending());
+
+    receiver.close();
+    assert_eq!(wakes(&count), 1);
+    assert_eq!(poll(send.as_mut(), &waker), Poll::Ready(Err(SendError(2))));
+    assert_eq!(sender.try_send(3), Err(TrySendError::Closed(3)));
+    // Уже отправленное значение можно получить.
+    assert_eq!(receiver.try_recv(), Ok(1));
+
+    let (sender, mut receiver) = mpsc::unbounded();
+    for value in 0..1000 {
+        sender.try_send(value).unwrap();
+    }
+    drop(sender);
+    assert_eq!(
+        (0..1000).map(|_| receiver.try_recv().unwrap()).sum::<i32>(),
+        499500
+    );
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+}
@@ -0,0 +1,93 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::future::{Future, ready};
+use core::pin::Pin;
+use core::task::{Context, Waker};
+use core::time::Duration;
+use enigma_kernel::task::timer::{self, Elapsed};
+use enigma_kernel::test_util::Setup;
+use enigma_kernel::thread::block_on;
+use enigma_kernel::time::Instant;
+
+// Таймеры продвигает прерывание таймера LAPIC.
+enigma_kernel::test_entry!(Setup {
+    apic: true,
+    ..Setup::default()
+});
+
+#[test_case]
+fn sleep_waits_for_deadline() {
+    let pending = timer::pending_timers();
+    let start = Instant::now();
+    block_on(timer::sleep(Duration::from_millis(20)));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(timer::pending_timers(), pending);
+
+    // Прошедший срок не регистрирует таймер.
+    block_on(timer::sleep_until(start));
+    assert_eq!(timer::pending_timers(), pending);
+}
+
+#[test_case]
+fn timeout_cancels_the_slower_side() {
+    let pending = timer::pending_timers();
+    assert_eq!(
+        block_on(timer::timeout(
+            Duration::from_millis(10),
+            timer::sleep(Duration::from_secs(60))
+        )),
+        Err(Elapsed)
+    );
+    assert_eq!(
+        block_on(timer::timeout(Duration::from_secs(60), ready(5))),
+        Ok(5)
+    );
+    // Внутренний sleep на минуту отменен и уже убран из кучи.
+    assert_eq!(timer::pending_timers(), pending);
+}
+
+#[test_case]
+fn cancelled_timers_leave_the_heap() {
+    // Остальные тесты свои таймеры не оставляют.
+    assert_eq!(timer::pending_timers(), 0);
+    let mut context = Context::from_waker(Waker::noop());
+    let mut sleeps: Vec<_> = (0..100)
+        .map(|_| timer::sleep(Duration::from_secs(60)))
+        .collect();
+    for sleep in &mut sleeps {
+        assert!(Pin::new(sleep).poll(&mut context).is_pending());
+    }
+    assert_eq!(timer::pending_timers(), 100);
+
+    // Куча очищается, как только отмененных больше половины.
+    sleeps.truncate(49);
+    assert_eq!(timer::pending_timers(), 49);
+    drop(sleeps);
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn interval_skips_missed_ticks() {
+    let period = Duration::from_millis(5);
+    let mut interval = timer::interval(period);
+    let first = block_on(interval.tick());
+    let second = block_on(interval.tick());
+    assert_eq!(second - first, period);
+
+    // Пропущенные тики не выдаются пачкой: после опоздания следующий тик
+    // через период от текущего момента.
+    block_on(timer::sleep(Duration::from_millis(20)));
+    let third = block_on(interval.tick());
+    assert_eq!(third - second, period);
+    let fourth = block_on(interval.tick());
+    assert!(fourth - third >= Duration::from_millis(20));
+    drop(interval);
+    assert_eq!(timer::pending_timers(), 0);
+}
@@ -0,0 +1,256 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, vec::Vec};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::fs::tmpfs::Tmpfs;
+use enigma_kernel::fs::vfs::{self, FileType, MountFlags, OpenFlags, SeekFrom, Timestamp};
+use enigma_kernel::process::fd::File;
+use enigma_kernel::test_util::{CREATE, Setup, names, read, write};
+
+enigma_kernel::test_entry!(Setup {
+    fs: true,
+    ..Setup::default()
+});
+
+#[test_case]
+fn reads_and_writes_files() {
+    vfs::mkdir("/io", 0o755).unwrap();
+    let file = vfs::open("/io/file", CREATE, 0o600).unwrap();
+    assert_eq!(file.write(b"hello"), Ok(5));
+    assert_eq!(file.seek(SeekFrom::Start(0)), Ok(0));
+
+    let mut buffer = [0; 16];
+    assert_eq!(file.read(&mut buffer), Ok(5));
+    assert_eq!(&buffer[..5], b"hello");
+    assert_eq!(file.read(&mut buffer), Ok(0));
+
+    // Смещение ограничено `i64::MAX`, и неудачный `seek` его не меняет.
+    let max = i64::MAX as u64;
+    assert_eq!(file.seek(SeekFrom::Start(max + 1)), Err(Errno::EINVAL));
+    assert_eq!(file.seek(SeekFrom::Start(max)), Ok(max));
+    assert_eq!(file.seek(SeekFrom::Current(1)), Err(Errno::EINVAL));
+    assert_eq!(file.seek(SeekFrom::End(-6)), Err(Errno::EINVAL));
+    assert_eq!(file.seek(SeekFrom::Current(0)), Ok(max));
+
+    let metadata = vfs::stat("/io/file").unwrap();
+    assert_eq!((metadata.kind, metadata.mode), (FileType::Regular, 0o600));
+    assert_eq!(metadata.size, 5);
+
+    // Запись через границу страницы.
+    let data: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+    assert_eq!(write("/io/big", &data), Ok(data.len()));
+    assert_eq!(read("/io/big").unwrap(), data);
+
+    let append = vfs::open("/io/file", OpenFlags::WRITE_ONLY | OpenFlags::APPEND, 0).unwrap();
+    append.write(b" world").unwrap();
+    assert_eq!(read("/io/file").unwrap(), b"hello world");
+    write("/io/file", b"x").unwrap();
+    assert_eq!(read("/io/file").unwrap(), b"x");
+}
+
+#[test_case]
+fn holes_and_truncate_read_as_zeroes() {
+    let file = vfs::open("/sparse", CREATE, 0o644).unwrap();
+    file.seek(SeekFrom::Start(3 * 4096)).unwrap();
+    file.write(b"end").unwrap();
+
+    let data = read("/sparse").unwrap();
+    assert_eq!(data.len(), 3 * 4096 + 3);
+    assert!(data[..3 * 4096].iter().all(|&byte| byte == 0));
+
+    vfs::truncate("/sparse", 3 * 4096 + 1).unwrap();
+    vfs::truncate("/sparse", 3 * 4096 + 3).unwrap();
+    assert_eq!(&read("/sparse").unwrap()[3 * 4096..], b"e\0\0");
+    vfs::unlink("/sparse").unwrap();
+}
+
+#[test_case]
+fn directories_count_links() {
+    vfs::mkdir("/dir", 0o755).unwrap();
+    vfs::mkdir("/dir/a", 0o755).unwrap();
+    vfs::mkdir("/dir/b", 0o755).unwrap();
+    write("/dir/file", b"").unwrap();
+
+    assert_eq!(vfs::stat("/dir").unwrap().nlink, 4);
+    assert_eq!(names("/dir"), ["a", "b", "file"]);
+    assert_eq!(vfs::mkdir("/dir/a", 0o755), Err(Errno::EEXIST));
+    assert_eq!(vfs::rmdir("/dir"), Err(Errno::ENOTEMPTY));
+    assert_eq!(vfs::rmdir("/dir/file"), Err(Errno::ENOTDIR));
+    assert_eq!(vfs::unlink("/dir/a"), Err(Errno::EISDIR));
+    assert_eq!(
+        vfs::open("/dir", OpenFlags::WRITE_ONLY, 0).err(),
+        Some(Errno::EISDIR)
+    );
+    assert_eq!(
+        vfs::stat("/dir/a/..").unwrap().ino,
+        vfs::stat("/dir").unwrap().ino
+    );
+
+    vfs::rmdir("/dir/a").unwrap();
+    vfs::rmdir("/dir/b").unwrap();
+    vfs::unlink("/dir/file").unwrap();
+    assert_eq!(vfs::stat("/dir").unwrap().nlink, 2);
+    vfs::rmdir("/dir").unwrap();
+    assert_eq!(vfs::stat("/dir"), Err(Errno::ENOENT));
+}
+
+#[test_case]
+fn hard_links_share_inode() {
+    write("/original", b"shared").unwrap();
+    vfs::link("/original", "/alias").unwrap();
+
+    let original = vfs::stat("/original").unwrap();
+    assert_eq!(original.nlink, 2);
+    assert_eq!(vfs::stat("/alias").unwrap().ino, original.ino);
+    assert_eq!(vfs::link("/", "/root-alias"), Err(Errno::EPERM));
+    assert_eq!(vfs::link("/original", "/alias"), Err(Errno::EEXIST));
+
+    vfs::unlink("/original").unwrap();
+    assert_eq!(vfs::stat("/alias").unwrap().nlink, 1);
+    assert_eq!(read("/alias").unwrap(), b"shared");
+    vfs::unlink("/alias").unwrap();
+}
+
+#[test_case]
+fn symlinks_are_followed() {
+    vfs::mkdir("/target", 0o755).unwrap();
+    write("/target/file", b"linked").unwrap();
+    vfs::symlink("target", "/relative").unwrap();
+    vfs::symlink("/target/file", "/absolute").unwrap();
+    vfs::symlink("/nowhere", "/dangling").unwrap();
+
+    assert_eq!(read("/relative/file").unwrap(), b"linked");
+    assert_eq!(read("/absolute").unwrap(), b"linked");
+    assert_eq!(vfs::readlink("/relative").as_deref(), Ok("target"));
+    assert_eq!(vfs::lstat("/dangling").unwrap().kind, FileType::Symlink);
+    assert_eq!(vfs::stat("/dangling"), Err(Errno::ENOENT));
+
+    for path in [
+        "/relative",
+        "/absolute",
+        "/dangling",
+        "/nowhere",
+        "/target/file",
+    ] {
+        vfs::unlink(path).unwrap();
+    }
+    vfs::rmdir("/target").unwrap();
+}
+
+#[test_case]
+fn unlinked_file_stays_open() {
+    let file = vfs::open("/doomed", CREATE, 0o644).unwrap();
+    file.write(b"still here").unwrap();
+    vfs::unlink("/doomed").unwrap();
+
+    assert_eq!(vfs::stat("/doomed"), Err(Errno::ENOENT));
+    assert_eq!(file.metadata().unwrap().nlink, 0);
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut buffer = [0; 10];
+    assert_eq!(file.read(&mut buffer), Ok(10));
+    assert_eq!(&buffer, b"still here");
+    assert_eq!(file.write(b"!"), Ok(1));
+}
+
+#[test_case]
+fn rename_follows_posix_rules() {
+    vfs::mkdir("/r", 0o755).unwrap();
+    vfs::mkdir("/r/dir", 0o755).unwrap();
+    vfs::mkdir("/r/empty", 0o755).unwrap();
+    vfs::mkdir("/r/full", 0o755).unwrap();
+    write("/r/full/x", b"").unwrap();
+    write("/r/a", b"a").unwrap();
+    write("/r/b", b"b").unwrap();
+
+    vfs::rename("/r/a", "/r/b").unwrap();
+    assert_eq!(read("/r/b").unwrap(), b"a");
+    assert_eq!(vfs::stat("/r/a"), Err(Errno::ENOENT));
+
+    assert_eq!(vfs::rename("/r/b", "/r/dir"), Err(Errno::EISDIR));
+    assert_eq!(vfs::rename("/r/dir", "/r/b"), Err(Errno::ENOTDIR));
+    assert_eq!(vfs::rename("/r/dir", "/r/full"), Err(Errno::ENOTEMPTY));
+    assert_eq!(vfs::rename("/r", "/r/dir/inside"), Err(Errno::EINVAL));
+    assert_eq!(vfs::rename("/r/b", "/r/.."), Err(Errno::EBUSY));
+    // Остальные ошибки имени не подменяются на `EBUSY`.
+    let long = format!("/r/{}", "x".repeat(300));
+    assert_eq!(vfs::rename("/r/b", &long), Err(Errno::ENAMETOOLONG));
+
+    vfs::rename("/r/dir", "/r/empty").unwrap();
+    assert_eq!(names("/r"), ["b", "empty", "full"]);
+    assert_eq!(vfs::stat("/r").unwrap().nlink, 4);
+
+    vfs::rename("/r/empty", "/r/full/moved").unwrap();
+    assert_eq!(vfs::stat("/r").unwrap().nlink, 3);
+    assert_eq!(vfs::stat("/r/full").unwrap().nlink, 3);
+    assert_eq!(
+        vfs::stat("/r/full/moved/..").unwrap().ino,
+        vfs::stat("/r/full").unwrap().ino
+    );
+}
+
+#[test_case]
+fn timestamps_are_updated() {
+    write("/times", b"").unwrap();
+    let old = Timestamp::new(1, 0);
+    let location = vfs::resolve("/times").unwrap();
+    location.inode().set_times(Some(old), Some(old)).unwrap();
+
+    let metadata = vfs::stat("/times").unwrap();
+    assert_eq!((metadata.atime, metadata.mtime), (old, old));
+
+    location
+        .inode()
+        .set_times(None, Some(Timestamp::new(5, 0)))
+        .unwrap();
+    let metadata = vfs::stat("/times").unwrap();
+    assert_eq!(
+        (metadata.atime, metadata.mtime),
+        (old, Timestamp::new(5, 0))
+    );
+
+    // Пустая запись время не трогает.
+    let file = vfs::open("/times", OpenFlags::WRITE_ONLY, 0).unwrap();
+    assert_eq!(file.write(b""), Ok(0));
+    assert_eq!(vfs::stat("/times").unwrap().mtime, Timestamp::new(5, 0));
+
+    let before = Timestamp::now();
+    write("/times", b"new").unwrap();
+    let mtime = vfs::stat("/times").unwrap().mtime;
+    assert!(mtime >= before);
+    assert_ne!(mtime, Timestamp::new(5, 0));
+    vfs::unlink("/times").unwrap();
+}
+
+#[test_case]
+fn limits_are_enforced() {
+    let small = Tmpfs::with_limits(2 * 4096, 3);
+    vfs::mkdir("/small", 0o755).unwrap();
+    vfs::mount(small.clone(), "/small", MountFlags::default()).unwrap();
+
+    let data = [1; 3 * 4096];
+    // Запись останавливается на границе лимита.
+    assert_eq!(write("/small/file", &data), Ok(2 * 4096));
+    assert_eq!(
+        vfs::open("/small/file", OpenFlags::WRITE_ONLY | OpenFlags::APPEND, 0)
+            .unwrap()
+            .write(b"x"),
+        Err(Errno::ENOSPC)
+    );
+    assert_eq!(small.stats().used_bytes, 2 * 4096);
+
+    write("/small/second", b"").unwrap();
+    assert_eq!(write("/small/third", b"").err(), Some(Errno::ENOSPC));
+
+    vfs::unlink("/small/file").unwrap();
+    let stats = small.stats();
+    assert_eq!((stats.used_bytes, stats.inodes), (0, 2));
+    assert_eq!(write("/small/third", &data[..4096]), Ok(4096));
+
+    assert_eq!(vfs::link("/small/third", "/outside"), Err(Errno::EXDEV));
+}
@@ -0,0 +1,138 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::process::{
+    self, Process, WaitTarget,
+    fd::{Fd, File},
+};
+use enigma_kernel::test_util::Setup;
+use enigma_kernel::thread;
+use spin::Mutex;
+use x86_64::{VirtAddr, structures::paging::PageTableFlags};
+
+// Процесс в бесконечном цикле прерывает только таймер LAPIC.
+enigma_kernel::test_entry!(Setup {
+    apic: true,
+    ..Setup::default()
+});
+
+/// Адрес кода и стека тестового процесса.
+const CODE: u64 = 0x40_0000;
+const STACK_TOP: u64 = 0x80_0000;
+
+/// Все, что процессы записали в дескриптор 1.
+static OUTPUT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+struct Output;
+
+impl File for Output {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, Errno> {
+        Ok(0)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, Errno> {
+        OUTPUT.lock().extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+}
+
+/// Запускает `code` в кольце 3.
+fn start_user(code: &[u8]) -> Arc<Process> {
+    let process = process::create("user").unwrap();
+    process
+        .with_files(|files| files.insert_at(Fd(1), Arc::new(Output)))
+        .unwrap();
+    let entry = VirtAddr::new(CODE);
+    let stack = VirtAddr::new(STACK_TOP);
+
+    process
+        .with_address_space(|space| {
+            space.map(entry, 4096, PageTableFlags::empty()).unwrap();
+            space.write(entry, code).unwrap();
+            space
+                .map(
+                    stack - 4096u64,
+                    4096,
+                    PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+                )
+                .unwrap();
+        })
+        .unwrap();
+
+    process.spawn_user_thread(entry, stack).unwrap();
+    process
+}
+
+/// Запускает `code` в кольце 3 и возвращает код завершения процесса.
+fn run_user(code: &[u8]) -> i32 {
+    let process = start_user(code);
+    let (pid, status) = process::wait(WaitTarget::Pid(process.pid())).unwrap();
+    assert_eq!(pid, process.pid());
+
+    status
+}
+
+#[test_case]
+fn privileged_instruction_kills_process() {
+    // hlt
+    assert_eq!(run_user(&[0xF4]), 128 + 11);
+}
+
+#[test_case]
+fn invalid_opcode_kills_process() {
+    // push rax; ud2 - заодно проверяет, что стек пользователя доступен.
+    assert_eq!(run_user(&[0x50, 0x0F, 0x0B]), 128 + 4);
+}
+
+#[test_case]
+fn kernel_memory_is_not_accessible() {
+    // mov rax, [heap]
+    let mut code = alloc::vec![0x48, 0xA1];
+    code.extend_from_slice(&(enigma_kernel::allocator::HEAP_START as u64).to_le_bytes());
+    assert_eq!(run_user(&code), 128 + 11);
+}
+
+#[test_case]
+fn syscall_writes_and_exits() {
+    // write(1, msg, 5); exit(результат write); msg: "hello"
+    let mut code = alloc::vec![
+        0xB8, 0x01, 0x00, 0x00, 0x00, 0xBF, 0x01, 0x00, 0x00, 0x00, 0x48, 0x8D, 0x35, 0x10, 0x00,
+        0x00, 0x00, 0xBA, 0x05, 0x00, 0x00, 0x00, 0x0F, 0x05, 0x89, 0xC7, 0xB8, 0x3C, 0x00, 0x00,
+        0x00, 0x0F, 0x05,
+    ];
+    code.extend_from_slice(b"hello");
+
+    OUTPUT.lock().clear();
+    assert_eq!(run_user(&code), 5);
+    assert_eq!(OUTPUT.lock().as_slice(), b"hello");
+}
+
+#[test_case]
+fn unknown_syscall_returns_enosys() {
+    // syscall(500); exit(-результат) через int 0x80
+    let code = [
+        0xB8, 0xF4, 0x01, 0x00, 0x00, 0x0F, 0x05, 0xF7, 0xD8, 0x89, 0xC7, 0xB8, 0x3C, 0x00, 0x00,
+        0x00, 0xCD, 0x80,
+    ];
+    assert_eq!(run_user(&code), Errno::ENOSYS as i32);
+}
+
+#[test_case]
+fn terminate_stops_process_spinning_in_user_mode() {
+    // jmp $
+    let process = start_user(&[0xEB, 0xFE]);
+    thread::sleep_ticks(2);
+
+    // Поток не делает системных вызовов и завершается на прерывании таймера.
+    process::terminate(process.pid(), 9).unwrap();
+    let (pid, status) = process::wait(WaitTarget::Pid(process.pid())).unwrap();
+    assert_eq!(pid, process.pid());
+    assert_eq!(status, 9);
+}
@@ -0,0 +1,167 @@
+#![reexport_test_harness_main = "test_main"]
+#![feature(custom_test_frameworks)]
+#![test_runner(enigma_kernel::test_runner)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, format, sync::Arc, vec::Vec};
+use enigma_kernel::errno::Errno;
+use enigma_kernel::fs::ramdisk::{Ramdisk, RamdiskFs};
+use enigma_kernel::fs::vfs::{self, FileType, MountFlags, OpenFlags};
+use enigma_kernel::process::{self, WaitTarget, fd::File};
+use enigma_kernel::test_util::{Setup, read};
+
+enigma_kernel::test_entry!(Setup::default(), mount_test_tree);
+
+const DIR: u32 = 0o040755;
+const FILE: u32 = 0o100644;
+const LINK: u32 = 0o120777;
+
+/// Собирает архив cpio (newc) из записей `(путь, режим, данные)`.
+fn cpio(entries: &[(&str, u32, &[u8])]) -> &'static Ramdisk {
+    let mut archive = Vec::new();
+    let trailer = [("TRAILER!!!", 0, &[][..])];
+
+    for (ino, (name, mode, data)) in entries.iter().chain(&trailer).enumerate() {
+        archive.extend_from_slice(b"070701");
+        let fields = [
+            ino,
+            *mode as usize,
+            0,
+            0,
+            1,
+            0,
+            data.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0,
+        ];
+        for field in fields {
+            archive.extend_from_slice(format!("{field:08X}").as_bytes());
+        }
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    }
+
+    let archive = Box::leak(archive.into_boxed_slice());
+    Box::leak(Box::new(Ramdisk::new(archive).unwrap()))
+}
+
+/// `/` - ramdisk с каталогами `etc` и `mnt`, в `/mnt` смонтирован второй.
+fn mount_test_tree() {
+    let root = cpio(&[
+        ("etc", DIR, b""),
+        ("etc/motd", FILE, b"hi"),
+        ("mnt", DIR, b""),
+        ("motd", LINK, b"etc/motd"),
+        ("loop", LINK, b"loop"),
+        ("file-in-mnt", LINK, b"/mnt/file"),
+    ]);
+    let inner = cpio(&[
+        ("file", FILE, b"mounted"),
+        ("sub", DIR, b""),
+        ("sub/back", LINK, b"../../etc/motd"),
+    ]);
+
+    vfs::mount(Arc::new(RamdiskFs::new(root)), "/", MountFlags::READ_ONLY).unwrap();
+    vfs::mount(
+        Arc::new(RamdiskFs::new(inner)),
+        "/mnt",
+        MountFlags::READ_ONLY,
+    )
+    .unwrap();
+}
+
+#[test_case]
+fn resolves_dot_and_dotdot() {
+    assert_eq!(vfs::stat("/etc/../etc/./motd").unwrap().size, 2);
+    assert_eq!(
+        vfs::stat("/../..").unwrap().ino,
+        vfs::stat("/").unwrap().ino
+    );
+    assert_eq!(vfs::stat("/etc/motd/"), Err(Errno::ENOTDIR));
+    assert_eq!(vfs::stat("/etc/motd/x"), Err(Errno::ENOTDIR));
+    assert_eq!(vfs::stat("/etc/none"), Err(Errno::ENOENT));
+}
+
+#[test_case]
+fn follows_symlinks() {
+    assert_eq!(read("/motd").as_deref(), Ok(&b"hi"[..]));
+    assert_eq!(vfs::lstat("/motd").unwrap().kind, FileType::Symlink);
+    assert_eq!(vfs::readlink("/motd").as_deref(), Ok("etc/motd"));
+    assert_eq!(vfs::stat("/loop"), Err(Errno::ELOOP));
+    assert_eq!(
+        vfs::open("/motd", OpenFlags::NOFOLLOW, 0).err(),
+        Some(Errno::ELOOP)
+    );
+}
+
+#[test_case]
+fn crosses_mount_points() {
+    assert_eq!(read("/mnt/file").as_deref(), Ok(&b"mounted"[..]));
+    assert_eq!(read("/file-in-mnt").as_deref(), Ok(&b"mounted"[..]));
+    // `..` из смонтированной системы ведет в родительскую.
+    assert_eq!(read("/mnt/sub/back").as_deref(), Ok(&b"hi"[..]));
+    assert_eq!(read("/mnt/../etc/motd").as_deref(), Ok(&b"hi"[..]));
+
+    let mounts = vfs::mounts();
+    assert_eq!(mounts.len(), 2);
+    assert_eq!(mounts[1].path, "/mnt");
+}
+
+#[test_case]
+fn read_only_mount_rejects_writes() {
+    assert_eq!(vfs::mkdir("/new", 0o755), Err(Errno::EROFS));
+    assert_eq!(vfs::unlink("/etc/motd"), Err(Errno::EROFS));
+    assert_eq!(
+        vfs::open("/etc/motd", OpenFlags::WRITE_ONLY, 0).err(),
+        Some(Errno::EROFS)
+    );
+    assert_eq!(vfs::mkdir("/etc", 0o755), Err(Errno::EROFS));
+    assert_eq!(vfs::rmdir("/mnt"), Err(Errno::EROFS));
+}
+
+#[test_case]
+fn process_has_its_own_cwd() {
+    let child = process::create("cwd").unwrap();
+    child
+        .spawn_thread("cwd", || {
+            vfs::chdir("/mnt/sub").unwrap();
+            assert_eq!(vfs::getcwd().as_deref(), Ok("/mnt/sub"));
+            assert_eq!(read("back").as_deref(), Ok(&b"hi"[..]));
+            assert_eq!(read("../file").as_deref(), Ok(&b"mounted"[..]));
+
+            vfs::chdir("../..").unwrap();
+            assert_eq!(vfs::getcwd().as_deref(), Ok("/"));
+            assert_eq!(vfs::chdir("etc/motd"), Err(Errno::ENOTDIR));
+            process::exit(7);
+        })
+        .unwrap();
+
+    assert_eq!(
+        process::wait(WaitTarget::Pid(child.pid())),
+        Ok((child.pid(), 7))
+    );
+    assert_eq!(vfs::getcwd().as_deref(), Ok("/"));
+}
+
+#[test_case]
+fn unmount_restores_mountpoint() {
+    let file = vfs::open("/mnt/file", OpenFlags::READ_ONLY, 0).unwrap();
+    assert_eq!(vfs::unmount("/"), Err(Errno::EBUSY));
+    assert_eq!(vfs::unmount("/etc"), Err(Errno::EINVAL));
+    vfs::unmount("/mnt").unwrap();
+
+    assert_eq!(vfs::stat("/mnt/file"), Err(Errno::ENOENT));
+    // Открытый файл продолжает работать.
+    let mut buffer = [0; 7];
+    assert_eq!(file.read(&mut buffer), Ok(7));
+}
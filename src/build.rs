@@ -1,18 +1,100 @@
+#[path = "cpio.rs"]
+mod cpio;
+
 use bootloader::DiskImageBuilder;
-use std::{env, path::PathBuf};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Каталог, содержимое которого попадает в ramdisk (файловую систему загрузки).
+const RAMDISK_DIR: &str = "ramdisk";
 
 fn main() {
     // set by cargo for the kernel artifact dependency
     let kernel_path = env::var("CARGO_BIN_FILE_ENIGMA_KERNEL_kernel").unwrap();
-    let disk_builder = DiskImageBuilder::new(PathBuf::from(kernel_path));
+    let mut disk_builder = DiskImageBuilder::new(PathBuf::from(kernel_path));
 
     // specify output paths
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let ramdisk_path = out_dir.join("ramdisk.cpio");
     let out_dir = out_dir.join("enigma-wave_uefi.img");
 
+    // pack the ramdisk
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join(RAMDISK_DIR);
+    println!("cargo:rerun-if-changed={}", root.display());
+    write_cpio(&root, &ramdisk_path).expect("failed to pack the ramdisk");
+    disk_builder.set_ramdisk(ramdisk_path);
+
     // create the disk images
     disk_builder.create_uefi_image(&out_dir).unwrap();
 
     // pass the disk image paths via environment variables
     println!("cargo:rustc-env=UEFI_IMAGE={}", out_dir.display());
 }
+
+/// Упаковывает каталог `root` в архив cpio формата newc. Пути в архиве
+/// относительные (`etc/motd`), время изменения обнуляется, а записи
+/// отсортированы, чтобы образ не зависел от машины сборки.
+fn write_cpio(root: &Path, output: &Path) -> io::Result<()> {
+    let mut archive = Vec::new();
+    let mut inode = 1;
+
+    if root.is_dir() {
+        add_directory(&mut archive, root, root, &mut inode)?;
+    }
+    cpio::write_entry(&mut archive, "TRAILER!!!", 0, 0, &[], 0)?;
+
+    fs::write(output, archive)
+}
+
+fn add_directory(
+    archive: &mut Vec<u8>,
+    root: &Path,
+    dir: &Path,
+    inode: &mut u32,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_str()
+            .ok_or_else(|| io::Error::other(format!("non UTF-8 path: {}", path.display())))?
+            .replace('\\', "/");
+        let metadata = fs::symlink_metadata(&path)?;
+        *inode += 1;
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(&path)?;
+            let target = target.to_string_lossy();
+            cpio::write_entry(archive, &name, 0o120777, *inode, target.as_bytes(), 1)?;
+        } else if metadata.is_dir() {
+            cpio::write_entry(archive, &name, 0o040755, *inode, &[], 2)?;
+            add_directory(archive, root, &path, inode)?;
+        } else {
+            let mode = if is_executable(&metadata) {
+                0o100755
+            } else {
+                0o100644
+            };
+            cpio::write_entry(archive, &name, mode, *inode, &fs::read(&path)?, 1)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
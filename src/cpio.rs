@@ -0,0 +1,47 @@
+//! Запись архивов cpio формата newc. Используется сценарием сборки (ramdisk
+//! образа) и программой запуска тестов (образы дисков для тестов).
+
+use std::io::{self, Write};
+
+/// Записывает заголовок newc (`070701` и 13 шестнадцатеричных полей), имя и
+/// данные, выравнивая оба по 4 байта.
+pub fn write_entry(
+    archive: &mut Vec<u8>,
+    name: &str,
+    mode: u32,
+    inode: u32,
+    data: &[u8],
+    links: u32,
+) -> io::Result<()> {
+    let fields = [
+        inode,
+        mode,
+        0, // uid
+        0, // gid
+        links,
+        0, // mtime
+        data.len() as u32,
+        0, // devmajor
+        0, // devminor
+        0, // rdevmajor
+        0, // rdevminor
+        name.len() as u32 + 1,
+        0, // check
+    ];
+
+    write!(archive, "070701")?;
+    for field in fields {
+        write!(archive, "{field:08X}")?;
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    pad(archive);
+    archive.extend_from_slice(data);
+    pad(archive);
+
+    Ok(())
+}
+
+fn pad(archive: &mut Vec<u8>) {
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
@@ -1,7 +1,24 @@
+mod cpio;
+mod runner;
+
+use std::path::Path;
 use std::process::{self, Command};
 use std::{env, fs};
 
 fn main() {
+    // `cargo test` в `enigma-kernel` запускает тестовые файлы через
+    // `EnigmaWave test <файл>`.
+    let mut args = env::args().skip(1);
+    if let Some(command) = args.next() {
+        match (command.as_str(), args.next()) {
+            ("test", Some(kernel)) => process::exit(runner::run(Path::new(&kernel))),
+            _ => {
+                eprintln!("usage: EnigmaWave [test <kernel test file>]");
+                process::exit(2);
+            }
+        }
+    }
+
     let current_exe = env::current_exe().unwrap();
     let uefi_target = current_exe.with_file_name("EnigmaWave-uefi.img");
 
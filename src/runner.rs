@@ -0,0 +1,144 @@
+//! Запуск тестов ядра в QEMU.
+//!
+//! `cargo test` в `enigma-kernel` вызывает `EnigmaWave test <файл>` для
+//! каждого собранного тестового файла (см. `enigma-kernel/.cargo/config.toml`).
+//! Из него собирается образ UEFI, который загружается на машине `q35`: у нее
+//! есть контроллер AHCI с MSI, поэтому тесты PCI и AHCI работают с настоящими
+//! устройствами. Тест сообщает результат записью в порт `isa-debug-exit`.
+//!
+//! Образы дисков для тестов файловых систем собирает крейт
+//! `enigma-test-images`; они передаются ядру в ramdisk.
+//!
+//! Тестам ext2 дополнительно подключается пустой том ext2 с серийным номером
+//! `scratch`; после теста его проверяет `e2fsck -n`.
+
+use crate::cpio;
+use bootloader::DiskImageBuilder;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+/// Код выхода QEMU при успехе: `QemuExitCode::Success` (0x10) из ядра
+/// устройство `isa-debug-exit` превращает в `(0x10 << 1) | 1`.
+const SUCCESS: i32 = 0x21;
+/// Время на один тестовый файл.
+const TIMEOUT: Duration = Duration::from_secs(300);
+/// Размер диска `scratch` в блоках по 1 КиБ.
+const SCRATCH_BLOCKS: u32 = 8192;
+
+/// Запускает тестовый файл `kernel`. Возвращает код выхода процесса.
+pub fn run(kernel: &Path) -> i32 {
+    let image = kernel.with_extension("uefi.img");
+    DiskImageBuilder::new(kernel.to_path_buf())
+        .set_ramdisk(test_images(kernel))
+        .create_uefi_image(&image)
+        .expect("failed to create the UEFI image");
+
+    let mut qemu = Command::new("qemu-system-x86_64");
+    qemu.args(["-machine", "q35", "-m", "512M", "-no-reboot"]);
+    qemu.args(["-serial", "stdio", "-display", "none"]);
+    qemu.args(["-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"]);
+    qemu.arg("-drive");
+    qemu.arg(format!("format=raw,file={}", image.display()));
+    qemu.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
+
+    let scratch = is_ext2_test(kernel).then(|| scratch_disk(kernel));
+    if let Some(scratch) = &scratch {
+        qemu.arg("-drive");
+        qemu.arg(format!(
+            "if=none,id=scratch,format=raw,file={}",
+            scratch.display()
+        ));
+        qemu.args(["-device", "ide-hd,drive=scratch,bus=ide.1,serial=scratch"]);
+    }
+
+    let code = match wait(qemu.stdin(Stdio::null())) {
+        Some(SUCCESS) => 0,
+        Some(code) => {
+            eprintln!("test failed: QEMU exited with {code:#x}");
+            return 1;
+        }
+        None => {
+            eprintln!("test timed out after {} s", TIMEOUT.as_secs());
+            return 1;
+        }
+    };
+
+    if let Some(scratch) = scratch {
+        let status = Command::new("e2fsck")
+            .args(["-n", "-f"])
+            .arg(&scratch)
+            .status()
+            .expect("failed to run e2fsck");
+        if !status.success() {
+            eprintln!("e2fsck found errors on {}", scratch.display());
+            return 1;
+        }
+    }
+    code
+}
+
+/// Собирает ramdisk с образами дисков для тестов. Образ, который не удалось
+/// создать, пропускается: без него падают только использующие его тесты.
+fn test_images(kernel: &Path) -> PathBuf {
+    let work_dir = kernel.with_extension("images");
+    fs::create_dir_all(&work_dir).expect("failed to create the test images directory");
+
+    let mut archive = Vec::new();
+    let images = enigma_test_images::build(&work_dir);
+    for (inode, (name, image)) in (1..).zip(images) {
+        match image {
+            Ok(data) => cpio::write_entry(&mut archive, &name, 0o100644, inode, &data, 1).unwrap(),
+            Err(error) => eprintln!("warning: test image {name} is not created: {error}"),
+        }
+    }
+    cpio::write_entry(&mut archive, "TRAILER!!!", 0, 0, &[], 0).unwrap();
+
+    let path = kernel.with_extension("images.cpio");
+    fs::write(&path, archive).expect("failed to write the test images ramdisk");
+    path
+}
+
+/// Тестовые файлы называются `<имя теста>-<хеш>`.
+fn is_ext2_test(kernel: &Path) -> bool {
+    kernel
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("ext2-"))
+}
+
+/// Создает пустой том ext2 с теми же параметрами, что и образ из
+/// `enigma-test-images`.
+fn scratch_disk(kernel: &Path) -> PathBuf {
+    let path = kernel.with_extension("scratch.img");
+    let _ = fs::remove_file(&path);
+    let status = Command::new("mke2fs")
+        .args([
+            "-q", "-F", "-t", "ext2", "-b", "1024", "-g", "2048", "-N", "512",
+        ])
+        .args(["-L", "SCRATCH", "-E", "root_owner=0:0"])
+        .arg(&path)
+        .arg(SCRATCH_BLOCKS.to_string())
+        .status()
+        .expect("failed to run mke2fs, install e2fsprogs");
+    assert!(status.success(), "mke2fs failed: {status}");
+    path
+}
+
+/// Запускает QEMU и ждет его завершения. `None`, если время вышло.
+fn wait(qemu: &mut Command) -> Option<i32> {
+    let mut child = qemu.spawn().expect("failed to start qemu-system-x86_64");
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return Some(status.code().unwrap_or(-1));
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,543 @@
+//! Образы дисков для интеграционных тестов ядра.
+//!
+//! Образы собирает программа запуска тестов (`src/runner.rs` корневого
+//! пакета) на машине разработчика и передает ядру в ramdisk, поэтому сборка
+//! самого ядра от этих зависимостей и от `mke2fs` не зависит. Все образы
+//! хранятся в разреженном формате (см. [`sparse`]).
+
+use fatfs::{FatType, FileSystem, FormatVolumeOptions, FsOptions};
+use std::{
+    fs,
+    io::{self, Cursor, Write},
+    path::Path,
+    process::Command,
+};
+
+const SECTOR_SIZE: usize = 512;
+
+/// Собирает все образы. `work_dir` - каталог для временных файлов (дерево
+/// файлов и образ для `mke2fs`). Возвращает имя файла образа в ramdisk и
+/// содержимое или ошибку, если образ не удалось создать (например, на
+/// машине нет e2fsprogs).
+pub fn build(work_dir: &Path) -> Vec<(String, io::Result<Vec<u8>>)> {
+    let mut images = Vec::new();
+
+    // Образы FAT для `tests/fat.rs`.
+    let fat = [
+        ("fat12", FatType::Fat12, 2880, 512),
+        ("fat16", FatType::Fat16, 32768, 2048),
+        ("fat32", FatType::Fat32, 70000, 512),
+    ];
+    for (name, fat_type, sectors, cluster) in fat {
+        images.push((name, fat_image(fat_type, sectors, cluster)));
+    }
+
+    // Образ ext2 для `tests/ext2.rs`. Его создает `mke2fs` из e2fsprogs.
+    images.push(("ext2", ext2_image(work_dir)));
+    // Образ exFAT для `tests/exfat.rs` и `tests/block.rs`.
+    images.push(("exfat", Ok(exfat_image())));
+    // Размеченные диски для `tests/partition.rs`.
+    images.push(("gpt", gpt_disk()));
+    images.push(("mbr", mbr_disk()));
+
+    images
+        .into_iter()
+        .map(|(name, image)| (format!("{name}.sparse"), image.map(|image| sparse(&image))))
+        .collect()
+}
+
+/// Форматирует образ и заполняет его тестовыми файлами: короткие и длинные
+/// имена, вложенные каталоги и каталог на несколько кластеров.
+fn fat_image(fat_type: FatType, sectors: u32, cluster: u32) -> io::Result<Vec<u8>> {
+    let mut image = Cursor::new(vec![0; sectors as usize * SECTOR_SIZE]);
+    let options = FormatVolumeOptions::new()
+        .fat_type(fat_type)
+        .total_sectors(sectors)
+        .bytes_per_cluster(cluster)
+        .volume_label(*b"ENIGMA     ");
+    fatfs::format_volume(&mut image, options)?;
+
+    let fs = FileSystem::new(&mut image, FsOptions::new())?;
+    {
+        let root = fs.root_dir();
+        root.create_file("HELLO.TXT")?.write_all(b"Hello, FAT!")?;
+        root.create_file("A long file name.text")?
+            .write_all(b"long names work")?;
+        root.create_file("Кириллица.txt")?
+            .write_all("привет".as_bytes())?;
+
+        let nested = root.create_dir("dir")?.create_dir("nested")?;
+        let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        nested.create_file("deep.bin")?.write_all(&data)?;
+
+        let many = root.create_dir("many")?;
+        for i in 0..40 {
+            many.create_file(&format!("file-{i}"))?
+                .write_all(format!("{i}").as_bytes())?;
+        }
+    }
+    fs.unmount()?;
+
+    Ok(image.into_inner())
+}
+
+/// Создает том ext2 из дерева тестовых файлов: прямые и косвенные блоки,
+/// символическая и жесткая ссылки, каталог на несколько блоков.
+fn ext2_image(work_dir: &Path) -> io::Result<Vec<u8>> {
+    let tree = work_dir.join("ext2-tree");
+    if tree.exists() {
+        fs::remove_dir_all(&tree)?;
+    }
+    fs::create_dir_all(tree.join("dir/nested"))?;
+    fs::create_dir_all(tree.join("many"))?;
+
+    fs::write(tree.join("hello.txt"), "Hello, ext2!")?;
+    fs::hard_link(tree.join("hello.txt"), tree.join("hard.txt"))?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("hello.txt", tree.join("link"))?;
+    let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+    fs::write(tree.join("dir/nested/big.bin"), data)?;
+    for i in 0..100 {
+        fs::write(tree.join(format!("many/file-{i}")), format!("{i}"))?;
+    }
+
+    let image = work_dir.join("ext2.img");
+    if image.exists() {
+        fs::remove_file(&image)?;
+    }
+    let output = Command::new("mke2fs")
+        .args([
+            "-q", "-F", "-t", "ext2", "-b", "1024", "-g", "2048", "-N", "512",
+        ])
+        .args(["-L", "ENIGMA", "-E", "root_owner=0:0", "-d"])
+        .arg(&tree)
+        .arg(&image)
+        .arg("8192")
+        .output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("mke2fs failed: {message}")));
+    }
+    fs::read(image)
+}
+
+/// Диск GPT (16 МиБ): системный раздел EFI с томом FAT12 и два раздела
+/// данных. В первом секторе разделов данных записана метка с номером.
+fn gpt_disk() -> io::Result<Vec<u8>> {
+    const SECTORS: usize = 32768;
+    let mut image = Cursor::new(vec![0; SECTORS * SECTOR_SIZE]);
+
+    let protective = gpt::mbr::ProtectiveMBR::with_lb_size(SECTORS as u32 - 1);
+    protective.overwrite_lba0(&mut image)?;
+    let guid = uuid::Uuid::from_u128(0x0123_4567_89AB_CDEF_0011_2233_4455_6677);
+    let mut disk = gpt::GptConfig::new()
+        .writable(true)
+        .initialized(false)
+        .logical_block_size(gpt::disk::LogicalBlockSize::Lb512)
+        .create_from_device(Box::new(&mut image), Some(guid))?;
+    disk.update_partitions(Default::default())?;
+
+    let esp = fat_image(FatType::Fat12, 2880, 512)?;
+    let types = [
+        ("EFI System", esp.len() as u64, gpt::partition_types::EFI),
+        ("data", 2 << 20, gpt::partition_types::LINUX_FS),
+        ("Рабочий", 1 << 20, gpt::partition_types::BASIC),
+    ];
+    for (name, size, kind) in types {
+        disk.add_partition(name, size, kind, 0, Some(2048))?;
+    }
+    let partitions = disk.partitions().clone();
+    disk.write()?;
+
+    let mut image = image.into_inner();
+    for (&number, partition) in &partitions {
+        let start = partition.first_lba as usize * SECTOR_SIZE;
+        match number {
+            1 => image[start..start + esp.len()].copy_from_slice(&esp),
+            _ => mark(&mut image[start..], number),
+        }
+    }
+    Ok(image)
+}
+
+/// Диск MBR (8 МиБ): два основных раздела и расширенный с двумя
+/// логическими. В первом секторе каждого раздела записана метка с номером.
+fn mbr_disk() -> io::Result<Vec<u8>> {
+    const SECTORS: usize = 16384;
+    let mut image = Cursor::new(vec![0; SECTORS * SECTOR_SIZE]);
+    let mut mbr = mbrman::MBR::new_from(&mut image, SECTOR_SIZE as u32, *b"ENIG")
+        .map_err(io::Error::other)?;
+
+    let entry = |boot, sys, starting_lba, sectors| mbrman::MBRPartitionEntry {
+        boot,
+        first_chs: mbrman::CHS::empty(),
+        sys,
+        last_chs: mbrman::CHS::empty(),
+        starting_lba,
+        sectors,
+    };
+    mbr[1] = entry(mbrman::BOOT_ACTIVE, 0x0C, 2048, 2048);
+    mbr[2] = entry(mbrman::BOOT_INACTIVE, 0x83, 4096, 4096);
+    mbr[3] = entry(mbrman::BOOT_INACTIVE, 0x0F, 8192, 8192);
+    mbr.push(0x83, 8192, 4096).map_err(io::Error::other)?;
+    mbr.push(0x07, 12288, 4096).map_err(io::Error::other)?;
+    mbr.write_into(&mut image).map_err(io::Error::other)?;
+
+    let mut image = image.into_inner();
+    for (number, partition) in mbr.iter() {
+        if partition.is_used() && !partition.is_extended() {
+            let start = partition.starting_lba as usize * SECTOR_SIZE;
+            mark(&mut image[start..], number as u32);
+        }
+    }
+    Ok(image)
+}
+
+/// Записывает в начало раздела метку `partition N`.
+fn mark(partition: &mut [u8], number: u32) {
+    let label = format!("partition {number}");
+    partition[..label.len()].copy_from_slice(label.as_bytes());
+}
+
+/// Файл или каталог тестового тома exFAT.
+enum ExFatNode {
+    File {
+        name: String,
+        data: Vec<u8>,
+        /// Записанная часть (`ValidDataLength`); дальше на диске мусор.
+        valid: usize,
+        /// Кластеры через один и цепочка в FAT вместо `NoFatChain`.
+        fragmented: bool,
+    },
+    Dir {
+        name: String,
+        children: Vec<ExFatNode>,
+    },
+}
+
+impl ExFatNode {
+    fn file(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+        let valid = data.len();
+        Self::File {
+            name: name.into(),
+            data,
+            valid,
+            fragmented: false,
+        }
+    }
+}
+
+/// Собирает том exFAT по спецификации Microsoft: форматировщика exFAT на
+/// машине сборки может не быть. Секторы по 512 байт, кластеры по 4 КиБ,
+/// 16 МиБ. В томе есть непрерывные (`NoFatChain`) и фрагментированные
+/// файлы, файл с `ValidDataLength` меньше длины, длинные и кириллические
+/// имена и каталог на несколько кластеров.
+fn exfat_image() -> Vec<u8> {
+    const SECTORS: usize = 32768;
+    const CLUSTER: usize = 4096;
+    const FAT_OFFSET: usize = 128;
+    const FAT_LENGTH: usize = 64;
+    const HEAP_OFFSET: usize = 256;
+    const CLUSTER_COUNT: u32 = ((SECTORS - HEAP_OFFSET) * SECTOR_SIZE / CLUSTER) as u32;
+
+    let upcase_map: Vec<u16> = (0..=0xFFFFu32)
+        .map(|code| {
+            let mut upper = char::from_u32(code)
+                .into_iter()
+                .flat_map(char::to_uppercase);
+            match (upper.next(), upper.next()) {
+                (Some(c), None) if (c as u32) < 0x10000 => c as u16,
+                _ => code as u16,
+            }
+        })
+        .collect();
+    // Сжатая таблица: участки, где символ переходит сам в себя, кодируются
+    // как 0xFFFF и длина участка.
+    let mut upcase_units = Vec::new();
+    let mut code = 0;
+    while code < upcase_map.len() {
+        let run = upcase_map[code..]
+            .iter()
+            .enumerate()
+            .take_while(|&(offset, &unit)| unit as usize == code + offset)
+            .count();
+        if run > 0 {
+            upcase_units.extend([0xFFFF, run as u16]);
+            code += run;
+        } else {
+            upcase_units.push(upcase_map[code]);
+            code += 1;
+        }
+    }
+    let upcase: Vec<u8> = upcase_units
+        .iter()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    let checksum32 = |bytes: &[u8]| {
+        bytes.iter().fold(0u32, |sum, &byte| {
+            sum.rotate_right(1).wrapping_add(byte as u32)
+        })
+    };
+
+    let mut volume = ExFatBuilder {
+        image: vec![0; SECTORS * SECTOR_SIZE],
+        fat_offset: FAT_OFFSET * SECTOR_SIZE,
+        heap_offset: HEAP_OFFSET * SECTOR_SIZE,
+        cluster_size: CLUSTER,
+        next: 2,
+        used: Vec::new(),
+        upcase: upcase_map,
+    };
+    let bitmap_cluster = volume.store(&vec![0; (CLUSTER_COUNT as usize).div_ceil(8)], true);
+    let upcase_cluster = volume.store(&upcase, true);
+
+    let many = (0..100)
+        .map(|i| ExFatNode::file(format!("file-{i}"), format!("{i}")))
+        .collect();
+    let tree = vec![
+        ExFatNode::file("Hello.txt", "Hello, exFAT!"),
+        ExFatNode::file("Привет.txt", "мир"),
+        ExFatNode::file(
+            "A file with a rather long name that needs several name entries.txt",
+            "long names work",
+        ),
+        ExFatNode::File {
+            name: "big.bin".into(),
+            data: (0..100_000).map(|i| (i % 251) as u8).collect(),
+            valid: 100_000,
+            fragmented: true,
+        },
+        ExFatNode::File {
+            name: "sparse.bin".into(),
+            data: [vec![b'v'; 100], vec![0xEE; 9900]].concat(),
+            valid: 100,
+            fragmented: false,
+        },
+        ExFatNode::Dir {
+            name: "dir".into(),
+            children: vec![ExFatNode::Dir {
+                name: "nested".into(),
+                children: vec![ExFatNode::file(
+                    "deep.bin",
+                    (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>(),
+                )],
+            }],
+        },
+        ExFatNode::Dir {
+            name: "many".into(),
+            children: many,
+        },
+    ];
+
+    // Служебные записи корня: метка, битовая карта и таблица регистра.
+    let mut label = [0; 32];
+    label[0] = 0x83;
+    label[1] = 6;
+    for (index, unit) in "ENIGMA".encode_utf16().enumerate() {
+        label[2 + index * 2..4 + index * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+    let mut bitmap = [0; 32];
+    bitmap[0] = 0x81;
+    bitmap[20..24].copy_from_slice(&bitmap_cluster.to_le_bytes());
+    bitmap[24..32].copy_from_slice(&(CLUSTER_COUNT as u64).div_ceil(8).to_le_bytes());
+    let mut upcase_entry = [0; 32];
+    upcase_entry[0] = 0x82;
+    upcase_entry[4..8].copy_from_slice(&checksum32(&upcase).to_le_bytes());
+    upcase_entry[20..24].copy_from_slice(&upcase_cluster.to_le_bytes());
+    upcase_entry[24..32].copy_from_slice(&(upcase.len() as u64).to_le_bytes());
+
+    let mut root = vec![label, bitmap, upcase_entry];
+    for node in &tree {
+        root.extend(volume.entry_set(node));
+    }
+    let root_cluster = volume.store(&root.concat(), true);
+
+    // Битовая карта размещения.
+    let mut bitmap = vec![0u8; (CLUSTER_COUNT as usize).div_ceil(8)];
+    for &cluster in &volume.used {
+        let bit = (cluster - 2) as usize;
+        bitmap[bit / 8] |= 1 << (bit % 8);
+    }
+    let offset = volume.cluster_offset(bitmap_cluster);
+    volume.image[offset..offset + bitmap.len()].copy_from_slice(&bitmap);
+    volume.set_fat(0, 0xFFFF_FFF8);
+    volume.set_fat(1, 0xFFFF_FFFF);
+
+    // Основная загрузочная область и ее копия.
+    let mut boot = vec![0u8; 12 * SECTOR_SIZE];
+    boot[..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    boot[3..11].copy_from_slice(b"EXFAT   ");
+    boot[72..80].copy_from_slice(&(SECTORS as u64).to_le_bytes());
+    boot[80..84].copy_from_slice(&(FAT_OFFSET as u32).to_le_bytes());
+    boot[84..88].copy_from_slice(&(FAT_LENGTH as u32).to_le_bytes());
+    boot[88..92].copy_from_slice(&(HEAP_OFFSET as u32).to_le_bytes());
+    boot[92..96].copy_from_slice(&CLUSTER_COUNT.to_le_bytes());
+    boot[96..100].copy_from_slice(&root_cluster.to_le_bytes());
+    boot[100..104].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    boot[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+    boot[108] = SECTOR_SIZE.trailing_zeros() as u8;
+    boot[109] = (CLUSTER / SECTOR_SIZE).trailing_zeros() as u8;
+    boot[110] = 1;
+    boot[111] = 0x80;
+    boot[112] = (volume.used.len() * 100 / CLUSTER_COUNT as usize) as u8;
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+    for sector in 1..9 {
+        boot[sector * SECTOR_SIZE + 510..(sector + 1) * SECTOR_SIZE].copy_from_slice(&[0x55, 0xAA]);
+    }
+    let checksum = boot[..11 * SECTOR_SIZE]
+        .iter()
+        .enumerate()
+        .filter(|&(offset, _)| !matches!(offset, 106 | 107 | 112))
+        .fold(0u32, |sum, (_, &byte)| {
+            sum.rotate_right(1).wrapping_add(byte as u32)
+        });
+    for chunk in boot[11 * SECTOR_SIZE..].chunks_exact_mut(4) {
+        chunk.copy_from_slice(&checksum.to_le_bytes());
+    }
+    volume.image[..boot.len()].copy_from_slice(&boot);
+    volume.image[boot.len()..2 * boot.len()].copy_from_slice(&boot);
+
+    volume.image
+}
+
+/// Раскладка тестового тома exFAT по кластерам.
+struct ExFatBuilder {
+    image: Vec<u8>,
+    fat_offset: usize,
+    heap_offset: usize,
+    cluster_size: usize,
+    /// Следующий свободный кластер.
+    next: u32,
+    used: Vec<u32>,
+    upcase: Vec<u16>,
+}
+
+impl ExFatBuilder {
+    fn cluster_offset(&self, cluster: u32) -> usize {
+        self.heap_offset + (cluster as usize - 2) * self.cluster_size
+    }
+
+    fn set_fat(&mut self, cluster: u32, value: u32) {
+        let offset = self.fat_offset + cluster as usize * 4;
+        self.image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Размещает `data` (хотя бы один кластер) и возвращает первый кластер.
+    /// Цепочка выписывается в FAT, если `chain`; фрагментированные данные
+    /// занимают кластеры через один.
+    fn place(&mut self, data: &[u8], chain: bool, fragmented: bool) -> u32 {
+        let count = data.len().div_ceil(self.cluster_size).max(1);
+        let step = if fragmented { 2 } else { 1 };
+        let clusters: Vec<u32> = (0..count as u32).map(|i| self.next + i * step).collect();
+        self.next += count as u32 * step;
+
+        for (index, &cluster) in clusters.iter().enumerate() {
+            let chunk = data.chunks(self.cluster_size).nth(index).unwrap_or(&[]);
+            let offset = self.cluster_offset(cluster);
+            self.image[offset..offset + chunk.len()].copy_from_slice(chunk);
+            if chain {
+                let next = clusters.get(index + 1).copied().unwrap_or(0xFFFF_FFFF);
+                self.set_fat(cluster, next);
+            }
+        }
+        self.used.extend(&clusters);
+        clusters[0]
+    }
+
+    fn store(&mut self, data: &[u8], chain: bool) -> u32 {
+        self.place(data, chain, false)
+    }
+
+    /// Набор записей файла или каталога; данные размещаются на томе.
+    fn entry_set(&mut self, node: &ExFatNode) -> Vec<[u8; 32]> {
+        let (name, attributes, first, valid, length, contiguous) = match node {
+            ExFatNode::File {
+                name,
+                data,
+                valid,
+                fragmented,
+            } => {
+                let first = match data.is_empty() {
+                    true => 0,
+                    false => self.place(data, *fragmented, *fragmented),
+                };
+                let (valid, length) = (*valid as u64, data.len() as u64);
+                (name.clone(), 0x20u16, first, valid, length, !fragmented)
+            }
+            ExFatNode::Dir { name, children } => {
+                let entries: Vec<[u8; 32]> = children
+                    .iter()
+                    .flat_map(|child| self.entry_set(child))
+                    .collect();
+                let size =
+                    (entries.len() * 32).div_ceil(self.cluster_size).max(1) * self.cluster_size;
+                let first = self.place(&entries.concat(), false, false);
+                (name.clone(), 0x10, first, size as u64, size as u64, true)
+            }
+        };
+
+        let name: Vec<u16> = name.encode_utf16().collect();
+        // 2024-01-01 12:00:00 в формате DOS.
+        let timestamp: u32 = ((2024 - 1980) << 25) | (1 << 21) | (1 << 16) | (12 << 11);
+        let mut file = [0u8; 32];
+        file[0] = 0x85;
+        file[1] = (1 + name.len().div_ceil(15)) as u8;
+        file[4..6].copy_from_slice(&attributes.to_le_bytes());
+        for offset in [8, 12, 16] {
+            file[offset..offset + 4].copy_from_slice(&timestamp.to_le_bytes());
+        }
+
+        let hash = name
+            .iter()
+            .flat_map(|&unit| self.upcase[unit as usize].to_le_bytes())
+            .fold(0u16, |hash, byte| {
+                hash.rotate_right(1).wrapping_add(byte as u16)
+            });
+        let mut stream = [0u8; 32];
+        stream[0] = 0xC0;
+        stream[1] = 0x01 | if contiguous { 0x02 } else { 0 };
+        stream[3] = name.len() as u8;
+        stream[4..6].copy_from_slice(&hash.to_le_bytes());
+        stream[8..16].copy_from_slice(&valid.to_le_bytes());
+        stream[20..24].copy_from_slice(&first.to_le_bytes());
+        stream[24..32].copy_from_slice(&length.to_le_bytes());
+
+        let mut set = vec![file, stream];
+        for chunk in name.chunks(15) {
+            let mut entry = [0u8; 32];
+            entry[0] = 0xC1;
+            for (index, unit) in chunk.iter().enumerate() {
+                entry[2 + index * 2..4 + index * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            set.push(entry);
+        }
+
+        let checksum = set
+            .concat()
+            .iter()
+            .enumerate()
+            .filter(|&(offset, _)| !matches!(offset, 2 | 3))
+            .fold(0u16, |sum, (_, &byte)| {
+                sum.rotate_right(1).wrapping_add(byte as u16)
+            });
+        set[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+        set
+    }
+}
+
+/// Разреженный образ: число секторов, затем только ненулевые секторы
+/// (номер и содержимое). Так образ FAT32 в десятки мегабайт занимает
+/// несколько килобайт.
+fn sparse(image: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    output.extend_from_slice(&((image.len() / SECTOR_SIZE) as u64).to_le_bytes());
+
+    for (lba, sector) in image.chunks(SECTOR_SIZE).enumerate() {
+        if sector.iter().any(|&byte| byte != 0) {
+            output.extend_from_slice(&(lba as u64).to_le_bytes());
+            output.extend_from_slice(sector);
+        }
+    }
+    output
+}